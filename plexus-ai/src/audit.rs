@@ -0,0 +1,189 @@
+use anyhow::Result;
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Seed used when a caller does not pick one.
+pub const DEFAULT_SEED: u64 = 42;
+
+/// Sampling settings of a generation, complete enough to reproduce it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    /// Seed of the sampling RNG.
+    pub seed: u64,
+    /// Softmax temperature; greedy decoding when `None` or 0.
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    /// Sample only among the `k` most likely tokens.
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// Nucleus sampling threshold.
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    /// Generation stops after this many tokens.
+    pub max_new_tokens: usize,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self {
+            seed: DEFAULT_SEED,
+            temperature: None,
+            top_k: None,
+            top_p: None,
+            max_new_tokens: 100,
+        }
+    }
+}
+
+impl GenerationParams {
+    /// Greedy decoding of up to `max_new_tokens` tokens.
+    pub fn greedy(max_new_tokens: usize) -> Self {
+        Self {
+            max_new_tokens,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f64) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        let temperature = match self.temperature {
+            Some(t) if t > 1e-7 => t,
+            _ => return Sampling::ArgMax,
+        };
+        match (self.top_k, self.top_p) {
+            (None, None) => Sampling::All { temperature },
+            (Some(k), None) => Sampling::TopK { k, temperature },
+            (None, Some(p)) => Sampling::TopP { p, temperature },
+            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+        }
+    }
+
+    /// A freshly seeded sampler; two sequences sampled from equal logits with equal
+    /// params pick the same tokens.
+    pub fn logits_processor(&self) -> LogitsProcessor {
+        LogitsProcessor::from_sampling(self.seed, self.sampling())
+    }
+}
+
+/// Identifies the exact weights and tokenizer an engine runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelFingerprint {
+    /// Human-readable model name, e.g. `repo/file.gguf`.
+    pub model: String,
+    /// SHA-256 of the weights file.
+    pub model_sha256: String,
+    /// SHA-256 of `tokenizer.json`.
+    pub tokenizer_sha256: String,
+}
+
+/// Audit record of a deterministic generation.
+///
+/// It holds everything but the prompt itself (which may be private), so a peer that
+/// knows the prompt can replay the generation with [`crate::LLMEngine::replay`] and
+/// check the output is bit-identical.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerationRecord {
+    #[serde(flatten)]
+    pub fingerprint: ModelFingerprint,
+    pub params: GenerationParams,
+    /// SHA-256 of the rendered prompt.
+    pub prompt_sha256: String,
+    /// SHA-256 of the generated token ids (little-endian `u32`s).
+    pub output_sha256: String,
+    /// Number of generated tokens.
+    pub output_tokens: usize,
+    /// Version of the generating engine. Kernels and the sampling RNG are only
+    /// guaranteed stable within one version.
+    pub engine_version: String,
+}
+
+impl GenerationRecord {
+    pub fn new(
+        fingerprint: ModelFingerprint,
+        params: GenerationParams,
+        prompt: &str,
+        output: &[u32],
+    ) -> Self {
+        Self {
+            fingerprint,
+            params,
+            prompt_sha256: sha256_hex(prompt.as_bytes()),
+            output_sha256: hash_tokens(output),
+            output_tokens: output.len(),
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Fails unless `prompt` is the prompt this record was generated from.
+    pub fn verify_prompt(&self, prompt: &str) -> Result<()> {
+        anyhow::ensure!(
+            sha256_hex(prompt.as_bytes()) == self.prompt_sha256,
+            "Prompt does not match the record's prompt hash"
+        );
+        Ok(())
+    }
+
+    /// Whether `replayed` reproduced this record's output.
+    ///
+    /// Fails if the replay ran on different weights, tokenizer or params, since its
+    /// output then says nothing about this record.
+    pub fn reproduced_by(&self, replayed: &GenerationRecord) -> Result<bool> {
+        anyhow::ensure!(
+            replayed.fingerprint.model_sha256 == self.fingerprint.model_sha256,
+            "Cannot replay: model hash {} differs from the record's {}",
+            replayed.fingerprint.model_sha256,
+            self.fingerprint.model_sha256
+        );
+        anyhow::ensure!(
+            replayed.fingerprint.tokenizer_sha256 == self.fingerprint.tokenizer_sha256,
+            "Cannot replay: tokenizer hash {} differs from the record's {}",
+            replayed.fingerprint.tokenizer_sha256,
+            self.fingerprint.tokenizer_sha256
+        );
+        anyhow::ensure!(
+            replayed.params == self.params && replayed.prompt_sha256 == self.prompt_sha256,
+            "Cannot replay: prompt or generation params differ from the record"
+        );
+        if replayed.engine_version != self.engine_version {
+            tracing::warn!(
+                "Replaying a record of engine {} on {}; outputs may legitimately differ",
+                self.engine_version,
+                replayed.engine_version
+            );
+        }
+        Ok(replayed.output_sha256 == self.output_sha256
+            && replayed.output_tokens == self.output_tokens)
+    }
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+fn hash_tokens(tokens: &[u32]) -> String {
+    let mut hasher = Sha256::new();
+    for token in tokens {
+        hasher.update(token.to_le_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
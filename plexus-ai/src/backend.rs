@@ -0,0 +1,413 @@
+//! Choosing and opening the vector store memory is kept in.
+//!
+//! Qdrant runs as a separate server that may be down when the node starts or go away
+//! while it runs. The [`QdrantFailoverStore`] keeps memory in the embedded store while the
+//! server is unreachable and keeps trying to reconnect, instead of deciding once at
+//! startup. Writes made in the meantime are replayed into Qdrant when it comes back.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+#[cfg(feature = "lancedb")]
+use crate::LanceDbStore;
+use crate::{
+    Document, EmbeddingModel, QdrantConfig, QdrantStore, SearchFilter, SearchResult,
+    SimpleVectorStore, VectorStore,
+};
+
+/// Which vector store memory is kept in, e.g. via `--vector-store`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorStoreKind {
+    /// Memory is disabled: nothing is saved and prompts are not augmented.
+    None,
+    /// The embedded HNSW store in the data directory.
+    Memory,
+    /// An embedded LanceDB table in the data directory; needs the `lancedb` feature.
+    Lancedb,
+    /// A Qdrant server, with the embedded store standing in while it is unreachable.
+    #[default]
+    Qdrant,
+}
+
+impl std::str::FromStr for VectorStoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "none" | "off" => Ok(VectorStoreKind::None),
+            "memory" | "hnsw" => Ok(VectorStoreKind::Memory),
+            "lancedb" | "lance" => Ok(VectorStoreKind::Lancedb),
+            "qdrant" => Ok(VectorStoreKind::Qdrant),
+            other => anyhow::bail!(
+                "Unknown vector store '{}' (expected none, memory, lancedb or qdrant)",
+                other
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for VectorStoreKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VectorStoreKind::None => write!(f, "none"),
+            VectorStoreKind::Memory => write!(f, "memory"),
+            VectorStoreKind::Lancedb => write!(f, "lancedb"),
+            VectorStoreKind::Qdrant => write!(f, "qdrant"),
+        }
+    }
+}
+
+/// The vector store to open and how to reach it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorStoreConfig {
+    pub kind: VectorStoreKind,
+    /// Used with [`VectorStoreKind::Qdrant`].
+    pub qdrant: QdrantConfig,
+    /// Seconds between attempts to reach Qdrant while it is down, and between health
+    /// checks while it is up. `0` disables reconnecting.
+    pub reconnect_secs: u64,
+}
+
+impl Default for VectorStoreConfig {
+    fn default() -> Self {
+        Self {
+            kind: VectorStoreKind::default(),
+            qdrant: QdrantConfig::default(),
+            reconnect_secs: 30,
+        }
+    }
+}
+
+impl VectorStoreConfig {
+    pub fn new(kind: VectorStoreKind) -> Self {
+        Self {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_qdrant(mut self, qdrant: QdrantConfig) -> Self {
+        self.qdrant = qdrant;
+        self
+    }
+
+    pub fn with_reconnect_secs(mut self, reconnect_secs: u64) -> Self {
+        self.reconnect_secs = reconnect_secs;
+        self
+    }
+
+    /// Opens the store for vectors of `model`, keeping embedded stores in `data_dir`.
+    /// `None` when memory is disabled.
+    ///
+    /// Must be called within a Tokio runtime: with Qdrant, reconnection runs as a
+    /// background task for as long as the store is alive.
+    pub async fn open(
+        &self,
+        data_dir: &Path,
+        model: &EmbeddingModel,
+    ) -> Result<Option<Arc<dyn VectorStore>>> {
+        let store: Arc<dyn VectorStore> = match self.kind {
+            VectorStoreKind::None => return Ok(None),
+            VectorStoreKind::Memory => Arc::new(open_memory(data_dir, model)?),
+            VectorStoreKind::Lancedb => open_lancedb(data_dir, model).await?,
+            VectorStoreKind::Qdrant => {
+                let fallback = open_embedded(data_dir, model).await?;
+                let store = Arc::new(QdrantFailoverStore::new(
+                    self.qdrant.clone(),
+                    model.clone(),
+                    fallback,
+                ));
+                store.refresh().await;
+                if self.reconnect_secs > 0 {
+                    store.spawn_reconnect(Duration::from_secs(self.reconnect_secs));
+                }
+                store
+            }
+        };
+        Ok(Some(store))
+    }
+
+    /// Like [`VectorStoreConfig::open`], but connects to Qdrant itself and fails when it
+    /// is unreachable instead of falling back to the embedded store. For one-off jobs such
+    /// as backups, which must not read or write the fallback by mistake.
+    pub async fn open_direct(
+        &self,
+        data_dir: &Path,
+        model: &EmbeddingModel,
+    ) -> Result<Option<Arc<dyn VectorStore>>> {
+        if self.kind != VectorStoreKind::Qdrant {
+            return self.open(data_dir, model).await;
+        }
+        let store = QdrantStore::connect(&self.qdrant, model)
+            .await
+            .with_context(|| format!("Qdrant at {} is unreachable", self.qdrant.url))?;
+        Ok(Some(Arc::new(store)))
+    }
+}
+
+fn open_memory(data_dir: &Path, model: &EmbeddingModel) -> Result<SimpleVectorStore> {
+    let path = data_dir.join("memory");
+    let store = SimpleVectorStore::open(&path, model)?;
+    tracing::info!("Opened Embedded HNSW Store at {:?}", path);
+    Ok(store)
+}
+
+#[cfg(feature = "lancedb")]
+async fn open_lancedb(data_dir: &Path, model: &EmbeddingModel) -> Result<Arc<dyn VectorStore>> {
+    let path = data_dir.join("vectors.lance");
+    let store = LanceDbStore::new(&path, model).await?;
+    tracing::info!("Connected to Embedded LanceDB at {:?}", path);
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "lancedb"))]
+async fn open_lancedb(_data_dir: &Path, _model: &EmbeddingModel) -> Result<Arc<dyn VectorStore>> {
+    anyhow::bail!("LanceDB support was not compiled in (enable the `lancedb` feature)")
+}
+
+/// The embedded store standing in for Qdrant: LanceDB when compiled in, otherwise the
+/// HNSW store.
+async fn open_embedded(data_dir: &Path, model: &EmbeddingModel) -> Result<Arc<dyn VectorStore>> {
+    if cfg!(feature = "lancedb") {
+        match open_lancedb(data_dir, model).await {
+            Ok(store) => return Ok(store),
+            Err(e) => tracing::error!("Failed to init LanceDB: {}. Using the HNSW Store.", e),
+        }
+    }
+    Ok(Arc::new(open_memory(data_dir, model)?))
+}
+
+/// Qdrant when it is reachable, otherwise an embedded store.
+///
+/// [`Self::refresh`] connects while Qdrant is down and drops the connection when a health
+/// check fails; [`Self::spawn_reconnect`] runs it periodically. Documents this process
+/// saved or deleted while Qdrant was down are replayed into it on reconnect, before
+/// requests switch back (see [`Self::pending_writes`]).
+pub struct QdrantFailoverStore {
+    config: QdrantConfig,
+    model: EmbeddingModel,
+    qdrant: Mutex<Option<Arc<QdrantStore>>>,
+    fallback: Arc<dyn VectorStore>,
+    /// Ids written to the fallback since the last replay into Qdrant. Kept in memory
+    /// only: writes made during an outage that outlives the process are not replayed.
+    pending: Mutex<HashSet<String>>,
+    /// Held by writes and exclusively while switching to Qdrant, so that no write lands
+    /// in the fallback after the final replay.
+    switching: tokio::sync::RwLock<()>,
+    generation: AtomicU64,
+}
+
+impl QdrantFailoverStore {
+    /// A store using `fallback` until [`Self::refresh`] reaches Qdrant.
+    pub fn new(
+        config: QdrantConfig,
+        model: EmbeddingModel,
+        fallback: Arc<dyn VectorStore>,
+    ) -> Self {
+        Self {
+            config,
+            model,
+            qdrant: Mutex::new(None),
+            fallback,
+            pending: Mutex::new(HashSet::new()),
+            switching: tokio::sync::RwLock::new(()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether requests currently go to Qdrant.
+    pub fn is_connected(&self) -> bool {
+        self.qdrant.lock().unwrap().is_some()
+    }
+
+    /// Connects to Qdrant if disconnected, or checks that it still answers if connected.
+    /// Returns whether requests go to Qdrant afterwards.
+    pub async fn refresh(&self) -> bool {
+        let current = self.qdrant.lock().unwrap().clone();
+        match current {
+            Some(store) => {
+                let healthy = tokio::time::timeout(self.config.timeout(), store.is_healthy()).await;
+                if !matches!(healthy, Ok(true)) {
+                    tracing::error!(
+                        "Lost connection to Qdrant at {}. Falling back to Embedded Store.",
+                        self.config.url
+                    );
+                    *self.qdrant.lock().unwrap() = None;
+                    self.generation.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            None => match QdrantStore::connect(&self.config, &self.model).await {
+                Ok(store) => {
+                    // Replay before blocking writes, so only the stragglers hold them up
+                    if self.replay(&store).await {
+                        let _switching = self.switching.write().await;
+                        if self.replay(&store).await {
+                            tracing::info!(
+                                "Connected to Qdrant Vector Database at {} (collection {}).",
+                                self.config.url,
+                                store.collection_name()
+                            );
+                            *self.qdrant.lock().unwrap() = Some(Arc::new(store));
+                            self.generation.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!(
+                    "Qdrant unavailable ({}). Using Embedded Store until it is reachable.",
+                    e
+                ),
+            },
+        }
+        self.is_connected()
+    }
+
+    /// Refreshes the connection every `interval` until the store is dropped.
+    pub fn spawn_reconnect(self: &Arc<Self>, interval: Duration) {
+        let store: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                store.refresh().await;
+            }
+        });
+    }
+
+    /// Number of writes made to the fallback that Qdrant has not received yet.
+    pub fn pending_writes(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Copies the pending writes from the fallback into `qdrant`. Returns whether all
+    /// of them made it; the rest stay pending for the next attempt.
+    async fn replay(&self, qdrant: &QdrantStore) -> bool {
+        let pending: Vec<String> = std::mem::take(&mut *self.pending.lock().unwrap())
+            .into_iter()
+            .collect();
+        if pending.is_empty() {
+            return true;
+        }
+        let result = async {
+            let wanted: HashSet<&str> = pending.iter().map(String::as_str).collect();
+            let mut entries: HashMap<String, (Document, Vec<f32>)> = self
+                .fallback
+                .entries()
+                .await
+                .map_err(|e| (0, e))?
+                .into_iter()
+                .filter(|(document, _)| wanted.contains(document.id.as_str()))
+                .map(|(document, vector)| (document.id.clone(), (document, vector)))
+                .collect();
+            for (replayed, id) in pending.iter().enumerate() {
+                let written = match entries.remove(id) {
+                    Some((document, vector)) => qdrant.upsert(document, vector).await,
+                    None => qdrant.delete(id).await,
+                };
+                written.map_err(|e| (replayed, e))?;
+            }
+            Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                tracing::info!(
+                    "Replayed {} writes made while Qdrant was unreachable.",
+                    pending.len()
+                );
+                true
+            }
+            Err((replayed, e)) => {
+                let mut remaining = self.pending.lock().unwrap();
+                remaining.extend(pending.into_iter().skip(replayed));
+                tracing::warn!(
+                    "Could not replay {} writes made while Qdrant was unreachable ({}). \
+                     Staying on the Embedded Store.",
+                    remaining.len(),
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    fn active(&self) -> Arc<dyn VectorStore> {
+        match self.qdrant.lock().unwrap().as_ref() {
+            Some(store) => store.clone(),
+            None => self.fallback.clone(),
+        }
+    }
+
+    /// The store a write of `id` goes to, recording it for replay if it is the fallback.
+    /// The caller holds `switching` for the duration of the write.
+    fn writer(&self, id: &str) -> Arc<dyn VectorStore> {
+        match self.qdrant.lock().unwrap().as_ref() {
+            Some(store) => store.clone(),
+            None => {
+                self.pending.lock().unwrap().insert(id.to_string());
+                self.fallback.clone()
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for QdrantFailoverStore {
+    async fn add(&self, id: &str, vector: Vec<f32>) -> Result<()> {
+        let _switching = self.switching.read().await;
+        self.writer(id).add(id, vector).await
+    }
+
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> Result<()> {
+        let _switching = self.switching.read().await;
+        self.writer(&document.id).upsert(document, vector).await
+    }
+
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        self.active().search_filtered(query_vector, k, filter).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Document>> {
+        self.active().get(id).await
+    }
+
+    async fn get_entry(&self, id: &str) -> Result<Option<(Document, Vec<f32>)>> {
+        self.active().get_entry(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        let _switching = self.switching.read().await;
+        self.writer(id).delete(id).await
+    }
+
+    async fn count(&self) -> Result<usize> {
+        self.active().count().await
+    }
+
+    async fn documents(&self) -> Result<Vec<Document>> {
+        self.active().documents().await
+    }
+
+    async fn entries(&self) -> Result<Vec<(Document, Vec<f32>)>> {
+        self.active().entries().await
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
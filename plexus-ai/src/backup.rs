@@ -0,0 +1,153 @@
+//! Portable memory export and import.
+//!
+//! Memory is written as JSON Lines, one [`MemoryRecord`] per line: the document with the
+//! vector it is stored with and the embedding model that produced it. Importing keeps
+//! the vectors of records from the importing node's model and re-embeds the text of the
+//! others, so memory can move between machines and embedding models alike.
+
+use crate::persist::AtomicFile;
+use crate::{Document, Embedder, VectorStore};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Texts re-embedded per embedder call.
+const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// One document of an export.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryRecord {
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    /// Embedding model `vector` was produced with, see [`crate::EmbedderRegistry`]
+    pub model: String,
+    pub vector: Vec<f32>,
+}
+
+impl MemoryRecord {
+    pub fn new(document: Document, model: &str, vector: Vec<f32>) -> Self {
+        Self {
+            id: document.id,
+            text: document.text,
+            metadata: document.metadata,
+            model: model.to_string(),
+            vector,
+        }
+    }
+
+    pub fn document(&self) -> Document {
+        Document {
+            id: self.id.clone(),
+            text: self.text.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+/// Outcome of an import.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Documents stored, replacing any with the same id.
+    pub imported: usize,
+    /// Documents whose text was embedded again because they came from another model.
+    pub reembedded: usize,
+}
+
+/// Exports the memory of a vector store and imports exports into it.
+pub struct MemoryBackup {
+    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
+    batch_size: usize,
+}
+
+impl MemoryBackup {
+    /// `embedder` is the model the vectors of `store` come from.
+    pub fn new(embedder: Arc<dyn Embedder>, store: Arc<dyn VectorStore>) -> Self {
+        Self {
+            embedder,
+            store,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets how many texts are re-embedded per embedder call.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Writes every document of the store to `writer`, ordered by id. Returns how many.
+    pub async fn export(&self, mut writer: impl Write) -> Result<usize> {
+        let mut entries = self.store.entries().await?;
+        entries.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+        let model = self.embedder.model_id();
+        for (document, vector) in &entries {
+            let record = MemoryRecord::new(document.clone(), model, vector.clone());
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        tracing::info!("Exported {} documents", entries.len());
+        Ok(entries.len())
+    }
+
+    /// Exports to `path` atomically: an interrupted export leaves any previous file whole.
+    pub async fn export_file(&self, path: &Path) -> Result<usize> {
+        let mut file = AtomicFile::create(path)?;
+        let count = self.export(BufWriter::new(file.file())).await?;
+        file.commit()?;
+        Ok(count)
+    }
+
+    /// Stores the records read from `reader`. The whole export is read and checked before
+    /// anything is stored.
+    pub async fn import(&self, reader: impl BufRead) -> Result<ImportReport> {
+        let mut records = vec![];
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: MemoryRecord = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid memory record on line {}", number + 1))?;
+            records.push(record);
+        }
+
+        let mut report = ImportReport::default();
+        // Vectors of another model, or of another size, are not comparable with ours
+        let (kept, stale): (Vec<_>, Vec<_>) = records.into_iter().partition(|r| {
+            r.model == self.embedder.model_id() && r.vector.len() == self.embedder.dimension()
+        });
+        for record in kept {
+            self.store.upsert(record.document(), record.vector).await?;
+            report.imported += 1;
+        }
+        for batch in stale.chunks(self.batch_size) {
+            let texts: Vec<&str> = batch.iter().map(|r| r.text.as_str()).collect();
+            let vectors = self.embedder.embed_batch(&texts).await?;
+            for (record, vector) in batch.iter().zip(vectors) {
+                self.store.upsert(record.document(), vector).await?;
+            }
+            report.imported += batch.len();
+            report.reembedded += batch.len();
+        }
+
+        tracing::info!(
+            "Imported {} documents, {} re-embedded",
+            report.imported,
+            report.reembedded
+        );
+        Ok(report)
+    }
+
+    pub async fn import_file(&self, path: &Path) -> Result<ImportReport> {
+        let file =
+            std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        self.import(BufReader::new(file)).await
+    }
+}
@@ -0,0 +1,909 @@
+use anyhow::{Context, Result};
+use candle_core::quantized::{gguf_file, QMatMul};
+use candle_core::{DType, Device, IndexOp, Module, Tensor};
+use candle_nn::Embedding;
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::quantized_llama::MAX_SEQ_LEN;
+use candle_transformers::quantized_nn::RmsNorm;
+use candle_transformers::utils::repeat_kv;
+use tokio::sync::mpsc;
+
+use crate::constrained::TokenConstraint;
+use crate::prefix_cache::PrefixCache;
+use crate::speculative::DraftModel;
+
+/// Per-sequence attention cache for [`BatchedLlama`].
+///
+/// Cloning is cheap (tensors are reference counted) and the clone is independent,
+/// since appending a token creates new tensors.
+#[derive(Debug, Clone)]
+pub struct KvCache {
+    layers: Vec<Option<(Tensor, Tensor)>>,
+    len: usize,
+}
+
+impl KvCache {
+    fn new(n_layers: usize) -> Self {
+        Self {
+            layers: vec![None; n_layers],
+            len: 0,
+        }
+    }
+
+    /// Number of tokens processed into this cache.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Drops every token after the first `len`, e.g. rejected speculative tokens.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        if len >= self.len {
+            return Ok(());
+        }
+        for layer in self.layers.iter_mut() {
+            *layer = match layer.take() {
+                Some(_) if len == 0 => None,
+                Some((k, v)) => Some((k.narrow(2, 0, len)?, v.narrow(2, 0, len)?)),
+                None => None,
+            };
+        }
+        self.len = len;
+        Ok(())
+    }
+}
+
+struct Layer {
+    attention_wq: QMatMul,
+    attention_wk: QMatMul,
+    attention_wv: QMatMul,
+    attention_wo: QMatMul,
+    attention_norm: RmsNorm,
+    feed_forward_w1: QMatMul,
+    feed_forward_w2: QMatMul,
+    feed_forward_w3: QMatMul,
+    ffn_norm: RmsNorm,
+}
+
+/// Quantized llama weights that run several sequences through one forward pass.
+///
+/// Mirrors `candle_transformers::models::quantized_llama::ModelWeights`, except that
+/// the KV caches live outside the model in a [`KvCache`] per sequence. The weights are
+/// shared by every sequence, so concurrent requests no longer need a model clone each.
+/// Mixture-of-experts GGUF files are not supported.
+pub struct BatchedLlama {
+    tok_embeddings: Embedding,
+    layers: Vec<Layer>,
+    norm: RmsNorm,
+    output: QMatMul,
+    cos: Tensor,
+    sin: Tensor,
+    neg_inf: Tensor,
+    n_head: usize,
+    n_kv_head: usize,
+    head_dim: usize,
+    device: Device,
+}
+
+impl BatchedLlama {
+    /// Loads the weights from a llama-architecture GGUF file.
+    pub fn from_gguf<R: std::io::Seek + std::io::Read>(
+        ct: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        let md_get = |s: &str| {
+            ct.metadata
+                .get(s)
+                .with_context(|| format!("cannot find {s} in metadata"))
+        };
+
+        let n_expert = md_get("llama.expert_count")
+            .and_then(|v| Ok(v.to_u32()?))
+            .unwrap_or(0);
+        anyhow::ensure!(n_expert <= 1, "Mixture-of-experts models are not supported");
+        let head_count = md_get("llama.attention.head_count")?.to_u32()? as usize;
+        let head_count_kv = md_get("llama.attention.head_count_kv")?.to_u32()? as usize;
+        let block_count = md_get("llama.block_count")?.to_u32()? as usize;
+        let embedding_length = md_get("llama.embedding_length")?.to_u32()? as usize;
+        let rope_dim = md_get("llama.rope.dimension_count")?.to_u32()? as usize;
+        let rms_norm_eps = md_get("llama.attention.layer_norm_rms_epsilon")?.to_f32()? as f64;
+        let rope_freq_base = md_get("llama.rope.freq_base")
+            .and_then(|v| Ok(v.to_f32()?))
+            .unwrap_or(10000f32);
+
+        let (cos, sin) = precompute_freqs_cis(rope_dim, rope_freq_base, device)?;
+
+        let tok_embeddings_q = ct.tensor(reader, "token_embd.weight", device)?;
+        let tok_embeddings = tok_embeddings_q.dequantize(device)?;
+        let norm = RmsNorm::from_qtensor(
+            ct.tensor(reader, "output_norm.weight", device)?,
+            rms_norm_eps,
+        )?;
+        // Models with tied embeddings have no separate output matrix
+        let output = match ct.tensor(reader, "output.weight", device) {
+            Ok(tensor) => tensor,
+            Err(_) => tok_embeddings_q,
+        };
+
+        let mut layers = Vec::with_capacity(block_count);
+        for layer_idx in 0..block_count {
+            let prefix = format!("blk.{layer_idx}");
+            let mut qmatmul = |name: &str| -> Result<QMatMul> {
+                let tensor = ct.tensor(reader, &format!("{prefix}.{name}.weight"), device)?;
+                Ok(QMatMul::from_qtensor(tensor)?)
+            };
+            let attention_wq = qmatmul("attn_q")?;
+            let attention_wk = qmatmul("attn_k")?;
+            let attention_wv = qmatmul("attn_v")?;
+            let attention_wo = qmatmul("attn_output")?;
+            let feed_forward_w1 = qmatmul("ffn_gate")?;
+            let feed_forward_w2 = qmatmul("ffn_down")?;
+            let feed_forward_w3 = qmatmul("ffn_up")?;
+            let attention_norm = RmsNorm::from_qtensor(
+                ct.tensor(reader, &format!("{prefix}.attn_norm.weight"), device)?,
+                rms_norm_eps,
+            )?;
+            let ffn_norm = RmsNorm::from_qtensor(
+                ct.tensor(reader, &format!("{prefix}.ffn_norm.weight"), device)?,
+                rms_norm_eps,
+            )?;
+            layers.push(Layer {
+                attention_wq,
+                attention_wk,
+                attention_wv,
+                attention_wo,
+                attention_norm,
+                feed_forward_w1,
+                feed_forward_w2,
+                feed_forward_w3,
+                ffn_norm,
+            });
+        }
+
+        Ok(Self {
+            tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
+            layers,
+            norm,
+            output: QMatMul::from_qtensor(output)?,
+            cos,
+            sin,
+            neg_inf: Tensor::new(f32::NEG_INFINITY, device)?,
+            n_head: head_count,
+            n_kv_head: head_count_kv,
+            head_dim: embedding_length / head_count,
+            device: device.clone(),
+        })
+    }
+
+    /// An empty cache for a new sequence.
+    pub fn new_cache(&self) -> KvCache {
+        KvCache::new(self.layers.len())
+    }
+
+    /// Longest sequence (prompt plus generated tokens) the model can process.
+    pub fn max_seq_len(&self) -> usize {
+        MAX_SEQ_LEN
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.tok_embeddings.embeddings().dims()[0]
+    }
+
+    /// Feeds `tokens` of a single sequence on top of `cache`.
+    ///
+    /// Returns the logits for the last token, shape `(vocab,)`.
+    pub fn prefill(&self, tokens: &[u32], cache: &mut KvCache) -> Result<Tensor> {
+        anyhow::ensure!(!tokens.is_empty(), "Cannot prefill an empty sequence");
+        let input = Tensor::new(tokens, &self.device)?.unsqueeze(0)?;
+        let logits = self.forward(&input, &mut [cache], false)?;
+        Ok(logits.squeeze(0)?)
+    }
+
+    /// Feeds one token for each sequence in a single forward pass.
+    ///
+    /// `tokens[i]` is appended to `caches[i]`. Returns logits of shape `(batch, vocab)`.
+    pub fn decode(&self, tokens: &[u32], caches: &mut [&mut KvCache]) -> Result<Tensor> {
+        anyhow::ensure!(
+            tokens.len() == caches.len(),
+            "Got {} tokens for {} sequences",
+            tokens.len(),
+            caches.len()
+        );
+        let input = Tensor::new(tokens, &self.device)?.unsqueeze(1)?;
+        self.forward(&input, caches, false)
+    }
+
+    /// Feeds an equally long run of tokens for each sequence and scores every position.
+    ///
+    /// `tokens[i]` is appended to `caches[i]`. Returns logits of shape
+    /// `(batch, seq_len, vocab)`, where position `j` predicts the token after `tokens[i][j]`.
+    pub fn verify(&self, tokens: &[Vec<u32>], caches: &mut [&mut KvCache]) -> Result<Tensor> {
+        anyhow::ensure!(
+            tokens.len() == caches.len(),
+            "Got {} token runs for {} sequences",
+            tokens.len(),
+            caches.len()
+        );
+        let seq_len = tokens.first().map_or(0, |t| t.len());
+        anyhow::ensure!(
+            seq_len > 0 && tokens.iter().all(|t| t.len() == seq_len),
+            "Token runs must be non-empty and of equal length"
+        );
+        let flat: Vec<u32> = tokens.concat();
+        let input = Tensor::from_vec(flat, (tokens.len(), seq_len), &self.device)?;
+        self.forward(&input, caches, true)
+    }
+
+    /// Runs `input` of shape `(batch, seq_len)` through the model.
+    ///
+    /// The weight matmuls are shared across the batch; rotary embeddings and attention are
+    /// applied per sequence since every sequence has its own position and cache length.
+    /// Returns logits for the last position, or for every position with `all_positions`.
+    fn forward(
+        &self,
+        input: &Tensor,
+        caches: &mut [&mut KvCache],
+        all_positions: bool,
+    ) -> Result<Tensor> {
+        let (b_sz, seq_len) = input.dims2()?;
+        let offsets: Vec<usize> = caches.iter().map(|c| c.len).collect();
+        if let Some(longest) = offsets.iter().max() {
+            anyhow::ensure!(
+                longest + seq_len <= MAX_SEQ_LEN,
+                "Sequence exceeds the maximum length of {} tokens",
+                MAX_SEQ_LEN
+            );
+        }
+
+        // Rotary tables for each sequence's positions: (batch, seq_len, head_dim / 2)
+        let positions: Vec<u32> = offsets
+            .iter()
+            .flat_map(|&offset| (offset..offset + seq_len).map(|p| p as u32))
+            .collect();
+        let positions = Tensor::new(positions.as_slice(), &self.device)?;
+        let cos = self
+            .cos
+            .index_select(&positions, 0)?
+            .reshape((b_sz, seq_len, ()))?;
+        let sin = self
+            .sin
+            .index_select(&positions, 0)?
+            .reshape((b_sz, seq_len, ()))?;
+
+        let mut xs = self.tok_embeddings.forward(input)?;
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            let residual = &xs;
+            let x = layer.attention_norm.forward(&xs)?;
+
+            let q = layer
+                .attention_wq
+                .forward(&x)?
+                .reshape((b_sz, seq_len, self.n_head, self.head_dim))?
+                .transpose(1, 2)?
+                .contiguous()?;
+            let k = layer
+                .attention_wk
+                .forward(&x)?
+                .reshape((b_sz, seq_len, self.n_kv_head, self.head_dim))?
+                .transpose(1, 2)?
+                .contiguous()?;
+            let v = layer
+                .attention_wv
+                .forward(&x)?
+                .reshape((b_sz, seq_len, self.n_kv_head, self.head_dim))?
+                .transpose(1, 2)?
+                .contiguous()?;
+            let q = candle_nn::rotary_emb::rope_i(&q, &cos, &sin)?;
+            let k = candle_nn::rotary_emb::rope_i(&k, &cos, &sin)?;
+
+            let mut ys = Vec::with_capacity(b_sz);
+            for (i, cache) in caches.iter_mut().enumerate() {
+                ys.push(self.attend(
+                    layer_idx,
+                    cache,
+                    &q.narrow(0, i, 1)?,
+                    k.narrow(0, i, 1)?,
+                    v.narrow(0, i, 1)?,
+                )?);
+            }
+            let y = Tensor::cat(&ys, 0)?;
+            let xs_attn = (layer.attention_wo.forward(&y)? + residual)?;
+
+            let residual = &xs_attn;
+            let x = layer.ffn_norm.forward(&xs_attn)?;
+            let w1 = layer.feed_forward_w1.forward(&x)?;
+            let w3 = layer.feed_forward_w3.forward(&x)?;
+            let mlp = layer
+                .feed_forward_w2
+                .forward(&(candle_nn::ops::silu(&w1)? * w3)?)?;
+            xs = (mlp + residual)?;
+        }
+        for cache in caches.iter_mut() {
+            cache.len += seq_len;
+        }
+
+        let xs = self.norm.forward(&xs)?;
+        if all_positions {
+            return Ok(self.output.forward(&xs.contiguous()?)?);
+        }
+        let xs = xs.i((.., seq_len - 1, ..))?;
+        Ok(self.output.forward(&xs)?)
+    }
+
+    /// Attention for one sequence: `q` is `(1, n_head, seq_len, head_dim)`, `k` and `v`
+    /// hold the new entries that are appended to the sequence's cache.
+    fn attend(
+        &self,
+        layer_idx: usize,
+        cache: &mut KvCache,
+        q: &Tensor,
+        k: Tensor,
+        v: Tensor,
+    ) -> Result<Tensor> {
+        let seq_len = q.dim(2)?;
+        let offset = cache.len;
+
+        let (k, v) = match &cache.layers[layer_idx] {
+            Some((k_cache, v_cache)) => (
+                Tensor::cat(&[k_cache, &k], 2)?,
+                Tensor::cat(&[v_cache, &v], 2)?,
+            ),
+            None => (k, v),
+        };
+        cache.layers[layer_idx] = Some((k.clone(), v.clone()));
+
+        let n_rep = self.n_head / self.n_kv_head;
+        let k = repeat_kv(k, n_rep)?;
+        let v = repeat_kv(v, n_rep)?;
+
+        let att = (q.matmul(&k.t()?)? / (self.head_dim as f64).sqrt())?;
+        let att = if seq_len > 1 {
+            // Causal mask shifted by the number of cached tokens
+            let total = offset + seq_len;
+            let mask: Vec<u8> = (0..seq_len)
+                .flat_map(|i| (0..total).map(move |j| u8::from(j > i + offset)))
+                .collect();
+            let mask = Tensor::from_slice(&mask, (seq_len, total), &self.device)?
+                .broadcast_as(att.shape())?;
+            mask.where_cond(&self.neg_inf.broadcast_as(att.shape())?, &att)?
+        } else {
+            att
+        };
+        let att = candle_nn::ops::softmax_last_dim(&att)?;
+        let y = att.matmul(&v.contiguous()?)?;
+
+        Ok(y.transpose(1, 2)?
+            .reshape((1, seq_len, self.n_head * self.head_dim))?)
+    }
+}
+
+fn precompute_freqs_cis(
+    head_dim: usize,
+    freq_base: f32,
+    device: &Device,
+) -> Result<(Tensor, Tensor)> {
+    let theta: Vec<_> = (0..head_dim)
+        .step_by(2)
+        .map(|i| 1f32 / freq_base.powf(i as f32 / head_dim as f32))
+        .collect();
+    let theta = Tensor::new(theta.as_slice(), device)?;
+    let idx_theta = Tensor::arange(0, MAX_SEQ_LEN as u32, device)?
+        .to_dtype(DType::F32)?
+        .reshape((MAX_SEQ_LEN, 1))?
+        .matmul(&theta.reshape((1, theta.elem_count()))?)?;
+    Ok((idx_theta.cos()?, idx_theta.sin()?))
+}
+
+/// A generation request submitted to the [`BatchScheduler`].
+pub struct SequenceRequest {
+    /// The tokenized prompt.
+    pub tokens: Vec<u32>,
+    /// Generation stops after this many sampled tokens.
+    pub max_new_tokens: usize,
+    /// Tokens that end the sequence. They are not sent to the caller.
+    pub stop_tokens: Vec<u32>,
+    pub logits_processor: LogitsProcessor,
+    /// Restricts sampling to tokens that keep the output valid (e.g. against a JSON schema).
+    pub constraint: Option<TokenConstraint>,
+    /// Decode bit-reproducibly: alone, from an empty cache, on a single thread.
+    pub deterministic: bool,
+}
+
+impl SequenceRequest {
+    /// Samples the next token from `logits` of shape `(vocab,)`, honoring the constraint.
+    fn sample(&mut self, logits: &Tensor) -> Result<u32> {
+        let token = match &self.constraint {
+            Some(constraint) => {
+                let logits = constraint.mask_logits(logits)?;
+                self.logits_processor.sample(&logits)?
+            }
+            None => self.logits_processor.sample(logits)?,
+        };
+        if let Some(constraint) = &mut self.constraint {
+            constraint.advance(token)?;
+        }
+        Ok(token)
+    }
+}
+
+struct Submission {
+    request: SequenceRequest,
+    tokens_tx: mpsc::UnboundedSender<Result<u32>>,
+}
+
+/// A sequence that is part of the running batch.
+struct ActiveSequence {
+    cache: KvCache,
+    /// The draft model's cache when decoding speculatively. It may lag behind `cache`.
+    draft_cache: Option<KvCache>,
+    /// Tokens already processed into `cache`.
+    fed_tokens: Vec<u32>,
+    /// Cache state right after the prompt, kept for the prefix cache.
+    prompt_state: (Vec<u32>, KvCache),
+    /// Last sampled token, fed in the next decode step.
+    next_token: u32,
+    generated: usize,
+    request: SequenceRequest,
+    tokens_tx: mpsc::UnboundedSender<Result<u32>>,
+}
+
+impl ActiveSequence {
+    /// Records a sampled token and reports whether the sequence should keep decoding.
+    fn accept(&mut self, token: u32, max_seq_len: usize) -> bool {
+        self.next_token = token;
+        if self.request.stop_tokens.contains(&token) {
+            return false;
+        }
+        if self.tokens_tx.send(Ok(token)).is_err() {
+            // The caller went away
+            return false;
+        }
+        self.generated += 1;
+        // A finished constraint has nothing left to generate (and may have no stop token)
+        let constraint_finished = self
+            .request
+            .constraint
+            .as_ref()
+            .is_some_and(|c| c.is_finished());
+        !constraint_finished
+            && self.generated < self.request.max_new_tokens
+            && self.fed_tokens.len() < max_seq_len
+    }
+}
+
+/// Continuous-batching scheduler for a [`BatchedLlama`].
+///
+/// Requests are merged into a running batch that shares one forward pass per decode
+/// step. New sequences are admitted (prefilled) between decode steps, and finished
+/// sequences are retired without waiting for the rest of the batch. The model runs on
+/// a dedicated thread; dropping the scheduler lets in-flight sequences finish and then
+/// stops the thread.
+///
+/// Prompt and final KV states of retired sequences are kept in a [`PrefixCache`], so a
+/// follow-up chat turn only prefills its new tokens.
+///
+/// With a [`DraftModel`] the batch is decoded speculatively: the draft proposes several
+/// tokens per sequence and the model verifies them all in one forward pass.
+///
+/// Deterministic requests bypass all of the above. Batch composition, cache reuse and
+/// verification change the order of floating point reductions, so such a sequence runs
+/// to completion on its own while the batch waits.
+pub struct BatchScheduler {
+    submit_tx: mpsc::UnboundedSender<Submission>,
+}
+
+impl BatchScheduler {
+    pub fn new(
+        model: BatchedLlama,
+        max_batch_size: usize,
+        prefix_cache: PrefixCache<KvCache>,
+    ) -> Self {
+        Self::spawn(model, None, max_batch_size, prefix_cache)
+    }
+
+    /// A scheduler that decodes speculatively with `draft`.
+    ///
+    /// The draft model must share the tokenizer of `model`.
+    pub fn speculative(
+        model: BatchedLlama,
+        draft: DraftModel,
+        max_batch_size: usize,
+        prefix_cache: PrefixCache<KvCache>,
+    ) -> Result<Self> {
+        anyhow::ensure!(
+            draft.vocab_size() == model.vocab_size(),
+            "Draft model vocabulary ({}) does not match the target model ({})",
+            draft.vocab_size(),
+            model.vocab_size()
+        );
+        Ok(Self::spawn(
+            model,
+            Some(draft),
+            max_batch_size,
+            prefix_cache,
+        ))
+    }
+
+    fn spawn(
+        model: BatchedLlama,
+        draft: Option<DraftModel>,
+        max_batch_size: usize,
+        prefix_cache: PrefixCache<KvCache>,
+    ) -> Self {
+        let (submit_tx, submit_rx) = mpsc::unbounded_channel();
+        let worker = BatchWorker {
+            model,
+            draft,
+            max_batch_size: max_batch_size.max(1),
+            prefix_cache,
+            active: Vec::new(),
+            isolated_pool: None,
+        };
+        std::thread::Builder::new()
+            .name("plexus-batch-scheduler".to_string())
+            .spawn(move || worker.run(submit_rx))
+            .expect("failed to spawn batch scheduler thread");
+        Self { submit_tx }
+    }
+
+    /// Queues a sequence for generation.
+    ///
+    /// Sampled tokens are streamed through the returned channel, which closes when the
+    /// sequence finishes. Dropping the receiver cancels the sequence.
+    pub fn submit(&self, request: SequenceRequest) -> Result<mpsc::UnboundedReceiver<Result<u32>>> {
+        let (tokens_tx, tokens_rx) = mpsc::unbounded_channel();
+        self.submit_tx
+            .send(Submission { request, tokens_tx })
+            .map_err(|_| anyhow::anyhow!("Batch scheduler has stopped"))?;
+        Ok(tokens_rx)
+    }
+}
+
+struct BatchWorker {
+    model: BatchedLlama,
+    draft: Option<DraftModel>,
+    max_batch_size: usize,
+    prefix_cache: PrefixCache<KvCache>,
+    active: Vec<ActiveSequence>,
+    /// Single-threaded pool for deterministic sequences, built on first use.
+    isolated_pool: Option<rayon::ThreadPool>,
+}
+
+impl BatchWorker {
+    fn run(mut self, mut submit_rx: mpsc::UnboundedReceiver<Submission>) {
+        let mut open = true;
+        loop {
+            // Admit waiting requests between decode steps, blocking only when idle
+            while open && self.active.len() < self.max_batch_size {
+                let submission = if self.active.is_empty() {
+                    submit_rx.blocking_recv()
+                } else {
+                    match submit_rx.try_recv() {
+                        Ok(submission) => Some(submission),
+                        Err(mpsc::error::TryRecvError::Empty) => break,
+                        Err(mpsc::error::TryRecvError::Disconnected) => None,
+                    }
+                };
+                match submission {
+                    Some(submission) if submission.request.deterministic => {
+                        self.run_isolated(submission)
+                    }
+                    Some(submission) => self.admit(submission),
+                    None => open = false,
+                }
+            }
+
+            if self.active.is_empty() {
+                if open {
+                    continue;
+                }
+                break;
+            }
+            if !self.speculative_step() {
+                self.step();
+            }
+        }
+        tracing::debug!("Batch scheduler stopped");
+    }
+
+    /// Prefills a new sequence and samples its first token.
+    fn admit(&mut self, submission: Submission) {
+        let Submission {
+            mut request,
+            tokens_tx,
+        } = submission;
+
+        let prefilled = (|| -> Result<(KvCache, u32)> {
+            let tokens = &request.tokens;
+            anyhow::ensure!(!tokens.is_empty(), "Prompt cannot be empty");
+            anyhow::ensure!(
+                tokens.len() < self.model.max_seq_len(),
+                "Prompt is longer than {} tokens",
+                self.model.max_seq_len()
+            );
+
+            let (matched, mut cache) = match self.prefix_cache.take(tokens) {
+                Some((matched, cache)) => {
+                    tracing::debug!(
+                        "Prefix cache hit: reusing {} of {} prompt tokens",
+                        matched,
+                        tokens.len()
+                    );
+                    (matched, cache)
+                }
+                None => (0, self.model.new_cache()),
+            };
+            let logits = self.model.prefill(&tokens[matched..], &mut cache)?;
+            let token = request.sample(&logits)?;
+            Ok((cache, token))
+        })();
+
+        let (cache, token) = match prefilled {
+            Ok(prefilled) => prefilled,
+            Err(e) => {
+                let _ = tokens_tx.send(Err(e));
+                return;
+            }
+        };
+
+        let mut sequence = ActiveSequence {
+            prompt_state: (request.tokens.clone(), cache.clone()),
+            fed_tokens: request.tokens.clone(),
+            cache,
+            draft_cache: None,
+            next_token: token,
+            generated: 0,
+            request,
+            tokens_tx,
+        };
+        if sequence.request.max_new_tokens > 0 && sequence.accept(token, self.model.max_seq_len()) {
+            self.active.push(sequence);
+        } else {
+            self.retire(sequence);
+        }
+    }
+
+    /// Runs a deterministic sequence to completion, outside the batch.
+    ///
+    /// The sequence starts from an empty cache, decodes one token per forward pass and
+    /// runs on a one-thread pool, so the model's matmuls reduce in a fixed order.
+    fn run_isolated(&mut self, submission: Submission) {
+        let Submission { request, tokens_tx } = submission;
+        let pool = match self.isolated_pool.take() {
+            Some(pool) => pool,
+            None => match rayon::ThreadPoolBuilder::new().num_threads(1).build() {
+                Ok(pool) => pool,
+                Err(e) => {
+                    let _ = tokens_tx.send(Err(anyhow::anyhow!(
+                        "Failed to create deterministic thread pool: {}",
+                        e
+                    )));
+                    return;
+                }
+            },
+        };
+
+        let model = &self.model;
+        let mut sequence = ActiveSequence {
+            prompt_state: (Vec::new(), model.new_cache()),
+            fed_tokens: request.tokens.clone(),
+            cache: model.new_cache(),
+            draft_cache: None,
+            next_token: 0,
+            generated: 0,
+            request,
+            tokens_tx,
+        };
+        let result = pool.install(|| -> Result<()> {
+            let tokens = &sequence.request.tokens;
+            anyhow::ensure!(!tokens.is_empty(), "Prompt cannot be empty");
+            anyhow::ensure!(
+                tokens.len() < model.max_seq_len(),
+                "Prompt is longer than {} tokens",
+                model.max_seq_len()
+            );
+            let logits = model.prefill(tokens, &mut sequence.cache)?;
+            let token = sequence.request.sample(&logits)?;
+            if sequence.request.max_new_tokens == 0 {
+                return Ok(());
+            }
+            let mut keep_going = sequence.accept(token, model.max_seq_len());
+            while keep_going {
+                let logits = model.decode(&[sequence.next_token], &mut [&mut sequence.cache])?;
+                sequence.fed_tokens.push(sequence.next_token);
+                let token = sequence.request.sample(&logits.get(0)?)?;
+                keep_going = sequence.accept(token, model.max_seq_len());
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            let _ = sequence.tokens_tx.send(Err(e));
+        }
+        self.isolated_pool = Some(pool);
+    }
+
+    /// Runs one decode step for the whole batch and retires finished sequences.
+    fn step(&mut self) {
+        let tokens: Vec<u32> = self.active.iter().map(|s| s.next_token).collect();
+        let mut caches: Vec<&mut KvCache> = self.active.iter_mut().map(|s| &mut s.cache).collect();
+        let logits = match self.model.decode(&tokens, &mut caches) {
+            Ok(logits) => logits,
+            Err(e) => {
+                tracing::error!("Batched decode step failed: {}", e);
+                for sequence in self.active.drain(..) {
+                    let _ = sequence.tokens_tx.send(Err(anyhow::anyhow!("{}", e)));
+                }
+                return;
+            }
+        };
+
+        let max_seq_len = self.model.max_seq_len();
+        let mut finished = Vec::new();
+        for (i, sequence) in self.active.iter_mut().enumerate() {
+            sequence.fed_tokens.push(sequence.next_token);
+            let token = logits
+                .get(i)
+                .map_err(anyhow::Error::from)
+                .and_then(|logits| sequence.request.sample(&logits));
+            let keep_going = match token {
+                Ok(token) => sequence.accept(token, max_seq_len),
+                Err(e) => {
+                    let _ = sequence.tokens_tx.send(Err(e));
+                    false
+                }
+            };
+            if !keep_going {
+                finished.push(i);
+            }
+        }
+
+        for i in finished.into_iter().rev() {
+            let sequence = self.active.swap_remove(i);
+            self.retire(sequence);
+        }
+    }
+
+    /// Runs one speculative decode step if a draft model is configured.
+    ///
+    /// Returns `false` without decoding when there is no draft model, a sequence is too
+    /// close to the context limit or drafting failed; the caller then runs [`Self::step`].
+    fn speculative_step(&mut self) -> bool {
+        let Some(draft) = self.draft.take() else {
+            return false;
+        };
+        let stepped = self.speculate(&draft);
+        self.draft = Some(draft);
+        stepped
+    }
+
+    fn speculate(&mut self, draft: &DraftModel) -> bool {
+        let k = draft.draft_tokens();
+        let limit = self.model.max_seq_len().min(draft.max_seq_len());
+        if k == 0 || self.active.iter().any(|s| s.cache.len() + k + 1 > limit) {
+            return false;
+        }
+
+        let proposals = match self.propose(draft) {
+            Ok(proposals) => proposals,
+            Err(e) => {
+                tracing::warn!("Draft model failed, decoding without speculation: {}", e);
+                for sequence in self.active.iter_mut() {
+                    sequence.draft_cache = None;
+                }
+                return false;
+            }
+        };
+
+        // Score the last sampled token plus all proposals in one pass
+        let inputs: Vec<Vec<u32>> = self
+            .active
+            .iter()
+            .zip(&proposals)
+            .map(|(s, proposal)| {
+                std::iter::once(s.next_token)
+                    .chain(proposal.iter().copied())
+                    .collect()
+            })
+            .collect();
+        let mut caches: Vec<&mut KvCache> = self.active.iter_mut().map(|s| &mut s.cache).collect();
+        let logits = match self.model.verify(&inputs, &mut caches) {
+            Ok(logits) => logits,
+            Err(e) => {
+                tracing::error!("Speculative verification failed: {}", e);
+                for sequence in self.active.drain(..) {
+                    let _ = sequence.tokens_tx.send(Err(anyhow::anyhow!("{}", e)));
+                }
+                return true;
+            }
+        };
+
+        let max_seq_len = self.model.max_seq_len();
+        let mut finished = Vec::new();
+        // Proposals left over when a sequence finishes are never checked
+        let (mut checked, mut accepted) = (0, 0);
+        for (i, (sequence, proposal)) in self.active.iter_mut().zip(&proposals).enumerate() {
+            let verified = (|| -> Result<bool> {
+                let logits = logits.get(i)?;
+                sequence.fed_tokens.push(sequence.next_token);
+                // Position j is valid as long as every earlier proposal was accepted
+                for j in 0..=proposal.len() {
+                    let token = sequence.request.sample(&logits.get(j)?)?;
+                    if !sequence.accept(token, max_seq_len) {
+                        return Ok(false);
+                    }
+                    if j < proposal.len() {
+                        checked += 1;
+                    }
+                    if proposal.get(j) != Some(&token) {
+                        break;
+                    }
+                    sequence.fed_tokens.push(token);
+                    accepted += 1;
+                }
+                Ok(true)
+            })();
+            // Forget the rejected proposals
+            let keep_going = verified.and_then(|keep_going| {
+                sequence.cache.truncate(sequence.fed_tokens.len())?;
+                Ok(keep_going)
+            });
+            match keep_going {
+                Ok(true) => {}
+                Ok(false) => finished.push(i),
+                Err(e) => {
+                    let _ = sequence.tokens_tx.send(Err(e));
+                    finished.push(i);
+                }
+            }
+        }
+        draft.record(checked, accepted);
+
+        for i in finished.into_iter().rev() {
+            let sequence = self.active.swap_remove(i);
+            self.retire(sequence);
+        }
+        true
+    }
+
+    /// Lets the draft model propose its tokens for every active sequence.
+    fn propose(&mut self, draft: &DraftModel) -> Result<Vec<Vec<u32>>> {
+        let mut pending = Vec::with_capacity(self.active.len());
+        for sequence in self.active.iter_mut() {
+            let cache = sequence
+                .draft_cache
+                .get_or_insert_with(|| draft.new_cache());
+            // The draft cache may still hold proposals that were rejected last step
+            let valid = cache.len().min(sequence.fed_tokens.len());
+            cache.truncate(valid)?;
+            let mut tokens = sequence.fed_tokens[valid..].to_vec();
+            tokens.push(sequence.next_token);
+            pending.push(tokens);
+        }
+        let mut caches: Vec<&mut KvCache> = self
+            .active
+            .iter_mut()
+            .filter_map(|s| s.draft_cache.as_mut())
+            .collect();
+        draft.propose(&pending, &mut caches)
+    }
+
+    /// Keeps the prompt and final KV states for the next chat turn.
+    fn retire(&mut self, sequence: ActiveSequence) {
+        let ActiveSequence {
+            cache,
+            fed_tokens,
+            prompt_state,
+            ..
+        } = sequence;
+        if fed_tokens.len() > prompt_state.0.len() {
+            self.prefix_cache
+                .insert(vec![prompt_state, (fed_tokens, cache)]);
+        } else {
+            self.prefix_cache.insert(vec![prompt_state]);
+        }
+    }
+}
@@ -1,23 +1,122 @@
+use crate::template::PromptTemplate;
+use crate::tools::ToolCall;
+use crate::LLMEngine;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
+/// Context window assumed for engines that do not report one.
+pub const DEFAULT_CONTEXT_LENGTH: usize = 2048;
+
+/// Words the running summary of evicted turns is asked to stay within.
+const SUMMARY_WORDS: usize = 120;
+
+/// Start of the retrieved-context messages older versions stored in the history.
+const LEGACY_CONTEXT_PREFIX: &str = "Context information:";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Role {
     User,
     Assistant,
     System,
+    /// The result of a tool call, answering an assistant message's `tool_calls`.
+    Tool,
+}
+
+impl Role {
+    /// The role name used by chat templates (`system`, `user`, `assistant`, `tool`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+            Role::Tool => "tool",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: Role,
     pub content: String,
+    /// Calls requested by an assistant message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// For `Role::Tool` messages, the id of the call this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: String) -> Self {
+        Self {
+            role,
+            content,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    /// A tool result answering the call with id `tool_call_id`.
+    pub fn tool(tool_call_id: &str, content: String) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+}
+
+/// How a [`ChatHistory`] keeps its prompt within the model's context window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextPolicy {
+    /// Tokens left free for the model's reply.
+    pub reserve_tokens: usize,
+    /// Condense evicted turns into a running summary with the LLM instead of
+    /// forgetting them.
+    pub summarize: bool,
+}
+
+impl Default for ContextPolicy {
+    fn default() -> Self {
+        Self {
+            reserve_tokens: 256,
+            summarize: false,
+        }
+    }
+}
+
+impl ContextPolicy {
+    pub fn with_reserve_tokens(mut self, reserve_tokens: usize) -> Self {
+        self.reserve_tokens = reserve_tokens;
+        self
+    }
+
+    pub fn with_summarize(mut self, summarize: bool) -> Self {
+        self.summarize = summarize;
+        self
+    }
 }
 
+/// The conversation of a chat session.
+///
+/// The system prompt is kept apart from the messages and is never evicted. System
+/// messages in the history belong to the turn they precede. Besides the `max_history`
+/// message cap, [`ChatHistory::fit_context`] drops the oldest turns until the rendered
+/// prompt fits the model's context window.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredHistory")]
 pub struct ChatHistory {
     messages: VecDeque<ChatMessage>,
     max_history: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system_prompt: Option<String>,
+    /// Running summary of evicted turns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    /// Evicted messages not yet folded into `summary`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unsummarized: Vec<ChatMessage>,
+    #[serde(skip)]
+    policy: ContextPolicy,
 }
 
 impl ChatHistory {
@@ -25,78 +124,345 @@ impl ChatHistory {
         Self {
             messages: VecDeque::new(),
             max_history,
+            system_prompt: None,
+            summary: None,
+            unsummarized: Vec::new(),
+            policy: ContextPolicy::default(),
         }
     }
 
+    pub fn with_context_policy(mut self, policy: ContextPolicy) -> Self {
+        self.set_context_policy(policy);
+        self
+    }
+
+    pub fn context_policy(&self) -> &ContextPolicy {
+        &self.policy
+    }
+
+    pub fn set_context_policy(&mut self, policy: ContextPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    /// Replaces the system prompt, keeping the conversation. An empty prompt removes it.
+    pub fn set_system_prompt(&mut self, prompt: String) {
+        self.system_prompt = (!prompt.is_empty()).then_some(prompt);
+    }
+
+    /// Number of messages in the history, not counting the system prompt.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Summary of the turns evicted so far, if summarization is enabled.
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
     pub fn add_user(&mut self, content: String) {
-        self.push(ChatMessage {
-            role: Role::User,
-            content,
-        });
+        self.push(ChatMessage::new(Role::User, content));
     }
 
     pub fn add_assistant(&mut self, content: String) {
-        self.push(ChatMessage {
-            role: Role::Assistant,
-            content,
-        });
+        self.push(ChatMessage::new(Role::Assistant, content));
     }
 
     pub fn add_system(&mut self, content: String) {
+        self.push(ChatMessage::new(Role::System, content));
+    }
+
+    /// Records an assistant turn that requested tool calls.
+    pub fn add_tool_calls(&mut self, content: String, tool_calls: Vec<ToolCall>) {
         self.push(ChatMessage {
-            role: Role::System,
-            content,
+            tool_calls,
+            ..ChatMessage::new(Role::Assistant, content)
         });
     }
 
+    pub fn add_tool_result(&mut self, tool_call_id: &str, content: String) {
+        self.push(ChatMessage::tool(tool_call_id, content));
+    }
+
+    /// Appends a message, then evicts the oldest turns while the history holds more
+    /// than `max_history` messages.
     fn push(&mut self, message: ChatMessage) {
-        if self.messages.len() >= self.max_history {
-            self.messages.pop_front();
-        }
         self.messages.push_back(message);
+        while self.messages.len() > self.max_history {
+            match self.evict_oldest_turn() {
+                Some(turn) => self.evicted(turn),
+                None => break,
+            }
+        }
+    }
+
+    /// Removes the oldest turn: everything up to its user message and the replies and
+    /// tool results that follow it.
+    ///
+    /// The latest turn is never removed; returns `None` if it is all that is left.
+    fn evict_oldest_turn(&mut self) -> Option<Vec<ChatMessage>> {
+        let user = self
+            .messages
+            .iter()
+            .position(|m| matches!(m.role, Role::User))?;
+        let end = self
+            .messages
+            .iter()
+            .skip(user + 1)
+            .position(|m| matches!(m.role, Role::User | Role::System))
+            .map(|i| user + 1 + i)?;
+        // Only evict if a user message remains after the turn
+        if !self
+            .messages
+            .iter()
+            .skip(end)
+            .any(|m| matches!(m.role, Role::User))
+        {
+            return None;
+        }
+        Some(self.messages.drain(..end).collect())
+    }
+
+    fn evicted(&mut self, messages: Vec<ChatMessage>) {
+        if self.policy.summarize {
+            self.unsummarized.extend(messages);
+        }
     }
 
     pub fn get_history(&self) -> Vec<ChatMessage> {
         self.messages.iter().cloned().collect()
     }
 
+    /// Removes the conversation, keeping the system prompt.
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.summary = None;
+        self.unsummarized.clear();
     }
 
-    pub fn format_for_llama(&self) -> String {
-        let mut formatted = String::new();
-        for msg in &self.messages {
-            match msg.role {
-                Role::User => {
-                    formatted.push_str(&format!("<|user|>\n{}</s>\n", msg.content));
+    /// Replaces the conversation with `other`'s, such as a copy that was extended and
+    /// fitted elsewhere, keeping this history's system prompt and context policy.
+    pub fn replace_conversation(&mut self, other: ChatHistory) {
+        self.messages = other.messages;
+        self.summary = other.summary;
+        self.unsummarized = other.unsummarized;
+    }
+
+    /// The messages a prompt is rendered from: the system prompt, the running summary,
+    /// then the remaining turns.
+    pub fn prompt_messages(&self) -> Vec<ChatMessage> {
+        self.prompt_messages_with(None)
+    }
+
+    /// Like [`ChatHistory::prompt_messages`], with `context` (such as retrieved memory)
+    /// as a system message right before the latest user message. The context is only
+    /// rendered, never stored.
+    pub fn prompt_messages_with(&self, context: Option<&str>) -> Vec<ChatMessage> {
+        let mut messages = vec![];
+        if let Some(prompt) = &self.system_prompt {
+            messages.push(ChatMessage::new(Role::System, prompt.clone()));
+        }
+        if let Some(summary) = &self.summary {
+            messages.push(ChatMessage::new(
+                Role::System,
+                format!("Summary of the earlier conversation: {}", summary),
+            ));
+        }
+        let latest_user = self
+            .messages
+            .iter()
+            .rposition(|m| matches!(m.role, Role::User))
+            .unwrap_or(self.messages.len());
+        for (i, message) in self.messages.iter().enumerate() {
+            if i == latest_user {
+                if let Some(context) = context {
+                    messages.push(ChatMessage::new(Role::System, context.to_string()));
                 }
-                Role::Assistant => {
-                    formatted.push_str(&format!("<|assistant|>\n{}</s>\n", msg.content));
+            }
+            messages.push(message.clone());
+        }
+        messages
+    }
+
+    /// Renders the history with the model's chat template, ending with the
+    /// assistant header so the model continues with its reply.
+    pub fn format_prompt(&self, template: &PromptTemplate) -> anyhow::Result<String> {
+        template.render(&self.prompt_messages(), true)
+    }
+
+    /// Evicts the oldest turns until the rendered prompt leaves
+    /// [`ContextPolicy::reserve_tokens`] of `engine`'s context window free, and returns
+    /// the prompt.
+    ///
+    /// With [`ContextPolicy::summarize`], evicted turns are folded into the running
+    /// summary, which is rendered as a system message after the system prompt. The
+    /// summary is generated once per call, after evicting enough turns to fit.
+    pub async fn fit_context(&mut self, engine: &dyn LLMEngine) -> anyhow::Result<String> {
+        self.fit_context_with(engine, None).await
+    }
+
+    /// Like [`ChatHistory::fit_context`], rendering `context` before the latest user
+    /// message as [`ChatHistory::prompt_messages_with`] does. The context counts
+    /// towards the context window but is never evicted or stored.
+    pub async fn fit_context_with(
+        &mut self,
+        engine: &dyn LLMEngine,
+        context: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let template = engine.chat_template().await?;
+        let budget = engine
+            .context_length()
+            .saturating_sub(self.policy.reserve_tokens);
+        let mut summarized = false;
+        loop {
+            let prompt = template.render(&self.prompt_messages_with(context), true)?;
+            let tokens = engine.count_tokens(&prompt).await?;
+            if tokens <= budget {
+                if summarized || self.unsummarized.is_empty() {
+                    return Ok(prompt);
+                }
+                // The new summary may be longer than the old one, so check the fit again
+                summarized = true;
+                if let Err(e) = self.summarize(engine, &template).await {
+                    // Keeping them would fail every later turn at the same point
+                    tracing::warn!(
+                        "Failed to summarize {} evicted messages, dropping them: {}",
+                        self.unsummarized.len(),
+                        e
+                    );
+                    self.unsummarized.clear();
                 }
-                Role::System => {
-                    formatted.push_str(&format!("<|system|>\n{}</s>\n", msg.content));
+                continue;
+            }
+            match self.evict_oldest_turn() {
+                Some(turn) => {
+                    tracing::debug!(
+                        "Prompt has {} tokens (budget {}), evicting {} messages",
+                        tokens,
+                        budget,
+                        turn.len()
+                    );
+                    self.evicted(turn);
                 }
+                None => anyhow::bail!(
+                    "The latest message, system prompt and context take {} tokens, more than the {} available",
+                    tokens,
+                    budget
+                ),
             }
         }
-        // Prepare for next assistant response
-        formatted.push_str("<|assistant|>\n");
-        formatted
     }
 
-    pub fn load_from_file(path: &std::path::Path) -> anyhow::Result<Self> {
-        if path.exists() {
-            let file = std::fs::File::open(path)?;
-            let history: ChatHistory = serde_json::from_reader(file)?;
-            Ok(history)
-        } else {
-            Ok(Self::new(10))
+    /// Folds the evicted messages into the running summary.
+    ///
+    /// The oldest evicted messages are left out when the summarization prompt would not
+    /// leave [`ContextPolicy::reserve_tokens`] of the context window for the summary.
+    async fn summarize(
+        &mut self,
+        engine: &dyn LLMEngine,
+        template: &PromptTemplate,
+    ) -> anyhow::Result<()> {
+        let budget = engine
+            .context_length()
+            .saturating_sub(self.policy.reserve_tokens);
+        let mut skipped = 0;
+        let prompt = loop {
+            let prompt = template.render(&self.summary_request(skipped), true)?;
+            if skipped + 1 >= self.unsummarized.len()
+                || engine.count_tokens(&prompt).await? <= budget
+            {
+                break prompt;
+            }
+            skipped += 1;
+        };
+        if skipped > 0 {
+            tracing::debug!(
+                "Left {} evicted messages out of the summary to fit the context window",
+                skipped
+            );
         }
+        let summary = engine.generate(&prompt).await?;
+        let summary = template.strip_stop_tokens(&summary).trim().to_string();
+        self.summary = (!summary.is_empty()).then_some(summary);
+        self.unsummarized.clear();
+        Ok(())
+    }
+
+    /// The request to summarize the running summary and the evicted messages after the
+    /// first `skip`.
+    fn summary_request(&self, skip: usize) -> Vec<ChatMessage> {
+        let mut transcript = String::new();
+        if let Some(summary) = &self.summary {
+            transcript.push_str(&format!("Summary so far: {}\n\n", summary));
+        }
+        for message in self.unsummarized.iter().skip(skip) {
+            transcript.push_str(&format!("{}: {}\n", message.role.as_str(), message.content));
+        }
+        vec![
+            ChatMessage::new(
+                Role::System,
+                format!(
+                    "Summarize the conversation below in at most {} words. Keep names, facts, \
+                     decisions and open questions. Reply with the summary only.",
+                    SUMMARY_WORDS
+                ),
+            ),
+            ChatMessage::new(Role::User, transcript),
+        ]
+    }
+
+    /// Loads a history saved with [`ChatHistory::save_to_file`], recovering the last
+    /// complete version if the file was damaged; an empty history if there is none.
+    pub fn load_from_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        Ok(crate::persist::read_json(path)?.unwrap_or_else(|| Self::new(10)))
     }
 
+    /// Saves the history atomically: a crash mid-write keeps the previous version.
     pub fn save_to_file(&self, path: &std::path::Path) -> anyhow::Result<()> {
-        let file = std::fs::File::create(path)?;
-        serde_json::to_writer_pretty(file, self)?;
-        Ok(())
+        crate::persist::write_json(path, self)
+    }
+}
+
+/// The stored form of a [`ChatHistory`]. Histories saved before the system prompt had a
+/// field of its own kept it as their first message.
+#[derive(Deserialize)]
+struct StoredHistory {
+    messages: VecDeque<ChatMessage>,
+    max_history: usize,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    summary: Option<String>,
+    #[serde(default)]
+    unsummarized: Vec<ChatMessage>,
+}
+
+impl From<StoredHistory> for ChatHistory {
+    fn from(stored: StoredHistory) -> Self {
+        let mut messages = stored.messages;
+        let mut system_prompt = stored.system_prompt;
+        if system_prompt.is_none() {
+            let legacy_prompt = messages.front().is_some_and(|m| {
+                matches!(m.role, Role::System) && !m.content.starts_with(LEGACY_CONTEXT_PREFIX)
+            });
+            if legacy_prompt {
+                system_prompt = messages.pop_front().map(|m| m.content);
+            }
+        }
+        Self {
+            messages,
+            max_history: stored.max_history,
+            system_prompt,
+            summary: stored.summary,
+            unsummarized: stored.unsummarized,
+            policy: ContextPolicy::default(),
+        }
     }
 }
@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use candle_core::Tensor;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+use crate::grammar::{Grammar, GrammarState};
+
+/// The shape generated output must have, mirroring OpenAI's `response_format`.
+///
+/// `regex` and `grammar` (GBNF) are Plexus extensions.
+///
+/// ```json
+/// {"type": "json_schema", "json_schema": {"name": "answer", "schema": {"type": "object"}}}
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Unconstrained text.
+    #[default]
+    Text,
+    /// Any JSON object.
+    JsonObject,
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+    Regex {
+        pattern: String,
+    },
+    Grammar {
+        grammar: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub schema: Value,
+    #[serde(default)]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    pub fn json_schema(schema: Value) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: None,
+                schema,
+                strict: None,
+            },
+        }
+    }
+
+    /// Compiles the format into a grammar. Returns `None` for unconstrained text.
+    pub fn grammar(&self) -> Result<Option<Arc<Grammar>>> {
+        let grammar = match self {
+            ResponseFormat::Text => return Ok(None),
+            ResponseFormat::JsonObject => Grammar::json_object()?,
+            ResponseFormat::JsonSchema { json_schema } => {
+                Grammar::from_json_schema(&json_schema.schema).context("Unsupported JSON schema")?
+            }
+            ResponseFormat::Regex { pattern } => Grammar::from_regex(pattern)?,
+            ResponseFormat::Grammar { grammar } => Grammar::from_gbnf(grammar)?,
+        };
+        Ok(Some(grammar))
+    }
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: Vec<(char, usize)>,
+    /// Tokens whose text ends at this node.
+    tokens: Vec<u32>,
+}
+
+/// The decoded text of every token, arranged in a character trie.
+///
+/// Walking the trie alongside a [`GrammarState`] finds all allowed tokens while
+/// visiting each shared prefix only once. Special tokens and tokens that decode to
+/// partial UTF-8 sequences are never allowed by a constraint.
+#[derive(Debug)]
+pub struct TokenVocabulary {
+    nodes: Vec<TrieNode>,
+    texts: Vec<Option<String>>,
+}
+
+impl TokenVocabulary {
+    /// Builds the vocabulary from a tokenizer. This decodes every token once, so the
+    /// result should be cached per model.
+    pub fn from_tokenizer(tokenizer: &Tokenizer) -> Self {
+        let size = tokenizer.get_vocab_size(true);
+        let special: std::collections::HashSet<u32> = tokenizer
+            .get_added_tokens_decoder()
+            .into_iter()
+            .filter(|(_, token)| token.special)
+            .map(|(id, _)| id)
+            .collect();
+
+        // Decoders strip the leading space of the first token (SentencePiece) so each token
+        // is decoded after an anchor token and the anchor's text is removed again.
+        let anchor = tokenizer.token_to_id("a").and_then(|id| {
+            let text = tokenizer.decode(&[id], false).ok()?;
+            Some((id, text))
+        });
+
+        let texts = (0..size as u32).map(|id| {
+            if special.contains(&id) {
+                return (id, None);
+            }
+            let text = match &anchor {
+                Some((anchor_id, anchor_text)) => tokenizer
+                    .decode(&[*anchor_id, id], false)
+                    .ok()
+                    .and_then(|t| t.strip_prefix(anchor_text.as_str()).map(str::to_string)),
+                None => tokenizer.decode(&[id], false).ok(),
+            };
+            (id, text)
+        });
+        Self::from_texts(texts)
+    }
+
+    /// Builds the vocabulary from `(token id, decoded text)` pairs.
+    pub fn from_texts(texts: impl IntoIterator<Item = (u32, Option<String>)>) -> Self {
+        let mut vocab = Self {
+            nodes: vec![TrieNode::default()],
+            texts: Vec::new(),
+        };
+        for (id, text) in texts {
+            let text = text.filter(|t| !t.is_empty() && !t.contains('\u{FFFD}'));
+            if vocab.texts.len() <= id as usize {
+                vocab.texts.resize(id as usize + 1, None);
+            }
+            if let Some(text) = &text {
+                vocab.insert(id, text);
+            }
+            vocab.texts[id as usize] = text;
+        }
+        vocab
+    }
+
+    fn insert(&mut self, id: u32, text: &str) {
+        let mut node = 0;
+        for c in text.chars() {
+            let existing = self.nodes[node]
+                .children
+                .iter()
+                .find(|(child_char, _)| *child_char == c)
+                .map(|(_, child)| *child);
+            node = match existing {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.push((c, child));
+                    child
+                }
+            };
+        }
+        self.nodes[node].tokens.push(id);
+    }
+
+    /// Number of token ids covered.
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    /// The text a token contributes to the output, if it can be constrained.
+    pub fn text(&self, token: u32) -> Option<&str> {
+        self.texts.get(token as usize)?.as_deref()
+    }
+}
+
+/// Masks sampling to tokens that keep the output valid under a [`Grammar`].
+///
+/// Stop tokens are only allowed once the output is a complete match.
+#[derive(Debug, Clone)]
+pub struct TokenConstraint {
+    vocab: Arc<TokenVocabulary>,
+    state: GrammarState,
+    stop_tokens: Vec<u32>,
+}
+
+impl TokenConstraint {
+    pub fn new(
+        grammar: &Arc<Grammar>,
+        vocab: Arc<TokenVocabulary>,
+        stop_tokens: Vec<u32>,
+    ) -> Result<Self> {
+        Ok(Self {
+            vocab,
+            state: grammar.start()?,
+            stop_tokens,
+        })
+    }
+
+    /// Tokens that may be sampled next.
+    pub fn allowed_tokens(&self) -> Vec<u32> {
+        let mut allowed = Vec::new();
+        self.collect(0, &self.state, &mut allowed);
+        if self.state.is_complete() {
+            allowed.extend(&self.stop_tokens);
+        }
+        allowed
+    }
+
+    fn collect(&self, node: usize, state: &GrammarState, allowed: &mut Vec<u32>) {
+        for &(c, child) in &self.vocab.nodes[node].children {
+            if let Some(next) = state.next(c) {
+                allowed.extend(&self.vocab.nodes[child].tokens);
+                self.collect(child, &next, allowed);
+            }
+        }
+    }
+
+    /// Sets the logits of every disallowed token to negative infinity.
+    ///
+    /// `logits` has shape `(vocab,)`. Fails if no token can continue the output.
+    pub fn mask_logits(&self, logits: &Tensor) -> Result<Tensor> {
+        let size = logits.dim(0)?;
+        let allowed = self.allowed_tokens();
+        anyhow::ensure!(
+            !allowed.is_empty(),
+            "No token can continue the constrained output"
+        );
+        let mut mask = vec![f32::NEG_INFINITY; size];
+        for token in allowed {
+            if let Some(m) = mask.get_mut(token as usize) {
+                *m = 0.0;
+            }
+        }
+        let mask = Tensor::from_vec(mask, size, logits.device())?.to_dtype(logits.dtype())?;
+        Ok((logits + mask)?)
+    }
+
+    /// Records a sampled token.
+    pub fn advance(&mut self, token: u32) -> Result<()> {
+        if self.stop_tokens.contains(&token) {
+            anyhow::ensure!(
+                self.state.is_complete(),
+                "Stop token sampled before the constrained output was complete"
+            );
+            return Ok(());
+        }
+        let text = self
+            .vocab
+            .text(token)
+            .with_context(|| format!("Token {} cannot be used in constrained output", token))?;
+        anyhow::ensure!(
+            self.state.advance_str(text),
+            "Token {:?} violates the constraint",
+            text
+        );
+        Ok(())
+    }
+
+    /// Whether the output so far is a complete match.
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    /// Whether the output is complete and nothing more can be appended.
+    pub fn is_finished(&self) -> bool {
+        self.state.is_complete() && !self.state.can_continue()
+    }
+}
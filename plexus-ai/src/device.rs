@@ -0,0 +1,195 @@
+use anyhow::Result;
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// The compute device requested by the user, e.g. via `--device`.
+///
+/// Parsed from `auto`, `cpu`, `cuda`, `metal` or `vulkan`, optionally followed by a
+/// device ordinal (`cuda:1`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DeviceRequest {
+    /// The first available accelerator enabled at build time, otherwise the CPU.
+    #[default]
+    Auto,
+    Cpu,
+    Cuda(usize),
+    Metal(usize),
+    Vulkan(usize),
+}
+
+impl std::str::FromStr for DeviceRequest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        let (name, ordinal) = match s.split_once(':') {
+            Some((name, ordinal)) => (
+                name,
+                ordinal
+                    .parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Invalid device ordinal '{}'", ordinal))?,
+            ),
+            None => (s.as_str(), 0),
+        };
+        match name {
+            "auto" => Ok(DeviceRequest::Auto),
+            "cpu" => Ok(DeviceRequest::Cpu),
+            "cuda" | "gpu" => Ok(DeviceRequest::Cuda(ordinal)),
+            "metal" => Ok(DeviceRequest::Metal(ordinal)),
+            "vulkan" => Ok(DeviceRequest::Vulkan(ordinal)),
+            _ => anyhow::bail!(
+                "Unknown device '{}' (expected auto, cpu, cuda, metal or vulkan)",
+                name
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceRequest::Auto => write!(f, "auto"),
+            DeviceRequest::Cpu => write!(f, "cpu"),
+            DeviceRequest::Cuda(i) => write!(f, "cuda:{}", i),
+            DeviceRequest::Metal(i) => write!(f, "metal:{}", i),
+            DeviceRequest::Vulkan(i) => write!(f, "vulkan:{}", i),
+        }
+    }
+}
+
+/// A device picked by [`DeviceSelector`], with a label for logs and heartbeats.
+#[derive(Debug, Clone)]
+pub struct SelectedDevice {
+    pub device: Device,
+    /// `cpu`, `cuda:0`, `metal:0`, ...
+    pub label: String,
+}
+
+impl SelectedDevice {
+    fn cpu() -> Self {
+        Self {
+            device: Device::Cpu,
+            label: "cpu".to_string(),
+        }
+    }
+
+    /// The label of the accelerator in use, or `None` when running on the CPU.
+    pub fn gpu_info(&self) -> Option<String> {
+        (!self.device.is_cpu()).then(|| self.label.clone())
+    }
+}
+
+/// Picks the compute device for the engines.
+///
+/// Only backends enabled through the `cuda`, `metal` and `vulkan` cargo features are
+/// tried. Whenever the requested backend is not compiled in or fails to initialize,
+/// selection falls back to the CPU instead of failing.
+///
+/// # Examples
+///
+/// ```rust
+/// use plexus_ai::{DeviceRequest, DeviceSelector};
+/// let selected = DeviceSelector::new(DeviceRequest::Cpu).select();
+/// assert_eq!(selected.label, "cpu");
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceSelector {
+    request: DeviceRequest,
+}
+
+impl DeviceSelector {
+    pub fn new(request: DeviceRequest) -> Self {
+        Self { request }
+    }
+
+    pub fn request(&self) -> DeviceRequest {
+        self.request
+    }
+
+    /// Resolves the request to a device, falling back to the CPU.
+    pub fn select(&self) -> SelectedDevice {
+        let selected = match self.request {
+            DeviceRequest::Auto => Self::auto(),
+            DeviceRequest::Cpu => Ok(SelectedDevice::cpu()),
+            DeviceRequest::Cuda(ordinal) => Self::cuda(ordinal),
+            DeviceRequest::Metal(ordinal) => Self::metal(ordinal),
+            DeviceRequest::Vulkan(ordinal) => Self::vulkan(ordinal),
+        };
+        match selected {
+            Ok(selected) => {
+                tracing::info!("Using compute device: {}", selected.label);
+                selected
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Device '{}' unavailable ({}). Falling back to CPU.",
+                    self.request,
+                    e
+                );
+                SelectedDevice::cpu()
+            }
+        }
+    }
+
+    fn auto() -> Result<SelectedDevice> {
+        if let Ok(selected) = Self::cuda(0) {
+            return Ok(selected);
+        }
+        if let Ok(selected) = Self::metal(0) {
+            return Ok(selected);
+        }
+        if let Ok(selected) = Self::vulkan(0) {
+            return Ok(selected);
+        }
+        Ok(SelectedDevice::cpu())
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda(ordinal: usize) -> Result<SelectedDevice> {
+        Ok(SelectedDevice {
+            device: Device::new_cuda(ordinal)?,
+            label: format!("cuda:{}", ordinal),
+        })
+    }
+
+    #[cfg(not(feature = "cuda"))]
+    fn cuda(_ordinal: usize) -> Result<SelectedDevice> {
+        anyhow::bail!("built without the `cuda` feature")
+    }
+
+    #[cfg(feature = "metal")]
+    fn metal(ordinal: usize) -> Result<SelectedDevice> {
+        Ok(SelectedDevice {
+            device: Device::new_metal(ordinal)?,
+            label: format!("metal:{}", ordinal),
+        })
+    }
+
+    #[cfg(not(feature = "metal"))]
+    fn metal(_ordinal: usize) -> Result<SelectedDevice> {
+        anyhow::bail!("built without the `metal` feature")
+    }
+
+    // candle has no Vulkan backend yet, so the feature only changes the reason we fall back.
+    #[cfg(feature = "vulkan")]
+    fn vulkan(_ordinal: usize) -> Result<SelectedDevice> {
+        anyhow::bail!("the Vulkan backend is not supported by candle yet")
+    }
+
+    #[cfg(not(feature = "vulkan"))]
+    fn vulkan(_ordinal: usize) -> Result<SelectedDevice> {
+        anyhow::bail!("built without the `vulkan` feature")
+    }
+}
+
+/// The automatically selected device, resolved once per process.
+///
+/// Used by engine constructors that do not take an explicit device.
+pub fn default_device() -> Device {
+    static DEFAULT: OnceLock<SelectedDevice> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| DeviceSelector::default().select())
+        .device
+        .clone()
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Metadata key naming where a document came from (e.g. `"chat"` or a file path).
+pub const SOURCE_KEY: &str = "source";
+/// Metadata key holding the document's tags as an array of strings.
+pub const TAGS_KEY: &str = "tags";
+/// Metadata key holding when the document was stored, in Unix seconds.
+pub const CREATED_AT_KEY: &str = "created_at";
+
+/// A text stored in a [`crate::VectorStore`] together with its metadata.
+///
+/// Metadata is a free-form JSON object. The `source`, `tags` and `created_at` keys
+/// have accessors and can be filtered on with a [`SearchFilter`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+impl Document {
+    /// A document stamped with the current time.
+    pub fn new(id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            text: text.into(),
+            metadata: Map::new(),
+        }
+        .with_created_at(unix_now())
+    }
+
+    pub fn with_source(self, source: impl Into<String>) -> Self {
+        self.with_metadata(SOURCE_KEY, source.into())
+    }
+
+    pub fn with_tags<I, S>(self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let tags: Vec<String> = tags.into_iter().map(Into::into).collect();
+        self.with_metadata(TAGS_KEY, tags)
+    }
+
+    pub fn with_created_at(self, created_at: u64) -> Self {
+        self.with_metadata(CREATED_AT_KEY, created_at)
+    }
+
+    /// Sets an arbitrary metadata entry, replacing any previous value.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        source(&self.metadata)
+    }
+
+    pub fn tags(&self) -> Vec<&str> {
+        tags(&self.metadata)
+    }
+
+    pub fn created_at(&self) -> Option<u64> {
+        created_at(&self.metadata)
+    }
+}
+
+/// Restricts a search to documents whose metadata matches every set condition.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchFilter {
+    pub source: Option<String>,
+    /// Tags the document must all have.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Inclusive bounds on `created_at`, in Unix seconds.
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+}
+
+impl SearchFilter {
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn with_created_after(mut self, timestamp: u64) -> Self {
+        self.created_after = Some(timestamp);
+        self
+    }
+
+    pub fn with_created_before(mut self, timestamp: u64) -> Self {
+        self.created_before = Some(timestamp);
+        self
+    }
+
+    /// True if the filter lets every document through.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn matches(&self, metadata: &Map<String, Value>) -> bool {
+        if let Some(wanted) = &self.source {
+            if source(metadata) != Some(wanted.as_str()) {
+                return false;
+            }
+        }
+        let document_tags = tags(metadata);
+        if !self
+            .tags
+            .iter()
+            .all(|tag| document_tags.contains(&tag.as_str()))
+        {
+            return false;
+        }
+        if self.created_after.is_some() || self.created_before.is_some() {
+            let Some(created_at) = created_at(metadata) else {
+                return false;
+            };
+            if self.created_after.is_some_and(|after| created_at < after)
+                || self
+                    .created_before
+                    .is_some_and(|before| created_at > before)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A document returned by a search, with its similarity to the query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub id: String,
+    pub text: String,
+    pub metadata: Map<String, Value>,
+    pub score: f32,
+}
+
+impl SearchResult {
+    pub fn new(document: Document, score: f32) -> Self {
+        Self {
+            id: document.id,
+            text: document.text,
+            metadata: document.metadata,
+            score,
+        }
+    }
+}
+
+fn source(metadata: &Map<String, Value>) -> Option<&str> {
+    metadata.get(SOURCE_KEY).and_then(Value::as_str)
+}
+
+fn tags(metadata: &Map<String, Value>) -> Vec<&str> {
+    metadata
+        .get(TAGS_KEY)
+        .and_then(Value::as_array)
+        .map(|tags| tags.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+fn created_at(metadata: &Map<String, Value>) -> Option<u64> {
+    metadata.get(CREATED_AT_KEY).and_then(Value::as_u64)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
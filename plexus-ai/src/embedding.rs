@@ -0,0 +1,173 @@
+use anyhow::Result;
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+
+use crate::BertEmbedder;
+
+/// Embedding model used when none is configured.
+pub const DEFAULT_EMBEDDING_MODEL: &str = "minilm";
+
+/// Network an embedding model runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Architecture {
+    Bert,
+    /// BERT with rotary positions and SwiGLU layers, used by nomic-embed-text.
+    NomicBert,
+}
+
+/// How token embeddings are reduced to one vector per text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    /// Average over the real (unpadded) tokens.
+    Mean,
+    /// The embedding of the leading `[CLS]` token.
+    Cls,
+}
+
+/// A sentence embedding model on the Hugging Face Hub.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbeddingModel {
+    /// Short name used in configuration and in collection names.
+    pub id: String,
+    pub repo: String,
+    /// Length of the vectors the model produces.
+    pub dimension: usize,
+    pub architecture: Architecture,
+    pub pooling: Pooling,
+    /// Instruction prepended to search queries, for models trained with one.
+    #[serde(default)]
+    pub query_prefix: String,
+    /// Instruction prepended to stored documents.
+    #[serde(default)]
+    pub document_prefix: String,
+}
+
+impl EmbeddingModel {
+    pub fn new(
+        id: &str,
+        repo: &str,
+        dimension: usize,
+        architecture: Architecture,
+        pooling: Pooling,
+    ) -> Self {
+        Self {
+            id: id.to_string(),
+            repo: repo.to_string(),
+            dimension,
+            architecture,
+            pooling,
+            query_prefix: String::new(),
+            document_prefix: String::new(),
+        }
+    }
+
+    pub fn with_prefixes(mut self, query_prefix: &str, document_prefix: &str) -> Self {
+        self.query_prefix = query_prefix.to_string();
+        self.document_prefix = document_prefix.to_string();
+        self
+    }
+
+    /// Suffix that keeps the vectors of different models in separate collections,
+    /// e.g. `minilm_384`. Vectors of two models are never comparable, even when their
+    /// dimensions agree.
+    pub fn collection_suffix(&self) -> String {
+        let id: String = self
+            .id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}_{}", id, self.dimension)
+    }
+}
+
+fn builtin_models() -> Vec<EmbeddingModel> {
+    vec![
+        EmbeddingModel::new(
+            "minilm",
+            "sentence-transformers/all-MiniLM-L6-v2",
+            384,
+            Architecture::Bert,
+            Pooling::Mean,
+        ),
+        EmbeddingModel::new(
+            "bge-small",
+            "BAAI/bge-small-en-v1.5",
+            384,
+            Architecture::Bert,
+            Pooling::Cls,
+        )
+        .with_prefixes(
+            "Represent this sentence for searching relevant passages: ",
+            "",
+        ),
+        EmbeddingModel::new(
+            "e5-small",
+            "intfloat/e5-small-v2",
+            384,
+            Architecture::Bert,
+            Pooling::Mean,
+        )
+        .with_prefixes("query: ", "passage: "),
+        EmbeddingModel::new(
+            "nomic",
+            "nomic-ai/nomic-embed-text-v1.5",
+            768,
+            Architecture::NomicBert,
+            Pooling::Mean,
+        )
+        .with_prefixes("search_query: ", "search_document: "),
+    ]
+}
+
+/// The embedding models a node can run, by id.
+pub struct EmbedderRegistry {
+    models: Vec<EmbeddingModel>,
+}
+
+impl Default for EmbedderRegistry {
+    fn default() -> Self {
+        Self {
+            models: builtin_models(),
+        }
+    }
+}
+
+impl EmbedderRegistry {
+    /// A registry of the built-in models: MiniLM, bge-small, e5-small and nomic.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a model, replacing any model with the same id.
+    pub fn register(&mut self, model: EmbeddingModel) {
+        self.models.retain(|m| m.id != model.id);
+        self.models.push(model);
+    }
+
+    pub fn list(&self) -> &[EmbeddingModel] {
+        &self.models
+    }
+
+    /// Looks a model up by id or Hub repository.
+    pub fn get(&self, id: &str) -> Result<EmbeddingModel> {
+        self.models
+            .iter()
+            .find(|m| m.id == id || m.repo == id)
+            .cloned()
+            .ok_or_else(|| {
+                let known: Vec<&str> = self.models.iter().map(|m| m.id.as_str()).collect();
+                anyhow::anyhow!(
+                    "Unknown embedding model '{}' (available: {})",
+                    id,
+                    known.join(", ")
+                )
+            })
+    }
+
+    /// An embedder for model `id` on `device`; weights are downloaded on first use.
+    pub fn create(&self, id: &str, device: &Device) -> Result<BertEmbedder> {
+        Ok(BertEmbedder::with_device(device.clone()).with_model(self.get(id)?))
+    }
+}
@@ -0,0 +1,1139 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Recursion limit when expanding rule references; only reached by left-recursive grammars.
+const MAX_EXPANSION_DEPTH: usize = 128;
+/// Upper bound on simultaneous parse stacks, guarding against ambiguous grammars.
+const MAX_STACKS: usize = 4096;
+
+/// A grammar expression before it is lowered into rules.
+#[derive(Debug, Clone)]
+enum Node {
+    Chars {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Ref(String),
+    Seq(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Node {
+    fn literal(s: &str) -> Node {
+        Node::Seq(
+            s.chars()
+                .map(|c| Node::Chars {
+                    ranges: vec![(c, c)],
+                    negated: false,
+                })
+                .collect(),
+        )
+    }
+
+    fn class(ranges: &[(char, char)]) -> Node {
+        Node::Chars {
+            ranges: ranges.to_vec(),
+            negated: false,
+        }
+    }
+
+    fn reference(name: &str) -> Node {
+        Node::Ref(name.to_string())
+    }
+
+    fn optional(node: Node) -> Node {
+        Node::Repeat {
+            node: Box::new(node),
+            min: 0,
+            max: Some(1),
+        }
+    }
+
+    fn star(node: Node) -> Node {
+        Node::Repeat {
+            node: Box::new(node),
+            min: 0,
+            max: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    Chars {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Rule(usize),
+}
+
+impl Element {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Element::Chars { ranges, negated } => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+            Element::Rule(_) => false,
+        }
+    }
+}
+
+/// A context-free grammar over characters, used to constrain generated text.
+///
+/// Grammars are built from GBNF source ([`Grammar::from_gbnf`]), a regular expression
+/// ([`Grammar::from_regex`]) or a JSON schema ([`Grammar::from_json_schema`]). The whole
+/// output must match: regular expressions are implicitly anchored at both ends.
+///
+/// # Examples
+///
+/// ```rust
+/// use plexus_ai::Grammar;
+/// let grammar = Grammar::from_gbnf(r#"root ::= "yes" | "no""#).unwrap();
+/// let mut state = grammar.start().unwrap();
+/// assert!(state.advance_str("ye"));
+/// assert!(!state.is_complete());
+/// assert!(state.advance_str("s"));
+/// assert!(state.is_complete());
+/// ```
+#[derive(Debug)]
+pub struct Grammar {
+    /// `rules[rule][alternative]` is a sequence of elements.
+    rules: Vec<Vec<Vec<Element>>>,
+    root: usize,
+}
+
+impl Grammar {
+    /// Parses a grammar in llama.cpp's GBNF format. The start rule is `root`.
+    pub fn from_gbnf(source: &str) -> Result<Arc<Self>> {
+        let defs = GbnfParser::new(source).parse()?;
+        Self::compile(defs, "root")
+    }
+
+    /// Builds a grammar accepting exactly the strings matched by `pattern`.
+    ///
+    /// Anchors and other look-around assertions are ignored since the whole output is
+    /// always matched.
+    pub fn from_regex(pattern: &str) -> Result<Arc<Self>> {
+        let node = regex_to_node(pattern)?;
+        Self::compile(vec![("root".to_string(), node)], "root")
+    }
+
+    /// Builds a grammar accepting JSON documents that conform to `schema`.
+    ///
+    /// Supports `type` (including type lists), `properties`/`required`, `items`,
+    /// `minItems`/`maxItems`, `enum`, `const`, `anyOf`/`oneOf`, string `pattern` and
+    /// `minLength`/`maxLength`, and local `$ref`s to `#/$defs` or `#/definitions`.
+    /// Required properties are emitted first, in the order of the `required` list;
+    /// additional properties are not allowed.
+    pub fn from_json_schema(schema: &Value) -> Result<Arc<Self>> {
+        let mut converter = SchemaConverter::new(schema);
+        let root = converter.visit(schema)?;
+        let mut defs = converter.into_defs();
+        defs.push(("root".to_string(), root));
+        Self::compile(defs, "root")
+    }
+
+    /// A grammar accepting any JSON object.
+    pub fn json_object() -> Result<Arc<Self>> {
+        let mut converter = SchemaConverter::new(&Value::Null);
+        let root = converter.primitive("object");
+        let mut defs = converter.into_defs();
+        defs.push(("root".to_string(), root));
+        Self::compile(defs, "root")
+    }
+
+    /// A parser positioned at the start of the grammar.
+    pub fn start(self: &Arc<Self>) -> Result<GrammarState> {
+        let mut stacks = HashSet::new();
+        for alt in 0..self.rules[self.root].len() {
+            let pos = Pos {
+                rule: self.root,
+                alt,
+                idx: 0,
+            };
+            self.expand(vec![pos], &mut stacks, 0)?;
+        }
+        Ok(GrammarState {
+            grammar: self.clone(),
+            stacks: stacks.into_iter().collect(),
+        })
+    }
+
+    fn compile(defs: Vec<(String, Node)>, root: &str) -> Result<Arc<Self>> {
+        let mut ids = HashMap::new();
+        for (name, _) in &defs {
+            let next = ids.len();
+            ids.entry(name.clone()).or_insert(next);
+        }
+        let mut lowering = Lowering {
+            ids,
+            rules: vec![Vec::new(); defs.len()],
+        };
+        lowering.rules.truncate(lowering.ids.len());
+        for (name, node) in defs {
+            let id = lowering.ids[&name];
+            let alternatives = lowering.lower_alternatives(&node)?;
+            lowering.rules[id].extend(alternatives);
+        }
+        let root = *lowering
+            .ids
+            .get(root)
+            .with_context(|| format!("Grammar has no '{}' rule", root))?;
+        Ok(Arc::new(Self {
+            rules: lowering.rules,
+            root,
+        }))
+    }
+
+    /// Advances `stack` through rule references and finished alternatives until its top
+    /// points at a character element (or the stack is empty, meaning the input so far is
+    /// a complete match).
+    fn expand(&self, mut stack: Vec<Pos>, out: &mut HashSet<Vec<Pos>>, depth: usize) -> Result<()> {
+        anyhow::ensure!(
+            depth < MAX_EXPANSION_DEPTH,
+            "Grammar is left-recursive or nested too deeply"
+        );
+        anyhow::ensure!(out.len() < MAX_STACKS, "Grammar is too ambiguous");
+
+        // Pop finished alternatives; the frame below already points past the reference
+        while let Some(top) = stack.last() {
+            if top.idx < self.rules[top.rule][top.alt].len() {
+                break;
+            }
+            stack.pop();
+        }
+        let Some(&top) = stack.last() else {
+            out.insert(stack);
+            return Ok(());
+        };
+
+        match &self.rules[top.rule][top.alt][top.idx] {
+            Element::Chars { .. } => {
+                out.insert(stack);
+            }
+            Element::Rule(rule) => {
+                let rule = *rule;
+                let mut base = stack;
+                if let Some(top) = base.last_mut() {
+                    top.idx += 1;
+                }
+                // Tail position: drop the finished frame so repetition does not grow the stack
+                while let Some(top) = base.last() {
+                    if top.idx < self.rules[top.rule][top.alt].len() {
+                        break;
+                    }
+                    base.pop();
+                }
+                for alt in 0..self.rules[rule].len() {
+                    let mut next = base.clone();
+                    next.push(Pos { rule, alt, idx: 0 });
+                    self.expand(next, out, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Pos {
+    rule: usize,
+    alt: usize,
+    idx: usize,
+}
+
+/// Incremental parser state for a [`Grammar`].
+///
+/// Tracks every way the input so far can be continued, so it can answer which characters
+/// may come next. Cloning is cheap enough to explore candidate tokens.
+#[derive(Debug, Clone)]
+pub struct GrammarState {
+    grammar: Arc<Grammar>,
+    stacks: Vec<Vec<Pos>>,
+}
+
+impl GrammarState {
+    /// Consumes one character. Returns `false` (leaving the state unchanged) if the
+    /// grammar does not allow it.
+    pub fn advance(&mut self, c: char) -> bool {
+        match self.next(c) {
+            Some(next) => {
+                *self = next;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Consumes a string. Returns `false` (leaving the state unchanged) if any character
+    /// is rejected.
+    pub fn advance_str(&mut self, s: &str) -> bool {
+        let mut state = self.clone();
+        for c in s.chars() {
+            match state.next(c) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        *self = state;
+        true
+    }
+
+    /// The state after consuming `c`, or `None` if `c` is not allowed.
+    pub fn next(&self, c: char) -> Option<GrammarState> {
+        let grammar = &self.grammar;
+        let mut out = HashSet::new();
+        for stack in &self.stacks {
+            let Some(top) = stack.last() else {
+                continue;
+            };
+            if !grammar.rules[top.rule][top.alt][top.idx].matches(c) {
+                continue;
+            }
+            let mut next = stack.clone();
+            if let Some(top) = next.last_mut() {
+                top.idx += 1;
+            }
+            if grammar.expand(next, &mut out, 0).is_err() {
+                return None;
+            }
+        }
+        if out.is_empty() {
+            return None;
+        }
+        Some(GrammarState {
+            grammar: self.grammar.clone(),
+            stacks: out.into_iter().collect(),
+        })
+    }
+
+    /// Whether the input so far is a complete match.
+    pub fn is_complete(&self) -> bool {
+        self.stacks.iter().any(|s| s.is_empty())
+    }
+
+    /// Whether any further character can be accepted.
+    pub fn can_continue(&self) -> bool {
+        self.stacks.iter().any(|s| !s.is_empty())
+    }
+}
+
+struct Lowering {
+    ids: HashMap<String, usize>,
+    rules: Vec<Vec<Vec<Element>>>,
+}
+
+impl Lowering {
+    fn new_rule(&mut self, alternatives: Vec<Vec<Element>>) -> usize {
+        self.rules.push(alternatives);
+        self.rules.len() - 1
+    }
+
+    fn lower_alternatives(&mut self, node: &Node) -> Result<Vec<Vec<Element>>> {
+        match node {
+            Node::Alt(alternatives) => {
+                let mut out = Vec::with_capacity(alternatives.len());
+                for alt in alternatives {
+                    let mut seq = Vec::new();
+                    self.lower_seq(alt, &mut seq)?;
+                    out.push(seq);
+                }
+                Ok(out)
+            }
+            _ => {
+                let mut seq = Vec::new();
+                self.lower_seq(node, &mut seq)?;
+                Ok(vec![seq])
+            }
+        }
+    }
+
+    fn lower_seq(&mut self, node: &Node, out: &mut Vec<Element>) -> Result<()> {
+        match node {
+            Node::Chars { ranges, negated } => out.push(Element::Chars {
+                ranges: ranges.clone(),
+                negated: *negated,
+            }),
+            Node::Ref(name) => {
+                let id = self
+                    .ids
+                    .get(name)
+                    .with_context(|| format!("Undefined grammar rule '{}'", name))?;
+                out.push(Element::Rule(*id));
+            }
+            Node::Seq(nodes) => {
+                for node in nodes {
+                    self.lower_seq(node, out)?;
+                }
+            }
+            Node::Alt(_) => {
+                let alternatives = self.lower_alternatives(node)?;
+                let id = self.new_rule(alternatives);
+                out.push(Element::Rule(id));
+            }
+            Node::Repeat { node, min, max } => {
+                if let Some(max) = max {
+                    anyhow::ensure!(min <= max, "Invalid repetition {{{},{}}}", min, max);
+                }
+                // The repeated item becomes its own rule so it is lowered only once
+                let item = {
+                    let alternatives = self.lower_alternatives(node)?;
+                    Element::Rule(self.new_rule(alternatives))
+                };
+                for _ in 0..*min {
+                    out.push(item.clone());
+                }
+                match max {
+                    // rest ::= item rest | ""
+                    None => {
+                        let id = self.new_rule(vec![]);
+                        self.rules[id] = vec![vec![item, Element::Rule(id)], vec![]];
+                        out.push(Element::Rule(id));
+                    }
+                    // opt_k ::= item opt_(k-1) | ""
+                    Some(max) if max > min => {
+                        let mut tail: Option<usize> = None;
+                        for _ in 0..(max - min) {
+                            let mut seq = vec![item.clone()];
+                            if let Some(tail) = tail {
+                                seq.push(Element::Rule(tail));
+                            }
+                            tail = Some(self.new_rule(vec![seq, vec![]]));
+                        }
+                        if let Some(tail) = tail {
+                            out.push(Element::Rule(tail));
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// --- GBNF ---
+
+#[derive(Debug, Clone, PartialEq)]
+enum GbnfToken {
+    Ident(String),
+    Defines,
+    Literal(String),
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Dot,
+    LParen,
+    RParen,
+    Pipe,
+    Star,
+    Plus,
+    Question,
+    Braces(u32, Option<u32>),
+}
+
+struct GbnfParser {
+    tokens: Vec<GbnfToken>,
+    pos: usize,
+    source: Vec<char>,
+}
+
+impl GbnfParser {
+    fn new(source: &str) -> Self {
+        Self {
+            tokens: Vec::new(),
+            pos: 0,
+            source: source.chars().collect(),
+        }
+    }
+
+    fn parse(mut self) -> Result<Vec<(String, Node)>> {
+        self.tokens = self.lex()?;
+        let mut defs = Vec::new();
+        while self.pos < self.tokens.len() {
+            let name = match self.tokens.get(self.pos) {
+                Some(GbnfToken::Ident(name)) => name.clone(),
+                other => anyhow::bail!("Expected rule name, found {:?}", other),
+            };
+            self.pos += 1;
+            anyhow::ensure!(
+                self.tokens.get(self.pos) == Some(&GbnfToken::Defines),
+                "Expected '::=' after rule '{}'",
+                name
+            );
+            self.pos += 1;
+            let body = self.parse_alternatives()?;
+            defs.push((name, body));
+        }
+        anyhow::ensure!(!defs.is_empty(), "Grammar is empty");
+        Ok(defs)
+    }
+
+    fn at_rule_start(&self) -> bool {
+        matches!(self.tokens.get(self.pos), Some(GbnfToken::Ident(_)))
+            && self.tokens.get(self.pos + 1) == Some(&GbnfToken::Defines)
+    }
+
+    fn parse_alternatives(&mut self) -> Result<Node> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.tokens.get(self.pos) == Some(&GbnfToken::Pipe) {
+            self.pos += 1;
+            alternatives.push(self.parse_sequence()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.remove(0)
+        } else {
+            Node::Alt(alternatives)
+        })
+    }
+
+    fn parse_sequence(&mut self) -> Result<Node> {
+        let mut items = Vec::new();
+        loop {
+            if self.at_rule_start() {
+                break;
+            }
+            let mut item = match self.tokens.get(self.pos).cloned() {
+                Some(GbnfToken::Ident(name)) => Node::Ref(name),
+                Some(GbnfToken::Literal(s)) => Node::literal(&s),
+                Some(GbnfToken::Class { ranges, negated }) => Node::Chars { ranges, negated },
+                Some(GbnfToken::Dot) => Node::Chars {
+                    ranges: vec![],
+                    negated: true,
+                },
+                Some(GbnfToken::LParen) => {
+                    self.pos += 1;
+                    let inner = self.parse_alternatives()?;
+                    anyhow::ensure!(
+                        self.tokens.get(self.pos) == Some(&GbnfToken::RParen),
+                        "Expected ')'"
+                    );
+                    inner
+                }
+                _ => break,
+            };
+            self.pos += 1;
+
+            loop {
+                let (min, max) = match self.tokens.get(self.pos) {
+                    Some(GbnfToken::Star) => (0, None),
+                    Some(GbnfToken::Plus) => (1, None),
+                    Some(GbnfToken::Question) => (0, Some(1)),
+                    Some(GbnfToken::Braces(min, max)) => (*min, *max),
+                    _ => break,
+                };
+                self.pos += 1;
+                item = Node::Repeat {
+                    node: Box::new(item),
+                    min,
+                    max,
+                };
+            }
+            items.push(item);
+        }
+        Ok(Node::Seq(items))
+    }
+
+    fn lex(&self) -> Result<Vec<GbnfToken>> {
+        let s = &self.source;
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < s.len() {
+            let c = s[i];
+            match c {
+                c if c.is_whitespace() => i += 1,
+                '#' => {
+                    while i < s.len() && s[i] != '\n' {
+                        i += 1;
+                    }
+                }
+                ':' if s[i..].starts_with(&[':', ':', '=']) => {
+                    tokens.push(GbnfToken::Defines);
+                    i += 3;
+                }
+                '"' => {
+                    i += 1;
+                    let mut literal = String::new();
+                    loop {
+                        match s.get(i) {
+                            None => anyhow::bail!("Unterminated string literal"),
+                            Some('"') => break,
+                            Some('\\') => {
+                                let (c, len) = parse_escape(&s[i..])?;
+                                literal.push(c);
+                                i += len;
+                            }
+                            Some(&c) => {
+                                literal.push(c);
+                                i += 1;
+                            }
+                        }
+                    }
+                    i += 1;
+                    tokens.push(GbnfToken::Literal(literal));
+                }
+                '[' => {
+                    i += 1;
+                    let negated = s.get(i) == Some(&'^');
+                    if negated {
+                        i += 1;
+                    }
+                    let mut ranges = Vec::new();
+                    loop {
+                        let lo = match s.get(i) {
+                            None => anyhow::bail!("Unterminated character class"),
+                            Some(']') => break,
+                            Some('\\') => {
+                                let (c, len) = parse_escape(&s[i..])?;
+                                i += len;
+                                c
+                            }
+                            Some(&c) => {
+                                i += 1;
+                                c
+                            }
+                        };
+                        let hi = if s.get(i) == Some(&'-') && s.get(i + 1) != Some(&']') {
+                            i += 1;
+                            match s.get(i) {
+                                Some('\\') => {
+                                    let (c, len) = parse_escape(&s[i..])?;
+                                    i += len;
+                                    c
+                                }
+                                Some(&c) => {
+                                    i += 1;
+                                    c
+                                }
+                                None => anyhow::bail!("Unterminated character class"),
+                            }
+                        } else {
+                            lo
+                        };
+                        ranges.push((lo, hi));
+                    }
+                    i += 1;
+                    tokens.push(GbnfToken::Class { ranges, negated });
+                }
+                '{' => {
+                    let end = s[i..]
+                        .iter()
+                        .position(|&c| c == '}')
+                        .context("Unterminated repetition")?;
+                    let body: String = s[i + 1..i + end].iter().collect();
+                    let parse = |v: &str| -> Result<u32> {
+                        v.trim()
+                            .parse()
+                            .with_context(|| format!("Invalid repetition '{{{}}}'", body))
+                    };
+                    let (min, max) = match body.split_once(',') {
+                        None => {
+                            let n = parse(&body)?;
+                            (n, Some(n))
+                        }
+                        Some((min, max)) if max.trim().is_empty() => (parse(min)?, None),
+                        Some((min, max)) => (parse(min)?, Some(parse(max)?)),
+                    };
+                    tokens.push(GbnfToken::Braces(min, max));
+                    i += end + 1;
+                }
+                '.' => {
+                    tokens.push(GbnfToken::Dot);
+                    i += 1;
+                }
+                '(' => {
+                    tokens.push(GbnfToken::LParen);
+                    i += 1;
+                }
+                ')' => {
+                    tokens.push(GbnfToken::RParen);
+                    i += 1;
+                }
+                '|' => {
+                    tokens.push(GbnfToken::Pipe);
+                    i += 1;
+                }
+                '*' => {
+                    tokens.push(GbnfToken::Star);
+                    i += 1;
+                }
+                '+' => {
+                    tokens.push(GbnfToken::Plus);
+                    i += 1;
+                }
+                '?' => {
+                    tokens.push(GbnfToken::Question);
+                    i += 1;
+                }
+                c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => {
+                    let start = i;
+                    while i < s.len()
+                        && (s[i].is_ascii_alphanumeric() || s[i] == '-' || s[i] == '_')
+                    {
+                        i += 1;
+                    }
+                    tokens.push(GbnfToken::Ident(s[start..i].iter().collect()));
+                }
+                c => anyhow::bail!("Unexpected character '{}' in grammar", c),
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+/// Parses an escape sequence starting at a backslash. Returns the character and the
+/// number of source characters consumed.
+fn parse_escape(s: &[char]) -> Result<(char, usize)> {
+    let hex = |len: usize| -> Result<(char, usize)> {
+        let digits: String = s
+            .get(2..2 + len)
+            .context("Truncated escape")?
+            .iter()
+            .collect();
+        let code = u32::from_str_radix(&digits, 16).context("Invalid hex escape")?;
+        Ok((char::from_u32(code).context("Invalid code point")?, 2 + len))
+    };
+    match s.get(1) {
+        Some('n') => Ok(('\n', 2)),
+        Some('r') => Ok(('\r', 2)),
+        Some('t') => Ok(('\t', 2)),
+        Some('x') => hex(2),
+        Some('u') => hex(4),
+        Some('U') => hex(8),
+        Some(&c) => Ok((c, 2)),
+        None => anyhow::bail!("Truncated escape"),
+    }
+}
+
+// --- Regular expressions ---
+
+fn regex_to_node(pattern: &str) -> Result<Node> {
+    let hir = regex_syntax::ParserBuilder::new()
+        .build()
+        .parse(pattern)
+        .context("Invalid regular expression")?;
+    Ok(hir_to_node(&hir))
+}
+
+fn hir_to_node(hir: &regex_syntax::hir::Hir) -> Node {
+    use regex_syntax::hir::{Class, HirKind};
+
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => Node::Seq(vec![]),
+        HirKind::Literal(literal) => Node::literal(&String::from_utf8_lossy(&literal.0)),
+        HirKind::Class(Class::Unicode(class)) => Node::Chars {
+            ranges: class
+                .ranges()
+                .iter()
+                .map(|r| (r.start(), r.end()))
+                .collect(),
+            negated: false,
+        },
+        HirKind::Class(Class::Bytes(class)) => Node::Chars {
+            ranges: class
+                .ranges()
+                .iter()
+                .map(|r| (r.start() as char, r.end() as char))
+                .collect(),
+            negated: false,
+        },
+        HirKind::Repetition(rep) => Node::Repeat {
+            node: Box::new(hir_to_node(&rep.sub)),
+            min: rep.min,
+            max: rep.max,
+        },
+        HirKind::Capture(capture) => hir_to_node(&capture.sub),
+        HirKind::Concat(hirs) => Node::Seq(hirs.iter().map(hir_to_node).collect()),
+        HirKind::Alternation(hirs) => Node::Alt(hirs.iter().map(hir_to_node).collect()),
+    }
+}
+
+// --- JSON schema ---
+
+struct SchemaConverter<'a> {
+    root: &'a Value,
+    defs: Vec<(String, Node)>,
+    /// Rule names of already converted `$ref` targets.
+    refs: HashMap<String, String>,
+    primitives: HashSet<&'static str>,
+}
+
+impl<'a> SchemaConverter<'a> {
+    fn new(root: &'a Value) -> Self {
+        Self {
+            root,
+            defs: Vec::new(),
+            refs: HashMap::new(),
+            primitives: HashSet::new(),
+        }
+    }
+
+    fn into_defs(self) -> Vec<(String, Node)> {
+        self.defs
+    }
+
+    fn visit(&mut self, schema: &Value) -> Result<Node> {
+        let obj = match schema {
+            Value::Bool(true) => return Ok(self.primitive("value")),
+            Value::Object(obj) => obj,
+            _ => anyhow::bail!("Unsupported schema: {}", schema),
+        };
+
+        if let Some(reference) = obj.get("$ref").and_then(|r| r.as_str()) {
+            return self.visit_ref(reference);
+        }
+        if let Some(value) = obj.get("const") {
+            return Ok(Node::literal(&value.to_string()));
+        }
+        if let Some(values) = obj.get("enum").and_then(|v| v.as_array()) {
+            return Ok(Node::Alt(
+                values
+                    .iter()
+                    .map(|v| Node::literal(&v.to_string()))
+                    .collect(),
+            ));
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(options) = obj.get(key).and_then(|v| v.as_array()) {
+                let options = options
+                    .iter()
+                    .map(|o| self.visit(o))
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok(Node::Alt(options));
+            }
+        }
+        if let Some(all) = obj.get("allOf").and_then(|v| v.as_array()) {
+            anyhow::ensure!(
+                all.len() == 1,
+                "allOf with several schemas is not supported"
+            );
+            return self.visit(&all[0]);
+        }
+
+        match obj.get("type") {
+            Some(Value::Array(types)) => {
+                let options = types
+                    .iter()
+                    .map(|t| {
+                        let mut single = obj.clone();
+                        single.insert("type".to_string(), t.clone());
+                        self.visit(&Value::Object(single))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Node::Alt(options))
+            }
+            Some(Value::String(t)) => match t.as_str() {
+                "object" => self.visit_object(obj),
+                "array" => self.visit_array(obj),
+                "string" => self.visit_string(obj),
+                "integer" => Ok(self.primitive("integer")),
+                "number" => Ok(self.primitive("number")),
+                "boolean" => Ok(self.primitive("boolean")),
+                "null" => Ok(self.primitive("null")),
+                other => anyhow::bail!("Unsupported schema type '{}'", other),
+            },
+            Some(other) => anyhow::bail!("Invalid schema type {}", other),
+            None if obj.contains_key("properties") => self.visit_object(obj),
+            None if obj.contains_key("items") => self.visit_array(obj),
+            None => Ok(self.primitive("value")),
+        }
+    }
+
+    fn visit_ref(&mut self, reference: &str) -> Result<Node> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(Node::reference(name));
+        }
+        let pointer = reference
+            .strip_prefix('#')
+            .with_context(|| format!("Only local $refs are supported, got '{}'", reference))?;
+        let target = self
+            .root
+            .pointer(pointer)
+            .with_context(|| format!("Unresolved $ref '{}'", reference))?;
+
+        // Register the name before visiting so recursive references terminate
+        let name = format!("ref-{}", self.refs.len());
+        self.refs.insert(reference.to_string(), name.clone());
+        let node = self.visit(target)?;
+        self.defs.push((name.clone(), node));
+        Ok(Node::reference(&name))
+    }
+
+    fn visit_object(&mut self, obj: &serde_json::Map<String, Value>) -> Result<Node> {
+        let Some(properties) = obj.get("properties").and_then(|p| p.as_object()) else {
+            return Ok(self.primitive("object"));
+        };
+        let required: Vec<&str> = obj
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| r.iter().filter_map(|k| k.as_str()).collect())
+            .unwrap_or_default();
+        for key in &required {
+            anyhow::ensure!(
+                properties.contains_key(*key),
+                "Required property '{}' is not defined",
+                key
+            );
+        }
+
+        let mut members = Vec::new();
+        for key in &required {
+            members.push((true, self.member(key, &properties[*key])?));
+        }
+        for (key, schema) in properties {
+            if !required.contains(&key.as_str()) {
+                members.push((false, self.member(key, schema)?));
+            }
+        }
+
+        let ws = self.primitive("ws");
+        let comma = Node::Seq(vec![ws.clone(), Node::literal(","), ws.clone()]);
+        let required_count = members.iter().filter(|(r, _)| *r).count();
+        let (required, optional): (Vec<_>, Vec<_>) = members.into_iter().partition(|(r, _)| *r);
+        let required: Vec<Node> = required.into_iter().map(|(_, m)| m).collect();
+        let optional: Vec<Node> = optional.into_iter().map(|(_, m)| m).collect();
+
+        let body = if required_count > 0 {
+            let mut seq = Vec::new();
+            for (i, member) in required.into_iter().enumerate() {
+                if i > 0 {
+                    seq.push(comma.clone());
+                }
+                seq.push(member);
+            }
+            for member in optional {
+                seq.push(Node::optional(Node::Seq(vec![comma.clone(), member])));
+            }
+            Node::Seq(seq)
+        } else {
+            // Any ordered subset of the optional members, comma separated
+            let options = (0..optional.len())
+                .map(|first| {
+                    let mut seq = vec![optional[first].clone()];
+                    for member in &optional[first + 1..] {
+                        seq.push(Node::optional(Node::Seq(vec![
+                            comma.clone(),
+                            member.clone(),
+                        ])));
+                    }
+                    Node::Seq(seq)
+                })
+                .collect();
+            Node::optional(Node::Alt(options))
+        };
+
+        Ok(Node::Seq(vec![
+            Node::literal("{"),
+            ws.clone(),
+            body,
+            ws,
+            Node::literal("}"),
+        ]))
+    }
+
+    fn member(&mut self, key: &str, schema: &Value) -> Result<Node> {
+        let ws = self.primitive("ws");
+        Ok(Node::Seq(vec![
+            Node::literal(&Value::String(key.to_string()).to_string()),
+            ws.clone(),
+            Node::literal(":"),
+            ws,
+            self.visit(schema)?,
+        ]))
+    }
+
+    fn visit_array(&mut self, obj: &serde_json::Map<String, Value>) -> Result<Node> {
+        let item = match obj.get("items") {
+            Some(items) => self.visit(items)?,
+            None => self.primitive("value"),
+        };
+        let min = obj.get("minItems").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        let max = obj
+            .get("maxItems")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32);
+        if let Some(max) = max {
+            anyhow::ensure!(min <= max, "minItems is larger than maxItems");
+        }
+
+        let ws = self.primitive("ws");
+        let comma = Node::Seq(vec![ws.clone(), Node::literal(","), ws.clone()]);
+        let rest = Node::Repeat {
+            node: Box::new(Node::Seq(vec![comma, item.clone()])),
+            min: min.saturating_sub(1),
+            max: max.map(|m| m.saturating_sub(1)),
+        };
+        let items = Node::Seq(vec![item, rest]);
+        let items = match (min, max) {
+            (_, Some(0)) => Node::Seq(vec![]),
+            (0, _) => Node::optional(items),
+            _ => items,
+        };
+        Ok(Node::Seq(vec![
+            Node::literal("["),
+            ws.clone(),
+            items,
+            ws,
+            Node::literal("]"),
+        ]))
+    }
+
+    fn visit_string(&mut self, obj: &serde_json::Map<String, Value>) -> Result<Node> {
+        if let Some(pattern) = obj.get("pattern").and_then(|p| p.as_str()) {
+            return Ok(Node::Seq(vec![
+                Node::literal("\""),
+                regex_to_node(pattern)?,
+                Node::literal("\""),
+            ]));
+        }
+        let min = obj.get("minLength").and_then(|v| v.as_u64());
+        let max = obj.get("maxLength").and_then(|v| v.as_u64());
+        if min.is_none() && max.is_none() {
+            return Ok(self.primitive("string"));
+        }
+        let char_node = self.primitive("char");
+        Ok(Node::Seq(vec![
+            Node::literal("\""),
+            Node::Repeat {
+                node: Box::new(char_node),
+                min: min.unwrap_or(0) as u32,
+                max: max.map(|m| m as u32),
+            },
+            Node::literal("\""),
+        ]))
+    }
+
+    /// Reference to a shared rule for a JSON building block, defining it on first use.
+    fn primitive(&mut self, name: &'static str) -> Node {
+        if self.primitives.insert(name) {
+            if name != "ws" {
+                self.primitive("ws");
+            }
+            let digit = || Node::class(&[('0', '9')]);
+            let ws = || Node::reference("ws");
+            let node = match name {
+                // Bounded so the model cannot pad the output with endless whitespace
+                "ws" => Node::Repeat {
+                    node: Box::new(Node::class(&[(' ', ' '), ('\t', '\t'), ('\n', '\n')])),
+                    min: 0,
+                    max: Some(4),
+                },
+                "char" => Node::Alt(vec![
+                    Node::Chars {
+                        ranges: vec![('"', '"'), ('\\', '\\'), ('\u{0}', '\u{1f}')],
+                        negated: true,
+                    },
+                    Node::Seq(vec![
+                        Node::literal("\\"),
+                        Node::Alt(vec![
+                            Node::Chars {
+                                ranges: "\"\\/bfnrt".chars().map(|c| (c, c)).collect(),
+                                negated: false,
+                            },
+                            Node::Seq(vec![
+                                Node::literal("u"),
+                                Node::Repeat {
+                                    node: Box::new(Node::class(&[
+                                        ('0', '9'),
+                                        ('a', 'f'),
+                                        ('A', 'F'),
+                                    ])),
+                                    min: 4,
+                                    max: Some(4),
+                                },
+                            ]),
+                        ]),
+                    ]),
+                ]),
+                "string" => Node::Seq(vec![
+                    Node::literal("\""),
+                    Node::star(self.primitive("char")),
+                    Node::literal("\""),
+                ]),
+                "integer" => Node::Seq(vec![
+                    Node::optional(Node::literal("-")),
+                    Node::Alt(vec![
+                        Node::literal("0"),
+                        Node::Seq(vec![
+                            Node::class(&[('1', '9')]),
+                            Node::Repeat {
+                                node: Box::new(digit()),
+                                min: 0,
+                                max: Some(15),
+                            },
+                        ]),
+                    ]),
+                ]),
+                "number" => Node::Seq(vec![
+                    self.primitive("integer"),
+                    Node::optional(Node::Seq(vec![
+                        Node::literal("."),
+                        Node::Repeat {
+                            node: Box::new(digit()),
+                            min: 1,
+                            max: Some(16),
+                        },
+                    ])),
+                    Node::optional(Node::Seq(vec![
+                        Node::class(&[('e', 'e'), ('E', 'E')]),
+                        Node::optional(Node::class(&[('-', '-'), ('+', '+')])),
+                        Node::Repeat {
+                            node: Box::new(digit()),
+                            min: 1,
+                            max: Some(3),
+                        },
+                    ])),
+                ]),
+                "boolean" => Node::Alt(vec![Node::literal("true"), Node::literal("false")]),
+                "null" => Node::literal("null"),
+                "value" => Node::Alt(vec![
+                    self.primitive("object"),
+                    self.primitive("array"),
+                    self.primitive("string"),
+                    self.primitive("number"),
+                    self.primitive("boolean"),
+                    self.primitive("null"),
+                ]),
+                "object" => {
+                    let string = self.primitive("string");
+                    let value = self.primitive("value");
+                    let member = Node::Seq(vec![string, ws(), Node::literal(":"), ws(), value]);
+                    Node::Seq(vec![
+                        Node::literal("{"),
+                        ws(),
+                        Node::optional(Node::Seq(vec![
+                            member.clone(),
+                            Node::star(Node::Seq(vec![ws(), Node::literal(","), ws(), member])),
+                        ])),
+                        ws(),
+                        Node::literal("}"),
+                    ])
+                }
+                "array" => {
+                    let value = self.primitive("value");
+                    Node::Seq(vec![
+                        Node::literal("["),
+                        ws(),
+                        Node::optional(Node::Seq(vec![
+                            value.clone(),
+                            Node::star(Node::Seq(vec![ws(), Node::literal(","), ws(), value])),
+                        ])),
+                        ws(),
+                        Node::literal("]"),
+                    ])
+                }
+                other => unreachable!("unknown JSON primitive '{}'", other),
+            };
+            self.defs.push((name.to_string(), node));
+        }
+        Node::reference(name)
+    }
+}
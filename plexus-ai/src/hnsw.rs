@@ -0,0 +1,384 @@
+//! Hierarchical navigable small world (HNSW) graphs for approximate nearest-neighbour
+//! search.
+//!
+//! Every vector is a node linked to its nearest neighbours on layer 0 and, with
+//! exponentially decreasing probability, on sparser layers above it. A search descends
+//! greedily from the top layer and explores layer 0 around the closest node found, so it
+//! visits a small fraction of the nodes. Vectors are normalized on insert and compared
+//! by cosine similarity.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Layers above this are never created; reached only with astronomically many nodes.
+const MAX_LEVEL: usize = 16;
+
+/// Graph parameters; larger values trade speed and memory for recall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HnswConfig {
+    /// Links per node on the upper layers; layer 0 keeps twice as many.
+    pub m: usize,
+    /// Candidates considered when linking a new node.
+    pub ef_construction: usize,
+    /// Candidates considered per search; raised to `k` when that is larger.
+    pub ef_search: usize,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    id: String,
+    vector: Vec<f32>,
+    /// Neighbours on every layer the node is on, layer 0 first
+    links: Vec<Vec<u32>>,
+    /// Removed nodes stay in the graph so searches can still pass through them
+    deleted: bool,
+}
+
+/// A node and its distance to the vector being searched for or linked.
+#[derive(Debug, Clone, Copy)]
+struct Scored {
+    distance: f32,
+    node: u32,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+/// Approximate nearest-neighbour index over string ids.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HnswIndex {
+    config: HnswConfig,
+    nodes: Vec<Node>,
+    /// Live node of every id
+    ids: HashMap<String, u32>,
+    /// Node on the top layer where searches start
+    entry: Option<u32>,
+}
+
+impl HnswIndex {
+    pub fn new(config: HnswConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> &HnswConfig {
+        &self.config
+    }
+
+    /// Number of indexed ids.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Removed nodes still taking up space in the graph; see [`Self::rebuild`].
+    pub fn tombstones(&self) -> usize {
+        self.nodes.len() - self.ids.len()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    /// The normalized vector stored for `id`.
+    pub fn vector(&self, id: &str) -> Option<&[f32]> {
+        self.ids
+            .get(id)
+            .map(|&node| self.nodes[node as usize].vector.as_slice())
+    }
+
+    /// Indexes `vector` under `id`, replacing any vector indexed under it.
+    pub fn insert(&mut self, id: &str, vector: &[f32]) {
+        self.remove(id);
+
+        let vector = normalized(vector);
+        let node = self.nodes.len() as u32;
+        let level = self.random_level(id);
+        self.nodes.push(Node {
+            id: id.to_string(),
+            vector,
+            links: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id.to_string(), node);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+        let query = self.nodes[node as usize].vector.clone();
+        let top = self.nodes[entry as usize].links.len() - 1;
+
+        let mut entry_points = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            let nearest = self.search_layer(&query, &entry_points, 1, layer);
+            entry_points = vec![nearest[0].node];
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let candidates =
+                self.search_layer(&query, &entry_points, self.config.ef_construction, layer);
+            let neighbours = self.select_neighbours(&candidates, self.config.m);
+            self.nodes[node as usize].links[layer] = neighbours.clone();
+            for neighbour in neighbours {
+                self.link(neighbour, node, layer);
+            }
+            entry_points = candidates.iter().map(|c| c.node).collect();
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Removes `id`; removing a missing id does nothing.
+    pub fn remove(&mut self, id: &str) {
+        if let Some(node) = self.ids.remove(id) {
+            self.nodes[node as usize].deleted = true;
+        }
+    }
+
+    /// The `k` ids accepted by `accept` with the highest cosine similarity to `query`,
+    /// best first. Approximate: a close vector is occasionally missed.
+    pub fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        accept: impl Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        let Some(entry) = self.entry else {
+            return vec![];
+        };
+        let query = normalized(query);
+        let top = self.nodes[entry as usize].links.len() - 1;
+
+        let mut entry_points = vec![entry];
+        for layer in (1..=top).rev() {
+            let nearest = self.search_layer(&query, &entry_points, 1, layer);
+            entry_points = vec![nearest[0].node];
+        }
+        let ef = self.config.ef_search.max(k);
+        self.search_layer(&query, &entry_points, ef, 0)
+            .into_iter()
+            .filter(|c| {
+                let node = &self.nodes[c.node as usize];
+                !node.deleted && accept(&node.id)
+            })
+            .take(k)
+            .map(|c| {
+                (
+                    self.nodes[c.node as usize].id.clone(),
+                    similarity(c.distance),
+                )
+            })
+            .collect()
+    }
+
+    /// Like [`Self::search`], but compares `query` with every indexed vector. Exact, and
+    /// cheaper than the graph for small or heavily filtered sets.
+    pub fn exact_search(
+        &self,
+        query: &[f32],
+        k: usize,
+        accept: impl Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        let query = normalized(query);
+        let mut scored: Vec<Scored> = self
+            .ids
+            .iter()
+            .filter(|(id, _)| accept(id))
+            .map(|(_, &node)| Scored {
+                distance: distance(&query, &self.nodes[node as usize].vector),
+                node,
+            })
+            .collect();
+        scored.sort();
+        scored
+            .into_iter()
+            .take(k)
+            .map(|c| {
+                (
+                    self.nodes[c.node as usize].id.clone(),
+                    similarity(c.distance),
+                )
+            })
+            .collect()
+    }
+
+    /// A copy of the index without tombstones.
+    pub fn rebuild(&self) -> Self {
+        let mut index = Self::new(self.config);
+        for node in self.nodes.iter().filter(|n| !n.deleted) {
+            index.insert(&node.id, &node.vector);
+        }
+        index
+    }
+
+    /// The `ef` nodes closest to `query` found by a best-first walk of `layer` from
+    /// `entry_points`, closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[u32],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = entry_points.iter().copied().collect();
+        // Closest unexplored node first
+        let mut candidates = BinaryHeap::new();
+        // Furthest kept node first, so it can be evicted
+        let mut nearest = BinaryHeap::new();
+        for &node in entry_points {
+            let scored = Scored {
+                distance: distance(query, &self.nodes[node as usize].vector),
+                node,
+            };
+            candidates.push(Reverse(scored));
+            nearest.push(scored);
+        }
+        while nearest.len() > ef {
+            nearest.pop();
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = nearest
+                .peek()
+                .map_or(f32::INFINITY, |s: &Scored| s.distance);
+            if candidate.distance > furthest && nearest.len() >= ef {
+                break;
+            }
+            for &neighbour in &self.nodes[candidate.node as usize].links[layer] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let distance = distance(query, &self.nodes[neighbour as usize].vector);
+                let furthest = nearest
+                    .peek()
+                    .map_or(f32::INFINITY, |s: &Scored| s.distance);
+                if nearest.len() < ef || distance < furthest {
+                    let scored = Scored {
+                        distance,
+                        node: neighbour,
+                    };
+                    candidates.push(Reverse(scored));
+                    nearest.push(scored);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+        nearest.into_sorted_vec()
+    }
+
+    /// Picks up to `m` of `candidates` (closest first) to link to, skipping candidates
+    /// closer to an already picked one than to the base node so links spread in all
+    /// directions. Skipped candidates fill any remaining slots.
+    fn select_neighbours(&self, candidates: &[Scored], m: usize) -> Vec<u32> {
+        let mut selected: Vec<Scored> = vec![];
+        let mut skipped = vec![];
+        for &candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            let vector = &self.nodes[candidate.node as usize].vector;
+            let diverse = selected.iter().all(|s| {
+                distance(vector, &self.nodes[s.node as usize].vector) > candidate.distance
+            });
+            if diverse {
+                selected.push(candidate);
+            } else {
+                skipped.push(candidate);
+            }
+        }
+        let missing = m.saturating_sub(selected.len());
+        selected.extend(skipped.into_iter().take(missing));
+        selected.into_iter().map(|s| s.node).collect()
+    }
+
+    /// Adds a link from `from` to `to` on `layer`, pruning `from`'s links if it has too many.
+    fn link(&mut self, from: u32, to: u32, layer: usize) {
+        let max_links = if layer == 0 {
+            2 * self.config.m
+        } else {
+            self.config.m
+        };
+        self.nodes[from as usize].links[layer].push(to);
+        if self.nodes[from as usize].links[layer].len() <= max_links {
+            return;
+        }
+        let base = &self.nodes[from as usize];
+        let mut candidates: Vec<Scored> = base.links[layer]
+            .iter()
+            .map(|&node| Scored {
+                distance: distance(&base.vector, &self.nodes[node as usize].vector),
+                node,
+            })
+            .collect();
+        candidates.sort();
+        let links = self.select_neighbours(&candidates, max_links);
+        self.nodes[from as usize].links[layer] = links;
+    }
+
+    /// Level of a new node, `floor(-ln(u) / ln(m))` for `u` uniform in (0, 1], derived
+    /// from the id and position so rebuilding an index reproduces it.
+    fn random_level(&self, id: &str) -> usize {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (id, self.nodes.len()).hash(&mut hasher);
+        let uniform = ((hasher.finish() >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        let scale = 1.0 / (self.config.m.max(2) as f64).ln();
+        ((-uniform.ln() * scale) as usize).min(MAX_LEVEL)
+    }
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+/// Cosine distance between normalized vectors.
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+}
+
+fn similarity(distance: f32) -> f32 {
+    (1.0 - distance).clamp(-1.0, 1.0)
+}
@@ -0,0 +1,370 @@
+//! Document ingestion for retrieval-augmented generation.
+//!
+//! Files are read as text (PDFs are extracted page by page), split into overlapping
+//! windows of embedder tokens, embedded in batches and stored as one document per chunk.
+//! Chunk `i` of a source is stored under the id `<source>#<i>` with its byte offsets and
+//! a hash of its text, so re-ingesting a source only re-embeds the chunks whose text is
+//! new; chunks that only moved, e.g. after an edit earlier in the file, keep their vector.
+
+use crate::audit::sha256_hex;
+use crate::document::SOURCE_KEY;
+use crate::{Document, Embedder, VectorStore};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Default chunk length, small enough for MiniLM-sized embedders.
+pub const DEFAULT_CHUNK_TOKENS: usize = 128;
+/// Default number of tokens shared by consecutive chunks.
+pub const DEFAULT_CHUNK_OVERLAP: usize = 16;
+/// Chunks embedded per embedder call.
+const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Metadata keys set on every chunk.
+const KIND_KEY: &str = "kind";
+const CHUNK_KEY: &str = "chunk";
+const START_KEY: &str = "start";
+const END_KEY: &str = "end";
+const PAGE_KEY: &str = "page";
+const CONTENT_HASH_KEY: &str = "content_hash";
+
+/// The kind of file a source was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Text,
+    Markdown,
+    Pdf,
+    Code,
+}
+
+impl SourceKind {
+    /// Guesses the kind from the file extension; `None` for unsupported files.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "" | "txt" | "text" | "log" | "rst" => Some(Self::Text),
+            "md" | "markdown" | "mdx" => Some(Self::Markdown),
+            "pdf" => Some(Self::Pdf),
+            "rs" | "py" | "js" | "jsx" | "ts" | "tsx" | "go" | "java" | "kt" | "c" | "h" | "cc"
+            | "cpp" | "hpp" | "cs" | "rb" | "php" | "swift" | "scala" | "sh" | "sql" | "html"
+            | "css" | "toml" | "yaml" | "yml" | "json" => Some(Self::Code),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Markdown => "markdown",
+            Self::Pdf => "pdf",
+            Self::Code => "code",
+        }
+    }
+}
+
+/// How text is split into chunks, in embedder tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkConfig {
+    pub chunk_tokens: usize,
+    /// Tokens repeated at the start of the next chunk so context is not cut mid-thought.
+    pub overlap_tokens: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            chunk_tokens: DEFAULT_CHUNK_TOKENS,
+            overlap_tokens: DEFAULT_CHUNK_OVERLAP,
+        }
+    }
+}
+
+impl ChunkConfig {
+    pub fn with_chunk_tokens(mut self, tokens: usize) -> Self {
+        self.chunk_tokens = tokens;
+        self
+    }
+
+    pub fn with_overlap_tokens(mut self, tokens: usize) -> Self {
+        self.overlap_tokens = tokens;
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.chunk_tokens == 0 {
+            anyhow::bail!("Chunks must be at least one token long");
+        }
+        if self.overlap_tokens >= self.chunk_tokens {
+            anyhow::bail!(
+                "Chunk overlap ({}) must be smaller than the chunk length ({})",
+                self.overlap_tokens,
+                self.chunk_tokens
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A window of a source's text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub index: usize,
+    /// Byte range of the chunk in the source text.
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Splits `text` into windows of `config.chunk_tokens` tokens overlapping by
+/// `config.overlap_tokens`, given the byte range of every token in `text`.
+pub fn chunk_text(
+    text: &str,
+    spans: &[(usize, usize)],
+    config: &ChunkConfig,
+) -> Result<Vec<Chunk>> {
+    config.validate()?;
+    let step = config.chunk_tokens - config.overlap_tokens;
+
+    let mut chunks = vec![];
+    let mut first = 0;
+    while first < spans.len() {
+        let last = (first + config.chunk_tokens).min(spans.len()) - 1;
+        let (start, end) = (spans[first].0, spans[last].1);
+        let text = text
+            .get(start..end)
+            .with_context(|| format!("Token span {}..{} is not in the text", start, end))?;
+        chunks.push(Chunk {
+            index: chunks.len(),
+            start,
+            end,
+            text: text.to_string(),
+        });
+        if last + 1 == spans.len() {
+            break;
+        }
+        first += step;
+    }
+    Ok(chunks)
+}
+
+/// Outcome of ingesting one source.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestReport {
+    pub source: String,
+    /// Chunks the source now consists of.
+    pub chunks: usize,
+    /// Chunks that were new or changed and had to be embedded.
+    pub embedded: usize,
+    /// Chunks already stored with the same content, possibly at another position.
+    pub unchanged: usize,
+    /// Chunks left over from a longer previous version and deleted.
+    pub removed: usize,
+}
+
+/// Chunks, embeds and stores documents.
+pub struct Ingestor {
+    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
+    chunking: ChunkConfig,
+    batch_size: usize,
+}
+
+impl Ingestor {
+    pub fn new(embedder: Arc<dyn Embedder>, store: Arc<dyn VectorStore>) -> Self {
+        Self {
+            embedder,
+            store,
+            chunking: ChunkConfig::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    pub fn with_chunk_config(mut self, chunking: ChunkConfig) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Sets how many chunks are embedded per embedder call.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Ingests a text, Markdown, PDF or source file; its path is used as the source.
+    pub async fn ingest_file(&self, path: &Path) -> Result<IngestReport> {
+        let kind = SourceKind::from_path(path)
+            .with_context(|| format!("Unsupported file type: {:?}", path))?;
+        let source = path.to_string_lossy().to_string();
+
+        if kind == SourceKind::Pdf {
+            let bytes = tokio::fs::read(path)
+                .await
+                .with_context(|| format!("Failed to read {:?}", path))?;
+            let pages = tokio::task::spawn_blocking(move || {
+                pdf_extract::extract_text_from_mem_by_pages(&bytes)
+                    .map_err(|e| anyhow::anyhow!("Failed to extract PDF text: {}", e))
+            })
+            .await??;
+            let mut text = String::new();
+            let mut page_starts = vec![];
+            for page in pages {
+                page_starts.push(text.len());
+                text.push_str(&page);
+                text.push('\n');
+            }
+            return self.ingest(&source, kind, &text, &page_starts).await;
+        }
+
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        self.ingest(&source, kind, &text, &[]).await
+    }
+
+    /// Ingests `text` under the name `source`, replacing an earlier version of it.
+    pub async fn ingest_text(
+        &self,
+        source: &str,
+        kind: SourceKind,
+        text: &str,
+    ) -> Result<IngestReport> {
+        self.ingest(source, kind, text, &[]).await
+    }
+
+    /// `page_starts` holds the byte offset at which each page starts, for PDFs.
+    async fn ingest(
+        &self,
+        source: &str,
+        kind: SourceKind,
+        text: &str,
+        page_starts: &[usize],
+    ) -> Result<IngestReport> {
+        let spans = self.embedder.token_spans(text).await?;
+        let chunks = chunk_text(text, &spans, &self.chunking)?;
+        let mut report = IngestReport {
+            source: source.to_string(),
+            chunks: chunks.len(),
+            ..Default::default()
+        };
+
+        // Chunks of the previous version, by index
+        let mut previous = vec![];
+        while let Some(document) = self.store.get(&chunk_id(source, previous.len())).await? {
+            previous.push(document);
+        }
+
+        let mut changed = vec![];
+        let mut moved = vec![];
+        for chunk in &chunks {
+            let mut metadata = Map::new();
+            metadata.insert(SOURCE_KEY.to_string(), source.into());
+            metadata.insert(KIND_KEY.to_string(), kind.as_str().into());
+            metadata.insert(CHUNK_KEY.to_string(), chunk.index.into());
+            metadata.insert(START_KEY.to_string(), chunk.start.into());
+            metadata.insert(END_KEY.to_string(), chunk.end.into());
+            if !page_starts.is_empty() {
+                let page = page_starts.partition_point(|&s| s <= chunk.start);
+                metadata.insert(PAGE_KEY.to_string(), page.into());
+            }
+            metadata.insert(
+                CONTENT_HASH_KEY.to_string(),
+                sha256_hex(chunk.text.as_bytes()).into(),
+            );
+
+            if previous
+                .get(chunk.index)
+                .is_some_and(|existing| same_chunk(&existing.metadata, &metadata))
+            {
+                report.unchanged += 1;
+                continue;
+            }
+            let mut document = Document::new(chunk_id(source, chunk.index), chunk.text.clone());
+            document.metadata.extend(metadata);
+            let hash = content_hash(&document.metadata);
+            if previous.iter().any(|p| content_hash(&p.metadata) == hash) {
+                moved.push(document);
+            } else {
+                changed.push(document);
+            }
+        }
+
+        // Read before any chunk is overwritten, as a moved chunk may take another's place
+        if !moved.is_empty() {
+            let mut vectors: HashMap<String, Vec<f32>> = HashMap::new();
+            for document in &moved {
+                let Some(hash) = content_hash(&document.metadata) else {
+                    continue;
+                };
+                let Some(existing) = previous
+                    .iter()
+                    .find(|p| content_hash(&p.metadata) == Some(hash))
+                else {
+                    continue;
+                };
+                if vectors.contains_key(hash) {
+                    continue;
+                }
+                if let Some((_, vector)) = self.store.get_entry(&existing.id).await? {
+                    vectors.insert(hash.to_string(), vector);
+                }
+            }
+            for document in moved {
+                match content_hash(&document.metadata).and_then(|hash| vectors.get(hash)) {
+                    Some(vector) => {
+                        self.store.upsert(document, vector.clone()).await?;
+                        report.unchanged += 1;
+                    }
+                    None => changed.push(document),
+                }
+            }
+        }
+
+        for batch in changed.chunks(self.batch_size) {
+            let texts: Vec<&str> = batch.iter().map(|d| d.text.as_str()).collect();
+            let vectors = self.embedder.embed_batch(&texts).await?;
+            for (document, vector) in batch.iter().zip(vectors) {
+                self.store.upsert(document.clone(), vector).await?;
+            }
+            report.embedded += batch.len();
+        }
+
+        // Drop the tail of a previous, longer version
+        for index in chunks.len()..previous.len() {
+            self.store.delete(&chunk_id(source, index)).await?;
+            report.removed += 1;
+        }
+
+        tracing::info!(
+            "Ingested {}: {} chunks, {} embedded, {} unchanged, {} removed",
+            source,
+            report.chunks,
+            report.embedded,
+            report.unchanged,
+            report.removed
+        );
+        Ok(report)
+    }
+}
+
+/// Id of chunk `index` of `source` in the vector store.
+pub fn chunk_id(source: &str, index: usize) -> String {
+    format!("{}#{}", source, index)
+}
+
+fn content_hash(metadata: &Map<String, Value>) -> Option<&str> {
+    metadata.get(CONTENT_HASH_KEY).and_then(Value::as_str)
+}
+
+/// True if a stored chunk has the content and position of a new one.
+fn same_chunk(stored: &Map<String, Value>, new: &Map<String, Value>) -> bool {
+    new.iter()
+        .all(|(key, value)| stored.get(key) == Some(value))
+}
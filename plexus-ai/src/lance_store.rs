@@ -1,145 +1,400 @@
-use crate::VectorStore;
+use crate::memory::check_dimension;
+use crate::{
+    Document, EmbeddingModel, SearchFilter, SearchResult, VectorStore, DEFAULT_EMBEDDING_MODEL,
+};
 use anyhow::Result;
-use arrow_array::{types::Float32Type, FixedSizeListArray, RecordBatch, StringArray};
-use arrow_schema::{DataType, Field, Schema};
+use arrow_array::{
+    FixedSizeListArray, Float32Array, Int64Array, RecordBatch, RecordBatchIterator, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
 use futures::TryStreamExt;
 use lancedb::{
     connect,
     query::{ExecutableQuery, QueryBase},
-    Connection, Table,
+    Connection, DistanceType, Table,
 };
 use std::sync::Arc;
 
+/// Table name prefix; the embedding model and dimension are appended.
+const TABLE_PREFIX: &str = "documents";
+/// Tables written before stores were split by embedding model, newest first. Both hold
+/// all-MiniLM-L6-v2 vectors; the older one has no metadata column.
+const LEGACY_TABLE_NAMES: [&str; 2] = ["documents", "vectors"];
+
+/// Embedded vector store. Searches use the cosine distance, so scores are cosine
+/// similarities like those of the other backends.
 pub struct LanceDbStore {
     table: Table,
+    dimension: usize,
 }
 
 impl LanceDbStore {
-    pub async fn new(path: &std::path::Path) -> Result<Self> {
+    /// Opens the table for vectors of `model`, creating it if needed.
+    pub async fn new(path: &std::path::Path, model: &EmbeddingModel) -> Result<Self> {
         let uri = path.to_string_lossy().to_string();
         let connection = connect(&uri).execute().await?;
+        let name = format!("{}_{}", TABLE_PREFIX, model.collection_suffix());
+        let dimension = model.dimension;
 
-        // Define Schema: id (utf8), text (utf8), vector (fixed_size_list<float32>[384])
-        // 384 is the dimension for all-MiniLM-L6-v2
-        let schema = Arc::new(Schema::new(vec![
-            Field::new("id", DataType::Utf8, false),
-            Field::new("text", DataType::Utf8, false),
-            Field::new(
-                "vector",
-                DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), 384),
-                false,
-            ),
-        ]));
-
-        let table = if connection
-            .table_names()
-            .execute()
-            .await?
-            .contains(&"vectors".to_string())
-        {
-            connection.open_table("vectors").execute().await?
+        let table_names = connection.table_names().execute().await?;
+        let table = if table_names.contains(&name) {
+            let table = connection.open_table(&name).execute().await?;
+            let stored = vector_dimension(&table).await?;
+            if stored != dimension {
+                anyhow::bail!(
+                    "LanceDB table {} holds {}-dimensional vectors, the embedder produces {}",
+                    name,
+                    stored,
+                    dimension
+                );
+            }
+            table
         } else {
-            connection
-                .create_empty_table("vectors", schema)
+            let table = connection
+                .create_empty_table(&name, schema(dimension))
                 .execute()
-                .await?
+                .await?;
+            if model.id == DEFAULT_EMBEDDING_MODEL {
+                let legacy = LEGACY_TABLE_NAMES
+                    .iter()
+                    .find(|legacy| table_names.contains(&legacy.to_string()));
+                if let Some(legacy) = legacy {
+                    migrate_legacy_table(&connection, legacy, &table, dimension).await?;
+                }
+            }
+            table
         };
 
-        Ok(Self { table })
+        Ok(Self { table, dimension })
+    }
+
+    /// Documents matching `predicate`, at most `limit` of them (plain queries are
+    /// otherwise capped at LanceDB's default top-k)
+    async fn query_documents(
+        &self,
+        predicate: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Document>> {
+        let mut query = self.table.query().limit(limit.max(1));
+        if let Some(predicate) = predicate {
+            query = query.only_if(predicate);
+        }
+        let batches = query.execute().await?.try_collect::<Vec<_>>().await?;
+        let mut documents = vec![];
+        for batch in &batches {
+            documents.extend(batch_documents(batch)?);
+        }
+        Ok(documents)
+    }
+
+    /// Like [`Self::query_documents`], with the vector of each document.
+    async fn query_entries(
+        &self,
+        predicate: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<(Document, Vec<f32>)>> {
+        let mut query = self.table.query().limit(limit.max(1));
+        if let Some(predicate) = predicate {
+            query = query.only_if(predicate);
+        }
+        let batches = query.execute().await?.try_collect::<Vec<_>>().await?;
+        let mut entries = vec![];
+        for batch in &batches {
+            entries.extend(
+                batch_documents(batch)?
+                    .into_iter()
+                    .zip(batch_vectors(batch)?),
+            );
+        }
+        Ok(entries)
     }
 }
 
-#[async_trait::async_trait]
-impl VectorStore for LanceDbStore {
-    async fn add(&self, _id: &str, _vector: Vec<f32>) -> Result<()> {
-        Err(anyhow::anyhow!("Use add_document instead"))
+/// Schema: id, text, metadata (JSON), the filterable metadata fields as columns, vector
+///
+/// Tags are stored as `,a,b,` so a tag can be matched with `LIKE '%,a,%'`.
+fn schema(dimension: usize) -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("metadata", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, true),
+        Field::new("tags", DataType::Utf8, true),
+        Field::new("created_at", DataType::Int64, true),
+        Field::new(
+            "vector",
+            DataType::FixedSizeList(
+                Arc::new(Field::new("item", DataType::Float32, true)),
+                dimension as i32,
+            ),
+            false,
+        ),
+    ]))
+}
+
+/// Length of the vectors in `table`'s `vector` column.
+async fn vector_dimension(table: &Table) -> Result<usize> {
+    let schema = table.schema().await?;
+    match schema.field_with_name("vector")?.data_type() {
+        DataType::FixedSizeList(_, size) => Ok(*size as usize),
+        other => anyhow::bail!("Invalid vector column type: {}", other),
     }
+}
 
-    async fn add_document(&self, id: &str, text: &str, vector: Vec<f32>) -> Result<()> {
-        let schema = self.table.schema().await?;
+fn document_batch(document: &Document, vector: Vec<f32>) -> Result<RecordBatch> {
+    let dimension = vector.len();
+    let tags = document.tags();
+    let tags = (!tags.is_empty()).then(|| format!(",{},", tags.join(",")));
 
-        let id_array = StringArray::from(vec![id]);
-        let text_array = StringArray::from(vec![text]);
+    // Construct FixedSizeListArray for vector
+    let values = Float32Array::from(vector);
+    let vector_array = FixedSizeListArray::new(
+        Arc::new(Field::new("item", DataType::Float32, true)),
+        dimension as i32,
+        Arc::new(values),
+        None,
+    );
 
-        // Construct FixedSizeListArray for vector
-        let values = arrow_array::Float32Array::from(vector);
-        let vector_array = FixedSizeListArray::new(
-            Arc::new(Field::new("item", DataType::Float32, true)),
-            384,
-            Arc::new(values),
-            None,
+    Ok(RecordBatch::try_new(
+        schema(dimension),
+        vec![
+            Arc::new(StringArray::from(vec![document.id.as_str()])),
+            Arc::new(StringArray::from(vec![document.text.as_str()])),
+            Arc::new(StringArray::from(vec![serde_json::to_string(
+                &document.metadata,
+            )?])),
+            Arc::new(StringArray::from(vec![document.source()])),
+            Arc::new(StringArray::from(vec![tags])),
+            Arc::new(Int64Array::from(vec![document
+                .created_at()
+                .map(|t| t as i64)])),
+            Arc::new(vector_array),
+        ],
+    )?)
+}
+
+fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch
+        .column_by_name(name)
+        .ok_or(anyhow::anyhow!("Missing {} column", name))?
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or(anyhow::anyhow!("Invalid {} array", name))
+}
+
+fn batch_documents(batch: &RecordBatch) -> Result<Vec<Document>> {
+    let ids = string_column(batch, "id")?;
+    let texts = string_column(batch, "text")?;
+    let metadata = string_column(batch, "metadata")?;
+    (0..batch.num_rows())
+        .map(|i| {
+            Ok(Document {
+                id: ids.value(i).to_string(),
+                text: texts.value(i).to_string(),
+                metadata: serde_json::from_str(metadata.value(i))?,
+            })
+        })
+        .collect()
+}
+
+/// Rows of the `vector` column.
+fn batch_vectors(batch: &RecordBatch) -> Result<Vec<Vec<f32>>> {
+    let vectors = batch
+        .column_by_name("vector")
+        .ok_or(anyhow::anyhow!("Missing vector column"))?
+        .as_any()
+        .downcast_ref::<FixedSizeListArray>()
+        .ok_or(anyhow::anyhow!("Invalid vector array"))?;
+    (0..batch.num_rows())
+        .map(|i| {
+            Ok(vectors
+                .value(i)
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .ok_or(anyhow::anyhow!("Invalid vector values"))?
+                .values()
+                .to_vec())
+        })
+        .collect()
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// SQL predicate selecting the rows `filter` lets through
+///
+/// Tag matching with `LIKE` can over-match tags containing `,`, `%` or `_`, so results
+/// are checked with [`SearchFilter::matches`] as well.
+fn sql_filter(filter: &SearchFilter) -> Option<String> {
+    let mut clauses = vec![];
+    if let Some(source) = &filter.source {
+        clauses.push(format!("source = {}", quote(source)));
+    }
+    for tag in &filter.tags {
+        clauses.push(format!("tags LIKE {}", quote(&format!("%,{},%", tag))));
+    }
+    if let Some(after) = filter.created_after {
+        clauses.push(format!("created_at >= {}", after));
+    }
+    if let Some(before) = filter.created_before {
+        clauses.push(format!("created_at <= {}", before));
+    }
+    (!clauses.is_empty()).then(|| clauses.join(" AND "))
+}
+
+/// Copies the documents of the legacy table `name` into `table`
+///
+/// Vectors of another dimension cannot come from the same model and are not copied.
+async fn migrate_legacy_table(
+    connection: &Connection,
+    name: &str,
+    table: &Table,
+    dimension: usize,
+) -> Result<()> {
+    let legacy = connection.open_table(name).execute().await?;
+    let stored = vector_dimension(&legacy).await?;
+    if stored != dimension {
+        tracing::warn!(
+            "Not migrating LanceDB table '{}': it holds {}-dimensional vectors, expected {}",
+            name,
+            stored,
+            dimension
         );
+        return Ok(());
+    }
+    let batches = legacy
+        .query()
+        .execute()
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let mut migrated = 0;
+    for batch in &batches {
+        let ids = string_column(batch, "id")?;
+        let texts = string_column(batch, "text")?;
+        let metadata = string_column(batch, "metadata").ok();
 
-        let batch = RecordBatch::try_new(
-            schema.clone(),
-            vec![
-                Arc::new(id_array),
-                Arc::new(text_array),
-                Arc::new(vector_array),
-            ],
-        )?;
+        let mut rows = vec![];
+        for (i, vector) in batch_vectors(batch)?.into_iter().enumerate() {
+            // The oldest table did not record metadata or when documents were added
+            let document = Document {
+                id: ids.value(i).to_string(),
+                text: texts.value(i).to_string(),
+                metadata: match metadata {
+                    Some(metadata) => serde_json::from_str(metadata.value(i))?,
+                    None => Default::default(),
+                },
+            };
+            rows.push(Ok(document_batch(&document, vector)?));
+        }
+        migrated += rows.len();
+        table
+            .add(RecordBatchIterator::new(rows, schema(dimension)))
+            .execute()
+            .await?;
+    }
+    tracing::info!(
+        "Migrated {} documents from the LanceDB '{}' table",
+        migrated,
+        name
+    );
+    Ok(())
+}
 
+#[async_trait::async_trait]
+impl VectorStore for LanceDbStore {
+    async fn add(&self, _id: &str, _vector: Vec<f32>) -> Result<()> {
+        Err(anyhow::anyhow!("Use add_document instead"))
+    }
+
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> Result<()> {
+        check_dimension(self.dimension, vector.len())?;
+        let batch = document_batch(&document, vector)?;
+        self.delete(&document.id).await?;
         self.table
-            .add(arrow_array::RecordBatchIterator::new(
+            .add(RecordBatchIterator::new(
                 vec![Ok(batch)],
-                schema.clone(),
+                schema(self.dimension),
             ))
             .execute()
             .await?;
         Ok(())
     }
 
-    async fn search(&self, query_vector: Vec<f32>, k: usize) -> Result<Vec<(String, f32)>> {
-        // LanceDB search
-        // We need to verify if 'vector' column is indexed or perform brute force.
-        // For embedded simply use standard query.
-
-        let results = self
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        check_dimension(self.dimension, query_vector.len())?;
+        let mut query = self
             .table
             .query()
             .limit(k)
             .nearest_to(query_vector)?
-            .execute()
-            .await?
-            .try_collect::<Vec<_>>()
-            .await?;
+            .distance_type(DistanceType::Cosine);
+        if let Some(predicate) = sql_filter(filter) {
+            query = query.only_if(predicate);
+        }
+        let results = query.execute().await?.try_collect::<Vec<_>>().await?;
 
         let mut matches = vec![];
 
         for batch in results {
-            let text_col = batch
-                .column_by_name("text")
-                .ok_or(anyhow::anyhow!("Missing text column"))?;
-            let dist_col = batch
+            let documents = batch_documents(&batch)?;
+            let dists = batch
                 .column_by_name("_distance")
-                .ok_or(anyhow::anyhow!("Missing _distance column"))?;
-
-            let texts = text_col
-                .as_any()
-                .downcast_ref::<StringArray>()
-                .ok_or(anyhow::anyhow!("Invalid text array"))?;
-            let dists = dist_col
+                .ok_or(anyhow::anyhow!("Missing _distance column"))?
                 .as_any()
-                .downcast_ref::<arrow_array::Float32Array>()
+                .downcast_ref::<Float32Array>()
                 .ok_or(anyhow::anyhow!("Invalid distance array"))?;
 
-            for i in 0..batch.num_rows() {
-                let text = texts.value(i).to_string();
-                let dist = dists.value(i);
-                // Convert distance to similarity score (Cosine distance is 1 - similarity usually, Lance might return L2)
-                // Assuming L2 for now or generic distance.
-                // Since our engine uses cosine similarity logic (1.0 is best), but search returns distance (0.0 is best).
-                // Let's invert it roughly: 1.0 / (1.0 + dist) or just return raw distance and handle loop side.
-                // However, RAG logic checks "score > 0.4". If score is similarity (0..1), we need similarity.
-                // LanceDB defaults to L2 distance.
-                // Similarity = 1 - (L2 / 2) for normalized vectors?
-                // Simplest: 1.0 - distance (if distance < 1.0).
-
-                let score = 1.0 - dist;
-                matches.push((text, score));
+            for (i, document) in documents.into_iter().enumerate() {
+                if !filter.matches(&document.metadata) {
+                    continue;
+                }
+                // Cosine distance is 1 - cosine similarity
+                let score = (1.0 - dists.value(i)).clamp(-1.0, 1.0);
+                matches.push(SearchResult::new(document, score));
             }
         }
 
         Ok(matches)
     }
+
+    async fn get(&self, id: &str) -> Result<Option<Document>> {
+        let documents = self
+            .query_documents(Some(format!("id = {}", quote(id))), 1)
+            .await?;
+        Ok(documents.into_iter().next())
+    }
+
+    async fn get_entry(&self, id: &str) -> Result<Option<(Document, Vec<f32>)>> {
+        let entries = self
+            .query_entries(Some(format!("id = {}", quote(id))), 1)
+            .await?;
+        Ok(entries.into_iter().next())
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.table.delete(&format!("id = {}", quote(id))).await?;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize> {
+        Ok(self.table.count_rows(None).await?)
+    }
+
+    async fn documents(&self) -> Result<Vec<Document>> {
+        let count = self.count().await?;
+        self.query_documents(None, count).await
+    }
+
+    async fn entries(&self) -> Result<Vec<(Document, Vec<f32>)>> {
+        let count = self.count().await?;
+        self.query_entries(None, count).await
+    }
 }
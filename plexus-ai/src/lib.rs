@@ -1,13 +1,63 @@
+mod audit;
+mod backend;
+mod batching;
+mod constrained;
+mod device;
+mod document;
+mod embedding;
 mod engine;
+mod grammar;
+mod hnsw;
 #[cfg(feature = "lancedb")]
 mod lance_store;
 mod memory;
-pub use engine::TinyLlamaEngine;
+mod nomic_bert;
+mod persist;
+mod prefix_cache;
+mod rerank;
+mod speculative;
+pub use audit::{GenerationParams, GenerationRecord, ModelFingerprint, DEFAULT_SEED};
+pub use backend::{QdrantFailoverStore, VectorStoreConfig, VectorStoreKind};
+pub use batching::{BatchScheduler, BatchedLlama, KvCache, SequenceRequest};
+pub use constrained::{JsonSchemaFormat, ResponseFormat, TokenConstraint, TokenVocabulary};
+pub use device::{default_device, DeviceRequest, DeviceSelector, SelectedDevice};
+pub use document::{Document, SearchFilter, SearchResult};
+pub use embedding::{
+    Architecture, EmbedderRegistry, EmbeddingModel, Pooling, DEFAULT_EMBEDDING_MODEL,
+};
+pub use engine::{ModelSource, TinyLlamaEngine, TINYLLAMA};
+pub use grammar::{Grammar, GrammarState};
+pub use hnsw::{HnswConfig, HnswIndex};
 #[cfg(feature = "lancedb")]
 pub use lance_store::LanceDbStore;
-pub use memory::{BertEmbedder, QdrantStore, SimpleVectorStore};
+pub use memory::{BertEmbedder, QdrantConfig, QdrantStore, SimpleVectorStore};
+pub use prefix_cache::PrefixCache;
+pub use rerank::{CrossEncoderReranker, DEFAULT_RERANK_MODEL};
+pub use speculative::{
+    DraftModel, SpeculativeConfig, SpeculativeMetrics, SpeculativeStats, DEFAULT_DRAFT_TOKENS,
+};
 pub mod chat;
-pub use chat::{ChatHistory, ChatMessage, Role};
+pub use chat::{ChatHistory, ChatMessage, ContextPolicy, Role, DEFAULT_CONTEXT_LENGTH};
+pub mod template;
+pub mod tools;
+pub use template::{ChatTemplate, PromptTemplate};
+pub use tools::{
+    FunctionCall, FunctionDefinition, ToolCall, ToolCallFormat, ToolChoice, ToolDefinition,
+    ToolKind,
+};
+pub mod backup;
+pub use backup::{ImportReport, MemoryBackup, MemoryRecord};
+pub mod ingest;
+pub use ingest::{
+    chunk_id, chunk_text, Chunk, ChunkConfig, IngestReport, Ingestor, SourceKind,
+    DEFAULT_CHUNK_OVERLAP, DEFAULT_CHUNK_TOKENS,
+};
+pub mod registry;
+pub mod retrieval;
+pub use registry::{create_engine, expected_footprint, EngineMetrics, ModelInfo, ModelRegistry};
+pub use retrieval::{reciprocal_rank_fusion, Bm25Index, HybridRetriever, RetrievalConfig};
+pub mod session;
+pub use session::{Session, SessionInfo, SessionStore, DEFAULT_SESSION_ID};
 pub mod voice;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -26,14 +76,161 @@ pub trait LLMEngine: Send + Sync {
         prompt: &str,
         sender: tokio::sync::mpsc::Sender<String>,
     ) -> Result<()>;
+
+    /// Generate text whose output is constrained to `format` (JSON schema, regex, grammar)
+    async fn generate_constrained(&self, prompt: &str, format: &ResponseFormat) -> Result<String> {
+        match format {
+            ResponseFormat::Text => self.generate(prompt).await,
+            _ => anyhow::bail!("This engine does not support constrained generation"),
+        }
+    }
+
+    /// Generate reproducibly, returning the output with an audit record of how it was made
+    async fn generate_deterministic(
+        &self,
+        _prompt: &str,
+        _params: &GenerationParams,
+    ) -> Result<(String, GenerationRecord)> {
+        anyhow::bail!("This engine does not support deterministic generation")
+    }
+
+    /// Re-run the generation described by `record` and check the output is bit-identical
+    ///
+    /// Fails if `prompt` is not the recorded prompt or this engine runs different weights.
+    async fn replay(&self, prompt: &str, record: &GenerationRecord) -> Result<bool> {
+        record.verify_prompt(prompt)?;
+        let (_, replayed) = self.generate_deterministic(prompt, &record.params).await?;
+        record.reproduced_by(&replayed)
+    }
+
+    /// Chat template of the loaded model, used to render conversations into prompts
+    async fn chat_template(&self) -> Result<PromptTemplate>;
+
+    /// Generate the next assistant turn of `messages`, which may call `tools`
+    ///
+    /// Calls found in the output are returned in the message's `tool_calls`.
+    async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        tool_choice: &ToolChoice,
+    ) -> Result<ChatMessage> {
+        let template = self.chat_template().await?;
+        let prompt = template.render_with_tools(messages, tools, tool_choice, true)?;
+        let output = self.generate(&prompt).await?;
+        Ok(template.parse_response(&output, tools, tool_choice))
+    }
+
+    /// Tokens the model attends to, prompt and reply together
+    fn context_length(&self) -> usize {
+        DEFAULT_CONTEXT_LENGTH
+    }
+
+    /// Number of tokens `text` encodes to
+    ///
+    /// The default is a rough estimate of four bytes per token.
+    async fn count_tokens(&self, text: &str) -> Result<usize> {
+        Ok(text.len().div_ceil(4))
+    }
+
+    /// Approximate bytes held by the loaded weights (0 if nothing is loaded)
+    fn memory_footprint(&self) -> u64;
+
+    /// Runtime counters such as the speculative decoding acceptance rate
+    fn metrics(&self) -> EngineMetrics {
+        EngineMetrics::default()
+    }
 }
 
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Id of the embedding model, see [`EmbedderRegistry`]
+    fn model_id(&self) -> &str;
+
+    /// Length of the vectors this embedder produces
+    fn dimension(&self) -> usize;
+
+    /// Embed a text into a vector
+    async fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Embed a search query; models trained with a query instruction apply it here
+    async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed(text).await
+    }
+
+    /// Embed several texts, returning one vector per text in order
+    async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for text in texts {
+            vectors.push(self.embed(text).await?);
+        }
+        Ok(vectors)
+    }
+
+    /// Byte ranges of the tokens `text` splits into, without special tokens
+    async fn token_spans(&self, text: &str) -> Result<Vec<(usize, usize)>>;
+}
+
+#[async_trait]
+pub trait Reranker: Send + Sync {
+    /// Relevance of each passage to `query`, in order; higher is more relevant
+    async fn rerank(&self, query: &str, passages: &[&str]) -> Result<Vec<f32>>;
+}
+
+/// Storage and nearest-neighbour search of embedded documents.
+///
+/// Every backend scores search results with the cosine similarity between the query and
+/// the stored vector, whatever its metric internally: a score lies in `[-1, 1]`, `1`
+/// meaning the same direction and `0` unrelated, and does not depend on vector length.
+/// A relevance threshold therefore means the same thing with every backend.
 #[async_trait]
 pub trait VectorStore: Send + Sync {
     /// Add a vector to the store
     async fn add(&self, id: &str, vector: Vec<f32>) -> Result<()>;
-    async fn add_document(&self, id: &str, text: &str, vector: Vec<f32>) -> Result<()>;
 
-    /// Search for nearest neighbors
-    async fn search(&self, query_vector: Vec<f32>, k: usize) -> Result<Vec<(String, f32)>>;
+    /// Add `text` without metadata, replacing any document stored under `id`
+    async fn add_document(&self, id: &str, text: &str, vector: Vec<f32>) -> Result<()> {
+        self.upsert(Document::new(id, text), vector).await
+    }
+
+    /// Add a document, replacing any document with the same id
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> Result<()>;
+
+    /// Search for nearest neighbors, highest cosine similarity first
+    async fn search(&self, query_vector: Vec<f32>, k: usize) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query_vector, k, &SearchFilter::default())
+            .await
+    }
+
+    /// Search for nearest neighbors among the documents matching `filter`
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>>;
+
+    /// Fetch a document by id
+    async fn get(&self, id: &str) -> Result<Option<Document>>;
+
+    /// Fetch a document by id with its vector
+    async fn get_entry(&self, id: &str) -> Result<Option<(Document, Vec<f32>)>>;
+
+    /// Remove a document; removing a missing id is not an error
+    async fn delete(&self, id: &str) -> Result<()>;
+
+    /// Number of stored documents
+    async fn count(&self) -> Result<usize>;
+
+    /// Every stored document, in no particular order
+    async fn documents(&self) -> Result<Vec<Document>>;
+
+    /// Every stored document with its vector, in no particular order
+    async fn entries(&self) -> Result<Vec<(Document, Vec<f32>)>>;
+
+    /// Changes whenever the store starts answering from a different set of documents,
+    /// as on failover, so anything built from [`VectorStore::documents`] must be rebuilt
+    fn generation(&self) -> u64 {
+        0
+    }
 }
@@ -1,57 +1,144 @@
-use anyhow::{Error as E, Result};
-use candle_core::{Device, Tensor};
+use anyhow::{Context, Error as E, Result};
+use candle_core::{Device, IndexOp, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config, DTYPE};
 use hf_hub::{api::tokio::Api, Repo, RepoType};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokenizers::{PaddingParams, Tokenizer};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
 use tokio::sync::Mutex as AsyncMutex;
 
-use crate::VectorStore;
-use std::convert::TryInto; // For payload conversion
+use crate::document::{CREATED_AT_KEY, SOURCE_KEY, TAGS_KEY};
+use crate::embedding::{Architecture, Pooling, DEFAULT_EMBEDDING_MODEL};
+use crate::hnsw::HnswIndex;
+use crate::nomic_bert::{Config as NomicConfig, NomicBertModel};
+use crate::{
+    Document, Embedder, EmbedderRegistry, EmbeddingModel, SearchFilter, SearchResult, VectorStore,
+};
+
+/// Texts per forward pass in [`BertEmbedder::embed_batch`]; bounds activation memory.
+const EMBED_BATCH_SIZE: usize = 32;
+
+/// The loaded network of an [`EmbeddingModel`].
+enum Encoder {
+    Bert(BertModel),
+    NomicBert(NomicBertModel),
+}
 
-const BERT_REPO: &str = "sentence-transformers/all-MiniLM-L6-v2";
+impl Encoder {
+    fn forward(
+        &self,
+        token_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> candle_core::Result<Tensor> {
+        match self {
+            Self::Bert(model) => model.forward(token_ids, token_type_ids, Some(attention_mask)),
+            Self::NomicBert(model) => model.forward(token_ids, token_type_ids, attention_mask),
+        }
+    }
+}
 
+/// Sentence embedder for the BERT-family models in [`crate::EmbedderRegistry`].
 pub struct BertEmbedder {
-    model: Arc<Mutex<Option<BertModel>>>,
-    tokenizer: Arc<Mutex<Option<Tokenizer>>>,
-    loading: Arc<AsyncMutex<bool>>,
+    spec: EmbeddingModel,
+    // Shared out of the lock so forward passes do not serialize on it
+    model: Arc<Mutex<Option<Arc<Encoder>>>>,
+    tokenizer: Arc<Mutex<Option<Arc<Tokenizer>>>>,
+    /// Held while loading, so concurrent first calls load the model once
+    loading: Arc<AsyncMutex<()>>,
+    device: Device,
 }
 
 impl BertEmbedder {
     pub fn new() -> Self {
+        Self::with_device(crate::default_device())
+    }
+
+    /// An embedder for the default model (all-MiniLM-L6-v2) on `device`.
+    pub fn with_device(device: Device) -> Self {
         Self {
+            spec: EmbedderRegistry::new()
+                .get(DEFAULT_EMBEDDING_MODEL)
+                .expect("default embedding model is built in"),
             model: Arc::new(Mutex::new(None)),
             tokenizer: Arc::new(Mutex::new(None)),
-            loading: Arc::new(AsyncMutex::new(false)),
+            loading: Arc::new(AsyncMutex::new(())),
+            device,
         }
     }
 
-    async fn ensure_loaded(&self) -> Result<()> {
-        {
-            if self.model.lock().unwrap().is_some() {
-                return Ok(());
-            }
-        }
+    /// Embeds with `spec` instead of the default model. Call before the first embedding.
+    pub fn with_model(mut self, spec: EmbeddingModel) -> Self {
+        self.spec = spec;
+        self
+    }
 
-        let mut loading_guard = self.loading.lock().await;
-        if *loading_guard {
-            // In real app wait here
-            return Ok(());
+    pub fn model(&self) -> &EmbeddingModel {
+        &self.spec
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn loaded(&self) -> Option<(Arc<Encoder>, Arc<Tokenizer>)> {
+        let model = self.model.lock().unwrap().clone()?;
+        let tokenizer = self.tokenizer.lock().unwrap().clone()?;
+        Some((model, tokenizer))
+    }
+
+    /// The model and its tokenizer, downloaded and loaded on first use. A failed load is
+    /// retried by the next call.
+    async fn ensure_loaded(&self) -> Result<(Arc<Encoder>, Arc<Tokenizer>)> {
+        if let Some(loaded) = self.loaded() {
+            return Ok(loaded);
+        }
+        let _loading = self.loading.lock().await;
+        if let Some(loaded) = self.loaded() {
+            // Loaded by the caller we waited for
+            return Ok(loaded);
         }
-        *loading_guard = true;
 
-        println!("Downloading Embedding Model (all-MiniLM-L6-v2)...");
+        println!("Downloading Embedding Model ({})...", self.spec.repo);
         let api = Api::new()?;
-        let repo = api.repo(Repo::new(BERT_REPO.to_string(), RepoType::Model));
+        let repo = api.repo(Repo::new(self.spec.repo.clone(), RepoType::Model));
 
         let config_filename = repo.get("config.json").await?;
         let tokenizer_filename = repo.get("tokenizer.json").await?;
         let weights_filename = repo.get("model.safetensors").await?;
 
         let config = std::fs::read_to_string(config_filename)?;
-        let config: Config = serde_json::from_str(&config)?;
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &self.device)?
+        };
+        let (model, hidden_size, max_positions) = match self.spec.architecture {
+            Architecture::Bert => {
+                let config: Config = serde_json::from_str(&config)?;
+                let model = BertModel::load(vb, &config)?;
+                (
+                    Encoder::Bert(model),
+                    config.hidden_size,
+                    config.max_position_embeddings,
+                )
+            }
+            Architecture::NomicBert => {
+                let config: NomicConfig = serde_json::from_str(&config)?;
+                let model = NomicBertModel::load(vb, &config)?;
+                (Encoder::NomicBert(model), config.n_embd, config.n_positions)
+            }
+        };
+        if hidden_size != self.spec.dimension {
+            anyhow::bail!(
+                "Embedding model '{}' produces {}-dimensional vectors, expected {}",
+                self.spec.id,
+                hidden_size,
+                self.spec.dimension
+            );
+        }
 
         let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
         let pp = PaddingParams {
@@ -59,68 +146,318 @@ impl BertEmbedder {
             ..Default::default()
         };
         tokenizer.with_padding(Some(pp));
-
-        let vb = unsafe {
-            VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &Device::Cpu)?
-        };
-        let model = BertModel::load(vb, &config)?;
-
-        {
-            let mut m_guard = self.model.lock().unwrap();
-            *m_guard = Some(model);
-            let mut t_guard = self.tokenizer.lock().unwrap();
-            *t_guard = Some(tokenizer);
+        // Longer inputs would overflow the position embeddings
+        if tokenizer.get_truncation().is_none() {
+            tokenizer
+                .with_truncation(Some(TruncationParams {
+                    max_length: max_positions,
+                    ..Default::default()
+                }))
+                .map_err(E::msg)?;
         }
 
+        let (model, tokenizer) = (Arc::new(model), Arc::new(tokenizer));
+        *self.model.lock().unwrap() = Some(model.clone());
+        *self.tokenizer.lock().unwrap() = Some(tokenizer.clone());
+
         println!("Embedding Model Loaded.");
-        *loading_guard = false;
-        Ok(())
+        Ok((model, tokenizer))
     }
 
+    /// Embeds a text to be stored and searched for.
     pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        self.ensure_loaded().await?;
+        let mut vectors = self.embed_batch(&[text]).await?;
+        Ok(vectors.remove(0))
+    }
+
+    /// Embeds a search query, with the model's query instruction if it has one.
+    pub async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        let mut vectors = self
+            .embed_with_prefix(&[text], &self.spec.query_prefix)
+            .await?;
+        Ok(vectors.remove(0))
+    }
+
+    /// Embeds `texts` to be stored, returning one vector per text in order.
+    ///
+    /// Texts are sorted by length and run through the model `EMBED_BATCH_SIZE` at a
+    /// time, so each forward pass pads as little as possible.
+    pub async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.embed_with_prefix(texts, &self.spec.document_prefix)
+            .await
+    }
 
-        // We hold the lock during inference for simplicity since BertModel isn't Clone
-        let mut guard = self.model.lock().unwrap();
-        let model = guard.as_mut().unwrap();
+    async fn embed_with_prefix(&self, texts: &[&str], prefix: &str) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+        let (model, tokenizer) = self.ensure_loaded().await?;
+        let device = self.device.clone();
+        let pooling = self.spec.pooling;
+        let texts: Vec<String> = texts.iter().map(|t| format!("{}{}", prefix, t)).collect();
+
+        tokio::task::spawn_blocking(move || {
+            let mut order: Vec<usize> = (0..texts.len()).collect();
+            order.sort_by_key(|&i| texts[i].len());
+
+            let mut vectors = vec![vec![]; texts.len()];
+            for batch in order.chunks(EMBED_BATCH_SIZE) {
+                let inputs: Vec<&str> = batch.iter().map(|&i| texts[i].as_str()).collect();
+                let embedded = forward_batch(&model, &tokenizer, &device, pooling, inputs)?;
+                for (&i, vector) in batch.iter().zip(embedded) {
+                    vectors[i] = vector;
+                }
+            }
+            Ok(vectors)
+        })
+        .await?
+    }
+
+    /// Byte ranges of the word pieces of `text`, ignoring the tokenizer's truncation.
+    pub async fn token_spans(&self, text: &str) -> Result<Vec<(usize, usize)>> {
+        let (_, tokenizer) = self.ensure_loaded().await?;
+        let mut tokenizer = (*tokenizer).clone();
+        tokenizer.with_truncation(None).map_err(E::msg)?;
+        let tokens = tokenizer.encode(text, false).map_err(E::msg)?;
+        Ok(tokens.get_offsets().to_vec())
+    }
+}
 
-        let tokenizer_guard = self.tokenizer.lock().unwrap();
-        let tokenizer = tokenizer_guard.as_ref().unwrap();
+#[async_trait::async_trait]
+impl Embedder for BertEmbedder {
+    fn model_id(&self) -> &str {
+        &self.spec.id
+    }
+
+    fn dimension(&self) -> usize {
+        self.spec.dimension
+    }
 
-        let tokens = tokenizer.encode(text, true).map_err(E::msg)?;
-        let token_ids = tokens.get_ids();
-        let token_ids = Tensor::new(token_ids, &Device::Cpu)?.unsqueeze(0)?;
-        let token_type_ids = token_ids.zeros_like()?;
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        BertEmbedder::embed(self, text).await
+    }
 
-        // Calculate embeddings
-        // forward(input_ids, token_type_ids, position_ids)
-        // Check signature: error said arg #3 is Option<&Tensor>
-        let embeddings = model.forward(&token_ids, &token_type_ids, None)?;
+    async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        BertEmbedder::embed_query(self, text).await
+    }
 
-        // Mean pooling
-        let (_n_sentence, n_tokens, _hidden_size) = embeddings.dims3()?;
-        let embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
-        let embeddings = normalize_l2(&embeddings)?;
+    async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        BertEmbedder::embed_batch(self, texts).await
+    }
 
-        let vec = embeddings.get(0)?.to_vec1::<f32>()?;
-        Ok(vec)
+    async fn token_spans(&self, text: &str) -> Result<Vec<(usize, usize)>> {
+        BertEmbedder::token_spans(self, text).await
     }
 }
 
+/// Runs one padded batch through the model and pools each sequence into one vector.
+///
+/// Mean pooling averages over the real tokens only; padding positions are masked out
+/// of both the sum and the count.
+fn forward_batch(
+    model: &Encoder,
+    tokenizer: &Tokenizer,
+    device: &Device,
+    pooling: Pooling,
+    texts: Vec<&str>,
+) -> Result<Vec<Vec<f32>>> {
+    let encodings = tokenizer.encode_batch(texts, true).map_err(E::msg)?;
+    let token_ids = encodings
+        .iter()
+        .map(|e| Tensor::new(e.get_ids(), device))
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    let attention_mask = encodings
+        .iter()
+        .map(|e| Tensor::new(e.get_attention_mask(), device))
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    let token_ids = Tensor::stack(&token_ids, 0)?;
+    let attention_mask = Tensor::stack(&attention_mask, 0)?;
+    let token_type_ids = token_ids.zeros_like()?;
+
+    // (batch, tokens, hidden)
+    let embeddings = model.forward(&token_ids, &token_type_ids, &attention_mask)?;
+
+    let embeddings = match pooling {
+        Pooling::Mean => {
+            let mask = attention_mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+            let summed = embeddings.broadcast_mul(&mask)?.sum(1)?;
+            let counts = mask.sum(1)?;
+            summed.broadcast_div(&counts)?
+        }
+        Pooling::Cls => embeddings.i((.., 0))?,
+    };
+    let embeddings = normalize_l2(&embeddings)?;
+
+    Ok(embeddings.to_vec2::<f32>()?)
+}
+
 fn normalize_l2(v: &Tensor) -> Result<Tensor> {
     let norm = v.sqr()?.sum_keepdim(1)?.sqrt()?;
     Ok(v.broadcast_div(&norm)?)
 }
 
+/// Stores with at most this many candidate vectors are searched exactly.
+const EXACT_SEARCH_LIMIT: usize = 512;
+/// Log entries a persistent store accumulates before it writes a new snapshot, at least.
+const COMPACT_MIN_LOG_ENTRIES: usize = 1024;
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "log.jsonl";
+
+/// A write to a persistent [`SimpleVectorStore`], logged before it is applied.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogEntry {
+    Upsert {
+        document: Document,
+        vector: Vec<f32>,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+/// Where a persistent store keeps its snapshot and write-ahead log.
+struct Storage {
+    dir: PathBuf,
+    log: File,
+    /// Entries in the log since the last snapshot
+    logged: usize,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreState {
+    /// Fixed by the embedding model for persistent stores, else by the first vector
+    dimension: Option<usize>,
+    documents: HashMap<String, Document>,
+    index: HnswIndex,
+    #[serde(skip)]
+    storage: Option<Storage>,
+}
+
+impl StoreState {
+    fn check_dimension(&self, actual: usize) -> Result<()> {
+        match self.dimension {
+            Some(expected) => check_dimension(expected, actual),
+            None => Ok(()),
+        }
+    }
+
+    fn apply(&mut self, entry: LogEntry) {
+        match entry {
+            LogEntry::Upsert { document, vector } => {
+                self.dimension.get_or_insert(vector.len());
+                self.index.insert(&document.id, &vector);
+                self.documents.insert(document.id.clone(), document);
+            }
+            LogEntry::Delete { id } => {
+                self.index.remove(&id);
+                self.documents.remove(&id);
+            }
+        }
+    }
+
+    /// Logs `entry` if the store is persistent, then applies it.
+    fn write(&mut self, entry: LogEntry) -> Result<()> {
+        if let Some(storage) = &mut self.storage {
+            crate::persist::append_json_line(&mut storage.log, &entry)?;
+            storage.logged += 1;
+        }
+        self.apply(entry);
+
+        let logged = self.storage.as_ref().map_or(0, |s| s.logged);
+        if logged >= COMPACT_MIN_LOG_ENTRIES.max(self.documents.len()) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a snapshot and empties the log. Rebuilds the index first if removed
+    /// vectors make up a large part of it.
+    fn compact(&mut self) -> Result<()> {
+        if self.index.tombstones() > self.index.len() / 4 {
+            self.index = self.index.rebuild();
+        }
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        crate::persist::write_json_compact(&storage.dir.join(SNAPSHOT_FILE), self)?;
+        // Replaying a log the snapshot already contains is harmless, so a crash between
+        // the two steps loses nothing
+        let storage = self.storage.as_mut().unwrap();
+        storage.log.set_len(0)?;
+        storage.log.sync_all()?;
+        storage.logged = 0;
+        Ok(())
+    }
+}
+
+/// Embedded vector store searched through an HNSW index.
+///
+/// [`SimpleVectorStore::new`] keeps everything in memory. [`SimpleVectorStore::open`]
+/// persists to a directory: every write is appended to a log before it is applied, and
+/// the log is folded into a snapshot of the documents and index once it grows as large
+/// as the store.
 pub struct SimpleVectorStore {
-    // Map ID -> (Vector, Text)
-    data: Arc<Mutex<HashMap<String, (Vec<f32>, String)>>>,
+    state: Arc<RwLock<StoreState>>,
 }
 
 impl SimpleVectorStore {
     pub fn new() -> Self {
+        Self::with_state(StoreState::default())
+    }
+
+    /// Opens the store for vectors of `model` under `dir`, creating it if needed. Each
+    /// model gets a subdirectory of its own.
+    pub fn open(dir: &Path, model: &EmbeddingModel) -> Result<Self> {
+        let dir = dir.join(model.collection_suffix());
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+        let mut state: StoreState =
+            crate::persist::read_json(&dir.join(SNAPSHOT_FILE))?.unwrap_or_default();
+        if let Some(dimension) = state.dimension {
+            if dimension != model.dimension {
+                anyhow::bail!(
+                    "Vector store {:?} holds {}-dimensional vectors, the embedder produces {}",
+                    dir,
+                    dimension,
+                    model.dimension
+                );
+            }
+        }
+        state.dimension = Some(model.dimension);
+
+        let log_path = dir.join(LOG_FILE);
+        let (entries, _) = crate::persist::read_json_lines::<LogEntry>(&log_path)?;
+        let replayed = entries.len();
+        for entry in entries {
+            state.apply(entry);
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .with_context(|| format!("Failed to open {:?}", log_path))?;
+        state.storage = Some(Storage {
+            dir: dir.clone(),
+            log,
+            logged: replayed,
+        });
+        // Start from an empty log so new entries never follow a torn line
+        if replayed > 0 || log_path.metadata()?.len() > 0 {
+            state.compact()?;
+        }
+
+        tracing::info!(
+            "Opened vector store {:?} with {} documents",
+            dir,
+            state.documents.len()
+        );
+        Ok(Self::with_state(state))
+    }
+
+    fn with_state(state: StoreState) -> Self {
         Self {
-            data: Arc::new(Mutex::new(HashMap::new())),
+            state: Arc::new(RwLock::new(state)),
         }
     }
 }
@@ -131,86 +468,340 @@ impl VectorStore for SimpleVectorStore {
         Err(anyhow::anyhow!("Use add_document instead"))
     }
 
-    async fn add_document(&self, id: &str, text: &str, vector: Vec<f32>) -> Result<()> {
-        let mut data = self.data.lock().unwrap();
-        data.insert(id.to_string(), (vector, text.to_string()));
-        Ok(())
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        // Vectors of another model are not comparable with the stored ones
+        state.check_dimension(vector.len())?;
+        state.write(LogEntry::Upsert { document, vector })
     }
 
-    async fn search(&self, query_vector: Vec<f32>, k: usize) -> Result<Vec<(String, f32)>> {
-        let data = self.data.lock().unwrap();
-        let mut results = vec![];
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        let state = self.state.read().unwrap();
+        state.check_dimension(query_vector.len())?;
+
+        let accept = |id: &str| filter.matches(&state.documents[id].metadata);
+        let candidates = if filter.is_empty() {
+            state.documents.len()
+        } else {
+            state
+                .documents
+                .values()
+                .filter(|d| filter.matches(&d.metadata))
+                .count()
+        };
+        // The graph only pays off when there are many candidates; with a selective
+        // filter it would also have to walk past many rejected nodes
+        let hits = if candidates <= EXACT_SEARCH_LIMIT {
+            state.index.exact_search(&query_vector, k, accept)
+        } else {
+            state.index.search(&query_vector, k, accept)
+        };
+
+        Ok(hits
+            .into_iter()
+            .map(|(id, score)| SearchResult::new(state.documents[&id].clone(), score))
+            .collect())
+    }
 
-        for (_id, (vec, text)) in data.iter() {
-            let similarity = cosine_similarity(&query_vector, vec);
-            results.push((text.clone(), similarity));
+    async fn get(&self, id: &str) -> Result<Option<Document>> {
+        Ok(self.state.read().unwrap().documents.get(id).cloned())
+    }
+
+    async fn get_entry(&self, id: &str) -> Result<Option<(Document, Vec<f32>)>> {
+        let state = self.state.read().unwrap();
+        Ok(state.documents.get(id).and_then(|document| {
+            let vector = state.index.vector(id)?;
+            Some((document.clone(), vector.to_vec()))
+        }))
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if !state.documents.contains_key(id) {
+            return Ok(());
         }
+        state.write(LogEntry::Delete { id: id.to_string() })
+    }
 
-        // Sort by similarity descending
-        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-        results.truncate(k);
-        Ok(results)
+    async fn count(&self) -> Result<usize> {
+        Ok(self.state.read().unwrap().documents.len())
     }
-}
 
-fn cosine_similarity(v1: &[f32], v2: &[f32]) -> f32 {
-    let dot_product: f32 = v1.iter().zip(v2.iter()).map(|(a, b)| a * b).sum();
-    let norm1: f32 = v1.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm2: f32 = v2.iter().map(|x| x * x).sum::<f32>().sqrt();
+    async fn documents(&self) -> Result<Vec<Document>> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .documents
+            .values()
+            .cloned()
+            .collect())
+    }
 
-    if norm1 == 0.0 || norm2 == 0.0 {
-        return 0.0;
+    async fn entries(&self) -> Result<Vec<(Document, Vec<f32>)>> {
+        let state = self.state.read().unwrap();
+        Ok(state
+            .documents
+            .values()
+            .filter_map(|document| {
+                let vector = state.index.vector(&document.id)?;
+                Some((document.clone(), vector.to_vec()))
+            })
+            .collect())
     }
+}
 
-    dot_product / (norm1 * norm2)
+/// Refuses a vector whose length differs from the store's dimension.
+pub(crate) fn check_dimension(expected: usize, actual: usize) -> Result<()> {
+    if expected != actual {
+        anyhow::bail!(
+            "Vector dimension mismatch: the store holds {}-dimensional vectors, got {}",
+            expected,
+            actual
+        );
+    }
+    Ok(())
 }
 
-use qdrant_client::prelude::*;
+use qdrant_client::qdrant::vector_output::Vector as VectorOutputKind;
 use qdrant_client::qdrant::vectors_config::Config as VectorsConfig;
-use qdrant_client::qdrant::with_payload_selector::SelectorOptions;
-use qdrant_client::qdrant::{CreateCollection, PointStruct, SearchPoints, VectorParams};
+use qdrant_client::qdrant::{
+    Condition, CountPointsBuilder, CreateCollectionBuilder, DeletePointsBuilder, Distance, Filter,
+    GetPointsBuilder, PointId, PointStruct, QueryPointsBuilder, Range, RetrievedPoint,
+    ScrollPointsBuilder, UpsertPointsBuilder, Value, VectorParamsBuilder,
+};
+use qdrant_client::{Payload, Qdrant};
+use std::time::Duration;
+
+/// Default collection name prefix; the embedding model and dimension are appended. The
+/// prefix alone names the collection of stores created before collections were split by
+/// embedding model, which holds all-MiniLM-L6-v2 vectors.
+const QDRANT_COLLECTION_PREFIX: &str = "plexus_memory";
+/// Points fetched per request when listing a collection.
+const QDRANT_SCROLL_PAGE: u32 = 256;
+
+/// Where a Qdrant server is and how to reach it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QdrantConfig {
+    /// gRPC endpoint of the server.
+    pub url: String,
+    pub api_key: Option<String>,
+    /// Collection name prefix; the embedding model and dimension are appended.
+    pub collection: String,
+    /// Limit on connecting and on every request.
+    pub timeout_ms: u64,
+}
+
+impl Default for QdrantConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:6334".to_string(),
+            api_key: None,
+            collection: QDRANT_COLLECTION_PREFIX.to_string(),
+            timeout_ms: 2000,
+        }
+    }
+}
+
+impl QdrantConfig {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    pub fn with_collection(mut self, collection: &str) -> Self {
+        self.collection = collection.to_string();
+        self
+    }
+
+    pub fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
 
 pub struct QdrantStore {
-    client: Arc<QdrantClient>,
+    client: Arc<Qdrant>,
     collection_name: String,
+    dimension: usize,
 }
 
 impl QdrantStore {
-    pub async fn new(url: &str) -> Result<Self> {
-        let client = QdrantClient::from_url(url).build()?;
-        let store = Self {
+    /// Opens the collection for vectors of `model` on the server at `url` with the default
+    /// settings, creating it if needed.
+    pub async fn new(url: &str, model: &EmbeddingModel) -> Result<Self> {
+        Self::connect(&QdrantConfig::new(url), model).await
+    }
+
+    /// Opens the collection for vectors of `model`, creating it if needed. Fails if the
+    /// server does not answer within the configured timeout.
+    pub async fn connect(config: &QdrantConfig, model: &EmbeddingModel) -> Result<Self> {
+        let client = Qdrant::from_url(&config.url)
+            .api_key(config.api_key.clone())
+            .timeout(config.timeout())
+            .connect_timeout(config.timeout())
+            .build()?;
+        let mut store = Self {
             client: Arc::new(client),
-            collection_name: "plexus_memory".to_string(),
+            collection_name: format!("{}_{}", config.collection, model.collection_suffix()),
+            dimension: model.dimension,
         };
-        store.init().await?;
+        tokio::time::timeout(config.timeout(), store.init(&config.collection, model))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out connecting to Qdrant at {}", config.url))??;
         Ok(store)
     }
 
-    async fn init(&self) -> Result<()> {
+    /// Whether the server answers a health check.
+    pub async fn is_healthy(&self) -> bool {
+        self.client.health_check().await.is_ok()
+    }
+
+    pub fn collection_name(&self) -> &str {
+        &self.collection_name
+    }
+
+    /// Every point of the collection, page by page.
+    async fn scroll_all(&self, with_vectors: bool) -> Result<Vec<RetrievedPoint>> {
+        let mut points = vec![];
+        let mut offset: Option<PointId> = None;
+        loop {
+            let mut request = ScrollPointsBuilder::new(&self.collection_name)
+                .limit(QDRANT_SCROLL_PAGE)
+                .with_payload(true)
+                .with_vectors(with_vectors);
+            if let Some(offset) = offset {
+                request = request.offset(offset);
+            }
+            let page = self.client.scroll(request).await?;
+            points.extend(page.result);
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(points),
+            }
+        }
+    }
+
+    /// `legacy` is the collection the default model used before collections were split
+    /// by embedding model.
+    async fn init(&mut self, legacy: &str, model: &EmbeddingModel) -> Result<()> {
         let collections = self.client.list_collections().await?;
-        if !collections
-            .collections
-            .iter()
-            .any(|c| c.name == self.collection_name)
-        {
-            println!("Creating Qdrant collection: {}", self.collection_name);
-            self.client
-                .create_collection(&CreateCollection {
-                    collection_name: self.collection_name.clone(),
-                    vectors_config: Some(
-                        VectorParams {
-                            size: 384, // MiniLM-L6-v2 dimension
-                            distance: Distance::Cosine.into(),
-                            ..Default::default()
-                        }
-                        .into(),
-                    ),
-                    ..Default::default()
-                })
-                .await?;
+        let exists = |name: &str| collections.collections.iter().any(|c| c.name == name);
+        if !exists(&self.collection_name) && model.id == DEFAULT_EMBEDDING_MODEL && exists(legacy) {
+            // Keep using the memory of the model it was written with
+            self.collection_name = legacy.to_string();
+        }
+        if exists(&self.collection_name) {
+            return self.check_collection().await;
         }
+
+        println!("Creating Qdrant collection: {}", self.collection_name);
+        self.client
+            .create_collection(
+                CreateCollectionBuilder::new(&self.collection_name).vectors_config(
+                    VectorParamsBuilder::new(self.dimension as u64, Distance::Cosine),
+                ),
+            )
+            .await?;
         Ok(())
     }
+
+    /// Refuses an existing collection whose vectors have another dimension.
+    async fn check_collection(&self) -> Result<()> {
+        let info = self.client.collection_info(&self.collection_name).await?;
+        let config = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config);
+        match config {
+            Some(VectorsConfig::Params(params)) if params.size as usize != self.dimension => {
+                anyhow::bail!(
+                    "Qdrant collection {} holds {}-dimensional vectors, the embedder produces {}",
+                    self.collection_name,
+                    params.size,
+                    self.dimension
+                )
+            }
+            // Scores are only cosine similarities with the cosine metric
+            Some(VectorsConfig::Params(params)) if params.distance != Distance::Cosine as i32 => {
+                anyhow::bail!(
+                    "Qdrant collection {} does not use the cosine distance",
+                    self.collection_name
+                )
+            }
+            Some(VectorsConfig::Params(_)) => Ok(()),
+            _ => anyhow::bail!(
+                "Qdrant collection {} does not have a single unnamed vector",
+                self.collection_name
+            ),
+        }
+    }
+}
+
+/// Qdrant point ids must be unsigned integers or UUIDs, so string ids are mapped to a
+/// UUID derived from their hash. The original id is kept in the payload.
+fn point_id(id: &str) -> PointId {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(id.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_custom_bytes(bytes)
+        .into_uuid()
+        .to_string()
+        .into()
+}
+
+/// Conditions on the `metadata` payload field equivalent to `filter`.
+fn qdrant_filter(filter: &SearchFilter) -> Option<Filter> {
+    if filter.is_empty() {
+        return None;
+    }
+    let mut conditions = vec![];
+    if let Some(source) = &filter.source {
+        conditions.push(Condition::matches(
+            format!("metadata.{}", SOURCE_KEY),
+            source.clone(),
+        ));
+    }
+    for tag in &filter.tags {
+        conditions.push(Condition::matches(
+            format!("metadata.{}", TAGS_KEY),
+            tag.clone(),
+        ));
+    }
+    if filter.created_after.is_some() || filter.created_before.is_some() {
+        conditions.push(Condition::range(
+            format!("metadata.{}", CREATED_AT_KEY),
+            Range {
+                gte: filter.created_after.map(|t| t as f64),
+                lte: filter.created_before.map(|t| t as f64),
+                ..Default::default()
+            },
+        ));
+    }
+    Some(Filter::must(conditions))
+}
+
+fn payload_document(payload: HashMap<String, Value>) -> Result<Document> {
+    Ok(serde_json::from_value(serde_json::to_value(payload)?)?)
 }
 
 #[async_trait::async_trait]
@@ -219,41 +810,124 @@ impl VectorStore for QdrantStore {
         Err(anyhow::anyhow!("Use add_document instead"))
     }
 
-    async fn add_document(&self, id: &str, text: &str, vector: Vec<f32>) -> Result<()> {
-        let payload: std::collections::HashMap<String, qdrant_client::qdrant::Value> =
-            serde_json::from_value(serde_json::json!({
-                "text": text
-            }))?;
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> Result<()> {
+        check_dimension(self.dimension, vector.len())?;
+        let payload = Payload::try_from(serde_json::to_value(&document)?)?;
 
-        let point = PointStruct::new(id.to_string(), vector, payload);
+        let point = PointStruct::new(point_id(&document.id), vector, payload);
 
         self.client
-            .upsert_points(self.collection_name.clone(), None, vec![point], None)
+            .upsert_points(UpsertPointsBuilder::new(&self.collection_name, vec![point]))
             .await?;
         Ok(())
     }
 
-    async fn search(&self, query_vector: Vec<f32>, k: usize) -> Result<Vec<(String, f32)>> {
-        let search_result = self
-            .client
-            .search_points(&SearchPoints {
-                collection_name: self.collection_name.clone(),
-                vector: query_vector,
-                limit: k as u64,
-                with_payload: Some(SelectorOptions::Enable(true).into()),
-                ..Default::default()
-            })
-            .await?;
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        check_dimension(self.dimension, query_vector.len())?;
+        let mut request = QueryPointsBuilder::new(&self.collection_name)
+            .query(query_vector)
+            .limit(k as u64)
+            .with_payload(true);
+        if let Some(filter) = qdrant_filter(filter) {
+            request = request.filter(filter);
+        }
+        let search_result = self.client.query(request).await?;
 
         let mut results = vec![];
         for point in search_result.result {
-            // point.payload is a HashMap<String, Value> in newer versions
-            let payload = point.payload;
-            if let Some(json_val) = payload.get("text") {
-                let text = format!("{}", json_val);
-                results.push((text, point.score));
+            match payload_document(point.payload) {
+                Ok(document) => results.push(SearchResult::new(document, point.score)),
+                // Points written before documents carried ids and metadata
+                Err(e) => tracing::warn!("Skipping Qdrant point with invalid payload: {}", e),
             }
         }
         Ok(results)
     }
+
+    async fn get(&self, id: &str) -> Result<Option<Document>> {
+        let response = self
+            .client
+            .get_points(
+                GetPointsBuilder::new(&self.collection_name, vec![point_id(id)])
+                    .with_vectors(false)
+                    .with_payload(true),
+            )
+            .await?;
+        response
+            .result
+            .into_iter()
+            .next()
+            .map(|point| payload_document(point.payload))
+            .transpose()
+    }
+
+    async fn get_entry(&self, id: &str) -> Result<Option<(Document, Vec<f32>)>> {
+        let response = self
+            .client
+            .get_points(
+                GetPointsBuilder::new(&self.collection_name, vec![point_id(id)])
+                    .with_vectors(true)
+                    .with_payload(true),
+            )
+            .await?;
+        let Some(point) = response.result.into_iter().next() else {
+            return Ok(None);
+        };
+        let vector = match point.vectors.as_ref().and_then(|v| v.get_vector()) {
+            Some(VectorOutputKind::Dense(dense)) => dense.data,
+            _ => anyhow::bail!("Qdrant point '{}' has no dense vector", id),
+        };
+        Ok(Some((payload_document(point.payload)?, vector)))
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.client
+            .delete_points(
+                DeletePointsBuilder::new(&self.collection_name).points(vec![point_id(id)]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize> {
+        let response = self
+            .client
+            .count(CountPointsBuilder::new(&self.collection_name).exact(true))
+            .await?;
+        Ok(response.result.map(|r| r.count as usize).unwrap_or(0))
+    }
+
+    async fn documents(&self) -> Result<Vec<Document>> {
+        let mut documents = vec![];
+        for point in self.scroll_all(false).await? {
+            match payload_document(point.payload) {
+                Ok(document) => documents.push(document),
+                Err(e) => tracing::warn!("Skipping Qdrant point with invalid payload: {}", e),
+            }
+        }
+        Ok(documents)
+    }
+
+    async fn entries(&self) -> Result<Vec<(Document, Vec<f32>)>> {
+        let mut entries = vec![];
+        for point in self.scroll_all(true).await? {
+            let vector = match point.vectors.as_ref().and_then(|v| v.get_vector()) {
+                Some(VectorOutputKind::Dense(dense)) => dense.data,
+                _ => {
+                    tracing::warn!("Skipping Qdrant point without a dense vector");
+                    continue;
+                }
+            };
+            match payload_document(point.payload) {
+                Ok(document) => entries.push((document, vector)),
+                Err(e) => tracing::warn!("Skipping Qdrant point with invalid payload: {}", e),
+            }
+        }
+        Ok(entries)
+    }
 }
@@ -0,0 +1,199 @@
+//! NomicBert, the encoder of nomic-embed-text.
+//!
+//! It differs from BERT in using rotary instead of learned position embeddings, a
+//! SwiGLU feed-forward layer and no biases in the attention and MLP projections.
+//! Layers are post-norm like BERT's.
+
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor};
+use candle_nn::{embedding, layer_norm, linear_no_bias, Embedding, LayerNorm, Linear, VarBuilder};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub vocab_size: usize,
+    pub n_embd: usize,
+    pub n_head: usize,
+    pub n_layer: usize,
+    pub n_inner: usize,
+    pub n_positions: usize,
+    #[serde(default = "default_type_vocab_size")]
+    pub type_vocab_size: usize,
+    #[serde(default = "default_layer_norm_epsilon")]
+    pub layer_norm_epsilon: f64,
+    #[serde(default = "default_rotary_emb_base")]
+    pub rotary_emb_base: f64,
+}
+
+fn default_type_vocab_size() -> usize {
+    2
+}
+
+fn default_layer_norm_epsilon() -> f64 {
+    1e-12
+}
+
+fn default_rotary_emb_base() -> f64 {
+    1000.0
+}
+
+struct Attention {
+    wqkv: Linear,
+    out_proj: Linear,
+    n_head: usize,
+    head_dim: usize,
+}
+
+impl Attention {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Ok(Self {
+            wqkv: linear_no_bias(config.n_embd, 3 * config.n_embd, vb.pp("Wqkv"))?,
+            out_proj: linear_no_bias(config.n_embd, config.n_embd, vb.pp("out_proj"))?,
+            n_head: config.n_head,
+            head_dim: config.n_embd / config.n_head,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, mask: &Tensor, cos: &Tensor, sin: &Tensor) -> Result<Tensor> {
+        let (b, t, _) = xs.dims3()?;
+        let qkv = self
+            .wqkv
+            .forward(xs)?
+            .reshape((b, t, 3, self.n_head, self.head_dim))?;
+        // (batch, heads, tokens, head_dim) each
+        let head = |i: usize| qkv.i((.., .., i))?.transpose(1, 2)?.contiguous();
+        let q = candle_nn::rotary_emb::rope(&head(0)?, cos, sin)?;
+        let k = candle_nn::rotary_emb::rope(&head(1)?, cos, sin)?;
+        let v = head(2)?;
+
+        let scores = (q.matmul(&k.t()?)? / (self.head_dim as f64).sqrt())?;
+        let scores = scores.broadcast_add(mask)?;
+        let probs = candle_nn::ops::softmax_last_dim(&scores)?;
+        let context =
+            probs
+                .matmul(&v)?
+                .transpose(1, 2)?
+                .reshape((b, t, self.n_head * self.head_dim))?;
+        self.out_proj.forward(&context)
+    }
+}
+
+struct GatedMlp {
+    fc11: Linear,
+    fc12: Linear,
+    fc2: Linear,
+}
+
+impl GatedMlp {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Ok(Self {
+            fc11: linear_no_bias(config.n_embd, config.n_inner, vb.pp("fc11"))?,
+            fc12: linear_no_bias(config.n_embd, config.n_inner, vb.pp("fc12"))?,
+            fc2: linear_no_bias(config.n_inner, config.n_embd, vb.pp("fc2"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let gate = candle_nn::ops::silu(&self.fc12.forward(xs)?)?;
+        self.fc2.forward(&(self.fc11.forward(xs)? * gate)?)
+    }
+}
+
+struct Block {
+    attn: Attention,
+    mlp: GatedMlp,
+    norm1: LayerNorm,
+    norm2: LayerNorm,
+}
+
+impl Block {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Ok(Self {
+            attn: Attention::load(vb.pp("attn"), config)?,
+            mlp: GatedMlp::load(vb.pp("mlp"), config)?,
+            norm1: layer_norm(config.n_embd, config.layer_norm_epsilon, vb.pp("norm1"))?,
+            norm2: layer_norm(config.n_embd, config.layer_norm_epsilon, vb.pp("norm2"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, mask: &Tensor, cos: &Tensor, sin: &Tensor) -> Result<Tensor> {
+        let xs = self
+            .norm1
+            .forward(&(self.attn.forward(xs, mask, cos, sin)? + xs)?)?;
+        self.norm2.forward(&(self.mlp.forward(&xs)? + &xs)?)
+    }
+}
+
+pub struct NomicBertModel {
+    word_embeddings: Embedding,
+    token_type_embeddings: Embedding,
+    emb_ln: LayerNorm,
+    layers: Vec<Block>,
+    head_dim: usize,
+    rotary_emb_base: f64,
+}
+
+impl NomicBertModel {
+    pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        // Some exports nest the weights under the model's base prefix
+        let vb = if vb.contains_tensor("embeddings.word_embeddings.weight") {
+            vb
+        } else {
+            vb.pp("bert")
+        };
+        let layers = (0..config.n_layer)
+            .map(|i| Block::load(vb.pp(format!("encoder.layers.{}", i)), config))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            word_embeddings: embedding(
+                config.vocab_size,
+                config.n_embd,
+                vb.pp("embeddings.word_embeddings"),
+            )?,
+            token_type_embeddings: embedding(
+                config.type_vocab_size,
+                config.n_embd,
+                vb.pp("embeddings.token_type_embeddings"),
+            )?,
+            emb_ln: layer_norm(config.n_embd, config.layer_norm_epsilon, vb.pp("emb_ln"))?,
+            layers,
+            head_dim: config.n_embd / config.n_head,
+            rotary_emb_base: config.rotary_emb_base,
+        })
+    }
+
+    /// Token embeddings `(batch, tokens, n_embd)`; `attention_mask` is 1 for real tokens.
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> Result<Tensor> {
+        let xs = (self.word_embeddings.forward(input_ids)?
+            + self.token_type_embeddings.forward(token_type_ids)?)?;
+        let mut xs = self.emb_ln.forward(&xs)?;
+
+        let dtype = xs.dtype();
+        let (cos, sin) = self.rotary(input_ids.dim(1)?, dtype, xs.device())?;
+        // (batch, 1, 1, tokens): 0 for real tokens, a large negative value for padding
+        let mask = attention_mask.unsqueeze(1)?.unsqueeze(1)?.to_dtype(dtype)?;
+        let mask = ((mask.ones_like()? - &mask)? * f32::MIN as f64)?;
+
+        for layer in &self.layers {
+            xs = layer.forward(&xs, &mask, &cos, &sin)?;
+        }
+        Ok(xs)
+    }
+
+    fn rotary(&self, seq_len: usize, dtype: DType, device: &Device) -> Result<(Tensor, Tensor)> {
+        let inv_freq: Vec<f32> = (0..self.head_dim)
+            .step_by(2)
+            .map(|i| 1.0 / self.rotary_emb_base.powf(i as f64 / self.head_dim as f64) as f32)
+            .collect();
+        let inv_freq = Tensor::new(inv_freq.as_slice(), device)?;
+        let positions = Tensor::arange(0u32, seq_len as u32, device)?.to_dtype(DType::F32)?;
+        let freqs = positions
+            .unsqueeze(1)?
+            .broadcast_mul(&inv_freq.unsqueeze(0)?)?;
+        Ok((freqs.cos()?.to_dtype(dtype)?, freqs.sin()?.to_dtype(dtype)?))
+    }
+}
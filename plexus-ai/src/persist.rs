@@ -0,0 +1,199 @@
+//! Crash-safe JSON files.
+//!
+//! A file is replaced by writing `<file>.tmp`, syncing it to disk and renaming it over
+//! the original, which is kept as `<file>.bak`. A crash at any point leaves a complete
+//! copy in one of the three, and [`read_json`] picks the newest one that parses.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Atomically replaces `path` with `value` as pretty-printed JSON.
+pub(crate) fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    write_with(path, |file| Ok(serde_json::to_writer_pretty(file, value)?))
+}
+
+/// Like [`write_json`] without whitespace, for large files such as vector snapshots.
+pub(crate) fn write_json_compact<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    write_with(path, |file| {
+        let mut writer = std::io::BufWriter::new(file);
+        serde_json::to_writer(&mut writer, value)?;
+        Ok(writer.flush()?)
+    })
+}
+
+fn write_with(path: &Path, write: impl FnOnce(&mut File) -> Result<()>) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    write(file.file())?;
+    file.commit()
+}
+
+/// A replacement for `path` written to `<file>.tmp`, for writers that cannot run inside
+/// [`write_json`]'s closure. It only replaces `path` on [`AtomicFile::commit`]; dropped
+/// uncommitted, it is deleted.
+pub(crate) struct AtomicFile {
+    file: File,
+    path: PathBuf,
+    tmp: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let tmp = sibling(path, "tmp");
+        let file = File::create(&tmp).with_context(|| format!("Failed to create {:?}", tmp))?;
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            tmp,
+            committed: false,
+        })
+    }
+
+    pub(crate) fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Syncs the file to disk and moves it over `path`, which is kept as `<file>.bak`.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file
+            .sync_all()
+            .with_context(|| format!("Failed to sync {:?}", self.tmp))?;
+        if self.path.exists() {
+            std::fs::rename(&self.path, sibling(&self.path, "bak"))
+                .with_context(|| format!("Failed to back up {:?}", self.path))?;
+        }
+        std::fs::rename(&self.tmp, &self.path)
+            .with_context(|| format!("Failed to replace {:?}", self.path))?;
+        self.committed = true;
+        sync_parent(&self.path)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// Reads the last complete version of `path`, or `None` if it was never written.
+///
+/// Candidates are tried newest first: the file itself, a temp file whose rename was
+/// interrupted, then the backup. Unreadable candidates are moved aside to
+/// `<file>.corrupt` so they are not lost. Fails only if every candidate is corrupt.
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let mut found = false;
+    for candidate in [
+        path.to_path_buf(),
+        sibling(path, "tmp"),
+        sibling(path, "bak"),
+    ] {
+        if !candidate.exists() {
+            continue;
+        }
+        found = true;
+        match read_file(&candidate) {
+            Ok(value) => {
+                if candidate != path {
+                    tracing::warn!("Recovered {:?} from {:?}", path, candidate);
+                }
+                return Ok(Some(value));
+            }
+            Err(e) => {
+                let aside = sibling(&candidate, "corrupt");
+                tracing::warn!(
+                    "{:?} is corrupt ({}), moving it to {:?}",
+                    candidate,
+                    e,
+                    aside
+                );
+                std::fs::rename(&candidate, &aside)?;
+            }
+        }
+    }
+    if found {
+        anyhow::bail!("No readable copy of {:?} left", path);
+    }
+    Ok(None)
+}
+
+/// Appends `value` as one JSON line to `log` and syncs it to disk.
+pub(crate) fn append_json_line<T: Serialize>(log: &mut File, value: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    log.write_all(&line)?;
+    log.sync_data()?;
+    Ok(())
+}
+
+/// Reads the JSON lines of `path` up to the first incomplete or unreadable one, which a
+/// crash in the middle of [`append_json_line`] can leave behind. Returns the values and
+/// whether the file was cut short.
+pub(crate) fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Result<(Vec<T>, bool)> {
+    if !path.exists() {
+        return Ok((vec![], false));
+    }
+    let content = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let mut values = vec![];
+    for line in content.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+        match serde_json::from_slice(line) {
+            Ok(value) => values.push(value),
+            Err(e) => {
+                tracing::warn!("Ignoring the rest of {:?} after a torn line: {}", path, e);
+                return Ok((values, true));
+            }
+        }
+    }
+    Ok((values, false))
+}
+
+/// Removes `path` together with its temp and backup files.
+pub(crate) fn remove(path: &Path) -> Result<()> {
+    for file in [
+        path.to_path_buf(),
+        sibling(path, "tmp"),
+        sibling(path, "bak"),
+    ] {
+        if file.exists() {
+            std::fs::remove_file(&file).with_context(|| format!("Failed to delete {:?}", file))?;
+        }
+    }
+    Ok(())
+}
+
+/// The file `candidate` is a temp or backup copy of, or `candidate` itself.
+pub(crate) fn persisted_path(candidate: &Path) -> PathBuf {
+    match candidate.extension().and_then(|e| e.to_str()) {
+        Some("tmp" | "bak") => candidate.with_extension(""),
+        _ => candidate.to_path_buf(),
+    }
+}
+
+fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Makes the renames durable; directories cannot be opened for syncing on Windows.
+fn sync_parent(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+/// A conversation's cached model state at one or more token boundaries.
+struct CachedSession<S> {
+    /// `(tokens, state)` pairs where `state` has processed exactly `tokens`.
+    checkpoints: Vec<(Vec<u32>, S)>,
+    last_used: Instant,
+}
+
+/// Cache of per-conversation model states (e.g. KV caches) keyed by token prefix.
+///
+/// Consecutive chat turns re-send the whole transcript, so the previous turn's tokens
+/// are a prefix of the next prompt. [`PrefixCache::take`] finds the longest cached
+/// prefix so the caller only has to prefill the new suffix.
+///
+/// Sessions are taken out of the cache while in use and re-inserted afterwards, so two
+/// concurrent requests never share mutable state. Sessions idle for longer than
+/// `idle_ttl` are dropped, and at most `max_sessions` are kept (least recently used
+/// are evicted first).
+pub struct PrefixCache<S> {
+    sessions: Vec<CachedSession<S>>,
+    max_sessions: usize,
+    idle_ttl: Duration,
+}
+
+impl<S> PrefixCache<S> {
+    pub fn new(max_sessions: usize, idle_ttl: Duration) -> Self {
+        Self {
+            sessions: Vec::new(),
+            max_sessions,
+            idle_ttl,
+        }
+    }
+
+    /// Removes and returns the state with the longest token prefix of `tokens`.
+    ///
+    /// Only proper prefixes match: at least one token must remain to be fed so the
+    /// caller gets fresh logits. Returns the number of matched tokens with the state.
+    pub fn take(&mut self, tokens: &[u32]) -> Option<(usize, S)> {
+        self.evict_idle();
+
+        let mut best: Option<(usize, usize, usize)> = None; // (session, checkpoint, len)
+        for (si, session) in self.sessions.iter().enumerate() {
+            for (ci, (prefix, _)) in session.checkpoints.iter().enumerate() {
+                let len = prefix.len();
+                let is_better = best.is_none_or(|(_, _, best_len)| len > best_len);
+                if len > 0 && len < tokens.len() && tokens.starts_with(prefix) && is_better {
+                    best = Some((si, ci, len));
+                }
+            }
+        }
+
+        let (si, ci, len) = best?;
+        let mut session = self.sessions.swap_remove(si);
+        let (_, state) = session.checkpoints.swap_remove(ci);
+        Some((len, state))
+    }
+
+    /// Stores a conversation's checkpoints, evicting idle and least recently used sessions.
+    pub fn insert(&mut self, checkpoints: Vec<(Vec<u32>, S)>) {
+        if checkpoints.is_empty() || self.max_sessions == 0 {
+            return;
+        }
+        self.evict_idle();
+        while self.sessions.len() >= self.max_sessions {
+            let lru = self
+                .sessions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(i, _)| i);
+            match lru {
+                Some(i) => {
+                    self.sessions.swap_remove(i);
+                }
+                None => break,
+            }
+        }
+        self.sessions.push(CachedSession {
+            checkpoints,
+            last_used: Instant::now(),
+        });
+    }
+
+    /// Drops sessions that have not been used within the idle TTL.
+    pub fn evict_idle(&mut self) {
+        let ttl = self.idle_ttl;
+        self.sessions.retain(|s| s.last_used.elapsed() <= ttl);
+    }
+
+    /// Number of cached sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+}
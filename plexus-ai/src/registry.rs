@@ -0,0 +1,300 @@
+use anyhow::Result;
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::speculative::{SpeculativeConfig, SpeculativeMetrics};
+use crate::{LLMEngine, ModelSource, TinyLlamaEngine, TINYLLAMA};
+
+/// A model this node can serve.
+struct SupportedModel {
+    id: &'static str,
+    source: ModelSource,
+    /// Size of the weights (the GGUF file)
+    bytes: u64,
+}
+
+/// Models this node can serve; all are llama-architecture GGUF files.
+const SUPPORTED_MODELS: &[SupportedModel] = &[
+    SupportedModel {
+        id: "tinyllama",
+        source: TINYLLAMA,
+        bytes: 668_788_096,
+    },
+    SupportedModel {
+        id: "zephyr",
+        source: ModelSource {
+            repo: "TheBloke/zephyr-7B-beta-GGUF",
+            file: "zephyr-7b-beta.Q4_K_M.gguf",
+            tokenizer_repo: "HuggingFaceH4/zephyr-7b-beta",
+            sha256: None,
+        },
+        bytes: 4_368_438_944,
+    },
+];
+
+fn supported_model(model_id: &str) -> Result<&'static SupportedModel> {
+    SUPPORTED_MODELS
+        .iter()
+        .find(|model| model.id == model_id)
+        .ok_or_else(|| {
+            let supported: Vec<&str> = SUPPORTED_MODELS.iter().map(|model| model.id).collect();
+            anyhow::anyhow!(
+                "Unknown model '{}' (supported: {})",
+                model_id,
+                supported.join(", ")
+            )
+        })
+}
+
+/// Memory a model's weights are expected to take once loaded, before any draft model.
+///
+/// Fails for identifiers no engine can serve.
+pub fn expected_footprint(model_id: &str) -> Result<u64> {
+    supported_model(model_id).map(|model| model.bytes)
+}
+
+/// Creates the engine implementation for a model identifier, running on `device`.
+///
+/// With `speculative` the engine decodes with the given draft model.
+pub fn create_engine(
+    model_id: &str,
+    device: &Device,
+    speculative: Option<&SpeculativeConfig>,
+) -> Result<Arc<dyn LLMEngine>> {
+    let model = supported_model(model_id)?;
+    let engine = TinyLlamaEngine::with_device(device.clone()).with_source(model.source);
+    Ok(match speculative {
+        Some(config) => Arc::new(engine.with_speculative(config.clone())),
+        None => Arc::new(engine),
+    })
+}
+
+/// Runtime counters reported by an engine.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct EngineMetrics {
+    /// Draft acceptance, when the engine decodes speculatively
+    #[serde(default)]
+    pub speculative: Option<SpeculativeMetrics>,
+}
+
+/// A resident model as reported by [`ModelRegistry::list`] and in heartbeats.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ModelInfo {
+    pub id: String,
+    pub memory_bytes: u64,
+    /// Unix timestamp of the last time the model served a request
+    pub last_used: u64,
+    #[serde(default)]
+    pub metrics: EngineMetrics,
+}
+
+struct ResidentModel {
+    engine: Arc<dyn LLMEngine>,
+    memory_bytes: u64,
+    last_used: u64,
+    /// Monotonic use counter; the smallest value is the least recently used model.
+    tick: u64,
+}
+
+/// Keeps several engines resident at once within a memory budget.
+///
+/// When loading a model would exceed the budget, the least recently used models are
+/// unloaded first. The default model is only evicted if nothing else is left.
+pub struct ModelRegistry {
+    models: HashMap<String, ResidentModel>,
+    memory_budget: u64,
+    default_model: String,
+    tick: u64,
+    /// Device new engines are created on.
+    device: Device,
+    /// Draft models for speculative decoding, by target model id.
+    speculative: HashMap<String, SpeculativeConfig>,
+}
+
+impl ModelRegistry {
+    pub fn new(default_model: &str, memory_budget: u64) -> Self {
+        Self {
+            models: HashMap::new(),
+            memory_budget,
+            default_model: default_model.to_string(),
+            tick: 0,
+            device: crate::default_device(),
+            speculative: HashMap::new(),
+        }
+    }
+
+    /// Creates engines on `device` instead of the default device.
+    pub fn with_device(mut self, device: Device) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Decodes `model_id` speculatively with the draft model in `config`.
+    ///
+    /// Takes effect the next time the model is loaded.
+    pub fn with_speculative(mut self, model_id: &str, config: SpeculativeConfig) -> Self {
+        self.speculative.insert(model_id.to_string(), config);
+        self
+    }
+
+    pub fn default_model(&self) -> &str {
+        &self.default_model
+    }
+
+    pub fn memory_budget(&self) -> u64 {
+        self.memory_budget
+    }
+
+    /// Total bytes currently held by resident models.
+    pub fn memory_used(&self) -> u64 {
+        self.models.values().map(|m| m.memory_bytes).sum()
+    }
+
+    pub fn is_resident(&self, model_id: &str) -> bool {
+        self.models.contains_key(model_id)
+    }
+
+    /// Creates, loads and registers a model. Returns the ids of evicted models.
+    ///
+    /// Loading an already resident model only refreshes its LRU position. Resident models
+    /// are only evicted once the new one has loaded, so a failed load leaves them in place.
+    pub async fn load(&mut self, model_id: &str) -> Result<Vec<String>> {
+        if self.is_resident(model_id) {
+            self.touch(model_id);
+            return Ok(vec![]);
+        }
+
+        let engine = self.prepare(model_id)?;
+        tracing::info!("Loading model '{}'...", model_id);
+        engine.load_model(model_id).await?;
+        self.insert(model_id, engine)
+    }
+
+    /// Creates the engine for `model_id` without loading or registering it.
+    ///
+    /// Fails if the model's expected footprint alone is larger than the budget. Load the
+    /// engine with [`LLMEngine::load_model`] (e.g. off the caller's event loop) and
+    /// register it with [`ModelRegistry::insert`].
+    pub fn prepare(&self, model_id: &str) -> Result<Arc<dyn LLMEngine>> {
+        let engine = create_engine(model_id, &self.device, self.speculative.get(model_id))?;
+        self.check_budget(model_id, expected_footprint(model_id)?)?;
+        Ok(engine)
+    }
+
+    /// Registers an already loaded engine, evicting LRU models until it fits the budget.
+    ///
+    /// Fails (and drops `engine`) if the model alone is larger than the budget.
+    pub fn insert(&mut self, model_id: &str, engine: Arc<dyn LLMEngine>) -> Result<Vec<String>> {
+        let memory_bytes = engine.memory_footprint();
+        self.check_budget(model_id, memory_bytes)?;
+
+        // Replacing an entry frees its memory first
+        self.models.remove(model_id);
+        let evicted = self.make_room(memory_bytes);
+
+        self.tick += 1;
+        self.models.insert(
+            model_id.to_string(),
+            ResidentModel {
+                engine,
+                memory_bytes,
+                last_used: unix_now(),
+                tick: self.tick,
+            },
+        );
+        Ok(evicted)
+    }
+
+    /// Unloads a model. Its weights are freed once in-flight requests drop their handle.
+    pub fn unload(&mut self, model_id: &str) -> Result<()> {
+        self.models
+            .remove(model_id)
+            .map(|_| ())
+            .ok_or_else(|| anyhow::anyhow!("Model '{}' is not loaded", model_id))
+    }
+
+    /// Returns the engine for `model_id` (or the default model) and marks it as used.
+    pub fn get(&mut self, model_id: Option<&str>) -> Option<Arc<dyn LLMEngine>> {
+        let model_id = model_id.unwrap_or(&self.default_model).to_string();
+        self.touch(&model_id);
+        self.models.get(&model_id).map(|m| m.engine.clone())
+    }
+
+    /// Like [`ModelRegistry::get`], but loads the model on demand when it is not resident.
+    ///
+    /// Only for requests the node's own user makes; peers get [`ModelRegistry::get`].
+    pub async fn get_or_load(&mut self, model_id: Option<&str>) -> Result<Arc<dyn LLMEngine>> {
+        let model_id = model_id.unwrap_or(&self.default_model).to_string();
+        if !self.is_resident(&model_id) {
+            self.load(&model_id).await?;
+        }
+        self.get(Some(&model_id))
+            .ok_or_else(|| anyhow::anyhow!("Model '{}' is not loaded", model_id))
+    }
+
+    /// Resident models, most recently used first.
+    pub fn list(&self) -> Vec<ModelInfo> {
+        let mut models: Vec<(&String, &ResidentModel)> = self.models.iter().collect();
+        models.sort_by_key(|(_, m)| std::cmp::Reverse(m.tick));
+        models
+            .into_iter()
+            .map(|(id, m)| ModelInfo {
+                id: id.clone(),
+                memory_bytes: m.memory_bytes,
+                last_used: m.last_used,
+                metrics: m.engine.metrics(),
+            })
+            .collect()
+    }
+
+    fn check_budget(&self, model_id: &str, memory_bytes: u64) -> Result<()> {
+        if memory_bytes > self.memory_budget {
+            anyhow::bail!(
+                "Model '{}' needs {} MB but the memory budget is {} MB",
+                model_id,
+                memory_bytes / 1024 / 1024,
+                self.memory_budget / 1024 / 1024
+            );
+        }
+        Ok(())
+    }
+
+    /// Evicts LRU models until `memory_bytes` more fit the budget. Returns their ids.
+    fn make_room(&mut self, memory_bytes: u64) -> Vec<String> {
+        let mut evicted = vec![];
+        while self.memory_used() + memory_bytes > self.memory_budget {
+            let Some(victim) = self.lru_candidate() else {
+                break;
+            };
+            tracing::info!("Evicting model '{}' to stay within memory budget", victim);
+            self.models.remove(&victim);
+            evicted.push(victim);
+        }
+        evicted
+    }
+
+    fn touch(&mut self, model_id: &str) {
+        if let Some(model) = self.models.get_mut(model_id) {
+            self.tick += 1;
+            model.tick = self.tick;
+            model.last_used = unix_now();
+        }
+    }
+
+    /// Least recently used model, preferring anything over the default model.
+    fn lru_candidate(&self) -> Option<String> {
+        self.models
+            .iter()
+            .min_by_key(|(id, m)| (**id == self.default_model, m.tick))
+            .map(|(id, _)| id.clone())
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
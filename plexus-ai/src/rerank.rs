@@ -0,0 +1,188 @@
+//! Cross-encoder reranking.
+//!
+//! A cross-encoder reads the query and a passage together and scores how well the passage
+//! answers the query. It is too slow to run over a whole store, but much more precise than
+//! comparing embeddings, so it is used to reorder the few candidates retrieval returns.
+
+use anyhow::{Error as E, Result};
+use candle_core::{Device, IndexOp, Module, Tensor};
+use candle_nn::{linear, Linear, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::{api::tokio::Api, Repo, RepoType};
+use std::sync::{Arc, Mutex};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::Reranker;
+
+/// MS MARCO passage-ranking cross-encoder, small enough to run on CPU.
+pub const DEFAULT_RERANK_MODEL: &str = "cross-encoder/ms-marco-MiniLM-L-6-v2";
+
+/// Query/passage pairs per forward pass.
+const RERANK_BATCH_SIZE: usize = 16;
+
+/// BERT with the pooler and single-logit classifier of `BertForSequenceClassification`.
+struct CrossEncoder {
+    bert: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+}
+
+impl CrossEncoder {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Ok(Self {
+            bert: BertModel::load(vb.pp("bert"), config)?,
+            pooler: linear(
+                config.hidden_size,
+                config.hidden_size,
+                vb.pp("bert.pooler.dense"),
+            )?,
+            classifier: linear(config.hidden_size, 1, vb.pp("classifier"))?,
+        })
+    }
+
+    /// Relevance logits `(batch,)`.
+    fn forward(
+        &self,
+        token_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> candle_core::Result<Tensor> {
+        let hidden = self
+            .bert
+            .forward(token_ids, token_type_ids, Some(attention_mask))?;
+        let pooled = self.pooler.forward(&hidden.i((.., 0))?)?.tanh()?;
+        self.classifier.forward(&pooled)?.squeeze(1)
+    }
+}
+
+/// Reranks with a BERT cross-encoder from the Hugging Face Hub.
+pub struct CrossEncoderReranker {
+    repo: String,
+    model: Arc<Mutex<Option<Arc<CrossEncoder>>>>,
+    tokenizer: Arc<Mutex<Option<Arc<Tokenizer>>>>,
+    /// Held while loading, so concurrent first calls load the model once
+    loading: Arc<AsyncMutex<()>>,
+    device: Device,
+}
+
+impl Default for CrossEncoderReranker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrossEncoderReranker {
+    pub fn new() -> Self {
+        Self::with_device(crate::default_device())
+    }
+
+    /// A reranker for [`DEFAULT_RERANK_MODEL`] on `device`.
+    pub fn with_device(device: Device) -> Self {
+        Self {
+            repo: DEFAULT_RERANK_MODEL.to_string(),
+            model: Arc::new(Mutex::new(None)),
+            tokenizer: Arc::new(Mutex::new(None)),
+            loading: Arc::new(AsyncMutex::new(())),
+            device,
+        }
+    }
+
+    /// Uses another single-logit BERT cross-encoder. Call before the first rerank.
+    pub fn with_repo(mut self, repo: &str) -> Self {
+        self.repo = repo.to_string();
+        self
+    }
+
+    fn loaded(&self) -> Option<(Arc<CrossEncoder>, Arc<Tokenizer>)> {
+        let model = self.model.lock().unwrap().clone()?;
+        let tokenizer = self.tokenizer.lock().unwrap().clone()?;
+        Some((model, tokenizer))
+    }
+
+    /// The model and its tokenizer, downloaded and loaded on first use. A failed load is
+    /// retried by the next call.
+    async fn ensure_loaded(&self) -> Result<(Arc<CrossEncoder>, Arc<Tokenizer>)> {
+        if let Some(loaded) = self.loaded() {
+            return Ok(loaded);
+        }
+        let _loading = self.loading.lock().await;
+        if let Some(loaded) = self.loaded() {
+            return Ok(loaded);
+        }
+
+        tracing::info!("Downloading reranker model ({})", self.repo);
+        let api = Api::new()?;
+        let repo = api.repo(Repo::new(self.repo.clone(), RepoType::Model));
+
+        let config_filename = repo.get("config.json").await?;
+        let tokenizer_filename = repo.get("tokenizer.json").await?;
+        let weights_filename = repo.get("model.safetensors").await?;
+
+        let config: Config = serde_json::from_str(&std::fs::read_to_string(config_filename)?)?;
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &self.device)?
+        };
+        let model = CrossEncoder::load(vb, &config)?;
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+        // Long passages are cut rather than overflowing the position embeddings
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+
+        let (model, tokenizer) = (Arc::new(model), Arc::new(tokenizer));
+        *self.model.lock().unwrap() = Some(model.clone());
+        *self.tokenizer.lock().unwrap() = Some(tokenizer.clone());
+
+        tracing::info!("Reranker model loaded");
+        Ok((model, tokenizer))
+    }
+}
+
+#[async_trait::async_trait]
+impl Reranker for CrossEncoderReranker {
+    async fn rerank(&self, query: &str, passages: &[&str]) -> Result<Vec<f32>> {
+        if passages.is_empty() {
+            return Ok(vec![]);
+        }
+        let (model, tokenizer) = self.ensure_loaded().await?;
+        let device = self.device.clone();
+        let pairs: Vec<(String, String)> = passages
+            .iter()
+            .map(|p| (query.to_string(), p.to_string()))
+            .collect();
+
+        tokio::task::spawn_blocking(move || {
+            let mut scores = Vec::with_capacity(pairs.len());
+            for batch in pairs.chunks(RERANK_BATCH_SIZE) {
+                let encodings = tokenizer
+                    .encode_batch(batch.to_vec(), true)
+                    .map_err(E::msg)?;
+                let stack = |field: fn(&tokenizers::Encoding) -> &[u32]| {
+                    let rows = encodings
+                        .iter()
+                        .map(|e| Tensor::new(field(e), &device))
+                        .collect::<candle_core::Result<Vec<_>>>()?;
+                    Tensor::stack(&rows, 0)
+                };
+                let token_ids = stack(|e| e.get_ids())?;
+                let token_type_ids = stack(|e| e.get_type_ids())?;
+                let attention_mask = stack(|e| e.get_attention_mask())?;
+
+                let logits = model.forward(&token_ids, &token_type_ids, &attention_mask)?;
+                let probabilities = candle_nn::ops::sigmoid(&logits)?;
+                scores.extend(probabilities.to_vec1::<f32>()?);
+            }
+            Ok(scores)
+        })
+        .await?
+    }
+}
@@ -0,0 +1,465 @@
+//! Hybrid retrieval for retrieval-augmented generation.
+//!
+//! Vector search finds passages that mean the same as the query but misses exact tokens
+//! such as error codes and ticket ids, which a lexical BM25 index finds reliably. The
+//! [`HybridRetriever`] runs both, merges the two rankings with reciprocal rank fusion and
+//! can rerank the merged candidates with a cross-encoder.
+
+use crate::{Document, Embedder, Reranker, SearchFilter, SearchResult, VectorStore};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// BM25 term-frequency saturation.
+const BM25_K1: f32 = 1.2;
+/// BM25 document-length normalization.
+const BM25_B: f32 = 0.75;
+/// Query words too common to say anything about relevance.
+const STOPWORDS: &[&str] = &[
+    "a", "about", "an", "and", "are", "as", "at", "be", "by", "can", "do", "does", "for", "from",
+    "has", "have", "how", "i", "in", "is", "it", "me", "my", "of", "on", "or", "that", "the",
+    "there", "this", "to", "was", "we", "what", "when", "where", "which", "who", "why", "will",
+    "with", "you", "your",
+];
+
+/// Splits text into lowercase terms.
+///
+/// Identifiers joined by `-`, `_` or `.` (`ERR-4012`, `user_id`) are kept whole as well
+/// as split into their parts, so a query for the full id matches it exactly.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = vec![];
+    for word in text
+        .split(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .map(|w| w.trim_matches(|c| matches!(c, '-' | '_' | '.')))
+        .filter(|w| !w.is_empty())
+    {
+        let word = word.to_lowercase();
+        let parts: Vec<&str> = word
+            .split(['-', '_', '.'])
+            .filter(|p| !p.is_empty())
+            .collect();
+        if parts.len() > 1 {
+            terms.extend(parts.iter().map(|p| p.to_string()));
+        }
+        terms.push(word);
+    }
+    terms
+}
+
+/// In-memory Okapi BM25 index over documents.
+#[derive(Default)]
+pub struct Bm25Index {
+    /// Id -> (document, length in terms)
+    documents: HashMap<String, (Document, usize)>,
+    /// Term -> id -> occurrences
+    postings: HashMap<String, HashMap<String, usize>>,
+    total_terms: usize,
+}
+
+impl Bm25Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes `document`, replacing any document with the same id.
+    pub fn insert(&mut self, document: Document) {
+        self.remove(&document.id);
+
+        let terms = tokenize(&document.text);
+        for term in &terms {
+            *self
+                .postings
+                .entry(term.clone())
+                .or_default()
+                .entry(document.id.clone())
+                .or_default() += 1;
+        }
+        self.total_terms += terms.len();
+        self.documents
+            .insert(document.id.clone(), (document, terms.len()));
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some((document, length)) = self.documents.remove(id) else {
+            return;
+        };
+        self.total_terms -= length;
+        for term in tokenize(&document.text) {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// The `k` documents matching `filter` with the highest BM25 score for `query`, best
+    /// first. Documents sharing no term but stopwords with the query are not returned.
+    pub fn search(&self, query: &str, k: usize, filter: &SearchFilter) -> Vec<SearchResult> {
+        self.search_terms(&query_terms(query), k, filter)
+    }
+
+    /// Like [`Bm25Index::search`], but only documents matching a selective query term
+    /// count as hits: a term that looks like an identifier (it has a digit or is joined
+    /// by `-`, `_` or `.`), or one found in at most `max_fraction` of the documents.
+    pub fn search_selective(
+        &self,
+        query: &str,
+        k: usize,
+        filter: &SearchFilter,
+        max_fraction: f32,
+    ) -> Vec<SearchResult> {
+        let max_df = (max_fraction * self.documents.len() as f32).max(1.0);
+        let terms: Vec<String> = query_terms(query)
+            .into_iter()
+            .filter(|term| {
+                let identifier = term
+                    .chars()
+                    .any(|c| c.is_ascii_digit() || matches!(c, '-' | '_' | '.'));
+                let df = self.postings.get(term).map_or(0, |p| p.len());
+                identifier || df as f32 <= max_df
+            })
+            .collect();
+        self.search_terms(&terms, k, filter)
+    }
+
+    fn search_terms(
+        &self,
+        query_terms: &[String],
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Vec<SearchResult> {
+        if self.documents.is_empty() {
+            return vec![];
+        }
+        let count = self.documents.len() as f32;
+        let average_length = self.total_terms as f32 / count;
+
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for term in query_terms {
+            let Some(posting) = self.postings.get(term) else {
+                continue;
+            };
+            let df = posting.len() as f32;
+            let idf = (1.0 + (count - df + 0.5) / (df + 0.5)).ln();
+            for (id, &tf) in posting {
+                let length = self.documents[id].1 as f32;
+                let tf = tf as f32;
+                let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * length / average_length.max(1.0));
+                *scores.entry(id.as_str()).or_default() += idf * tf * (BM25_K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut results: Vec<SearchResult> = scores
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let document = &self.documents[id].0;
+                filter
+                    .matches(&document.metadata)
+                    .then(|| SearchResult::new(document.clone(), score))
+            })
+            .collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+        results.truncate(k);
+        results
+    }
+}
+
+/// The distinct terms of `query` that are not stopwords.
+fn query_terms(query: &str) -> Vec<String> {
+    let mut terms = tokenize(query);
+    terms.retain(|term| !STOPWORDS.contains(&term.as_str()));
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+/// Merges rankings with reciprocal rank fusion: a document scores `1 / (k + rank)` in
+/// every ranking it appears in (ranks start at 1), summed. Best first.
+///
+/// Only ranks are used, so rankings with incomparable scores such as cosine similarity
+/// and BM25 can be merged. A larger `k` flattens the advantage of the top ranks.
+pub fn reciprocal_rank_fusion(rankings: &[Vec<SearchResult>], k: f32) -> Vec<SearchResult> {
+    let mut fused: Vec<SearchResult> = vec![];
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for ranking in rankings {
+        for (rank, result) in ranking.iter().enumerate() {
+            let score = 1.0 / (k + rank as f32 + 1.0);
+            match positions.get(&result.id) {
+                Some(&i) => fused[i].score += score,
+                None => {
+                    positions.insert(result.id.clone(), fused.len());
+                    fused.push(SearchResult {
+                        score,
+                        ..result.clone()
+                    });
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused
+}
+
+/// How many passages retrieval returns and how relevant they must be.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetrievalConfig {
+    /// Passages returned.
+    pub top_k: usize,
+    /// Candidates taken from each of the vector and lexical searches before fusion.
+    pub candidates: usize,
+    /// Constant of reciprocal rank fusion.
+    pub rrf_k: f32,
+    /// Minimum relevance of a returned passage. Without a reranker this is the cosine
+    /// similarity of vector hits; with one it is the cross-encoder score.
+    pub threshold: f32,
+    /// Without a reranker, keyword hits must match an identifier-like query term or one
+    /// found in at most this fraction of the documents; common words alone do not count.
+    #[serde(default = "default_lexical_rarity")]
+    pub lexical_rarity: f32,
+}
+
+fn default_lexical_rarity() -> f32 {
+    0.2
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            top_k: 3,
+            candidates: 20,
+            rrf_k: 60.0,
+            threshold: 0.4,
+            lexical_rarity: default_lexical_rarity(),
+        }
+    }
+}
+
+impl RetrievalConfig {
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn with_candidates(mut self, candidates: usize) -> Self {
+        self.candidates = candidates;
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_lexical_rarity(mut self, lexical_rarity: f32) -> Self {
+        self.lexical_rarity = lexical_rarity;
+        self
+    }
+}
+
+/// Vector store with a BM25 index kept alongside it, searched by both.
+///
+/// Writes go through to the store and the index. The index is built from the store's
+/// documents on first use, so documents written by earlier runs are found as well.
+pub struct HybridRetriever {
+    embedder: Arc<dyn Embedder>,
+    store: Arc<dyn VectorStore>,
+    /// The lexical index and the store generation it was built from.
+    lexical: Arc<Mutex<Option<(u64, Bm25Index)>>>,
+    /// Writes made through the retriever, counted while holding `lexical`, so that a
+    /// build can tell that a write it may have missed raced it.
+    writes: Arc<AtomicU64>,
+    reranker: Option<Arc<dyn Reranker>>,
+    config: RetrievalConfig,
+}
+
+impl HybridRetriever {
+    pub fn new(embedder: Arc<dyn Embedder>, store: Arc<dyn VectorStore>) -> Self {
+        Self {
+            embedder,
+            store,
+            lexical: Arc::new(Mutex::new(None)),
+            writes: Arc::new(AtomicU64::new(0)),
+            reranker: None,
+            config: RetrievalConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: RetrievalConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Reorders the fused candidates by `reranker`'s score.
+    pub fn with_reranker(mut self, reranker: Arc<dyn Reranker>) -> Self {
+        self.reranker = Some(reranker);
+        self
+    }
+
+    /// The store the retriever writes through to.
+    pub fn store(&self) -> Arc<dyn VectorStore> {
+        self.store.clone()
+    }
+
+    pub fn config(&self) -> &RetrievalConfig {
+        &self.config
+    }
+
+    pub fn reranker(&self) -> Option<Arc<dyn Reranker>> {
+        self.reranker.clone()
+    }
+
+    async fn ensure_indexed(&self) -> Result<()> {
+        let generation = self.store.generation();
+        let current = |lexical: &Option<(u64, Bm25Index)>| {
+            lexical
+                .as_ref()
+                .is_some_and(|(built, _)| *built == generation)
+        };
+        loop {
+            let writes = self.writes.load(Ordering::SeqCst);
+            if current(&self.lexical.lock().unwrap()) {
+                return Ok(());
+            }
+            let documents = self.store.documents().await?;
+            let mut lexical = self.lexical.lock().unwrap();
+            if current(&lexical) {
+                return Ok(());
+            }
+            // A write that finished meanwhile skipped the missing index and may not be
+            // in `documents`; writes after this point update the installed index
+            if self.writes.load(Ordering::SeqCst) != writes {
+                continue;
+            }
+            let mut index = Bm25Index::new();
+            for document in documents {
+                index.insert(document);
+            }
+            tracing::info!("Built lexical index over {} documents", index.len());
+            *lexical = Some((generation, index));
+            return Ok(());
+        }
+    }
+
+    /// The passages most relevant to `query`, best first.
+    pub async fn retrieve(&self, query: &str) -> Result<Vec<SearchResult>> {
+        self.retrieve_filtered(query, &SearchFilter::default())
+            .await
+    }
+
+    /// The passages matching `filter` most relevant to `query`, best first.
+    ///
+    /// Scores are the cross-encoder's when reranking and fused ranks otherwise.
+    pub async fn retrieve_filtered(
+        &self,
+        query: &str,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        self.ensure_indexed().await?;
+        let config = &self.config;
+
+        let query_vector = self.embedder.embed_query(query).await?;
+        let mut semantic = self
+            .store
+            .search_filtered(query_vector, config.candidates, filter)
+            .await?;
+        if self.reranker.is_none() {
+            semantic.retain(|r| r.score >= config.threshold);
+        }
+        let lexical = {
+            let index = self.lexical.lock().unwrap();
+            let (_, index) = index.as_ref().expect("index built above");
+            match self.reranker {
+                Some(_) => index.search(query, config.candidates, filter),
+                None => {
+                    index.search_selective(query, config.candidates, filter, config.lexical_rarity)
+                }
+            }
+        };
+
+        let mut results = reciprocal_rank_fusion(&[semantic, lexical], config.rrf_k);
+        if let Some(reranker) = &self.reranker {
+            results.truncate(config.candidates);
+            let passages: Vec<&str> = results.iter().map(|r| r.text.as_str()).collect();
+            let scores = reranker.rerank(query, &passages).await?;
+            for (result, score) in results.iter_mut().zip(scores) {
+                result.score = score;
+            }
+            results.retain(|r| r.score >= config.threshold);
+            results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+        results.truncate(config.top_k);
+        Ok(results)
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for HybridRetriever {
+    async fn add(&self, id: &str, vector: Vec<f32>) -> Result<()> {
+        self.store.add(id, vector).await
+    }
+
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> Result<()> {
+        self.store.upsert(document.clone(), vector).await?;
+        let mut lexical = self.lexical.lock().unwrap();
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        if let Some((_, index)) = lexical.as_mut() {
+            index.insert(document);
+        }
+        Ok(())
+    }
+
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        self.store.search_filtered(query_vector, k, filter).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Document>> {
+        self.store.get(id).await
+    }
+
+    async fn get_entry(&self, id: &str) -> Result<Option<(Document, Vec<f32>)>> {
+        self.store.get_entry(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.store.delete(id).await?;
+        let mut lexical = self.lexical.lock().unwrap();
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        if let Some((_, index)) = lexical.as_mut() {
+            index.remove(id);
+        }
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize> {
+        self.store.count().await
+    }
+
+    async fn documents(&self) -> Result<Vec<Document>> {
+        self.store.documents().await
+    }
+
+    async fn entries(&self) -> Result<Vec<(Document, Vec<f32>)>> {
+        self.store.entries().await
+    }
+
+    fn generation(&self) -> u64 {
+        self.store.generation()
+    }
+}
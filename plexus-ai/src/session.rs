@@ -0,0 +1,246 @@
+use crate::chat::{ChatHistory, ContextPolicy};
+use crate::persist;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Session used by requests that do not name one.
+pub const DEFAULT_SESSION_ID: &str = "default";
+
+/// Message cap of a session's history; the context window usually trims it first.
+const SESSION_MAX_MESSAGES: usize = 100;
+
+/// Summary of a session for listings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub name: String,
+    /// Unix timestamps (seconds).
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub message_count: usize,
+    pub system_prompt: Option<String>,
+}
+
+/// A named conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub history: ChatHistory,
+}
+
+impl Session {
+    fn new(id: String, name: &str) -> Self {
+        let now = unix_now();
+        Self {
+            id,
+            name: name.to_string(),
+            created_at: now,
+            updated_at: now,
+            history: ChatHistory::new(SESSION_MAX_MESSAGES),
+        }
+    }
+
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            message_count: self.history.len(),
+            system_prompt: self.history.system_prompt().map(str::to_string),
+        }
+    }
+}
+
+/// Named chat sessions, persisted as one JSON file per session in a directory.
+///
+/// Sessions can be created, renamed, forked, deleted and exported/imported as JSON.
+/// Each session has its own system prompt, kept by its history.
+pub struct SessionStore {
+    dir: PathBuf,
+    sessions: HashMap<String, Session>,
+    policy: ContextPolicy,
+}
+
+impl SessionStore {
+    /// Opens the store in `dir`, creating the directory if needed.
+    ///
+    /// Sessions interrupted mid-save are recovered from their temp or backup file.
+    /// Sessions with no readable copy left are skipped with a warning.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create session directory {:?}", dir))?;
+
+        let mut paths = BTreeSet::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = persist::persisted_path(&entry?.path());
+            if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                paths.insert(path);
+            }
+        }
+        let mut sessions = HashMap::new();
+        for path in paths {
+            match persist::read_json::<Session>(&path) {
+                Ok(Some(session)) => {
+                    sessions.insert(session.id.clone(), session);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Skipping unreadable session {:?}: {}", path, e),
+            }
+        }
+        tracing::info!("Loaded {} chat sessions from {:?}", sessions.len(), dir);
+
+        Ok(Self {
+            dir,
+            sessions,
+            policy: ContextPolicy::default(),
+        })
+    }
+
+    /// Applies `policy` to every session, current and future.
+    pub fn with_context_policy(mut self, policy: ContextPolicy) -> Self {
+        for session in self.sessions.values_mut() {
+            session.history.set_context_policy(policy.clone());
+        }
+        self.policy = policy;
+        self
+    }
+
+    /// All sessions, most recently used first.
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self.sessions.values().map(Session::info).collect();
+        sessions.sort_by(|a, b| {
+            b.updated_at
+                .cmp(&a.updated_at)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        sessions
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Session> {
+        self.sessions.get(id)
+    }
+
+    pub fn create(&mut self, name: &str, system_prompt: Option<String>) -> Result<SessionInfo> {
+        let mut session = Session::new(uuid::Uuid::new_v4().to_string(), name);
+        if let Some(prompt) = system_prompt {
+            session.history.set_system_prompt(prompt);
+        }
+        self.insert(session)
+    }
+
+    /// The history of session `id`, creating an empty session under that id if it is
+    /// [`DEFAULT_SESSION_ID`].
+    ///
+    /// Call [`SessionStore::save`] after changing it.
+    pub fn history_mut(&mut self, id: &str) -> Result<&mut ChatHistory> {
+        if id == DEFAULT_SESSION_ID && !self.sessions.contains_key(id) {
+            self.insert(Session::new(DEFAULT_SESSION_ID.to_string(), "Default"))?;
+        }
+        Ok(&mut self.session_mut(id)?.history)
+    }
+
+    /// Persists session `id` and marks it as just used.
+    pub fn save(&mut self, id: &str) -> Result<()> {
+        let session = self.session_mut(id)?;
+        session.updated_at = unix_now();
+        let session = self.session(id)?;
+        self.write(session)
+    }
+
+    pub fn rename(&mut self, id: &str, name: &str) -> Result<()> {
+        self.session_mut(id)?.name = name.to_string();
+        self.save(id)
+    }
+
+    /// Replaces the system prompt of session `id` without clearing its conversation.
+    pub fn set_system_prompt(&mut self, id: &str, prompt: String) -> Result<()> {
+        self.history_mut(id)?.set_system_prompt(prompt);
+        self.save(id)
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<()> {
+        self.sessions
+            .remove(id)
+            .with_context(|| format!("Session '{}' not found", id))?;
+        persist::remove(&self.path(id))
+    }
+
+    /// Copies session `id`, conversation and system prompt included, into a new session.
+    pub fn fork(&mut self, id: &str, name: &str) -> Result<SessionInfo> {
+        let source = self.session(id)?;
+        let mut fork = Session::new(uuid::Uuid::new_v4().to_string(), name);
+        fork.history = source.history.clone();
+        self.insert(fork)
+    }
+
+    /// Session `id` as JSON, for [`SessionStore::import`].
+    pub fn export(&self, id: &str) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self.session(id)?)?)
+    }
+
+    /// Adds an exported session. It gets a new id, so importing twice yields two copies.
+    pub fn import(&mut self, json: &str) -> Result<SessionInfo> {
+        let mut session: Session = serde_json::from_str(json).context("Invalid session export")?;
+        session.id = uuid::Uuid::new_v4().to_string();
+        session.updated_at = unix_now();
+        self.insert(session)
+    }
+
+    /// Imports a history file from before sessions existed as the default session.
+    ///
+    /// The file is renamed to `*.migrated` afterwards, so a deleted default session does
+    /// not come back. Does nothing if the file is missing or the default session exists.
+    pub fn migrate_legacy_history(&mut self, path: &Path) -> Result<()> {
+        if !path.exists() || self.sessions.contains_key(DEFAULT_SESSION_ID) {
+            return Ok(());
+        }
+        let mut session = Session::new(DEFAULT_SESSION_ID.to_string(), "Default");
+        session.history = ChatHistory::load_from_file(path)?;
+        self.insert(session)?;
+        std::fs::rename(path, path.with_extension("json.migrated"))?;
+        tracing::info!("Migrated {:?} into the default chat session", path);
+        Ok(())
+    }
+
+    fn insert(&mut self, mut session: Session) -> Result<SessionInfo> {
+        session.history.set_context_policy(self.policy.clone());
+        self.write(&session)?;
+        let info = session.info();
+        self.sessions.insert(session.id.clone(), session);
+        Ok(info)
+    }
+
+    fn session(&self, id: &str) -> Result<&Session> {
+        self.sessions
+            .get(id)
+            .with_context(|| format!("Session '{}' not found", id))
+    }
+
+    fn session_mut(&mut self, id: &str) -> Result<&mut Session> {
+        self.sessions
+            .get_mut(id)
+            .with_context(|| format!("Session '{}' not found", id))
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn write(&self, session: &Session) -> Result<()> {
+        persist::write_json(&self.path(&session.id), session)
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::batching::{BatchedLlama, KvCache};
+
+/// Tokens the draft model proposes per step unless configured otherwise.
+pub const DEFAULT_DRAFT_TOKENS: usize = 4;
+
+/// The draft model used to speed up a target model.
+///
+/// The draft must be a llama-architecture GGUF that shares the target's tokenizer,
+/// e.g. a much smaller model of the same family.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeculativeConfig {
+    /// HuggingFace repository holding the draft GGUF.
+    pub draft_repo: String,
+    /// GGUF file name within `draft_repo`.
+    pub draft_file: String,
+    /// Tokens proposed per step; more pays off when the draft agrees often.
+    #[serde(default = "default_draft_tokens")]
+    pub draft_tokens: usize,
+}
+
+fn default_draft_tokens() -> usize {
+    DEFAULT_DRAFT_TOKENS
+}
+
+impl SpeculativeConfig {
+    pub fn new(draft_repo: &str, draft_file: &str) -> Self {
+        Self {
+            draft_repo: draft_repo.to_string(),
+            draft_file: draft_file.to_string(),
+            draft_tokens: DEFAULT_DRAFT_TOKENS,
+        }
+    }
+
+    pub fn with_draft_tokens(mut self, draft_tokens: usize) -> Self {
+        self.draft_tokens = draft_tokens;
+        self
+    }
+}
+
+impl std::str::FromStr for SpeculativeConfig {
+    type Err = anyhow::Error;
+
+    /// Parses `<repo>/<file>.gguf[:<draft tokens>]`, e.g.
+    /// `TheBloke/TinyLlama-1.1B-Chat-v1.0-GGUF/tinyllama-1.1b-chat-v1.0.Q2_K.gguf:4`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (path, draft_tokens) = match s.rsplit_once(':') {
+            Some((path, k)) => {
+                let k = k
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid draft token count '{}'", k))?;
+                (path, k)
+            }
+            None => (s, DEFAULT_DRAFT_TOKENS),
+        };
+        let (repo, file) = path
+            .rsplit_once('/')
+            .filter(|(repo, file)| repo.contains('/') && !file.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Expected <owner>/<repo>/<file>.gguf for the draft model, got '{}'",
+                    s
+                )
+            })?;
+        Ok(Self::new(repo, file).with_draft_tokens(draft_tokens))
+    }
+}
+
+/// Acceptance counters of a speculative decoder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpeculativeMetrics {
+    /// Draft tokens checked by the target model.
+    pub proposed: u64,
+    /// Draft tokens the target model agreed with.
+    pub accepted: u64,
+    /// `accepted / proposed`, or 0 before the first step.
+    pub acceptance_rate: f64,
+}
+
+/// Counters shared between the scheduler thread and the engine reporting them.
+#[derive(Debug, Default)]
+pub struct SpeculativeStats {
+    proposed: AtomicU64,
+    accepted: AtomicU64,
+}
+
+impl SpeculativeStats {
+    pub fn snapshot(&self) -> SpeculativeMetrics {
+        let proposed = self.proposed.load(Ordering::Relaxed);
+        let accepted = self.accepted.load(Ordering::Relaxed);
+        SpeculativeMetrics {
+            proposed,
+            accepted,
+            acceptance_rate: if proposed == 0 {
+                0.0
+            } else {
+                accepted as f64 / proposed as f64
+            },
+        }
+    }
+}
+
+/// A small model that proposes tokens for a [`crate::BatchScheduler`] to verify.
+///
+/// Proposals are greedy. A proposal is accepted only if sampling the target model yields
+/// the same token, so the output still follows the target model's distribution.
+pub struct DraftModel {
+    model: BatchedLlama,
+    draft_tokens: usize,
+    stats: Arc<SpeculativeStats>,
+}
+
+impl DraftModel {
+    pub fn new(model: BatchedLlama, draft_tokens: usize) -> Self {
+        Self {
+            model,
+            draft_tokens,
+            stats: Arc::new(SpeculativeStats::default()),
+        }
+    }
+
+    /// Tokens proposed per decode step.
+    pub fn draft_tokens(&self) -> usize {
+        self.draft_tokens
+    }
+
+    /// Live acceptance counters, updated after every step.
+    pub fn stats(&self) -> Arc<SpeculativeStats> {
+        self.stats.clone()
+    }
+
+    pub(crate) fn vocab_size(&self) -> usize {
+        self.model.vocab_size()
+    }
+
+    pub(crate) fn max_seq_len(&self) -> usize {
+        self.model.max_seq_len()
+    }
+
+    pub(crate) fn new_cache(&self) -> KvCache {
+        self.model.new_cache()
+    }
+
+    pub(crate) fn record(&self, proposed: usize, accepted: usize) {
+        self.stats
+            .proposed
+            .fetch_add(proposed as u64, Ordering::Relaxed);
+        self.stats
+            .accepted
+            .fetch_add(accepted as u64, Ordering::Relaxed);
+    }
+
+    /// Proposes `draft_tokens` tokens for each sequence.
+    ///
+    /// `pending[i]` holds the tokens of sequence `i` that are not in `caches[i]` yet,
+    /// ending with its last sampled token. The last proposal is not fed to the cache.
+    pub(crate) fn propose(
+        &self,
+        pending: &[Vec<u32>],
+        caches: &mut [&mut KvCache],
+    ) -> Result<Vec<Vec<u32>>> {
+        let mut proposals: Vec<Vec<u32>> = Vec::with_capacity(pending.len());
+        for (tokens, cache) in pending.iter().zip(caches.iter_mut()) {
+            let logits = self.model.prefill(tokens, cache)?;
+            proposals.push(vec![logits.argmax(0)?.to_scalar::<u32>()?]);
+        }
+        for _ in 1..self.draft_tokens {
+            let last: Vec<u32> = proposals.iter().map(|p| p[p.len() - 1]).collect();
+            let logits = self.model.decode(&last, caches)?;
+            let next = logits.argmax(1)?.to_vec1::<u32>()?;
+            for (proposal, token) in proposals.iter_mut().zip(next) {
+                proposal.push(token);
+            }
+        }
+        Ok(proposals)
+    }
+}
@@ -0,0 +1,406 @@
+use anyhow::{Context, Result};
+use candle_core::quantized::gguf_file;
+use minijinja::{context, Environment, Error as JinjaError, ErrorKind};
+use serde::Serialize;
+use tokenizers::Tokenizer;
+
+use crate::chat::{ChatMessage, Role};
+use crate::tools::{self, ToolCallFormat, ToolChoice, ToolDefinition};
+
+const CHATML_TEMPLATE: &str = "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+
+const LLAMA3_TEMPLATE: &str = "{{ bos_token }}{% for message in messages %}<|start_header_id|>{{ message.role }}<|end_header_id|>\n\n{{ message.content | trim }}<|eot_id|>{% endfor %}{% if add_generation_prompt %}<|start_header_id|>assistant<|end_header_id|>\n\n{% endif %}";
+
+const PHI3_TEMPLATE: &str = "{% for message in messages %}<|{{ message.role }}|>\n{{ message.content }}<|end|>\n{% endfor %}{% if add_generation_prompt %}<|assistant|>\n{% endif %}";
+
+// Mistral has no system role: system messages are folded into the first user turn.
+const MISTRAL_TEMPLATE: &str = "{{ bos_token }}{% set system = messages | selectattr('role', 'eq', 'system') | map(attribute='content') | join('\n\n') %}{% set ns = namespace(first=true) %}{% for message in messages %}{% if message.role == 'user' %}{% if ns.first and system %}[INST] {{ system }}\n\n{{ message.content }} [/INST]{% else %}[INST] {{ message.content }} [/INST]{% endif %}{% set ns.first = false %}{% elif message.role == 'assistant' %}{{ message.content }}{{ eos_token }}{% endif %}{% endfor %}";
+
+const ZEPHYR_TEMPLATE: &str = "{% for message in messages %}<|{{ message.role }}|>\n{{ message.content }}{{ eos_token }}\n{% endfor %}{% if add_generation_prompt %}<|assistant|>\n{% endif %}";
+
+/// The prompt format expected by a model family.
+///
+/// `Jinja` holds a raw `chat_template` as shipped in `tokenizer_config.json` or
+/// in the `tokenizer.chat_template` GGUF metadata key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatTemplate {
+    ChatMl,
+    Llama3,
+    Phi3,
+    Mistral,
+    Zephyr,
+    Jinja(String),
+}
+
+impl ChatTemplate {
+    /// Picks a built-in template from a model name or architecture string.
+    ///
+    /// Falls back to ChatML, which most recent instruction-tuned models understand.
+    pub fn infer_from_name(name: &str) -> Self {
+        let name = name.to_lowercase();
+        if name.contains("llama-3") || name.contains("llama3") {
+            ChatTemplate::Llama3
+        } else if name.contains("phi-3") || name.contains("phi3") {
+            ChatTemplate::Phi3
+        } else if name.contains("mistral") || name.contains("mixtral") {
+            ChatTemplate::Mistral
+        } else if name.contains("tinyllama") || name.contains("zephyr") {
+            ChatTemplate::Zephyr
+        } else {
+            ChatTemplate::ChatMl
+        }
+    }
+
+    fn source(&self) -> &str {
+        match self {
+            ChatTemplate::ChatMl => CHATML_TEMPLATE,
+            ChatTemplate::Llama3 => LLAMA3_TEMPLATE,
+            ChatTemplate::Phi3 => PHI3_TEMPLATE,
+            ChatTemplate::Mistral => MISTRAL_TEMPLATE,
+            ChatTemplate::Zephyr => ZEPHYR_TEMPLATE,
+            ChatTemplate::Jinja(source) => source,
+        }
+    }
+
+    fn default_special_tokens(&self) -> (&'static str, &'static str) {
+        match self {
+            ChatTemplate::ChatMl => ("", "<|im_end|>"),
+            ChatTemplate::Llama3 => ("<|begin_of_text|>", "<|eot_id|>"),
+            ChatTemplate::Phi3 => ("<s>", "<|endoftext|>"),
+            ChatTemplate::Mistral | ChatTemplate::Zephyr | ChatTemplate::Jinja(_) => {
+                ("<s>", "</s>")
+            }
+        }
+    }
+
+    fn default_stop_tokens(&self) -> &'static [&'static str] {
+        match self {
+            ChatTemplate::ChatMl => &["<|im_end|>"],
+            ChatTemplate::Llama3 => &["<|eot_id|>", "<|end_of_text|>"],
+            ChatTemplate::Phi3 => &["<|end|>", "<|endoftext|>"],
+            ChatTemplate::Mistral | ChatTemplate::Zephyr | ChatTemplate::Jinja(_) => &["</s>"],
+        }
+    }
+
+    /// How tools are described to, and called by, this model family.
+    pub fn tool_call_format(&self) -> ToolCallFormat {
+        match self {
+            ChatTemplate::Llama3 => ToolCallFormat::Llama3,
+            ChatTemplate::Mistral => ToolCallFormat::Mistral,
+            ChatTemplate::ChatMl
+            | ChatTemplate::Phi3
+            | ChatTemplate::Zephyr
+            | ChatTemplate::Jinja(_) => ToolCallFormat::Hermes,
+        }
+    }
+
+    /// Whether the template renders `tools` and tool messages itself, as the
+    /// templates of tool-trained models on HuggingFace do.
+    pub fn supports_tools(&self) -> bool {
+        matches!(self, ChatTemplate::Jinja(source) if source.contains("tools"))
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateMessage {
+    role: String,
+    content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl TemplateMessage {
+    /// Passes the message through unchanged for templates that handle tools natively.
+    /// Call arguments are decoded since templates apply `tojson` to them.
+    fn native(message: &ChatMessage) -> Self {
+        let tool_calls = message
+            .tool_calls
+            .iter()
+            .map(|call| {
+                serde_json::json!({
+                    "id": call.id,
+                    "type": "function",
+                    "function": {
+                        "name": call.function.name,
+                        "arguments": call.arguments().unwrap_or_default(),
+                    },
+                })
+            })
+            .collect();
+        Self {
+            role: message.role.as_str().to_string(),
+            content: message.content.clone(),
+            tool_calls,
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+
+    /// Rewrites tool calls and results as the plain text the model family expects.
+    fn lowered(message: &ChatMessage, format: ToolCallFormat) -> Self {
+        let (role, content) = match message.role {
+            Role::Tool => {
+                let (role, content) = format.tool_result(&message.content);
+                (role.to_string(), content)
+            }
+            _ if !message.tool_calls.is_empty() => {
+                let calls = format.render_calls(&message.tool_calls);
+                let content = if message.content.is_empty() {
+                    calls
+                } else {
+                    format!("{}\n{}", message.content, calls)
+                };
+                (message.role.as_str().to_string(), content)
+            }
+            _ => (message.role.as_str().to_string(), message.content.clone()),
+        };
+        Self {
+            role,
+            content,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+/// A chat template together with the special tokens needed to render and stop on it.
+///
+/// # Examples
+///
+/// ```rust
+/// use plexus_ai::{ChatMessage, ChatTemplate, PromptTemplate, Role};
+/// let template = PromptTemplate::new(ChatTemplate::ChatMl);
+/// let prompt = template
+///     .render(&[ChatMessage::new(Role::User, "Hi".into())], true)
+///     .unwrap();
+/// assert!(prompt.ends_with("<|im_start|>assistant\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    template: ChatTemplate,
+    bos_token: String,
+    eos_token: String,
+    stop_tokens: Vec<String>,
+}
+
+impl PromptTemplate {
+    /// Creates a template with the default BOS/EOS and stop tokens of its family.
+    pub fn new(template: ChatTemplate) -> Self {
+        let (bos, eos) = template.default_special_tokens();
+        let stop_tokens = template
+            .default_stop_tokens()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        Self {
+            template,
+            bos_token: bos.to_string(),
+            eos_token: eos.to_string(),
+            stop_tokens,
+        }
+    }
+
+    /// Overrides the BOS and EOS tokens. The EOS token is always treated as a stop token.
+    pub fn with_special_tokens(mut self, bos_token: &str, eos_token: &str) -> Self {
+        self.bos_token = bos_token.to_string();
+        self.eos_token = eos_token.to_string();
+        self.add_stop_token(eos_token);
+        self
+    }
+
+    /// Registers an additional string that ends generation.
+    pub fn add_stop_token(&mut self, token: &str) {
+        if !token.is_empty() && !self.stop_tokens.iter().any(|t| t == token) {
+            self.stop_tokens.push(token.to_string());
+        }
+    }
+
+    /// Loads the template from a HuggingFace `tokenizer_config.json`.
+    ///
+    /// Both the plain string form of `chat_template` and the list-of-named-templates
+    /// form are supported; for the latter the `default` entry is used.
+    pub fn from_tokenizer_config(path: &std::path::Path) -> Result<Self> {
+        let config: serde_json::Value = serde_json::from_reader(
+            std::fs::File::open(path).context("Failed to open tokenizer_config.json")?,
+        )?;
+
+        let source = match config.get("chat_template") {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(serde_json::Value::Array(entries)) => entries
+                .iter()
+                .find(|e| e.get("name").and_then(|n| n.as_str()) == Some("default"))
+                .or_else(|| entries.first())
+                .and_then(|e| e.get("template"))
+                .and_then(|t| t.as_str())
+                .context("chat_template list has no usable entry")?
+                .to_string(),
+            _ => anyhow::bail!("tokenizer_config.json has no chat_template"),
+        };
+
+        // Special tokens are either plain strings or AddedToken objects with a `content` field.
+        let special = |key: &str| -> Option<String> {
+            match config.get(key)? {
+                serde_json::Value::String(s) => Some(s.clone()),
+                v => v.get("content")?.as_str().map(|s| s.to_string()),
+            }
+        };
+
+        let mut template = Self::new(ChatTemplate::Jinja(source));
+        let bos = special("bos_token").unwrap_or_else(|| template.bos_token.clone());
+        let eos = special("eos_token").unwrap_or_else(|| template.eos_token.clone());
+        template = template.with_special_tokens(&bos, &eos);
+        Ok(template)
+    }
+
+    /// Builds the template from GGUF metadata.
+    ///
+    /// Uses `tokenizer.chat_template` when present and otherwise infers a built-in
+    /// template from `general.name` / `general.architecture`. BOS/EOS ids are resolved
+    /// to token strings with `tokenizer`.
+    pub fn from_gguf(content: &gguf_file::Content, tokenizer: &Tokenizer) -> Self {
+        let meta_str = |key: &str| {
+            content
+                .metadata
+                .get(key)
+                .and_then(|v| v.to_string().ok())
+                .cloned()
+        };
+        let meta_token = |key: &str| {
+            content
+                .metadata
+                .get(key)
+                .and_then(|v| v.to_u32().ok())
+                .and_then(|id| tokenizer.id_to_token(id))
+        };
+
+        let chat_template = match meta_str("tokenizer.chat_template") {
+            Some(source) => ChatTemplate::Jinja(source),
+            None => {
+                let name = meta_str("general.name")
+                    .or_else(|| meta_str("general.architecture"))
+                    .unwrap_or_default();
+                ChatTemplate::infer_from_name(&name)
+            }
+        };
+
+        let mut template = Self::new(chat_template);
+        let bos = meta_token("tokenizer.ggml.bos_token_id").unwrap_or(template.bos_token.clone());
+        let eos = meta_token("tokenizer.ggml.eos_token_id").unwrap_or(template.eos_token.clone());
+        template = template.with_special_tokens(&bos, &eos);
+        template
+    }
+
+    pub fn template(&self) -> &ChatTemplate {
+        &self.template
+    }
+
+    pub fn bos_token(&self) -> &str {
+        &self.bos_token
+    }
+
+    pub fn eos_token(&self) -> &str {
+        &self.eos_token
+    }
+
+    pub fn stop_tokens(&self) -> &[String] {
+        &self.stop_tokens
+    }
+
+    /// Resolves the stop tokens to ids. Strings that are not single tokens are skipped.
+    pub fn stop_token_ids(&self, tokenizer: &Tokenizer) -> Vec<u32> {
+        self.stop_tokens
+            .iter()
+            .filter_map(|t| tokenizer.token_to_id(t))
+            .collect()
+    }
+
+    /// Whether a rendered prompt already starts with BOS, in which case the tokenizer
+    /// must not add another one.
+    pub fn includes_bos(&self, rendered: &str) -> bool {
+        !self.bos_token.is_empty() && rendered.starts_with(&self.bos_token)
+    }
+
+    /// Removes any stop token (and everything after it) from generated text.
+    pub fn strip_stop_tokens(&self, text: &str) -> String {
+        let end = self
+            .stop_tokens
+            .iter()
+            .filter_map(|t| text.find(t.as_str()))
+            .min()
+            .unwrap_or(text.len());
+        text[..end].to_string()
+    }
+
+    /// Renders `messages` into a prompt string.
+    ///
+    /// With `add_generation_prompt` the output ends with the header that opens the
+    /// assistant's turn.
+    pub fn render(&self, messages: &[ChatMessage], add_generation_prompt: bool) -> Result<String> {
+        self.render_with_tools(messages, &[], &ToolChoice::None, add_generation_prompt)
+    }
+
+    /// Renders `messages` with `tools` offered to the model.
+    ///
+    /// Templates that support tools receive them as the `tools` variable. For the
+    /// others the tool schemas are described in the system prompt, and tool calls and
+    /// results in the history are written out in the family's [`ToolCallFormat`].
+    pub fn render_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        tool_choice: &ToolChoice,
+        add_generation_prompt: bool,
+    ) -> Result<String> {
+        tool_choice.validate(tools)?;
+        let tools = match tool_choice {
+            ToolChoice::None => &[],
+            _ => tools,
+        };
+        let mut env = Environment::new();
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function(
+            "raise_exception",
+            |msg: String| -> Result<String, JinjaError> {
+                Err(JinjaError::new(ErrorKind::InvalidOperation, msg))
+            },
+        );
+        env.add_template("chat", self.template.source())
+            .context("Invalid chat template")?;
+
+        let native = self.template.supports_tools();
+        let format = self.template.tool_call_format();
+        let mut messages = messages.to_vec();
+        if !tools.is_empty() && !native {
+            tools::with_system_prompt(&mut messages, &format.system_prompt(tools, tool_choice));
+        }
+        let messages: Vec<TemplateMessage> = messages
+            .iter()
+            .map(|m| match native {
+                true => TemplateMessage::native(m),
+                false => TemplateMessage::lowered(m, format),
+            })
+            .collect();
+
+        let rendered = env.get_template("chat")?.render(context! {
+            messages => messages,
+            tools => (!tools.is_empty()).then_some(tools),
+            bos_token => self.bos_token,
+            eos_token => self.eos_token,
+            add_generation_prompt => add_generation_prompt,
+        })?;
+        Ok(rendered)
+    }
+
+    /// Parses generated text into an assistant message, extracting any calls to `tools`.
+    pub fn parse_response(
+        &self,
+        text: &str,
+        tools: &[ToolDefinition],
+        tool_choice: &ToolChoice,
+    ) -> ChatMessage {
+        self.template
+            .tool_call_format()
+            .parse(&self.strip_stop_tokens(text), tools, tool_choice)
+    }
+}
@@ -0,0 +1,445 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::chat::{ChatMessage, Role};
+
+/// The kind of a tool. OpenAI only defines `function`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolKind {
+    #[default]
+    Function,
+}
+
+/// A tool the model may call, in OpenAI's `tools` format.
+///
+/// ```json
+/// {"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    #[serde(rename = "type", default)]
+    pub kind: ToolKind,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments object.
+    #[serde(default = "empty_parameters")]
+    pub parameters: Value,
+}
+
+fn empty_parameters() -> Value {
+    serde_json::json!({"type": "object", "properties": {}})
+}
+
+impl ToolDefinition {
+    pub fn function(name: &str, description: &str, parameters: Value) -> Self {
+        Self {
+            kind: ToolKind::Function,
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: Some(description.to_string()).filter(|d| !d.is_empty()),
+                parameters,
+            },
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+}
+
+/// A call the model made, as returned in an assistant message's `tool_calls`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub kind: ToolKind,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as a JSON-encoded string, as in the OpenAI API.
+    pub arguments: String,
+}
+
+impl ToolCall {
+    pub fn new(name: &str, arguments: &Value) -> Self {
+        Self {
+            id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+            kind: ToolKind::Function,
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    /// The decoded arguments object.
+    pub fn arguments(&self) -> Result<Value> {
+        serde_json::from_str(&self.function.arguments).with_context(|| {
+            format!(
+                "Tool call '{}' has invalid JSON arguments",
+                self.function.name
+            )
+        })
+    }
+}
+
+/// Whether and which tool the model should call (OpenAI's `tool_choice`).
+///
+/// Serialized as `"none"`, `"auto"`, `"required"` or
+/// `{"type": "function", "function": {"name": "..."}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "ToolChoiceRepr", into = "ToolChoiceRepr")]
+pub enum ToolChoice {
+    /// Tools are not offered to the model.
+    None,
+    /// The model decides whether to call a tool.
+    #[default]
+    Auto,
+    /// The model must call at least one tool.
+    Required,
+    /// The model must call the named function.
+    Function(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ToolChoiceRepr {
+    Mode(String),
+    Named {
+        #[serde(rename = "type", default)]
+        kind: ToolKind,
+        function: NamedFunction,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct NamedFunction {
+    name: String,
+}
+
+impl TryFrom<ToolChoiceRepr> for ToolChoice {
+    type Error = String;
+
+    fn try_from(repr: ToolChoiceRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ToolChoiceRepr::Mode(mode) => match mode.as_str() {
+                "none" => Ok(ToolChoice::None),
+                "auto" => Ok(ToolChoice::Auto),
+                "required" => Ok(ToolChoice::Required),
+                other => Err(format!("Unknown tool_choice '{}'", other)),
+            },
+            ToolChoiceRepr::Named { function, .. } => Ok(ToolChoice::Function(function.name)),
+        }
+    }
+}
+
+impl From<ToolChoice> for ToolChoiceRepr {
+    fn from(choice: ToolChoice) -> Self {
+        match choice {
+            ToolChoice::None => ToolChoiceRepr::Mode("none".to_string()),
+            ToolChoice::Auto => ToolChoiceRepr::Mode("auto".to_string()),
+            ToolChoice::Required => ToolChoiceRepr::Mode("required".to_string()),
+            ToolChoice::Function(name) => ToolChoiceRepr::Named {
+                kind: ToolKind::Function,
+                function: NamedFunction { name },
+            },
+        }
+    }
+}
+
+impl ToolChoice {
+    /// Checks that a named function is among `tools`.
+    pub fn validate(&self, tools: &[ToolDefinition]) -> Result<()> {
+        if let ToolChoice::Function(name) = self {
+            anyhow::ensure!(
+                tools.iter().any(|t| t.name() == name),
+                "tool_choice names unknown function '{}'",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// How a model family expects tools to be described and emits its calls.
+///
+/// Used for the built-in templates and for Jinja templates that do not handle
+/// `tools` themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolCallFormat {
+    /// `<tool_call>{"name": ..., "arguments": ...}</tool_call>` (Hermes, Qwen and most ChatML models).
+    Hermes,
+    /// A bare `{"name": ..., "parameters": ...}` object, optionally after `<|python_tag|>`.
+    Llama3,
+    /// `[TOOL_CALLS] [{"name": ..., "arguments": ...}]`.
+    Mistral,
+}
+
+impl ToolCallFormat {
+    /// The system prompt text that describes `tools` to the model.
+    pub(crate) fn system_prompt(&self, tools: &[ToolDefinition], choice: &ToolChoice) -> String {
+        let mut prompt = match self {
+            ToolCallFormat::Hermes => {
+                let signatures: Vec<String> = tools
+                    .iter()
+                    .map(|t| serde_json::to_string(t).unwrap_or_default())
+                    .collect();
+                format!(
+                    "You are a function calling AI model. You are provided with function signatures within <tools></tools> XML tags. You may call one or more functions to assist with the user query. Don't make assumptions about what values to plug into functions. Here are the available tools:\n<tools>\n{}\n</tools>\nFor each function call return a json object with function name and arguments within <tool_call></tool_call> XML tags:\n<tool_call>\n{{\"name\": <function-name>, \"arguments\": <args-dict>}}\n</tool_call>",
+                    signatures.join("\n")
+                )
+            }
+            ToolCallFormat::Llama3 => {
+                let signatures: Vec<String> = tools
+                    .iter()
+                    .map(|t| serde_json::to_string_pretty(t).unwrap_or_default())
+                    .collect();
+                format!(
+                    "Given the following functions, please respond with a JSON for a function call with its proper arguments that best answers the given prompt.\n\nRespond in the format {{\"name\": function name, \"parameters\": dictionary of argument name and its value}}. Do not use variables.\n\n{}",
+                    signatures.join("\n\n")
+                )
+            }
+            ToolCallFormat::Mistral => format!(
+                "[AVAILABLE_TOOLS] {} [/AVAILABLE_TOOLS]\nTo call tools, reply with [TOOL_CALLS] followed by a JSON list of {{\"name\": ..., \"arguments\": ...}} objects.",
+                serde_json::to_string(tools).unwrap_or_default()
+            ),
+        };
+        if let Some(instruction) = choice_instruction(choice) {
+            prompt.push_str("\n\n");
+            prompt.push_str(&instruction);
+        }
+        prompt
+    }
+
+    /// Renders the calls of an assistant turn the way the model would have written them.
+    pub(crate) fn render_calls(&self, calls: &[ToolCall]) -> String {
+        let objects: Vec<Value> = calls
+            .iter()
+            .map(|call| {
+                let arguments = call
+                    .arguments()
+                    .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+                let key = match self {
+                    ToolCallFormat::Llama3 => "parameters",
+                    _ => "arguments",
+                };
+                serde_json::json!({"name": call.function.name, key: arguments})
+            })
+            .collect();
+        match self {
+            ToolCallFormat::Hermes => objects
+                .iter()
+                .map(|o| format!("<tool_call>\n{}\n</tool_call>", o))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ToolCallFormat::Llama3 => objects
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+            ToolCallFormat::Mistral => {
+                format!("[TOOL_CALLS] {}", Value::Array(objects))
+            }
+        }
+    }
+
+    /// The role and content a tool result is rendered with.
+    pub(crate) fn tool_result(&self, content: &str) -> (&'static str, String) {
+        match self {
+            ToolCallFormat::Hermes => (
+                "user",
+                format!("<tool_response>\n{}\n</tool_response>", content),
+            ),
+            ToolCallFormat::Llama3 => ("ipython", content.to_string()),
+            ToolCallFormat::Mistral => (
+                "user",
+                format!("[TOOL_RESULTS] {} [/TOOL_RESULTS]", content),
+            ),
+        }
+    }
+
+    /// Splits generated text into the assistant's reply and its calls to `tools`.
+    ///
+    /// Besides the family's own markers, a reply that consists only of a
+    /// `{"name": ..., "arguments": ...}` object is recognised, since small models often
+    /// drop the markers. Nothing is parsed when no tools were offered, and calls to
+    /// functions that were not offered are dropped; a reply left without calls is kept
+    /// as text.
+    pub fn parse(&self, text: &str, tools: &[ToolDefinition], choice: &ToolChoice) -> ChatMessage {
+        if tools.is_empty() || *choice == ToolChoice::None {
+            return ChatMessage::new(Role::Assistant, text.trim().to_string());
+        }
+        let (content, tool_calls) = match self {
+            ToolCallFormat::Hermes => parse_tagged(text),
+            ToolCallFormat::Llama3 => {
+                let stripped = text.trim().trim_start_matches("<|python_tag|>");
+                match parse_call_list(stripped, ';') {
+                    Some(calls) => (String::new(), calls),
+                    None => (text.to_string(), Vec::new()),
+                }
+            }
+            ToolCallFormat::Mistral => match text.find("[TOOL_CALLS]") {
+                Some(start) => {
+                    let calls = parse_json_calls(&text[start + "[TOOL_CALLS]".len()..]);
+                    (text[..start].to_string(), calls)
+                }
+                None => (text.to_string(), Vec::new()),
+            },
+        };
+
+        let (content, tool_calls) = if tool_calls.is_empty() {
+            match parse_call_list(content.trim(), ';') {
+                Some(calls) => (String::new(), calls),
+                None => (content, tool_calls),
+            }
+        } else {
+            (content, tool_calls)
+        };
+
+        let (offered, unknown): (Vec<ToolCall>, Vec<ToolCall>) = tool_calls
+            .into_iter()
+            .partition(|call| tools.iter().any(|t| t.name() == call.function.name));
+        for call in &unknown {
+            tracing::warn!(
+                "Dropping call to '{}', which was not offered",
+                call.function.name
+            );
+        }
+        let (content, tool_calls) = match offered.is_empty() {
+            true => (text.to_string(), offered),
+            false => (content, offered),
+        };
+
+        let mut message = ChatMessage::new(Role::Assistant, content.trim().to_string());
+        message.tool_calls = tool_calls;
+        message
+    }
+}
+
+fn choice_instruction(choice: &ToolChoice) -> Option<String> {
+    match choice {
+        ToolChoice::Required => Some("You must call at least one function.".to_string()),
+        ToolChoice::Function(name) => Some(format!("You must call the function '{}'.", name)),
+        ToolChoice::None | ToolChoice::Auto => None,
+    }
+}
+
+/// Adds the tool description to the system message, creating one if needed.
+pub(crate) fn with_system_prompt(messages: &mut Vec<ChatMessage>, prompt: &str) {
+    match messages.first_mut() {
+        Some(first) if matches!(first.role, Role::System) => {
+            first.content = format!("{}\n\n{}", first.content, prompt);
+        }
+        _ => messages.insert(0, ChatMessage::new(Role::System, prompt.to_string())),
+    }
+}
+
+fn parse_tagged(text: &str) -> (String, Vec<ToolCall>) {
+    const OPEN: &str = "<tool_call>";
+    const CLOSE: &str = "</tool_call>";
+    let mut content = String::new();
+    let mut calls = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        content.push_str(&rest[..start]);
+        let body = &rest[start + OPEN.len()..];
+        // The closing tag may be cut off by a stop token
+        let (inner, after) = match body.find(CLOSE) {
+            Some(end) => (&body[..end], &body[end + CLOSE.len()..]),
+            None => (body, ""),
+        };
+        calls.extend(parse_json_calls(inner));
+        rest = after;
+    }
+    content.push_str(rest);
+    (content, calls)
+}
+
+/// Parses `{...}`, `[{...}, ...]` or `{...}; {...}` where every object is a call.
+fn parse_call_list(text: &str, separator: char) -> Option<Vec<ToolCall>> {
+    if !text.starts_with('{') && !text.starts_with('[') {
+        return None;
+    }
+    let mut calls = Vec::new();
+    for part in split_top_level(text, separator) {
+        let value: Value = serde_json::from_str(part.trim()).ok()?;
+        calls.extend(calls_from_value(&value)?);
+    }
+    Some(calls).filter(|c| !c.is_empty())
+}
+
+fn parse_json_calls(text: &str) -> Vec<ToolCall> {
+    let mut stream = serde_json::Deserializer::from_str(text.trim()).into_iter::<Value>();
+    match stream.next() {
+        Some(Ok(value)) => calls_from_value(&value).unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+fn calls_from_value(value: &Value) -> Option<Vec<ToolCall>> {
+    match value {
+        Value::Array(items) => items.iter().map(call_from_object).collect(),
+        object => call_from_object(object).map(|call| vec![call]),
+    }
+}
+
+fn call_from_object(value: &Value) -> Option<ToolCall> {
+    let name = value.get("name")?.as_str()?;
+    let arguments = value
+        .get("arguments")
+        .or_else(|| value.get("parameters"))
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({}));
+    // Some models encode the arguments as a string
+    let arguments = match arguments {
+        Value::String(s) => serde_json::from_str(&s).ok()?,
+        other => other,
+    };
+    arguments
+        .is_object()
+        .then(|| ToolCall::new(name, &arguments))
+}
+
+/// Splits on `separator` outside of JSON strings, objects and arrays.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts.retain(|p| !p.trim().is_empty());
+    parts
+}
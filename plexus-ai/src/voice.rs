@@ -1,34 +1,354 @@
+//! Speech recognition with Whisper.
+//!
+//! Whisper reads 30 seconds of 16 kHz audio at a time. Longer recordings are cut into
+//! windows that overlap by a couple of seconds, so words at a window edge are heard whole
+//! by one of them, and each window is decoded with timestamp tokens into timed segments.
+//!
+//! Unless a language is given, it is detected from the decoder's first prediction on the
+//! first window and kept for the whole recording. Speech can also be translated to English.
+
 use anyhow::{Error, Result};
-use candle_core::{Device, Tensor};
+use candle_core::{Device, IndexOp, Tensor};
 use candle_nn::VarBuilder;
-use candle_transformers::models::whisper::{audio, model::Whisper as Model, Config};
+use candle_transformers::models::whisper::{
+    audio, model::Whisper as Model, Config, EOT_TOKEN, HOP_LENGTH, NO_TIMESTAMPS_TOKEN, N_FRAMES,
+    N_SAMPLES, SAMPLE_RATE, SOT_TOKEN, TRANSCRIBE_TOKEN, TRANSLATE_TOKEN,
+};
 use hf_hub::{api::tokio::Api, Repo, RepoType};
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use tokenizers::Tokenizer;
 use tracing::info;
 
-// Stub removed. Using aliased import.
+/// Mel filter banks of `librosa.filters.mel(sr=16000, n_fft=400, n_mels=N)`, which Whisper
+/// models were trained with: `N` rows of 201 little-endian `f32` weights.
+const MEL_FILTERS_80: &[u8] = include_bytes!("../assets/mel_filters_80.bytes");
+const MEL_FILTERS_128: &[u8] = include_bytes!("../assets/mel_filters_128.bytes");
+
+/// Seconds of audio Whisper reads at a time.
+pub const WINDOW_SECS: f64 = N_SAMPLES as f64 / SAMPLE_RATE as f64;
+/// Seconds shared by consecutive windows of a long recording.
+pub const DEFAULT_WINDOW_OVERLAP: f64 = 2.0;
+/// Seconds between consecutive timestamp tokens.
+const TIMESTAMP_STEP: f64 = 0.02;
+/// Latest time, in timestamp steps, the first segment of a window may start at.
+const MAX_INITIAL_TIMESTAMP: u32 = 50;
+
+/// Codes of the languages multilingual Whisper models know, in the order of their
+/// `<|xx|>` tokens. Only large-v3 knows Cantonese (`yue`).
+pub const LANGUAGES: &[&str] = &[
+    "en", "zh", "de", "es", "ru", "ko", "fr", "ja", "pt", "tr", "pl", "ca", "nl", "ar", "sv", "it",
+    "id", "hi", "fi", "vi", "he", "uk", "el", "ms", "cs", "ro", "da", "hu", "ta", "no", "th", "ur",
+    "hr", "bg", "lt", "la", "mi", "ml", "cy", "sk", "te", "fa", "lv", "bn", "sr", "az", "sl", "kn",
+    "et", "mk", "br", "eu", "is", "hy", "ne", "mn", "bs", "kk", "sq", "sw", "gl", "mr", "pa", "si",
+    "km", "sn", "yo", "so", "af", "oc", "ka", "be", "tg", "sd", "gu", "am", "yi", "lo", "uz", "fo",
+    "ht", "ps", "tk", "nn", "mt", "sa", "lb", "my", "bo", "tl", "mg", "as", "tt", "haw", "ln",
+    "ha", "ba", "jw", "su", "yue",
+];
+
+/// The mel filter bank for models reading `num_mel_bins` bins, in the layout
+/// [`audio::pcm_to_mel`] expects.
+pub fn mel_filters(num_mel_bins: usize) -> Result<Vec<f32>> {
+    let bytes = match num_mel_bins {
+        80 => MEL_FILTERS_80,
+        128 => MEL_FILTERS_128,
+        n => anyhow::bail!("No mel filters for {} bins (80 and 128 are bundled)", n),
+    };
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// A stretch of speech; times are seconds from the start of the recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// What Whisper does with the speech it hears.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Task {
+    /// Writes the speech down in its own language
+    #[default]
+    Transcribe,
+    /// Writes the speech down in English
+    Translate,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscribeOptions {
+    /// Language code from [`LANGUAGES`]; detected from the audio when `None`
+    pub language: Option<String>,
+    pub task: Task,
+}
+
+impl TranscribeOptions {
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn with_task(mut self, task: Task) -> Self {
+        self.task = task;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub text: String,
+    pub segments: Vec<Segment>,
+    /// Language of the speech, as given or detected; `None` for English-only models
+    pub language: Option<String>,
+}
+
+impl Transcript {
+    pub fn from_segments(segments: Vec<Segment>) -> Self {
+        let text = segments
+            .iter()
+            .map(|s| s.text.trim())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            text,
+            segments,
+            language: None,
+        }
+    }
+}
+
+/// The most likely language according to the logits of the token following
+/// `<|startoftranscript|>`, among `languages` and their token ids.
+pub fn detect_language<'a>(logits: &[f32], languages: &[(&'a str, u32)]) -> Option<&'a str> {
+    languages
+        .iter()
+        .filter_map(|&(code, token)| Some((code, *logits.get(token as usize)?)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(code, _)| code)
+}
+
+/// First sample of every window needed to cover `samples` samples, with consecutive
+/// windows sharing `overlap` seconds.
+pub fn window_starts(samples: usize, overlap: f64) -> Vec<usize> {
+    // Whole mel frames, and at most half a window so that windows always advance
+    let overlap = ((overlap.max(0.0) * (SAMPLE_RATE / HOP_LENGTH) as f64).round() as usize
+        * HOP_LENGTH)
+        .min(N_SAMPLES / 2);
+    let mut starts = vec![0];
+    while starts[starts.len() - 1] + N_SAMPLES < samples {
+        starts.push(starts[starts.len() - 1] + N_SAMPLES - overlap);
+    }
+    starts
+}
+
+/// Joins the segments of overlapping windows, given with the second each window starts
+/// at. Of the segments starting within the overlap of two windows, those before its middle
+/// are taken from the earlier window and the others from the later one.
+pub fn merge_windows(windows: Vec<(f64, Vec<Segment>)>) -> Vec<Segment> {
+    let middles: Vec<f64> = windows
+        .windows(2)
+        .map(|pair| (pair[1].0 + pair[0].0 + WINDOW_SECS) / 2.0)
+        .collect();
+    let mut merged = vec![];
+    for (i, (_, segments)) in windows.into_iter().enumerate() {
+        let from = if i == 0 {
+            f64::NEG_INFINITY
+        } else {
+            middles[i - 1]
+        };
+        let until = middles.get(i).copied().unwrap_or(f64::INFINITY);
+        merged.extend(
+            segments
+                .into_iter()
+                .filter(|s| s.start >= from && s.start < until),
+        );
+    }
+    merged
+}
+
+/// Splits the tokens decoded for a window starting at second `offset` into segments at
+/// timestamp tokens (`timestamp_begin` and above). Text after the last timestamp runs
+/// until `end`, where the window's audio ends.
+pub fn segments_from_tokens(
+    tokens: &[u32],
+    timestamp_begin: u32,
+    offset: f64,
+    end: f64,
+    decode: impl Fn(&[u32]) -> Result<String>,
+) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
+    let mut start = offset;
+    let mut text = vec![];
+    for &token in tokens {
+        if token < timestamp_begin {
+            text.push(token);
+            continue;
+        }
+        let time = offset + (token - timestamp_begin) as f64 * TIMESTAMP_STEP;
+        if !text.is_empty() {
+            segments.push(Segment {
+                start,
+                end: time,
+                text: decode(&text)?,
+            });
+            text.clear();
+        }
+        // A closing timestamp also opens the next segment unless another one follows
+        start = time;
+    }
+    if !text.is_empty() {
+        segments.push(Segment {
+            start,
+            end: end.max(start),
+            text: decode(&text)?,
+        });
+    }
+    segments.retain(|s| !s.text.trim().is_empty());
+    Ok(segments)
+}
+
+/// Constraints on timestamp tokens while decoding, as in Whisper's reference decoder.
+#[derive(Debug, Clone)]
+pub struct TimestampRules {
+    /// `<|endoftext|>`; the special tokens follow it
+    pub eot: u32,
+    /// `<|0.00|>`; the later timestamps follow it
+    pub timestamp_begin: u32,
+}
+
+impl TimestampRules {
+    /// Masks the logits of the tokens that may not follow `sampled`, the tokens generated
+    /// so far in the window:
+    ///
+    /// - special tokens other than `<|endoftext|>` are never generated;
+    /// - a window starts with a timestamp no later than one second;
+    /// - timestamps come in pairs around text, except right before `<|endoftext|>`;
+    /// - timestamps do not go back in time;
+    /// - a timestamp is taken when timestamps are more likely in total than any text token.
+    pub fn apply(&self, logits: &mut [f32], sampled: &[u32]) {
+        let tb = self.timestamp_begin as usize;
+        let vocab = logits.len();
+        let mask = |logits: &mut [f32], range: std::ops::Range<usize>| {
+            for logit in &mut logits[range.start.min(vocab)..range.end.min(vocab)] {
+                *logit = f32::NEG_INFINITY;
+            }
+        };
+        mask(logits, self.eot as usize + 1..tb);
+
+        let is_timestamp = |token: &u32| *token >= self.timestamp_begin;
+        let Some(last) = sampled.last() else {
+            mask(logits, 0..tb);
+            mask(logits, tb + MAX_INITIAL_TIMESTAMP as usize + 1..vocab);
+            return;
+        };
+        let last_was_timestamp = is_timestamp(last);
+        let penultimate_was_timestamp =
+            sampled.len() < 2 || is_timestamp(&sampled[sampled.len() - 2]);
+        if last_was_timestamp {
+            if penultimate_was_timestamp {
+                mask(logits, tb..vocab);
+            } else {
+                mask(logits, 0..self.eot as usize);
+            }
+        }
+        if let Some(&latest) = sampled.iter().rev().find(|t| is_timestamp(t)) {
+            // The timestamp closing a segment may open the next one at the same time
+            let earliest = if last_was_timestamp && !penultimate_was_timestamp {
+                latest
+            } else {
+                latest + 1
+            };
+            mask(logits, tb..earliest as usize);
+        }
+
+        let log_sum_exp = |logits: &[f32]| {
+            let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            if max == f32::NEG_INFINITY {
+                return max;
+            }
+            max + logits.iter().map(|l| (l - max).exp()).sum::<f32>().ln()
+        };
+        let timestamps = log_sum_exp(&logits[tb.min(vocab)..]);
+        let best_text = logits[..tb.min(vocab)]
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        if timestamps > best_text {
+            mask(logits, 0..tb);
+        }
+    }
+}
+
+/// Ids of the special tokens decoding uses.
+struct SpecialTokens {
+    sot: u32,
+    transcribe: u32,
+    translate: u32,
+    eot: u32,
+    timestamp_begin: u32,
+    /// Language tokens in the vocabulary; none in English-only models
+    languages: Vec<(&'static str, u32)>,
+}
+
+impl SpecialTokens {
+    fn new(tokenizer: &Tokenizer) -> Self {
+        let id = |token: &str, default: u32| tokenizer.token_to_id(token).unwrap_or(default);
+        let no_timestamps = id(NO_TIMESTAMPS_TOKEN, 50363);
+        Self {
+            sot: id(SOT_TOKEN, 50258),
+            transcribe: id(TRANSCRIBE_TOKEN, 50359),
+            translate: id(TRANSLATE_TOKEN, 50358),
+            eot: id(EOT_TOKEN, 50257),
+            // Timestamps follow <|notimestamps|> in every Whisper vocabulary
+            timestamp_begin: id("<|0.00|>", no_timestamps + 1),
+            languages: LANGUAGES
+                .iter()
+                .filter_map(|&code| Some((code, tokenizer.token_to_id(&format!("<|{}|>", code))?)))
+                .collect(),
+        }
+    }
+
+    fn language(&self, code: &str) -> Option<(&'static str, u32)> {
+        self.languages.iter().copied().find(|(c, _)| *c == code)
+    }
+}
 
 pub struct WhisperEngine {
     model: Option<Arc<Mutex<Model>>>,
     tokenizer: Option<Tokenizer>,
     device: Device,
     config: Option<Config>,
-    _mel_filters: Vec<f32>,
+    mel_filters: Vec<f32>,
+    window_overlap: f64,
 }
 
 impl WhisperEngine {
     pub fn new() -> Self {
-        let device = Device::new_metal(0).unwrap_or(Device::Cpu);
+        Self::with_device(crate::default_device())
+    }
+
+    pub fn with_device(device: Device) -> Self {
         Self {
             model: None,
             tokenizer: None,
             device,
             config: None,
-            _mel_filters: vec![],
+            mel_filters: vec![],
+            window_overlap: DEFAULT_WINDOW_OVERLAP,
         }
     }
 
+    /// Sets the seconds shared by consecutive windows of long recordings.
+    pub fn with_window_overlap(mut self, seconds: f64) -> Self {
+        self.window_overlap = seconds;
+        self
+    }
+
     pub async fn load_model(&mut self) -> Result<()> {
         let api = Api::new()?;
         let repo = api.repo(Repo::with_revision(
@@ -43,12 +363,7 @@ impl WhisperEngine {
 
         let config: Config = serde_json::from_str(&std::fs::read_to_string(config_filename)?)?;
         let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(Error::msg)?;
-
-        // Load Mel Filters (simplified, in reality we need to load or compute them)
-        // Usually these are in the model assets or computed.
-        // For MVP, we'll assume we can use a crate or omit this step for now if we can't find it.
-        // Actually, let's try to see if config has it.
-        // self.mel_filters = config.mel_filters.clone(); // hypothetical
+        let mel_filters = mel_filters(config.num_mel_bins)?;
 
         let vb = unsafe {
             VarBuilder::from_mmaped_safetensors(
@@ -62,64 +377,143 @@ impl WhisperEngine {
         self.model = Some(Arc::new(Mutex::new(model)));
         self.tokenizer = Some(tokenizer);
         self.config = Some(config);
+        self.mel_filters = mel_filters;
 
         info!("Whisper model loaded successfully");
         Ok(())
     }
 
-    pub async fn transcribe(&self, pcm_data: Vec<f32>) -> Result<String> {
-        if self.model.is_none() {
+    /// Transcribes, or translates to English, 16 kHz mono audio into timed segments.
+    pub async fn transcribe(
+        &self,
+        pcm_data: &[f32],
+        options: &TranscribeOptions,
+    ) -> Result<Transcript> {
+        let (Some(model), Some(tokenizer), Some(config)) =
+            (&self.model, &self.tokenizer, &self.config)
+        else {
             return Err(anyhow::anyhow!("Model not loaded"));
+        };
+        let special = SpecialTokens::new(tokenizer);
+        let mut language = match options.language.as_deref() {
+            Some(code) => {
+                let code = code.trim().to_lowercase();
+                let language = special.language(&code).ok_or_else(|| {
+                    anyhow::anyhow!("Unknown language '{}' for this Whisper model", code)
+                })?;
+                Some(language)
+            }
+            None => None,
+        };
+        if pcm_data.is_empty() {
+            return Ok(Transcript {
+                language: language.map(|(code, _)| code.to_string()),
+                ..Transcript::default()
+            });
         }
-        let model_arc = self.model.as_ref().unwrap();
-        let mut model = model_arc.lock().unwrap();
-        let tokenizer = self.tokenizer.as_ref().unwrap();
-        let config = self.config.as_ref().unwrap();
-
-        // 1. Preprocess Audio
-        // Assumes pcm_data is 16kHz
-        let mel = audio::pcm_to_mel(config, &pcm_data, &self._mel_filters); // Removed ?
-        let mel_len = mel.len();
-        let mel_tensor = Tensor::from_vec(mel, (1, 80, mel_len / 80), &self.device)?; // 80 bins
-
-        // 2. Encode
-        let audio_features = model.encoder.forward(&mel_tensor, true)?;
-
-        // 3. Decode (Greedy)
-        // SOT: <|startoftranscript|> (50258), <|en|> (50259), <|transcribe|> (50359), <|notimestamps|> (50363)
-        // We can look these up dynamically or hardcode for MVP if tokenizer allows.
-        // TinyLlama tokenizer might be different than Whisper tokenizer?
-        // Whisper uses a specific tokenizer.
-        // We loaded `openai/whisper-tiny` tokenizer.json, so lookups should work.
-
-        let sot_token = tokenizer
-            .token_to_id("<|startoftranscript|>")
-            .unwrap_or(50258);
-        let trans_token = tokenizer.token_to_id("<|transcribe|>").unwrap_or(50359);
-        let notime_token = tokenizer.token_to_id("<|notimestamps|>").unwrap_or(50363);
-        // We default to English usually, but let's just start with SOT
-
-        let mut tokens = vec![sot_token, trans_token, notime_token];
-        let mut generated_text = String::new();
-
-        for _ in 0..100 {
-            let input = Tensor::new(&tokens[..], &self.device)?.unsqueeze(0)?;
-            let logits = model.decoder.forward(&input, &audio_features, true)?;
-            let logits = logits.squeeze(0)?;
-            let logits = logits.get(logits.dim(0)? - 1)?;
-
-            let next_token = logits.argmax(0)?.to_scalar::<u32>()?;
-            tokens.push(next_token);
-
-            let token_str = tokenizer.decode(&[next_token], true).unwrap_or_default();
-            generated_text.push_str(&token_str);
-
-            if next_token == 50257 {
-                // <|endoftext|>
-                break;
+        let mut model = model.lock().unwrap();
+
+        // The spectrogram is normalized over the whole recording, then cut into windows
+        let mel = audio::pcm_to_mel(config, pcm_data, &self.mel_filters);
+        let bins = config.num_mel_bins;
+        let frames = mel.len() / bins;
+        let mel = Tensor::from_vec(mel, (1, bins, frames), &self.device)?;
+
+        let duration = pcm_data.len() as f64 / SAMPLE_RATE as f64;
+        let mut windows = vec![];
+        for start in window_starts(pcm_data.len(), self.window_overlap) {
+            let first_frame = start / HOP_LENGTH;
+            let window = mel.narrow(2, first_frame, N_FRAMES.min(frames - first_frame))?;
+            let audio_features = model.encoder.forward(&window, true)?;
+            if language.is_none() && !special.languages.is_empty() {
+                let logits = self.next_logits(&mut model, &[special.sot], &audio_features)?;
+                language = detect_language(&logits, &special.languages)
+                    .and_then(|code| special.language(code));
+                if let Some((code, _)) = language {
+                    info!("Detected language: {}", code);
+                }
             }
+
+            let mut prompt = vec![special.sot];
+            if let Some((_, token)) = language {
+                prompt.push(token);
+            }
+            prompt.push(match options.task {
+                Task::Transcribe => special.transcribe,
+                Task::Translate => special.translate,
+            });
+            let tokens =
+                self.decode_window(&mut model, config, &audio_features, prompt, &special)?;
+
+            let offset = start as f64 / SAMPLE_RATE as f64;
+            let end = (offset + WINDOW_SECS).min(duration);
+            let segments =
+                segments_from_tokens(&tokens, special.timestamp_begin, offset, end, |t| {
+                    tokenizer.decode(t, true).map_err(Error::msg)
+                })?;
+            windows.push((offset, segments));
         }
+        Ok(Transcript {
+            language: language.map(|(code, _)| code.to_string()),
+            ..Transcript::from_segments(merge_windows(windows))
+        })
+    }
+
+    /// Logits of the token following `tokens`, decoded from scratch.
+    fn next_logits(
+        &self,
+        model: &mut Model,
+        tokens: &[u32],
+        audio_features: &Tensor,
+    ) -> Result<Vec<f32>> {
+        let input = Tensor::new(tokens, &self.device)?.unsqueeze(0)?;
+        let hidden = model.decoder.forward(&input, audio_features, true)?;
+        let last = hidden.dim(1)? - 1;
+        let logits = model.decoder.final_linear(&hidden.i((..1, last..))?)?;
+        Ok(logits.i(0)?.i(0)?.to_vec1::<f32>()?)
+    }
+
+    /// Greedily decodes one window of encoded audio after `prompt`, returning the text and
+    /// timestamp tokens up to `<|endoftext|>`.
+    fn decode_window(
+        &self,
+        model: &mut Model,
+        config: &Config,
+        audio_features: &Tensor,
+        mut tokens: Vec<u32>,
+        special: &SpecialTokens,
+    ) -> Result<Vec<u32>> {
+        let rules = TimestampRules {
+            eot: special.eot,
+            timestamp_begin: special.timestamp_begin,
+        };
+        let prompt_len = tokens.len();
+
+        for i in 0..config.max_target_positions / 2 {
+            let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+            let hidden = model.decoder.forward(&input, audio_features, i == 0)?;
+            let last = hidden.dim(1)? - 1;
+            let logits = model.decoder.final_linear(&hidden.i((..1, last..))?)?;
+            let mut logits = logits.i(0)?.i(0)?.to_vec1::<f32>()?;
 
-        Ok(generated_text)
+            for &token in &config.suppress_tokens {
+                if let Some(logit) = logits.get_mut(token as usize) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+            rules.apply(&mut logits, &tokens[prompt_len..]);
+
+            let next = logits
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(token, _)| token as u32)
+                .unwrap_or(special.eot);
+            if next == special.eot {
+                break;
+            }
+            tokens.push(next);
+        }
+        Ok(tokens.split_off(prompt_len))
     }
 }
@@ -0,0 +1,76 @@
+use plexus_ai::{
+    Document, EmbedderRegistry, QdrantConfig, QdrantFailoverStore, SimpleVectorStore, VectorStore,
+    VectorStoreConfig, VectorStoreKind,
+};
+use std::sync::Arc;
+
+#[test]
+fn test_parse_vector_store_kind() -> anyhow::Result<()> {
+    for kind in [
+        VectorStoreKind::None,
+        VectorStoreKind::Memory,
+        VectorStoreKind::Lancedb,
+        VectorStoreKind::Qdrant,
+    ] {
+        assert_eq!(kind.to_string().parse::<VectorStoreKind>()?, kind);
+    }
+    assert_eq!(
+        " HNSW ".parse::<VectorStoreKind>()?,
+        VectorStoreKind::Memory
+    );
+    assert!("redis".parse::<VectorStoreKind>().is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_open_embedded_kinds() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let model = EmbedderRegistry::new().get("minilm")?;
+
+    let disabled = VectorStoreConfig::new(VectorStoreKind::None);
+    assert!(disabled.open(dir.path(), &model).await?.is_none());
+
+    let memory = VectorStoreConfig::new(VectorStoreKind::Memory);
+    {
+        let store = memory.open(dir.path(), &model).await?.unwrap();
+        store
+            .upsert(Document::new("kept", "kept"), vec![0.5; 384])
+            .await?;
+    }
+    let store = memory.open(dir.path(), &model).await?.unwrap();
+    assert!(store.get("kept").await?.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unreachable_qdrant_falls_back() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let model = EmbedderRegistry::new().get("minilm")?;
+    let qdrant = QdrantConfig::new("http://127.0.0.1:1").with_timeout_ms(500);
+
+    // Opening does not fail; memory goes to the embedded store instead
+    let config = VectorStoreConfig::new(VectorStoreKind::Qdrant)
+        .with_qdrant(qdrant.clone())
+        .with_reconnect_secs(0);
+    let store = config.open(dir.path(), &model).await?.unwrap();
+    store
+        .upsert(Document::new("offline", "saved while down"), vec![0.5; 384])
+        .await?;
+    assert_eq!(store.count().await?, 1);
+
+    // Reconnecting keeps failing over while the server stays down
+    let fallback = Arc::new(SimpleVectorStore::new());
+    let failover = QdrantFailoverStore::new(qdrant, model, fallback.clone());
+    assert!(!failover.refresh().await);
+    assert!(!failover.is_connected());
+    failover.add_document("x", "x", vec![0.5; 384]).await?;
+    assert_eq!(fallback.count().await?, 1);
+
+    // Writes made meanwhile are kept for replay once Qdrant is back
+    failover.add_document("y", "y", vec![0.5; 384]).await?;
+    failover.delete("x").await?;
+    assert_eq!(failover.pending_writes(), 2);
+    assert!(!failover.refresh().await);
+    assert_eq!(failover.pending_writes(), 2);
+    Ok(())
+}
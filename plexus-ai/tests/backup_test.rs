@@ -0,0 +1,199 @@
+use async_trait::async_trait;
+use plexus_ai::{
+    Document, Embedder, MemoryBackup, MemoryRecord, SearchFilter, SearchResult, SimpleVectorStore,
+    VectorStore,
+};
+use std::sync::{Arc, Mutex};
+
+/// Embeds a text as its length, under the model id it is given, and records what it
+/// embeds.
+struct LengthEmbedder {
+    model_id: &'static str,
+    embedded: Mutex<Vec<String>>,
+}
+
+impl LengthEmbedder {
+    fn new(model_id: &'static str) -> Arc<Self> {
+        Arc::new(Self {
+            model_id,
+            embedded: Mutex::new(vec![]),
+        })
+    }
+}
+
+#[async_trait]
+impl Embedder for LengthEmbedder {
+    fn model_id(&self) -> &str {
+        self.model_id
+    }
+
+    fn dimension(&self) -> usize {
+        2
+    }
+
+    async fn embed(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+        self.embedded.lock().unwrap().push(text.to_string());
+        Ok(vec![text.len() as f32, 1.0])
+    }
+
+    async fn token_spans(&self, _text: &str) -> anyhow::Result<Vec<(usize, usize)>> {
+        Ok(vec![])
+    }
+}
+
+async fn store_with(
+    documents: &[(&str, &str, Vec<f32>)],
+) -> anyhow::Result<Arc<SimpleVectorStore>> {
+    let store = Arc::new(SimpleVectorStore::new());
+    for (id, text, vector) in documents {
+        let document = Document::new(*id, *text).with_source("notes.md");
+        store.upsert(document, vector.clone()).await?;
+    }
+    Ok(store)
+}
+
+#[tokio::test]
+async fn test_round_trip_keeps_vectors_of_same_model() -> anyhow::Result<()> {
+    let source = store_with(&[
+        ("b", "second", vec![0.0, 1.0]),
+        ("a", "first", vec![1.0, 0.0]),
+    ])
+    .await?;
+    let mut export = vec![];
+    let count = MemoryBackup::new(LengthEmbedder::new("minilm"), source)
+        .export(&mut export)
+        .await?;
+    assert_eq!(count, 2);
+
+    // One self-describing record per line, in id order
+    let lines: Vec<MemoryRecord> = String::from_utf8(export.clone())?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(lines[0].id, "a");
+    assert_eq!(lines[0].model, "minilm");
+    assert_eq!(lines[1].metadata["source"], "notes.md");
+
+    let embedder = LengthEmbedder::new("minilm");
+    let target = Arc::new(SimpleVectorStore::new());
+    let report = MemoryBackup::new(embedder.clone(), target.clone())
+        .import(export.as_slice())
+        .await?;
+    assert_eq!((report.imported, report.reembedded), (2, 0));
+    assert!(embedder.embedded.lock().unwrap().is_empty());
+
+    let results = target.search(vec![1.0, 0.0], 1).await?;
+    assert_eq!(results[0].id, "a");
+    assert_eq!(
+        target.get("b").await?.unwrap().metadata["source"],
+        "notes.md"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_reembeds_other_models() -> anyhow::Result<()> {
+    let source = store_with(&[("old", "from minilm", vec![0.3, 0.7])]).await?;
+    let mut export = vec![];
+    MemoryBackup::new(LengthEmbedder::new("minilm"), source)
+        .export(&mut export)
+        .await?;
+
+    let embedder = LengthEmbedder::new("nomic");
+    let target = Arc::new(SimpleVectorStore::new());
+    let report = MemoryBackup::new(embedder.clone(), target.clone())
+        .import(export.as_slice())
+        .await?;
+    assert_eq!((report.imported, report.reembedded), (1, 1));
+    assert_eq!(*embedder.embedded.lock().unwrap(), ["from minilm"]);
+    // The new embedding of the text, [11, 1], is stored normalized
+    let (_, vector) = &target.entries().await?[0];
+    assert!((vector[0] / vector[1] - 11.0).abs() < 1e-4, "{:?}", vector);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_export_imports_nothing() -> anyhow::Result<()> {
+    let valid = serde_json::to_string(&MemoryRecord::new(
+        Document::new("ok", "ok"),
+        "minilm",
+        vec![1.0, 0.0],
+    ))?;
+    let export = format!("{}\n\n{{\"id\": \"broken\"}}\n", valid);
+
+    let target = Arc::new(SimpleVectorStore::new());
+    let error = MemoryBackup::new(LengthEmbedder::new("minilm"), target.clone())
+        .import(export.as_bytes())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("line 3"), "{}", error);
+    assert_eq!(target.count().await?, 0);
+    Ok(())
+}
+
+/// A store whose server went away: every call fails.
+struct UnreachableStore;
+
+#[async_trait]
+impl VectorStore for UnreachableStore {
+    async fn add(&self, _id: &str, _vector: Vec<f32>) -> anyhow::Result<()> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn upsert(&self, _document: Document, _vector: Vec<f32>) -> anyhow::Result<()> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn search_filtered(
+        &self,
+        _query_vector: Vec<f32>,
+        _k: usize,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<Vec<SearchResult>> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn get(&self, _id: &str) -> anyhow::Result<Option<Document>> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn get_entry(&self, _id: &str) -> anyhow::Result<Option<(Document, Vec<f32>)>> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn delete(&self, _id: &str) -> anyhow::Result<()> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn count(&self) -> anyhow::Result<usize> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn documents(&self) -> anyhow::Result<Vec<Document>> {
+        anyhow::bail!("unreachable")
+    }
+
+    async fn entries(&self) -> anyhow::Result<Vec<(Document, Vec<f32>)>> {
+        anyhow::bail!("unreachable")
+    }
+}
+
+#[tokio::test]
+async fn test_failed_export_keeps_previous_file() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("memory.jsonl");
+    let source = store_with(&[("a", "first", vec![1.0, 0.0])]).await?;
+    MemoryBackup::new(LengthEmbedder::new("minilm"), source)
+        .export_file(&path)
+        .await?;
+    let exported = std::fs::read_to_string(&path)?;
+
+    let broken = MemoryBackup::new(LengthEmbedder::new("minilm"), Arc::new(UnreachableStore));
+    assert!(broken.export_file(&path).await.is_err());
+    assert_eq!(std::fs::read_to_string(&path)?, exported);
+    let names: Vec<_> = std::fs::read_dir(dir.path())?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(names, ["memory.jsonl"]);
+    Ok(())
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use candle_core::quantized::gguf_file;
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use candle_transformers::models::quantized_llama::ModelWeights;
+use common::tiny_gguf;
+use plexus_ai::{BatchScheduler, BatchedLlama, PrefixCache, SequenceRequest};
+use std::io::Cursor;
+use std::time::Duration;
+
+const VOCAB: usize = 32;
+
+fn load_both(bytes: &[u8]) -> anyhow::Result<(ModelWeights, BatchedLlama)> {
+    let mut reader = Cursor::new(bytes);
+    let content = gguf_file::Content::read(&mut reader)?;
+    let reference = ModelWeights::from_gguf(content, &mut reader, &Device::Cpu)?;
+    let mut reader = Cursor::new(bytes);
+    let content = gguf_file::Content::read(&mut reader)?;
+    let batched = BatchedLlama::from_gguf(content, &mut reader, &Device::Cpu)?;
+    Ok((reference, batched))
+}
+
+fn max_diff(a: &Tensor, b: &Tensor) -> anyhow::Result<f32> {
+    Ok((a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()?)
+}
+
+/// Greedy decoding with candle's single-sequence model, one token at a time.
+fn reference_generate(model: &ModelWeights, prompt: &[u32], n: usize) -> anyhow::Result<Vec<u32>> {
+    let mut model = model.clone();
+    let input = Tensor::new(prompt, &Device::Cpu)?.unsqueeze(0)?;
+    let mut logits = model.forward(&input, 0)?.squeeze(0)?;
+    let mut out = vec![];
+    for i in 0..n {
+        let token = logits.argmax(0)?.to_scalar::<u32>()?;
+        out.push(token);
+        let input = Tensor::new(&[token], &Device::Cpu)?.unsqueeze(0)?;
+        logits = model.forward(&input, prompt.len() + i)?.squeeze(0)?;
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_batched_decode_matches_reference() -> anyhow::Result<()> {
+    let (reference, batched) = load_both(&tiny_gguf(VOCAB, 2)?)?;
+    let prompts: [&[u32]; 2] = [&[1, 5, 9, 3], &[2, 7]];
+
+    let mut caches = vec![];
+    for prompt in prompts {
+        let mut cache = batched.new_cache();
+        let logits = batched.prefill(prompt, &mut cache)?;
+        let input = Tensor::new(prompt, &Device::Cpu)?.unsqueeze(0)?;
+        let expected = reference.clone().forward(&input, 0)?.squeeze(0)?;
+        assert!(max_diff(&logits, &expected)? < 1e-4);
+        caches.push(cache);
+    }
+
+    // One shared step for both sequences, at different positions
+    let next = [4u32, 11];
+    let mut refs: Vec<_> = caches.iter_mut().collect();
+    let logits = batched.decode(&next, &mut refs)?;
+    assert_eq!(logits.dims(), &[2, VOCAB]);
+    for (i, prompt) in prompts.iter().enumerate() {
+        let mut model = reference.clone();
+        model.forward(&Tensor::new(*prompt, &Device::Cpu)?.unsqueeze(0)?, 0)?;
+        let input = Tensor::new(&[next[i]], &Device::Cpu)?.unsqueeze(0)?;
+        let expected = model.forward(&input, prompt.len())?.squeeze(0)?;
+        assert!(max_diff(&logits.get(i)?, &expected)? < 1e-4);
+        assert_eq!(caches[i].len(), prompt.len() + 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_prefill_on_cached_prefix() -> anyhow::Result<()> {
+    let (_, batched) = load_both(&tiny_gguf(VOCAB, 2)?)?;
+    let tokens = [1u32, 5, 9, 3, 8, 2];
+
+    let mut full = batched.new_cache();
+    let expected = batched.prefill(&tokens, &mut full)?;
+
+    // Feeding a multi-token suffix on top of a cached prefix gives the same result
+    let mut split = batched.new_cache();
+    batched.prefill(&tokens[..2], &mut split)?;
+    let logits = batched.prefill(&tokens[2..], &mut split)?;
+    assert!(max_diff(&logits, &expected)? < 1e-4);
+    assert_eq!(split.len(), tokens.len());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scheduler_batches_concurrent_requests() -> anyhow::Result<()> {
+    let bytes = tiny_gguf(VOCAB, 2)?;
+    let (reference, batched) = load_both(&bytes)?;
+    let scheduler = BatchScheduler::new(batched, 2, PrefixCache::new(4, Duration::from_secs(60)));
+
+    let prompts: Vec<Vec<u32>> = vec![vec![1, 2, 3], vec![4, 5], vec![6, 7, 8, 9], vec![10]];
+    let lengths = [5usize, 3, 6, 4];
+
+    // More requests than the batch size: later ones are admitted as earlier ones retire
+    let mut receivers = vec![];
+    for (prompt, &max_new_tokens) in prompts.iter().zip(&lengths) {
+        receivers.push(scheduler.submit(SequenceRequest {
+            tokens: prompt.clone(),
+            max_new_tokens,
+            stop_tokens: vec![],
+            logits_processor: LogitsProcessor::from_sampling(0, Sampling::ArgMax),
+            constraint: None,
+            deterministic: false,
+        })?);
+    }
+
+    for ((mut rx, prompt), &n) in receivers.into_iter().zip(&prompts).zip(&lengths) {
+        let mut generated = vec![];
+        while let Some(token) = rx.recv().await {
+            generated.push(token?);
+        }
+        assert_eq!(generated, reference_generate(&reference, prompt, n)?);
+    }
+
+    // Invalid requests fail without affecting the scheduler
+    let mut rx = scheduler.submit(SequenceRequest {
+        tokens: vec![],
+        max_new_tokens: 3,
+        stop_tokens: vec![],
+        logits_processor: LogitsProcessor::from_sampling(0, Sampling::ArgMax),
+        constraint: None,
+        deterministic: false,
+    })?;
+    assert!(rx.recv().await.unwrap().is_err());
+    assert!(rx.recv().await.is_none());
+    Ok(())
+}
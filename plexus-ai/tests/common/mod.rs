@@ -0,0 +1,178 @@
+//! Helpers shared by the integration tests: tiny models to run and a stub engine.
+// Every test crate compiles this module but uses only some of it
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use plexus_ai::{
+    ChatTemplate, GenerationParams, GenerationRecord, LLMEngine, PromptTemplate,
+    DEFAULT_CONTEXT_LENGTH,
+};
+use std::io::Cursor;
+use std::sync::Mutex;
+
+const EMBD: usize = 16;
+const HEADS: usize = 4;
+const KV_HEADS: usize = 2;
+const FF: usize = 32;
+
+/// Writes a tiny llama GGUF with random weights, `vocab` tokens and `layers` blocks.
+pub fn tiny_gguf(vocab: usize, layers: usize) -> anyhow::Result<Vec<u8>> {
+    let head_dim = EMBD / HEADS;
+    let kv_dim = KV_HEADS * head_dim;
+    let mut tensors: Vec<(String, QTensor)> = vec![];
+    let mut add = |name: String, shape: &[usize]| -> anyhow::Result<()> {
+        let t = Tensor::randn(0f32, 0.5, shape, &Device::Cpu)?;
+        tensors.push((name, QTensor::quantize(&t, GgmlDType::F32)?));
+        Ok(())
+    };
+    add("token_embd.weight".into(), &[vocab, EMBD])?;
+    add("output_norm.weight".into(), &[EMBD])?;
+    add("output.weight".into(), &[vocab, EMBD])?;
+    for i in 0..layers {
+        add(format!("blk.{i}.attn_q.weight"), &[EMBD, EMBD])?;
+        add(format!("blk.{i}.attn_k.weight"), &[kv_dim, EMBD])?;
+        add(format!("blk.{i}.attn_v.weight"), &[kv_dim, EMBD])?;
+        add(format!("blk.{i}.attn_output.weight"), &[EMBD, EMBD])?;
+        add(format!("blk.{i}.ffn_gate.weight"), &[FF, EMBD])?;
+        add(format!("blk.{i}.ffn_down.weight"), &[EMBD, FF])?;
+        add(format!("blk.{i}.ffn_up.weight"), &[FF, EMBD])?;
+        add(format!("blk.{i}.attn_norm.weight"), &[EMBD])?;
+        add(format!("blk.{i}.ffn_norm.weight"), &[EMBD])?;
+    }
+
+    let metadata = [
+        (
+            "llama.attention.head_count",
+            gguf_file::Value::U32(HEADS as u32),
+        ),
+        (
+            "llama.attention.head_count_kv",
+            gguf_file::Value::U32(KV_HEADS as u32),
+        ),
+        ("llama.block_count", gguf_file::Value::U32(layers as u32)),
+        ("llama.embedding_length", gguf_file::Value::U32(EMBD as u32)),
+        (
+            "llama.rope.dimension_count",
+            gguf_file::Value::U32(head_dim as u32),
+        ),
+        (
+            "llama.attention.layer_norm_rms_epsilon",
+            gguf_file::Value::F32(1e-5),
+        ),
+    ];
+    let metadata: Vec<(&str, &gguf_file::Value)> = metadata.iter().map(|(k, v)| (*k, v)).collect();
+    let tensors: Vec<(&str, &QTensor)> = tensors.iter().map(|(k, v)| (k.as_str(), v)).collect();
+
+    let mut buf = Cursor::new(Vec::new());
+    gguf_file::write(&mut buf, &metadata, &tensors)?;
+    Ok(buf.into_inner())
+}
+
+type DeterministicFn =
+    dyn Fn(&str, &GenerationParams) -> anyhow::Result<(String, GenerationRecord)> + Send + Sync;
+
+/// Engine stub with a ChatML template that answers every prompt with the same reply.
+///
+/// It counts whitespace-separated words as tokens and, like a real model, fails on
+/// prompts longer than its context window.
+pub struct StubEngine {
+    reply: String,
+    context_length: usize,
+    memory_bytes: u64,
+    deterministic: Option<Box<DeterministicFn>>,
+    /// Every prompt `generate` answered, oldest first
+    pub prompts: Mutex<Vec<String>>,
+}
+
+impl Default for StubEngine {
+    fn default() -> Self {
+        Self {
+            reply: String::new(),
+            context_length: DEFAULT_CONTEXT_LENGTH,
+            memory_bytes: 0,
+            deterministic: None,
+            prompts: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl StubEngine {
+    pub fn with_reply(mut self, reply: &str) -> Self {
+        self.reply = reply.to_string();
+        self
+    }
+
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = context_length;
+        self
+    }
+
+    pub fn with_memory_footprint(mut self, memory_bytes: u64) -> Self {
+        self.memory_bytes = memory_bytes;
+        self
+    }
+
+    /// Answers deterministic generations with `generate`.
+    pub fn with_deterministic(
+        mut self,
+        generate: impl Fn(&str, &GenerationParams) -> anyhow::Result<(String, GenerationRecord)>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.deterministic = Some(Box::new(generate));
+        self
+    }
+}
+
+#[async_trait]
+impl LLMEngine for StubEngine {
+    async fn load_model(&self, _model_id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn generate(&self, prompt: &str) -> anyhow::Result<String> {
+        if prompt.split_whitespace().count() > self.context_length {
+            anyhow::bail!("Prompt does not fit the context window");
+        }
+        self.prompts.lock().unwrap().push(prompt.to_string());
+        Ok(self.reply.clone())
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        _sender: tokio::sync::mpsc::Sender<String>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn generate_deterministic(
+        &self,
+        prompt: &str,
+        params: &GenerationParams,
+    ) -> anyhow::Result<(String, GenerationRecord)> {
+        match &self.deterministic {
+            Some(generate) => generate(prompt, params),
+            None => anyhow::bail!("This engine does not support deterministic generation"),
+        }
+    }
+
+    async fn chat_template(&self) -> anyhow::Result<PromptTemplate> {
+        Ok(PromptTemplate::new(ChatTemplate::ChatMl))
+    }
+
+    fn context_length(&self) -> usize {
+        self.context_length
+    }
+
+    async fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        Ok(text.split_whitespace().count())
+    }
+
+    fn memory_footprint(&self) -> u64 {
+        self.memory_bytes
+    }
+}
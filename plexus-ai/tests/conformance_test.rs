@@ -0,0 +1,104 @@
+//! The score contract of [`VectorStore`], checked against every backend available.
+//!
+//! LanceDB runs with the `lancedb` feature; Qdrant runs when `QDRANT_URL` points at a
+//! server (e.g. `http://localhost:6334`).
+
+use plexus_ai::{EmbedderRegistry, SimpleVectorStore, VectorStore};
+
+const DIMENSION: usize = 384;
+
+/// A vector with the leading components `head`, padded with zeros.
+fn vector(head: &[f32]) -> Vec<f32> {
+    let mut vector = head.to_vec();
+    vector.resize(DIMENSION, 0.0);
+    vector
+}
+
+async fn check_scores(store: &dyn VectorStore) -> anyhow::Result<()> {
+    // Lengths differ on purpose: scores must not depend on them
+    store
+        .add_document("same", "same", vector(&[2.0, 0.0]))
+        .await?;
+    store
+        .add_document("close", "close", vector(&[3.0, 4.0]))
+        .await?;
+    store
+        .add_document("unrelated", "unrelated", vector(&[0.0, 0.5]))
+        .await?;
+    store
+        .add_document("opposite", "opposite", vector(&[-1.0, 0.0]))
+        .await?;
+
+    let results = store.search(vector(&[1.0, 0.0]), 4).await?;
+    let scores: Vec<(&str, f32)> = results.iter().map(|r| (r.id.as_str(), r.score)).collect();
+    let expected = [
+        ("same", 1.0),
+        ("close", 0.6),
+        ("unrelated", 0.0),
+        ("opposite", -1.0),
+    ];
+    assert_eq!(scores.len(), expected.len(), "{:?}", scores);
+    for ((id, score), (expected_id, expected_score)) in scores.iter().zip(expected) {
+        assert_eq!(*id, expected_id, "{:?}", scores);
+        assert!(
+            (score - expected_score).abs() < 1e-4,
+            "{} scored {}, expected {}",
+            id,
+            score,
+            expected_score
+        );
+    }
+
+    let results = store.search(vector(&[1.0, 0.0]), 2).await?;
+    assert_eq!(results.len(), 2);
+    assert!(store.search(vec![1.0, 0.0], 2).await.is_err());
+
+    // Stored vectors may be normalized but keep their direction
+    let mut entries = store.entries().await?;
+    entries.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+    let ids: Vec<&str> = entries.iter().map(|(d, _)| d.id.as_str()).collect();
+    assert_eq!(ids, ["close", "opposite", "same", "unrelated"]);
+    let (_, close) = &entries[0];
+    assert_eq!(close.len(), DIMENSION);
+    assert!(
+        (close[0] / close[1] - 0.75).abs() < 1e-4,
+        "{:?}",
+        &close[..2]
+    );
+
+    // A single entry is fetched the same way
+    let (document, vector) = store.get_entry("close").await?.expect("close is stored");
+    assert_eq!(document.text, "close");
+    assert_eq!(&vector, close);
+    assert!(store.get_entry("missing").await?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_memory_store_scores() -> anyhow::Result<()> {
+    check_scores(&SimpleVectorStore::new()).await
+}
+
+#[cfg(feature = "lancedb")]
+#[tokio::test]
+async fn test_lancedb_store_scores() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let model = EmbedderRegistry::new().get("minilm")?;
+    let store =
+        plexus_ai::LanceDbStore::new(&temp_dir.path().join("vectors.lance"), &model).await?;
+    check_scores(&store).await
+}
+
+#[tokio::test]
+async fn test_qdrant_store_scores() -> anyhow::Result<()> {
+    let Ok(url) = std::env::var("QDRANT_URL") else {
+        eprintln!("QDRANT_URL not set, skipping");
+        return Ok(());
+    };
+    // A model id of its own keeps the test away from real memory; the ids it writes are
+    // fixed, so reruns overwrite them
+    let mut model = EmbedderRegistry::new().get("minilm")?;
+    model.id = "conformance".to_string();
+    let store = plexus_ai::QdrantStore::new(&url, &model).await?;
+    check_scores(&store).await
+}
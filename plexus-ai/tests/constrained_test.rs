@@ -0,0 +1,132 @@
+use candle_core::{Device, Tensor};
+use plexus_ai::{Grammar, ResponseFormat, TokenConstraint, TokenVocabulary};
+use serde_json::json;
+use std::sync::Arc;
+
+fn accepts(grammar: &Arc<Grammar>, text: &str) -> bool {
+    let mut state = grammar.start().unwrap();
+    state.advance_str(text) && state.is_complete()
+}
+
+#[test]
+fn test_gbnf_grammar() -> anyhow::Result<()> {
+    let grammar = Grammar::from_gbnf(
+        r#"
+        # A list of small numbers
+        root ::= "[" ws num ("," ws num)* "]"
+        num  ::= [1-9] [0-9]?
+        ws   ::= " "?
+        "#,
+    )?;
+
+    assert!(accepts(&grammar, "[1]"));
+    assert!(accepts(&grammar, "[12, 3,45]"));
+    assert!(!accepts(&grammar, "[123]"));
+    assert!(!accepts(&grammar, "[0]"));
+    assert!(!accepts(&grammar, "[1,]"));
+
+    let mut state = grammar.start()?;
+    assert!(state.advance_str("[4"));
+    assert!(!state.advance('x'));
+    assert!(state.can_continue());
+
+    assert!(Grammar::from_gbnf(r#"root ::= missing"#).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_regex_grammar() -> anyhow::Result<()> {
+    let grammar = Grammar::from_regex(r"^\d{3}-[A-Z]+$")?;
+    assert!(accepts(&grammar, "123-AB"));
+    assert!(!accepts(&grammar, "12-AB"));
+    assert!(!accepts(&grammar, "123-ab"));
+    assert!(!accepts(&grammar, "123-"));
+    Ok(())
+}
+
+#[test]
+fn test_json_schema_grammar() -> anyhow::Result<()> {
+    let format: ResponseFormat = serde_json::from_value(json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "person",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "age": {"type": "integer"},
+                    "role": {"enum": ["admin", "user"]}
+                },
+                "required": ["name", "age"]
+            }
+        }
+    }))?;
+    let grammar = format.grammar()?.expect("json_schema is constrained");
+
+    assert!(accepts(&grammar, r#"{"name": "Ada", "age": 36}"#));
+    assert!(accepts(
+        &grammar,
+        r#"{"name":"Ada","age":-2,"role":"admin"}"#
+    ));
+    assert!(!accepts(&grammar, r#"{"age": 36, "name": "Ada"}"#));
+    assert!(!accepts(&grammar, r#"{"name": "Ada"}"#));
+    assert!(!accepts(&grammar, r#"{"name": "Ada", "age": 3.5}"#));
+    assert!(!accepts(
+        &grammar,
+        r#"{"name": "Ada", "age": 1, "role": "root"}"#
+    ));
+
+    assert!(ResponseFormat::Text.grammar()?.is_none());
+    assert!(accepts(
+        &ResponseFormat::JsonObject.grammar()?.unwrap(),
+        r#"{"a": [1, true, null, {"b": "c"}]}"#
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_token_constraint_masks_logits() -> anyhow::Result<()> {
+    const EOS: u32 = 5;
+    let vocab = Arc::new(TokenVocabulary::from_texts([
+        (0, Some("ye".to_string())),
+        (1, Some("s".to_string())),
+        (2, Some("no".to_string())),
+        (3, Some("maybe".to_string())),
+        (4, None),
+        (EOS, None),
+    ]));
+    let grammar = Grammar::from_gbnf(r#"root ::= "yes" | "no""#)?;
+    let mut constraint = TokenConstraint::new(&grammar, vocab, vec![EOS])?;
+
+    let mut allowed = constraint.allowed_tokens();
+    allowed.sort();
+    assert_eq!(allowed, vec![0, 2]);
+
+    // The model prefers "maybe", but only "ye" and "no" survive the mask
+    let logits = Tensor::new(&[1f32, 0.0, 2.0, 9.0, 9.0, 9.0], &Device::Cpu)?;
+    let masked = constraint.mask_logits(&logits)?.to_vec1::<f32>()?;
+    assert_eq!(masked[2], 2.0);
+    assert!(masked[3].is_infinite() && masked[4].is_infinite() && masked[5].is_infinite());
+
+    constraint.advance(0)?;
+    assert_eq!(constraint.allowed_tokens(), vec![1]);
+    assert!(constraint.advance(EOS).is_err());
+    constraint.advance(1)?;
+    assert!(constraint.is_finished());
+    assert_eq!(constraint.allowed_tokens(), vec![EOS]);
+    assert!(constraint.advance(2).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_response_format_wire_shape() -> anyhow::Result<()> {
+    let format = ResponseFormat::json_schema(json!({"type": "boolean"}));
+    let value = serde_json::to_value(&format)?;
+    assert_eq!(value["type"], "json_schema");
+    assert_eq!(value["json_schema"]["schema"]["type"], "boolean");
+    assert_eq!(serde_json::from_value::<ResponseFormat>(value)?, format);
+
+    let text: ResponseFormat = serde_json::from_value(json!({"type": "text"}))?;
+    assert_eq!(text, ResponseFormat::Text);
+    Ok(())
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use common::StubEngine;
+use plexus_ai::{ChatHistory, ContextPolicy, LLMEngine, Role};
+
+/// An engine with a context window of `context_length` words that summarizes every
+/// conversation the same way.
+fn word_engine(context_length: usize) -> StubEngine {
+    StubEngine::default()
+        .with_context_length(context_length)
+        .with_reply("They talked about cats.")
+}
+
+fn words(n: usize) -> String {
+    vec!["word"; n].join(" ")
+}
+
+fn conversation(policy: ContextPolicy) -> ChatHistory {
+    let mut history = ChatHistory::new(100).with_context_policy(policy);
+    history.set_system_prompt("Be brief.".to_string());
+    for turn in 0..4 {
+        history.add_user(format!("question {turn} {}", words(10)));
+        history.add_assistant(format!("answer {turn} {}", words(10)));
+    }
+    history.add_user("latest question".to_string());
+    history
+}
+
+#[tokio::test]
+async fn test_oldest_turns_evicted_by_tokens() -> anyhow::Result<()> {
+    let engine = word_engine(60);
+    let mut history = conversation(ContextPolicy::default().with_reserve_tokens(10));
+
+    let prompt = history.fit_context(&engine).await?;
+    assert!(engine.count_tokens(&prompt).await? <= 50);
+    assert!(prompt.contains("Be brief."));
+    assert!(prompt.contains("latest question"));
+    assert!(prompt.contains("answer 3"));
+    assert!(!prompt.contains("question 0"));
+
+    // Whole turns go: no reply is left without its question
+    let messages = history.get_history();
+    assert_eq!(history.system_prompt(), Some("Be brief."));
+    assert!(matches!(messages[0].role, Role::User));
+    assert!(engine.prompts.lock().unwrap().is_empty());
+    assert!(history.summary().is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_evicted_turns_are_summarized() -> anyhow::Result<()> {
+    let engine = word_engine(90);
+    let mut history = conversation(
+        ContextPolicy::default()
+            .with_reserve_tokens(10)
+            .with_summarize(true),
+    );
+
+    let prompt = history.fit_context(&engine).await?;
+    assert_eq!(history.summary(), Some("They talked about cats."));
+    assert!(prompt.starts_with("<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>system\nSummary of the earlier conversation: They talked about cats.<|im_end|>"));
+    assert!(prompt.contains("latest question"));
+
+    // Turns are evicted until the prompt fits, then summarized in one request
+    let prompts = engine.prompts.lock().unwrap().clone();
+    assert_eq!(prompts.len(), 1);
+    assert!(prompts[0].contains("user: question 0"));
+    assert!(prompts[0].contains("assistant: answer 1"));
+    assert!(!prompts[0].contains("question 2"));
+
+    // The summary is part of the persisted state
+    let restored: ChatHistory = serde_json::from_str(&serde_json::to_string(&history)?)?;
+    assert_eq!(restored.summary(), history.summary());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_latest_turn_is_never_evicted() -> anyhow::Result<()> {
+    let engine = word_engine(40);
+    let mut history = ChatHistory::new(100);
+    history.set_system_prompt("Be brief.".to_string());
+    history.add_user("short".to_string());
+    history.add_assistant("reply".to_string());
+    history.add_user(words(100));
+
+    assert!(history.fit_context(&engine).await.is_err());
+    assert_eq!(history.get_history().len(), 1);
+    assert_eq!(history.system_prompt(), Some("Be brief."));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_oversized_summary_does_not_wedge_session() -> anyhow::Result<()> {
+    let engine = word_engine(60);
+    let policy = ContextPolicy::default()
+        .with_reserve_tokens(10)
+        .with_summarize(true);
+
+    // The oldest messages are left out of a transcript too long to summarize
+    let mut history = ChatHistory::new(100).with_context_policy(policy.clone());
+    history.add_user(format!("early {}", words(45)));
+    history.add_assistant("noted".to_string());
+    history.add_user("latest question".to_string());
+    history.fit_context(&engine).await?;
+    let summarized = engine.prompts.lock().unwrap().join("\n");
+    assert!(!summarized.contains("early"));
+    assert!(summarized.contains("assistant: noted"));
+    assert_eq!(history.summary(), Some("They talked about cats."));
+
+    // A single message too long to summarize is dropped instead of failing every turn
+    let mut history = ChatHistory::new(100).with_context_policy(policy);
+    history.add_user(words(100));
+    history.add_user("latest question".to_string());
+    let prompt = history.fit_context(&engine).await?;
+    assert!(prompt.contains("latest question"));
+    assert_eq!(history.summary(), None);
+
+    history.add_assistant("answer".to_string());
+    history.add_user("next question".to_string());
+    assert!(history
+        .fit_context(&engine)
+        .await?
+        .contains("next question"));
+    Ok(())
+}
+
+#[test]
+fn test_message_cap_evicts_whole_turns() {
+    let mut history = ChatHistory::new(3);
+    history.set_system_prompt("Be brief.".to_string());
+    history.add_user("one".to_string());
+    history.add_assistant("two".to_string());
+    history.add_user("three".to_string());
+    history.add_assistant("four".to_string());
+
+    // Dropping only "one" would leave a reply without its question
+    let messages: Vec<String> = history
+        .get_history()
+        .into_iter()
+        .map(|m| m.content)
+        .collect();
+    assert_eq!(messages, vec!["three", "four"]);
+    assert_eq!(history.system_prompt(), Some("Be brief."));
+
+    // The latest turn is kept whole even when it alone exceeds the cap
+    history.add_tool_calls("five".to_string(), vec![]);
+    history.add_assistant("six".to_string());
+    assert_eq!(history.len(), 4);
+}
+
+#[tokio::test]
+async fn test_retrieved_context_is_rendered_not_stored() -> anyhow::Result<()> {
+    let engine = word_engine(1000);
+    let mut history = ChatHistory::new(100);
+    history.add_user("first".to_string());
+    history.add_assistant("reply".to_string());
+    history.add_user("latest".to_string());
+
+    let prompt = history
+        .fit_context_with(&engine, Some("Context information:\n- cats purr"))
+        .await?;
+    assert!(prompt.ends_with("<|im_start|>system\nContext information:\n- cats purr<|im_end|>\n<|im_start|>user\nlatest<|im_end|>\n<|im_start|>assistant\n"));
+
+    // A session without a system prompt does not adopt the context as one
+    assert_eq!(history.system_prompt(), None);
+    assert_eq!(history.get_history().len(), 3);
+    assert!(!history
+        .format_prompt(&engine.chat_template().await?)?
+        .contains("cats"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replace_conversation_keeps_system_prompt() -> anyhow::Result<()> {
+    let engine = word_engine(60);
+    let mut history = conversation(
+        ContextPolicy::default()
+            .with_reserve_tokens(10)
+            .with_summarize(true),
+    );
+
+    // A copy is fitted and answered while the system prompt changes
+    let mut copy = history.clone();
+    copy.fit_context(&engine).await?;
+    copy.add_assistant("latest answer".to_string());
+    history.set_system_prompt("Be thorough.".to_string());
+
+    history.replace_conversation(copy.clone());
+    assert_eq!(history.system_prompt(), Some("Be thorough."));
+    assert_eq!(history.summary(), Some("They talked about cats."));
+    assert_eq!(history.len(), copy.len());
+    assert!(history.len() < 10);
+    Ok(())
+}
+
+#[test]
+fn test_legacy_history_system_prompt() -> anyhow::Result<()> {
+    // Older histories kept the system prompt, and retrieved context, as messages
+    let legacy = r#"{"max_history": 10, "messages": [
+        {"role": "System", "content": "Be brief."},
+        {"role": "User", "content": "Hello"}
+    ]}"#;
+    let history: ChatHistory = serde_json::from_str(legacy)?;
+    assert_eq!(history.system_prompt(), Some("Be brief."));
+    assert_eq!(history.len(), 1);
+
+    let legacy = r#"{"max_history": 10, "messages": [
+        {"role": "System", "content": "Context information:\n- cats purr"},
+        {"role": "User", "content": "Hello"}
+    ]}"#;
+    let history: ChatHistory = serde_json::from_str(legacy)?;
+    assert_eq!(history.system_prompt(), None);
+    assert_eq!(history.len(), 2);
+
+    // The system prompt round-trips in its own field
+    let mut history = ChatHistory::new(10);
+    history.set_system_prompt("Be brief.".to_string());
+    history.add_user("Hello".to_string());
+    let restored: ChatHistory = serde_json::from_str(&serde_json::to_string(&history)?)?;
+    assert_eq!(restored.system_prompt(), Some("Be brief."));
+    assert_eq!(restored.len(), 1);
+    Ok(())
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use candle_core::quantized::gguf_file;
+use candle_core::Device;
+use candle_transformers::generation::Sampling;
+use common::{tiny_gguf, StubEngine};
+use plexus_ai::{
+    BatchScheduler, BatchedLlama, GenerationParams, GenerationRecord, LLMEngine, ModelFingerprint,
+    PrefixCache, SequenceRequest,
+};
+use std::io::Cursor;
+use std::time::Duration;
+
+fn load(bytes: &[u8]) -> anyhow::Result<BatchedLlama> {
+    let mut reader = Cursor::new(bytes);
+    let content = gguf_file::Content::read(&mut reader)?;
+    BatchedLlama::from_gguf(content, &mut reader, &Device::Cpu)
+}
+
+fn request(tokens: Vec<u32>, params: &GenerationParams, deterministic: bool) -> SequenceRequest {
+    SequenceRequest {
+        tokens,
+        max_new_tokens: params.max_new_tokens,
+        stop_tokens: vec![],
+        logits_processor: params.logits_processor(),
+        constraint: None,
+        deterministic,
+    }
+}
+
+async fn collect(
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<u32>>,
+) -> anyhow::Result<Vec<u32>> {
+    let mut out = vec![];
+    while let Some(token) = tokio::time::timeout(Duration::from_secs(30), rx.recv()).await? {
+        out.push(token?);
+    }
+    Ok(out)
+}
+
+#[tokio::test]
+async fn test_deterministic_sequences_reproduce_under_load() -> anyhow::Result<()> {
+    let weights = tiny_gguf(32, 2)?;
+    let params = GenerationParams::greedy(12)
+        .with_seed(7)
+        .with_temperature(0.9)
+        .with_top_k(8);
+    let prompt = vec![1u32, 5, 9, 3];
+
+    // Alone on a fresh scheduler
+    let scheduler = BatchScheduler::new(
+        load(&weights)?,
+        4,
+        PrefixCache::new(4, Duration::from_secs(60)),
+    );
+    let expected = collect(scheduler.submit(request(prompt.clone(), &params, true))?).await?;
+    assert_eq!(expected.len(), 12);
+
+    // Again while other sequences share the batch and warm the prefix cache
+    let scheduler = BatchScheduler::new(
+        load(&weights)?,
+        4,
+        PrefixCache::new(4, Duration::from_secs(60)),
+    );
+    let noise = GenerationParams::greedy(20);
+    let warm = collect(scheduler.submit(request(prompt.clone(), &noise, false))?).await?;
+    assert_eq!(warm.len(), 20);
+    let background: Vec<_> = [vec![2u32, 7], vec![30, 1, 1]]
+        .into_iter()
+        .map(|p| scheduler.submit(request(p, &noise, false)))
+        .collect::<anyhow::Result<_>>()?;
+    let replayed = collect(scheduler.submit(request(prompt.clone(), &params, true))?).await?;
+    assert_eq!(replayed, expected);
+    for rx in background {
+        assert_eq!(collect(rx).await?.len(), 20);
+    }
+
+    // The seed is part of the reproducible state
+    let mut outputs = vec![];
+    for seed in 0..4 {
+        let params = params.clone().with_seed(seed);
+        outputs.push(collect(scheduler.submit(request(prompt.clone(), &params, true))?).await?);
+    }
+    assert!(outputs.iter().any(|o| o != &expected));
+    Ok(())
+}
+
+#[test]
+fn test_params_select_sampling() {
+    assert!(matches!(
+        GenerationParams::default().sampling(),
+        Sampling::ArgMax
+    ));
+    assert!(matches!(
+        GenerationParams::default().with_temperature(0.0).sampling(),
+        Sampling::ArgMax
+    ));
+    assert!(matches!(
+        GenerationParams::default()
+            .with_temperature(0.7)
+            .with_top_p(0.9)
+            .sampling(),
+        Sampling::TopP { .. }
+    ));
+    assert!(matches!(
+        GenerationParams::default()
+            .with_temperature(0.7)
+            .with_top_k(5)
+            .with_top_p(0.9)
+            .sampling(),
+        Sampling::TopKThenTopP { k: 5, .. }
+    ));
+}
+
+fn fingerprint() -> ModelFingerprint {
+    ModelFingerprint {
+        model: "owner/repo/model.gguf".to_string(),
+        model_sha256: "aa".repeat(32),
+        tokenizer_sha256: "bb".repeat(32),
+    }
+}
+
+#[test]
+fn test_record_verification() -> anyhow::Result<()> {
+    let params = GenerationParams::greedy(3).with_seed(1);
+    let record = GenerationRecord::new(fingerprint(), params.clone(), "prompt", &[4, 5, 6]);
+    assert_eq!(record.output_tokens, 3);
+
+    // Round trips through the wire format
+    let json = serde_json::to_value(&record)?;
+    assert_eq!(json["model_sha256"], "aa".repeat(32));
+    assert_eq!(serde_json::from_value::<GenerationRecord>(json)?, record);
+
+    assert!(record.verify_prompt("prompt").is_ok());
+    assert!(record.verify_prompt("another prompt").is_err());
+
+    let same = GenerationRecord::new(fingerprint(), params.clone(), "prompt", &[4, 5, 6]);
+    assert!(record.reproduced_by(&same)?);
+    let diverged = GenerationRecord::new(fingerprint(), params.clone(), "prompt", &[4, 5, 7]);
+    assert!(!record.reproduced_by(&diverged)?);
+
+    // Replays on other weights prove nothing either way
+    let other_model = ModelFingerprint {
+        model_sha256: "cc".repeat(32),
+        ..fingerprint()
+    };
+    let foreign = GenerationRecord::new(other_model, params, "prompt", &[4, 5, 6]);
+    assert!(record.reproduced_by(&foreign).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_checks_peer_output() -> anyhow::Result<()> {
+    // Echoes the token count the params ask for, as a deterministic engine would
+    let engine = StubEngine::default().with_deterministic(|prompt, params| {
+        let tokens: Vec<u32> = (0..params.max_new_tokens as u32).collect();
+        let record = GenerationRecord::new(fingerprint(), params.clone(), prompt, &tokens);
+        Ok((format!("{:?}", tokens), record))
+    });
+    let (_, record) = engine
+        .generate_deterministic("prompt", &GenerationParams::greedy(4))
+        .await?;
+    assert!(engine.replay("prompt", &record).await?);
+    assert!(engine.replay("other", &record).await.is_err());
+
+    // A peer that claims a different output is caught
+    let mut forged = record.clone();
+    forged.output_sha256 = "00".repeat(32);
+    assert!(!engine.replay("prompt", &forged).await?);
+    Ok(())
+}
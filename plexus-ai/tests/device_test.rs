@@ -0,0 +1,38 @@
+use plexus_ai::{DeviceRequest, DeviceSelector};
+
+#[test]
+fn test_parse_device_request() -> anyhow::Result<()> {
+    assert_eq!("auto".parse::<DeviceRequest>()?, DeviceRequest::Auto);
+    assert_eq!("CPU".parse::<DeviceRequest>()?, DeviceRequest::Cpu);
+    assert_eq!("cuda".parse::<DeviceRequest>()?, DeviceRequest::Cuda(0));
+    assert_eq!("cuda:1".parse::<DeviceRequest>()?, DeviceRequest::Cuda(1));
+    assert_eq!("metal".parse::<DeviceRequest>()?, DeviceRequest::Metal(0));
+    assert_eq!(
+        "vulkan:2".parse::<DeviceRequest>()?,
+        DeviceRequest::Vulkan(2)
+    );
+    assert_eq!(DeviceRequest::Cuda(1).to_string(), "cuda:1");
+
+    assert!("tpu".parse::<DeviceRequest>().is_err());
+    assert!("cuda:x".parse::<DeviceRequest>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_cpu_selection_reports_no_gpu() {
+    let selected = DeviceSelector::new(DeviceRequest::Cpu).select();
+    assert!(selected.device.is_cpu());
+    assert_eq!(selected.gpu_info(), None);
+}
+
+#[cfg(not(feature = "cuda"))]
+#[test]
+fn test_unavailable_backend_falls_back_to_cpu() {
+    let selected = DeviceSelector::new(DeviceRequest::Cuda(0)).select();
+    assert!(selected.device.is_cpu());
+    assert_eq!(selected.label, "cpu");
+
+    // Vulkan has no candle backend, so it always falls back
+    let selected = DeviceSelector::new(DeviceRequest::Vulkan(0)).select();
+    assert!(selected.device.is_cpu());
+}
@@ -0,0 +1,85 @@
+use plexus_ai::{
+    Architecture, BertEmbedder, Document, EmbedderRegistry, EmbeddingModel, Pooling,
+    SimpleVectorStore, VectorStore, DEFAULT_EMBEDDING_MODEL,
+};
+
+#[test]
+fn test_registry_lookup() -> anyhow::Result<()> {
+    let registry = EmbedderRegistry::new();
+    let ids: Vec<&str> = registry.list().iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, ["minilm", "bge-small", "e5-small", "nomic"]);
+
+    let minilm = registry.get(DEFAULT_EMBEDDING_MODEL)?;
+    assert_eq!(minilm.dimension, 384);
+    assert_eq!(
+        registry.get("sentence-transformers/all-MiniLM-L6-v2")?,
+        minilm
+    );
+
+    let nomic = registry.get("nomic")?;
+    assert_eq!(
+        (nomic.dimension, nomic.architecture),
+        (768, Architecture::NomicBert)
+    );
+    assert_eq!(nomic.query_prefix, "search_query: ");
+
+    let err = registry.get("word2vec").unwrap_err().to_string();
+    assert!(err.contains("word2vec") && err.contains("bge-small"));
+    Ok(())
+}
+
+#[test]
+fn test_register_and_collection_suffix() -> anyhow::Result<()> {
+    let mut registry = EmbedderRegistry::new();
+    registry.register(EmbeddingModel::new(
+        "bge-small",
+        "BAAI/bge-base-en-v1.5",
+        768,
+        Architecture::Bert,
+        Pooling::Cls,
+    ));
+    assert_eq!(registry.list().len(), 4);
+    let bge = registry.get("bge-small")?;
+    assert_eq!(bge.dimension, 768);
+
+    // Same dimension, different model: still separate collections
+    assert_eq!(bge.collection_suffix(), "bge_small_768");
+    assert_ne!(
+        registry.get("minilm")?.collection_suffix(),
+        registry.get("e5-small")?.collection_suffix()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_store_refuses_dimension_mismatch() -> anyhow::Result<()> {
+    let store = SimpleVectorStore::new();
+    store
+        .upsert(Document::new("a", "first"), vec![1.0, 0.0, 0.0])
+        .await?;
+
+    assert!(store
+        .upsert(Document::new("b", "second"), vec![1.0, 0.0])
+        .await
+        .is_err());
+    assert!(store.search(vec![1.0, 0.0], 1).await.is_err());
+    assert_eq!(store.count().await?, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_load_is_an_error_every_time() {
+    let missing = EmbeddingModel::new(
+        "missing",
+        "plexus-test/does-not-exist",
+        384,
+        Architecture::Bert,
+        Pooling::Mean,
+    );
+    let embedder = BertEmbedder::new().with_model(missing);
+
+    // The second call retries instead of finding a half-loaded embedder
+    assert!(embedder.embed("first").await.is_err());
+    assert!(embedder.embed("second").await.is_err());
+    assert!(embedder.token_spans("third").await.is_err());
+}
@@ -0,0 +1,148 @@
+use plexus_ai::{
+    Architecture, Document, EmbedderRegistry, EmbeddingModel, HnswConfig, HnswIndex, Pooling,
+    SearchFilter, SimpleVectorStore, VectorStore,
+};
+use std::collections::HashSet;
+
+/// Deterministic pseudo-random vectors with components in [-1, 1).
+fn random_vectors(count: usize, dimension: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((state >> 40) as f32 / (1u64 << 24) as f32) * 2.0 - 1.0
+    };
+    (0..count)
+        .map(|_| (0..dimension).map(|_| next()).collect())
+        .collect()
+}
+
+#[test]
+fn test_hnsw_recall() {
+    let vectors = random_vectors(2_000, 32, 7);
+    let mut index = HnswIndex::new(HnswConfig::default());
+    for (i, vector) in vectors.iter().enumerate() {
+        index.insert(&i.to_string(), vector);
+    }
+    assert_eq!(index.len(), 2_000);
+
+    let mut found = 0;
+    let queries = random_vectors(50, 32, 11);
+    for query in &queries {
+        let exact: HashSet<String> = index
+            .exact_search(query, 10, |_| true)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        let approximate = index.search(query, 10, |_| true);
+        assert_eq!(approximate.len(), 10);
+        assert!(approximate.windows(2).all(|w| w[0].1 >= w[1].1));
+        found += approximate
+            .iter()
+            .filter(|(id, _)| exact.contains(id))
+            .count();
+    }
+    let recall = found as f32 / (queries.len() * 10) as f32;
+    assert!(recall >= 0.9, "recall {}", recall);
+}
+
+#[test]
+fn test_hnsw_remove_and_rebuild() {
+    let vectors = random_vectors(300, 8, 3);
+    let mut index = HnswIndex::new(HnswConfig::default());
+    for (i, vector) in vectors.iter().enumerate() {
+        index.insert(&i.to_string(), vector);
+    }
+    for i in 0..100 {
+        index.remove(&i.to_string());
+    }
+    // Replacing a vector leaves a tombstone too
+    index.insert("150", &vectors[0]);
+    assert_eq!((index.len(), index.tombstones()), (200, 101));
+
+    let hits = index.search(&vectors[0], 5, |_| true);
+    assert_eq!(hits[0].0, "150");
+    assert!((hits[0].1 - 1.0).abs() < 1e-5);
+    assert!(hits
+        .iter()
+        .all(|(id, _)| id.parse::<usize>().unwrap() >= 100));
+
+    let rebuilt = index.rebuild();
+    assert_eq!((rebuilt.len(), rebuilt.tombstones()), (200, 0));
+    let ids = |hits: Vec<(String, f32)>| hits.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+    assert_eq!(ids(rebuilt.search(&vectors[0], 5, |_| true)), ids(hits));
+}
+
+#[tokio::test]
+async fn test_store_persists() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut registry = EmbedderRegistry::new();
+    registry.register(EmbeddingModel::new(
+        "tiny",
+        "example/tiny",
+        16,
+        Architecture::Bert,
+        Pooling::Mean,
+    ));
+    let tiny = registry.get("tiny")?;
+    let vectors = random_vectors(1_000, 16, 5);
+
+    {
+        let store = SimpleVectorStore::open(dir.path(), &tiny)?;
+        for (i, vector) in vectors.iter().enumerate() {
+            let source = if i % 2 == 0 { "even" } else { "odd" };
+            let document = Document::new(i.to_string(), format!("doc {}", i)).with_source(source);
+            store.upsert(document, vector.clone()).await?;
+        }
+        store.delete("0").await?;
+        assert!(store
+            .upsert(Document::new("x", "x"), vec![1.0; 384])
+            .await
+            .is_err());
+    }
+
+    let store = SimpleVectorStore::open(dir.path(), &tiny)?;
+    assert_eq!(store.count().await?, 999);
+    assert!(store.get("0").await?.is_none());
+    assert_eq!(store.get("7").await?.unwrap().source(), Some("odd"));
+
+    // Large enough to be searched through the graph, with and without a filter
+    let results = store.search(vectors[42].clone(), 3).await?;
+    assert_eq!(results[0].id, "42");
+    let odd = SearchFilter::default().with_source("odd");
+    let results = store.search_filtered(vectors[42].clone(), 3, &odd).await?;
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|r| r.metadata["source"] == "odd"));
+
+    // Another model starts from an empty store of its own
+    let minilm = SimpleVectorStore::open(dir.path(), &registry.get("minilm")?)?;
+    assert_eq!(minilm.count().await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_store_recovers_from_torn_log() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let model = EmbedderRegistry::new().get("minilm")?;
+    {
+        let store = SimpleVectorStore::open(dir.path(), &model)?;
+        store.add_document("kept", "kept", vec![0.5; 384]).await?;
+    }
+
+    // A crash in the middle of a write leaves half a line behind
+    let log = dir.path().join(model.collection_suffix()).join("log.jsonl");
+    let mut content = std::fs::read(&log)?;
+    content.extend_from_slice(b"{\"op\":\"upsert\",\"document\":{\"id\":\"lo");
+    std::fs::write(&log, content)?;
+
+    let store = SimpleVectorStore::open(dir.path(), &model)?;
+    assert_eq!(store.count().await?, 1);
+    store.add_document("after", "after", vec![0.5; 384]).await?;
+    drop(store);
+
+    let store = SimpleVectorStore::open(dir.path(), &model)?;
+    assert!(store.get("kept").await?.is_some());
+    assert!(store.get("after").await?.is_some());
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use plexus_ai::{
+    chunk_id, chunk_text, ChunkConfig, Embedder, Ingestor, SimpleVectorStore, SourceKind,
+    VectorStore,
+};
+use std::sync::{Arc, Mutex};
+
+/// Treats whitespace-separated words as tokens and records every batch it embeds.
+#[derive(Default)]
+struct WordEmbedder {
+    batches: Mutex<Vec<usize>>,
+}
+
+impl WordEmbedder {
+    fn embedded(&self) -> usize {
+        self.batches.lock().unwrap().iter().sum()
+    }
+}
+
+#[async_trait]
+impl Embedder for WordEmbedder {
+    fn model_id(&self) -> &str {
+        "words"
+    }
+
+    fn dimension(&self) -> usize {
+        2
+    }
+
+    async fn embed(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+        Ok(vec![text.len() as f32, 1.0])
+    }
+
+    async fn embed_batch(&self, texts: &[&str]) -> anyhow::Result<Vec<Vec<f32>>> {
+        self.batches.lock().unwrap().push(texts.len());
+        Ok(texts.iter().map(|t| vec![t.len() as f32, 1.0]).collect())
+    }
+
+    async fn token_spans(&self, text: &str) -> anyhow::Result<Vec<(usize, usize)>> {
+        let mut spans = vec![];
+        let mut start = None;
+        for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(s), true) => {
+                    spans.push((s, i));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        Ok(spans)
+    }
+}
+
+fn words(range: std::ops::Range<usize>) -> String {
+    range.map(|i| format!("w{i}")).collect::<Vec<_>>().join(" ")
+}
+
+#[tokio::test]
+async fn test_chunks_overlap() -> anyhow::Result<()> {
+    let text = words(0..10);
+    let spans = WordEmbedder::default().token_spans(&text).await?;
+    let config = ChunkConfig::default()
+        .with_chunk_tokens(4)
+        .with_overlap_tokens(1);
+
+    let chunks = chunk_text(&text, &spans, &config)?;
+    let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, ["w0 w1 w2 w3", "w3 w4 w5 w6", "w6 w7 w8 w9"]);
+    assert_eq!(&text[chunks[1].start..chunks[1].end], "w3 w4 w5 w6");
+
+    assert!(chunk_text("", &[], &config)?.is_empty());
+    let invalid = config.with_overlap_tokens(4);
+    assert!(chunk_text(&text, &spans, &invalid).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reingest_dedupes_by_content() -> anyhow::Result<()> {
+    let embedder = Arc::new(WordEmbedder::default());
+    let store = Arc::new(SimpleVectorStore::new());
+    let ingestor = Ingestor::new(embedder.clone(), store.clone())
+        .with_chunk_config(
+            ChunkConfig::default()
+                .with_chunk_tokens(4)
+                .with_overlap_tokens(0),
+        )
+        .with_batch_size(2);
+
+    let report = ingestor
+        .ingest_text("notes.md", SourceKind::Markdown, &words(0..12))
+        .await?;
+    assert_eq!((report.chunks, report.embedded), (3, 3));
+    assert_eq!(*embedder.batches.lock().unwrap(), [2, 1]);
+
+    let chunk = store.get(&chunk_id("notes.md", 1)).await?.unwrap();
+    assert_eq!(chunk.text, "w4 w5 w6 w7");
+    assert_eq!(chunk.source(), Some("notes.md"));
+    assert_eq!(chunk.metadata["kind"], "markdown");
+    assert_eq!(chunk.metadata["start"], 12);
+    assert_eq!(chunk.metadata["end"], 23);
+
+    // Unchanged content is not embedded again
+    let report = ingestor
+        .ingest_text("notes.md", SourceKind::Markdown, &words(0..12))
+        .await?;
+    assert_eq!((report.embedded, report.unchanged), (0, 3));
+    assert_eq!(embedder.embedded(), 3);
+
+    // An edit in the last chunk re-embeds only that chunk; a shorter file drops the tail
+    let edited = format!("{} changed", words(0..7));
+    let report = ingestor
+        .ingest_text("notes.md", SourceKind::Markdown, &edited)
+        .await?;
+    assert_eq!(
+        (
+            report.chunks,
+            report.embedded,
+            report.unchanged,
+            report.removed
+        ),
+        (2, 1, 1, 1)
+    );
+    assert_eq!(store.count().await?, 2);
+
+    // Text inserted before existing chunks re-embeds only the new chunk
+    let prepended = format!("a b c d {}", edited);
+    let report = ingestor
+        .ingest_text("notes.md", SourceKind::Markdown, &prepended)
+        .await?;
+    assert_eq!(
+        (report.chunks, report.embedded, report.unchanged),
+        (3, 1, 2)
+    );
+    assert_eq!(embedder.embedded(), 5);
+    let chunk = store.get(&chunk_id("notes.md", 1)).await?.unwrap();
+    assert_eq!(chunk.text, "w0 w1 w2 w3");
+    assert_eq!(chunk.metadata["start"], 8);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ingest_file() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("lib.rs");
+    std::fs::write(&path, "fn main() {\n    println!(\"hi\");\n}\n")?;
+
+    let store = Arc::new(SimpleVectorStore::new());
+    let ingestor = Ingestor::new(Arc::new(WordEmbedder::default()), store.clone());
+    let report = ingestor.ingest_file(&path).await?;
+    assert_eq!(report.chunks, 1);
+    let chunk = store.get(&chunk_id(&report.source, 0)).await?.unwrap();
+    assert_eq!(chunk.metadata["kind"], "code");
+
+    let unsupported = dir.path().join("photo.png");
+    std::fs::write(&unsupported, [0u8; 4])?;
+    assert!(ingestor.ingest_file(&unsupported).await.is_err());
+    Ok(())
+}
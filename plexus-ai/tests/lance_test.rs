@@ -1,7 +1,7 @@
 #[cfg(feature = "lancedb")]
 #[tokio::test]
 async fn test_lancedb_embedded() {
-    use plexus_ai::{LanceDbStore, VectorStore};
+    use plexus_ai::{EmbedderRegistry, LanceDbStore, VectorStore};
     use tempfile::Builder;
 
     // Create a temporary directory for the embedded DB
@@ -12,7 +12,8 @@ async fn test_lancedb_embedded() {
     let db_path = temp_dir.path().join("vectors.lance");
 
     // Initialize Store
-    let store = LanceDbStore::new(&db_path)
+    let model = EmbedderRegistry::new().get("minilm").unwrap();
+    let store = LanceDbStore::new(&db_path, &model)
         .await
         .expect("Failed to create LanceDbStore");
 
@@ -31,7 +32,74 @@ async fn test_lancedb_embedded() {
     let results = store.search(query, 1).await.expect("Failed to search");
 
     assert_eq!(results.len(), 1);
-    assert_eq!(results[0].0, text);
+    assert_eq!(results[0].text, text);
     // Similarity should be close to 1.0 (since dist is 0.0)
-    assert!(results[0].1 > 0.99);
+    assert!(results[0].score > 0.99);
+}
+
+#[cfg(feature = "lancedb")]
+#[tokio::test]
+async fn test_lancedb_metadata() -> anyhow::Result<()> {
+    use plexus_ai::{Document, EmbedderRegistry, LanceDbStore, SearchFilter, VectorStore};
+
+    let temp_dir = tempfile::tempdir()?;
+    let model = EmbedderRegistry::new().get("minilm")?;
+    let store = LanceDbStore::new(&temp_dir.path().join("vectors.lance"), &model).await?;
+
+    let note = Document::new("note", "Buy milk")
+        .with_source("chat")
+        .with_tags(["todo"]);
+    store.upsert(note.clone(), vec![0.1; 384]).await?;
+    store
+        .upsert(
+            Document::new("readme", "It's a mesh").with_source("README.md"),
+            vec![0.2; 384],
+        )
+        .await?;
+    assert_eq!(store.count().await?, 2);
+    assert_eq!(store.get("note").await?, Some(note));
+
+    let filter = SearchFilter::default().with_source("README.md");
+    let results = store.search_filtered(vec![0.1; 384], 5, &filter).await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id, "readme");
+
+    // Upserting replaces instead of duplicating
+    store
+        .add_document("note", "Buy oat milk", vec![0.1; 384])
+        .await?;
+    assert_eq!(store.count().await?, 2);
+
+    store.delete("note").await?;
+    store.delete("note").await?;
+    assert!(store.get("note").await?.is_none());
+    assert_eq!(store.count().await?, 1);
+    Ok(())
+}
+
+#[cfg(feature = "lancedb")]
+#[tokio::test]
+async fn test_lancedb_tables_per_model() -> anyhow::Result<()> {
+    use plexus_ai::{EmbedderRegistry, LanceDbStore, VectorStore};
+
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("vectors.lance");
+    let registry = EmbedderRegistry::new();
+
+    let minilm = LanceDbStore::new(&path, &registry.get("minilm")?).await?;
+    minilm.add_document("doc1", "Hello", vec![0.1; 384]).await?;
+    assert!(minilm
+        .add_document("doc2", "Hi", vec![0.1; 768])
+        .await
+        .is_err());
+
+    // Another model gets an empty table of its own dimension
+    let nomic = LanceDbStore::new(&path, &registry.get("nomic")?).await?;
+    assert_eq!(nomic.count().await?, 0);
+    assert!(nomic.search(vec![0.1; 384], 1).await.is_err());
+    nomic.add_document("doc1", "Hello", vec![0.1; 768]).await?;
+
+    let minilm = LanceDbStore::new(&path, &registry.get("minilm")?).await?;
+    assert_eq!(minilm.count().await?, 1);
+    Ok(())
 }
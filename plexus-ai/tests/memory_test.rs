@@ -34,7 +34,7 @@ async fn test_vector_memory() -> anyhow::Result<()> {
 
     assert_eq!(results.len(), 3);
     // greetings should be closer than food
-    assert!(results[0].0.contains("greeting") || results[1].0.contains("greeting"));
+    assert!(results[0].text.contains("greeting") || results[1].text.contains("greeting"));
 
     // Check similarity
     // vec1 and vec2 should be reasonably close
@@ -42,3 +42,23 @@ async fn test_vector_memory() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_embed_batch_ignores_padding() -> anyhow::Result<()> {
+    let embedder = BertEmbedder::new();
+    let texts = [
+        "hi",
+        "a much longer sentence that forces the short one to be padded",
+    ];
+
+    let batch = embedder.embed_batch(&texts).await?;
+    assert_eq!(batch.len(), 2);
+    // Padding must not shift the pooled vector of the shorter text
+    for (text, batched) in texts.iter().zip(&batch) {
+        let single = embedder.embed(text).await?;
+        let diff: f32 = single.iter().zip(batched).map(|(a, b)| (a - b).abs()).sum();
+        assert!(diff < 1e-3, "{text}: batched embedding differs by {diff}");
+    }
+    assert!(embedder.embed_batch(&[]).await?.is_empty());
+    Ok(())
+}
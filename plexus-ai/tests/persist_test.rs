@@ -0,0 +1,101 @@
+use plexus_ai::{ChatHistory, SessionStore};
+use std::path::{Path, PathBuf};
+
+fn history(messages: &[&str]) -> ChatHistory {
+    let mut history = ChatHistory::new(10);
+    for message in messages {
+        history.add_user(message.to_string());
+    }
+    history
+}
+
+fn contents(history: &ChatHistory) -> Vec<String> {
+    history
+        .get_history()
+        .into_iter()
+        .map(|m| m.content)
+        .collect()
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), suffix))
+}
+
+#[test]
+fn test_save_keeps_previous_version() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("chat_history.json");
+
+    history(&["one"]).save_to_file(&path)?;
+    history(&["one", "two"]).save_to_file(&path)?;
+    assert!(!sibling(&path, "tmp").exists());
+    assert_eq!(
+        contents(&ChatHistory::load_from_file(&sibling(&path, "bak"))?),
+        ["one"]
+    );
+
+    // A write torn by a crash falls back to the last complete version
+    std::fs::write(&path, "{\"messages\": [{\"role\": \"Us")?;
+    let recovered = ChatHistory::load_from_file(&path)?;
+    assert_eq!(contents(&recovered), ["one"]);
+    // The damaged file is kept for inspection
+    assert!(sibling(&path, "corrupt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_recover_interrupted_rename() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("chat_history.json");
+
+    // Crash after the new version was synced but before it replaced the old one
+    history(&["one"]).save_to_file(&path)?;
+    std::fs::rename(&path, sibling(&path, "bak"))?;
+    history(&["one", "two"]).save_to_file(&sibling(&path, "tmp"))?;
+
+    assert_eq!(
+        contents(&ChatHistory::load_from_file(&path)?),
+        ["one", "two"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unrecoverable_history_is_an_error() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("chat_history.json");
+    assert!(ChatHistory::load_from_file(&path)?.is_empty());
+
+    std::fs::write(&path, "not json")?;
+    assert!(ChatHistory::load_from_file(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_session_store_recovers_damaged_sessions() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut store = SessionStore::open(dir.path())?;
+    let session = store.create("Notes", None)?;
+    store
+        .history_mut(&session.id)?
+        .add_user("first".to_string());
+    store.save(&session.id)?;
+    store
+        .history_mut(&session.id)?
+        .add_user("second".to_string());
+    store.save(&session.id)?;
+
+    let path = dir.path().join(format!("{}.json", session.id));
+    std::fs::write(&path, "")?;
+    let store = SessionStore::open(dir.path())?;
+    let recovered = &store.get(&session.id).unwrap().history;
+    assert_eq!(contents(recovered), ["first"]);
+
+    // Only the backup is left after the main file went missing mid-save
+    std::fs::remove_file(sibling(&path, "corrupt"))?;
+    let mut store = SessionStore::open(dir.path())?;
+    assert_eq!(store.list().len(), 1);
+    store.delete(&session.id)?;
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use plexus_ai::PrefixCache;
+use std::time::Duration;
+
+#[test]
+fn test_longest_prefix_is_taken() {
+    let mut cache = PrefixCache::new(4, Duration::from_secs(60));
+    cache.insert(vec![
+        (vec![1, 2, 3], "prompt"),
+        (vec![1, 2, 3, 4, 5], "generated"),
+    ]);
+
+    // The next turn extends the previous transcript
+    let (matched, state) = cache.take(&[1, 2, 3, 4, 5, 6, 7]).unwrap();
+    assert_eq!(matched, 5);
+    assert_eq!(state, "generated");
+
+    // Taking a session removes it until it is re-inserted
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_falls_back_to_shorter_checkpoint_and_needs_a_suffix() {
+    let mut cache = PrefixCache::new(4, Duration::from_secs(60));
+    cache.insert(vec![
+        (vec![1, 2, 3], "prompt"),
+        (vec![1, 2, 3, 9], "generated"),
+    ]);
+
+    // Re-tokenization diverged after the prompt: only the prompt checkpoint matches
+    assert_eq!(cache.take(&[1, 2, 3, 4]), Some((3, "prompt")));
+
+    // An identical prompt leaves nothing to prefill, so it is not a hit
+    cache.insert(vec![(vec![1, 2], "same")]);
+    assert_eq!(cache.take(&[1, 2]), None);
+    assert_eq!(cache.take(&[7, 8, 9]), None);
+}
+
+#[test]
+fn test_eviction() {
+    let mut cache = PrefixCache::new(2, Duration::from_secs(60));
+    cache.insert(vec![(vec![1], "a")]);
+    cache.insert(vec![(vec![2], "b")]);
+    cache.insert(vec![(vec![3], "c")]);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.take(&[1, 0]), None);
+
+    let mut idle = PrefixCache::new(4, Duration::from_millis(10));
+    idle.insert(vec![(vec![1], "a")]);
+    std::thread::sleep(Duration::from_millis(30));
+    idle.evict_idle();
+    assert!(idle.is_empty());
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::StubEngine;
+use plexus_ai::{expected_footprint, ModelRegistry};
+use std::sync::Arc;
+
+const MB: u64 = 1024 * 1024;
+
+/// An engine stub holding `bytes` of weights.
+fn sized(bytes: u64) -> Arc<StubEngine> {
+    Arc::new(StubEngine::default().with_memory_footprint(bytes))
+}
+
+#[test]
+fn test_lru_eviction_within_budget() -> anyhow::Result<()> {
+    let mut registry = ModelRegistry::new("base", 1000 * MB);
+
+    registry.insert("base", sized(400 * MB))?;
+    registry.insert("coder", sized(400 * MB))?;
+    assert_eq!(registry.memory_used(), 800 * MB);
+
+    // Touch "coder" so "base" becomes the LRU, but the default model is evicted last
+    assert!(registry.get(Some("coder")).is_some());
+    let evicted = registry.insert("vision", sized(500 * MB))?;
+    assert_eq!(evicted, vec!["coder".to_string()]);
+
+    let ids: Vec<String> = registry.list().into_iter().map(|m| m.id).collect();
+    assert_eq!(ids, vec!["vision".to_string(), "base".to_string()]);
+    Ok(())
+}
+
+#[test]
+fn test_model_larger_than_budget_is_refused() {
+    let mut registry = ModelRegistry::new("base", 100 * MB);
+    assert!(registry.insert("huge", sized(200 * MB)).is_err());
+    assert!(registry.list().is_empty());
+}
+
+#[test]
+fn test_get_routes_to_default_and_unload() -> anyhow::Result<()> {
+    let mut registry = ModelRegistry::new("base", 1000 * MB);
+    registry.insert("base", sized(10 * MB))?;
+
+    assert!(registry.get(None).is_some());
+    assert!(registry.get(Some("missing")).is_none());
+
+    registry.unload("base")?;
+    assert!(registry.get(None).is_none());
+    assert!(registry.unload("base").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unknown_or_oversized_models_are_not_loaded() -> anyhow::Result<()> {
+    assert!(expected_footprint("tinyllama")? > 100 * MB);
+    assert!(expected_footprint("llama3").is_err());
+
+    let mut registry = ModelRegistry::new("tinyllama", 100 * MB);
+    registry.insert("small", sized(50 * MB))?;
+
+    // Neither request downloads anything nor evicts the resident model
+    let error = registry.load("x1").await.unwrap_err();
+    assert!(error.to_string().contains("Unknown model"), "{}", error);
+    let error = registry.load("tinyllama").await.unwrap_err();
+    assert!(error.to_string().contains("memory budget"), "{}", error);
+    assert!(registry.is_resident("small"));
+    assert!(!registry.is_resident("x1"));
+    Ok(())
+}
+
+#[test]
+fn test_prepare_evicts_nothing_until_insert() -> anyhow::Result<()> {
+    assert!(expected_footprint("zephyr")? > expected_footprint("tinyllama")?);
+
+    let mut registry = ModelRegistry::new("base", 1000 * MB);
+    registry.insert("small", sized(900 * MB))?;
+
+    // A load that fails after this point leaves the resident model in place
+    let engine = registry.prepare("tinyllama")?;
+    assert!(registry.is_resident("small"));
+    assert!(!registry.is_resident("tinyllama"));
+    assert!(registry.prepare("zephyr").is_err());
+    drop(engine);
+
+    let evicted = registry.insert("tinyllama", sized(640 * MB))?;
+    assert_eq!(evicted, vec!["small".to_string()]);
+    Ok(())
+}
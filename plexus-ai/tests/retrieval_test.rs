@@ -0,0 +1,367 @@
+use async_trait::async_trait;
+use plexus_ai::retrieval::tokenize;
+use plexus_ai::{
+    reciprocal_rank_fusion, Bm25Index, CrossEncoderReranker, Document, Embedder, HybridRetriever,
+    Reranker, RetrievalConfig, SearchFilter, SearchResult, SimpleVectorStore, VectorStore,
+};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Embeds every query as `[1, 0]`, so vector search always prefers the deploy note.
+struct FixedEmbedder;
+
+#[async_trait]
+impl Embedder for FixedEmbedder {
+    fn model_id(&self) -> &str {
+        "fixed"
+    }
+
+    fn dimension(&self) -> usize {
+        2
+    }
+
+    async fn embed(&self, _text: &str) -> anyhow::Result<Vec<f32>> {
+        Ok(vec![1.0, 0.0])
+    }
+
+    async fn token_spans(&self, _text: &str) -> anyhow::Result<Vec<(usize, usize)>> {
+        Ok(vec![])
+    }
+}
+
+/// Scores passages mentioning the error code as relevant.
+struct KeywordReranker;
+
+#[async_trait]
+impl Reranker for KeywordReranker {
+    async fn rerank(&self, _query: &str, passages: &[&str]) -> anyhow::Result<Vec<f32>> {
+        Ok(passages
+            .iter()
+            .map(|p| if p.contains("4012") { 0.9 } else { 0.1 })
+            .collect())
+    }
+}
+
+/// Answers from `primary` or `backup` depending on the generation, like a failover store.
+struct SwitchingStore {
+    primary: SimpleVectorStore,
+    backup: SimpleVectorStore,
+    generation: AtomicU64,
+}
+
+impl SwitchingStore {
+    fn active(&self) -> &SimpleVectorStore {
+        match self.generation.load(Ordering::SeqCst) % 2 {
+            0 => &self.primary,
+            _ => &self.backup,
+        }
+    }
+}
+
+#[async_trait]
+impl VectorStore for SwitchingStore {
+    async fn add(&self, id: &str, vector: Vec<f32>) -> anyhow::Result<()> {
+        self.active().add(id, vector).await
+    }
+
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> anyhow::Result<()> {
+        self.active().upsert(document, vector).await
+    }
+
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<Vec<SearchResult>> {
+        self.active().search_filtered(query_vector, k, filter).await
+    }
+
+    async fn get(&self, id: &str) -> anyhow::Result<Option<Document>> {
+        self.active().get(id).await
+    }
+
+    async fn get_entry(&self, id: &str) -> anyhow::Result<Option<(Document, Vec<f32>)>> {
+        self.active().get_entry(id).await
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.active().delete(id).await
+    }
+
+    async fn count(&self) -> anyhow::Result<usize> {
+        self.active().count().await
+    }
+
+    async fn documents(&self) -> anyhow::Result<Vec<Document>> {
+        self.active().documents().await
+    }
+
+    async fn entries(&self) -> anyhow::Result<Vec<(Document, Vec<f32>)>> {
+        self.active().entries().await
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
+
+/// Holds its first listing of the documents until released, so writes can land meanwhile.
+struct GatedStore {
+    inner: Arc<SimpleVectorStore>,
+    gated: AtomicBool,
+    listed: Notify,
+    release: Notify,
+}
+
+#[async_trait]
+impl VectorStore for GatedStore {
+    async fn add(&self, id: &str, vector: Vec<f32>) -> anyhow::Result<()> {
+        self.inner.add(id, vector).await
+    }
+
+    async fn upsert(&self, document: Document, vector: Vec<f32>) -> anyhow::Result<()> {
+        self.inner.upsert(document, vector).await
+    }
+
+    async fn search_filtered(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<Vec<SearchResult>> {
+        self.inner.search_filtered(query_vector, k, filter).await
+    }
+
+    async fn get(&self, id: &str) -> anyhow::Result<Option<Document>> {
+        self.inner.get(id).await
+    }
+
+    async fn get_entry(&self, id: &str) -> anyhow::Result<Option<(Document, Vec<f32>)>> {
+        self.inner.get_entry(id).await
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.inner.delete(id).await
+    }
+
+    async fn count(&self) -> anyhow::Result<usize> {
+        self.inner.count().await
+    }
+
+    async fn documents(&self) -> anyhow::Result<Vec<Document>> {
+        let documents = self.inner.documents().await?;
+        if self.gated.swap(false, Ordering::SeqCst) {
+            self.listed.notify_one();
+            self.release.notified().await;
+        }
+        Ok(documents)
+    }
+
+    async fn entries(&self) -> anyhow::Result<Vec<(Document, Vec<f32>)>> {
+        self.inner.entries().await
+    }
+}
+
+async fn store() -> anyhow::Result<Arc<SimpleVectorStore>> {
+    let store = Arc::new(SimpleVectorStore::new());
+    for (id, text, vector) in [
+        (
+            "error",
+            "Build failed with ERR-4012 on the runner",
+            [0.0, 1.0],
+        ),
+        ("deploy", "Deploys happen on Thursdays", [1.0, 0.0]),
+        ("pizza", "Pizza place closes at 11pm", [0.6, 0.8]),
+    ] {
+        store.add_document(id, text, vector.to_vec()).await?;
+    }
+    Ok(store)
+}
+
+fn ids(results: &[SearchResult]) -> Vec<&str> {
+    results.iter().map(|r| r.id.as_str()).collect()
+}
+
+#[test]
+fn test_tokenize_keeps_identifiers() {
+    assert_eq!(
+        tokenize("See ERR-4012, then user_id."),
+        ["see", "err", "4012", "err-4012", "then", "user", "id", "user_id"]
+    );
+}
+
+#[test]
+fn test_bm25_ranks_exact_matches() {
+    let mut index = Bm25Index::new();
+    index.insert(Document::new("a", "ticket PLX-231 is blocked on review"));
+    index.insert(Document::new("b", "ticket PLX-232 shipped"));
+    index.insert(Document::new("c", "lunch menu"));
+
+    let results = index.search("status of PLX-231", 5, &SearchFilter::default());
+    assert_eq!(ids(&results), ["a", "b"]);
+    assert!(results[0].score > results[1].score);
+
+    index.remove("a");
+    let results = index.search("PLX-231", 5, &SearchFilter::default());
+    assert_eq!(ids(&results), ["b"]);
+    assert_eq!(index.len(), 2);
+}
+
+#[test]
+fn test_reciprocal_rank_fusion() {
+    let result = |id: &str| SearchResult::new(Document::new(id, id), 0.0);
+    let fused = reciprocal_rank_fusion(
+        &[
+            vec![result("a"), result("b"), result("c")],
+            vec![result("c"), result("b")],
+        ],
+        60.0,
+    );
+    // b and c appear in both rankings; c ranks first in one of them
+    assert_eq!(ids(&fused), ["c", "b", "a"]);
+    assert!((fused[2].score - 1.0 / 61.0).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn test_hybrid_finds_keyword_matches() -> anyhow::Result<()> {
+    // Documents written before the retriever existed are indexed on first use
+    let retriever = HybridRetriever::new(Arc::new(FixedEmbedder), store().await?)
+        .with_config(RetrievalConfig::default().with_top_k(2));
+
+    // The error note is far from the query vector but matches its keywords
+    let results = retriever.retrieve("what does ERR-4012 mean").await?;
+    assert_eq!(ids(&results), ["deploy", "error"]);
+
+    // Weak vector matches are dropped by the threshold
+    let strict = HybridRetriever::new(Arc::new(FixedEmbedder), retriever.store())
+        .with_config(RetrievalConfig::default().with_threshold(0.7));
+    let results = strict.retrieve("pizza").await?;
+    assert_eq!(ids(&results), ["deploy", "pizza"]);
+    let results = strict.retrieve("opening hours").await?;
+    assert_eq!(ids(&results), ["deploy"]);
+
+    // Deletes through the retriever also leave the lexical index
+    retriever.delete("error").await?;
+    let results = retriever.retrieve("ERR-4012").await?;
+    assert_eq!(ids(&results), ["deploy", "pizza"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_common_words_are_not_keyword_matches() -> anyhow::Result<()> {
+    let store = Arc::new(SimpleVectorStore::new());
+    for i in 0..10 {
+        let text = format!("The memory note number {} is about topic{}", i, i);
+        store
+            .add_document(&format!("n{}", i), &text, vec![0.0, 1.0])
+            .await?;
+    }
+    // Vector matches are all below the threshold, so only keyword hits could be returned
+    let retriever = HybridRetriever::new(Arc::new(FixedEmbedder), store);
+
+    // Stopwords and words in every note match nothing
+    assert!(retriever
+        .retrieve("what is the note about")
+        .await?
+        .is_empty());
+    // Identifiers and rare words do
+    assert_eq!(ids(&retriever.retrieve("topic7").await?), ["n7"]);
+    assert_eq!(ids(&retriever.retrieve("the note on 3").await?), ["n3"]);
+
+    // The BM25 index itself ranks everything sharing a word other than stopwords
+    let mut index = Bm25Index::new();
+    index.insert(Document::new("a", "the cat is here"));
+    assert!(index
+        .search("is the", 5, &SearchFilter::default())
+        .is_empty());
+    assert_eq!(
+        ids(&index.search("the cat", 5, &SearchFilter::default())),
+        ["a"]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lexical_index_follows_store_switch() -> anyhow::Result<()> {
+    let store = Arc::new(SwitchingStore {
+        primary: SimpleVectorStore::new(),
+        backup: SimpleVectorStore::new(),
+        generation: AtomicU64::new(0),
+    });
+    store
+        .primary
+        .add_document("primary", "ticket PLX-100 is open", vec![0.0, 1.0])
+        .await?;
+    store
+        .backup
+        .add_document("backup", "Deploy ABC-200 failed", vec![0.0, 1.0])
+        .await?;
+    let retriever = HybridRetriever::new(Arc::new(FixedEmbedder), store.clone());
+    assert_eq!(ids(&retriever.retrieve("PLX-100").await?), ["primary"]);
+
+    // After a failover the index covers the documents the store now answers from
+    store.generation.fetch_add(1, Ordering::SeqCst);
+    assert!(retriever.retrieve("PLX-100").await?.is_empty());
+    assert_eq!(ids(&retriever.retrieve("ABC-200").await?), ["backup"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_write_during_index_build_is_indexed() -> anyhow::Result<()> {
+    let store = Arc::new(GatedStore {
+        inner: store().await?,
+        gated: AtomicBool::new(true),
+        listed: Notify::new(),
+        release: Notify::new(),
+    });
+    let retriever = Arc::new(HybridRetriever::new(Arc::new(FixedEmbedder), store.clone()));
+
+    // The first retrieval lists the documents, then a write lands before it indexes them
+    let first = tokio::spawn({
+        let retriever = retriever.clone();
+        async move { retriever.retrieve("ERR-9000").await }
+    });
+    store.listed.notified().await;
+    retriever
+        .upsert(
+            Document::new("late", "Deploy failed with ERR-9000"),
+            vec![0.0, 1.0],
+        )
+        .await?;
+    store.release.notify_one();
+    first.await??;
+
+    let results = retriever.retrieve("ERR-9000").await?;
+    assert!(ids(&results).contains(&"late"), "{:?}", ids(&results));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rerank_applies_threshold() -> anyhow::Result<()> {
+    let retriever = HybridRetriever::new(Arc::new(FixedEmbedder), store().await?)
+        .with_reranker(Arc::new(KeywordReranker));
+
+    let results = retriever.retrieve("what does ERR-4012 mean").await?;
+    assert_eq!(ids(&results), ["error"]);
+    assert_eq!(results[0].score, 0.9);
+
+    // Writes through the retriever are searchable right away
+    retriever
+        .upsert(
+            Document::new("fix", "ERR-4012 is fixed by clearing the cache"),
+            vec![0.0, 1.0],
+        )
+        .await?;
+    let results = retriever.retrieve("ERR-4012").await?;
+    assert_eq!(results.len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_reranker_load_is_an_error_every_time() {
+    let reranker = CrossEncoderReranker::new().with_repo("plexus-test/does-not-exist");
+    assert!(reranker.rerank("query", &["passage"]).await.is_err());
+    assert!(reranker.rerank("query", &["passage"]).await.is_err());
+}
@@ -0,0 +1,98 @@
+use plexus_ai::{ChatHistory, SessionStore, DEFAULT_SESSION_ID};
+
+#[test]
+fn test_session_lifecycle() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut store = SessionStore::open(dir.path())?;
+    assert!(store.list().is_empty());
+
+    let work = store.create("Work", Some("You are a lawyer.".to_string()))?;
+    assert_eq!(work.system_prompt.as_deref(), Some("You are a lawyer."));
+    store
+        .history_mut(&work.id)?
+        .add_user("Draft a contract".to_string());
+    store.save(&work.id)?;
+
+    store.rename(&work.id, "Contracts")?;
+    let fork = store.fork(&work.id, "Contracts (alt)")?;
+    // The system prompt is not a message
+    assert_eq!(fork.message_count, 1);
+    store
+        .history_mut(&fork.id)?
+        .add_user("Make it shorter".to_string());
+    store.save(&fork.id)?;
+    assert_eq!(store.get(&work.id).unwrap().history.len(), 1);
+
+    // Changing the system prompt keeps the conversation
+    store.set_system_prompt(&work.id, "You are a paralegal.".to_string())?;
+    let history = &store.get(&work.id).unwrap().history;
+    assert_eq!(history.system_prompt(), Some("You are a paralegal."));
+    assert_eq!(history.len(), 1);
+
+    // Everything survives a restart
+    let mut store = SessionStore::open(dir.path())?;
+    let names: Vec<String> = store.list().into_iter().map(|s| s.name).collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.contains(&"Contracts".to_string()));
+    assert_eq!(store.get(&fork.id).unwrap().history.len(), 2);
+    assert_eq!(
+        store.get(&fork.id).unwrap().history.system_prompt(),
+        Some("You are a lawyer.")
+    );
+
+    store.delete(&fork.id)?;
+    assert!(store.get(&fork.id).is_none());
+    assert!(store.delete(&fork.id).is_err());
+    assert_eq!(SessionStore::open(dir.path())?.list().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_export_import() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut store = SessionStore::open(dir.path())?;
+    let session = store.create("Trip", None)?;
+    store
+        .history_mut(&session.id)?
+        .add_user("Plan a trip".to_string());
+    store.save(&session.id)?;
+
+    let exported = store.export(&session.id)?;
+    let other = tempfile::tempdir()?;
+    let mut other_store = SessionStore::open(other.path())?;
+    let imported = other_store.import(&exported)?;
+    assert_eq!(imported.name, "Trip");
+    assert_eq!(imported.message_count, 1);
+
+    // Imports never overwrite an existing session
+    let again = store.import(&exported)?;
+    assert_ne!(again.id, session.id);
+    assert_eq!(store.list().len(), 2);
+
+    assert!(store.import("{not json").is_err());
+    assert!(store.history_mut("missing").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_default_session_and_migration() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let legacy = dir.path().join("chat_history.json");
+    let mut history = ChatHistory::new(10);
+    history.set_system_prompt("Be brief.".to_string());
+    history.add_user("Hello".to_string());
+    history.save_to_file(&legacy)?;
+
+    let mut store = SessionStore::open(dir.path().join("sessions"))?;
+    store.migrate_legacy_history(&legacy)?;
+    assert!(!legacy.exists());
+    let default = store.get(DEFAULT_SESSION_ID).unwrap();
+    assert_eq!(default.history.system_prompt(), Some("Be brief."));
+    assert_eq!(default.history.len(), 1);
+
+    // The default session is recreated on demand
+    store.delete(DEFAULT_SESSION_ID)?;
+    store.migrate_legacy_history(&legacy)?;
+    assert!(store.history_mut(DEFAULT_SESSION_ID)?.is_empty());
+    Ok(())
+}
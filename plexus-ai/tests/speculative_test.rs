@@ -0,0 +1,172 @@
+mod common;
+
+use candle_core::quantized::gguf_file;
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use candle_transformers::models::quantized_llama::ModelWeights;
+use common::tiny_gguf;
+use plexus_ai::{
+    BatchScheduler, BatchedLlama, DraftModel, PrefixCache, SequenceRequest, SpeculativeConfig,
+};
+use std::io::Cursor;
+use std::time::Duration;
+
+fn load(bytes: &[u8]) -> anyhow::Result<BatchedLlama> {
+    let mut reader = Cursor::new(bytes);
+    let content = gguf_file::Content::read(&mut reader)?;
+    BatchedLlama::from_gguf(content, &mut reader, &Device::Cpu)
+}
+
+/// Greedy decoding with candle's single-sequence model, one token at a time.
+fn reference_generate(bytes: &[u8], prompt: &[u32], n: usize) -> anyhow::Result<Vec<u32>> {
+    let mut reader = Cursor::new(bytes);
+    let content = gguf_file::Content::read(&mut reader)?;
+    let mut model = ModelWeights::from_gguf(content, &mut reader, &Device::Cpu)?;
+    let input = Tensor::new(prompt, &Device::Cpu)?.unsqueeze(0)?;
+    let mut logits = model.forward(&input, 0)?.squeeze(0)?;
+    let mut out = vec![];
+    for i in 0..n {
+        let token = logits.argmax(0)?.to_scalar::<u32>()?;
+        out.push(token);
+        let input = Tensor::new(&[token], &Device::Cpu)?.unsqueeze(0)?;
+        logits = model.forward(&input, prompt.len() + i)?.squeeze(0)?;
+    }
+    Ok(out)
+}
+
+fn greedy_request(tokens: Vec<u32>, max_new_tokens: usize) -> SequenceRequest {
+    SequenceRequest {
+        tokens,
+        max_new_tokens,
+        stop_tokens: vec![],
+        logits_processor: LogitsProcessor::from_sampling(0, Sampling::ArgMax),
+        constraint: None,
+        deterministic: false,
+    }
+}
+
+#[test]
+fn test_verify_scores_every_position() -> anyhow::Result<()> {
+    let model = load(&tiny_gguf(32, 2)?)?;
+    let prompt = [1u32, 5, 9];
+    let run = vec![4u32, 11, 2];
+
+    let mut cache = model.new_cache();
+    model.prefill(&prompt, &mut cache)?;
+    let mut sequential = cache.clone();
+
+    let logits = model.verify(std::slice::from_ref(&run), &mut [&mut cache])?;
+    assert_eq!(logits.dims(), &[1, 3, 32]);
+    assert_eq!(cache.len(), 6);
+    for (j, token) in run.iter().enumerate() {
+        let expected = model
+            .decode(&[*token], &mut [&mut sequential])?
+            .squeeze(0)?;
+        let got = logits.get(0)?.get(j)?;
+        let diff = (got - expected)?.abs()?.max(0)?.to_scalar::<f32>()?;
+        assert!(diff < 1e-4);
+    }
+
+    // Rolling back the last two tokens and feeding a different one matches a fresh run
+    cache.truncate(4)?;
+    assert_eq!(cache.len(), 4);
+    let rolled_back = model.decode(&[7], &mut [&mut cache])?;
+    let mut fresh = model.new_cache();
+    let expected = model.prefill(&[1, 5, 9, 4, 7], &mut fresh)?;
+    let diff = (rolled_back.squeeze(0)? - expected)?
+        .abs()?
+        .max(0)?
+        .to_scalar::<f32>()?;
+    assert!(diff < 1e-4);
+    Ok(())
+}
+
+async fn collect(
+    scheduler: &BatchScheduler,
+    prompt: Vec<u32>,
+    n: usize,
+) -> anyhow::Result<Vec<u32>> {
+    let mut rx = scheduler.submit(greedy_request(prompt, n))?;
+    let mut out = vec![];
+    while let Some(token) = tokio::time::timeout(Duration::from_secs(30), rx.recv()).await? {
+        out.push(token?);
+    }
+    Ok(out)
+}
+
+#[tokio::test]
+async fn test_speculative_output_matches_plain_decoding() -> anyhow::Result<()> {
+    let target = tiny_gguf(32, 2)?;
+    let prompts = [vec![1u32, 5, 9, 3], vec![2, 7], vec![30, 1, 1]];
+
+    // A draft identical to the target agrees on every token
+    let draft = DraftModel::new(load(&target)?, 3);
+    let stats = draft.stats();
+    let scheduler = BatchScheduler::speculative(
+        load(&target)?,
+        draft,
+        4,
+        PrefixCache::new(4, Duration::from_secs(60)),
+    )?;
+    for prompt in &prompts {
+        let expected = reference_generate(&target, prompt, 12)?;
+        assert_eq!(collect(&scheduler, prompt.clone(), 12).await?, expected);
+    }
+    let metrics = stats.snapshot();
+    assert!(metrics.proposed > 0);
+    assert_eq!(metrics.accepted, metrics.proposed);
+    assert_eq!(metrics.acceptance_rate, 1.0);
+
+    // An unrelated draft is mostly rejected, but the output must not change
+    let draft = DraftModel::new(load(&tiny_gguf(32, 1)?)?, 4);
+    let stats = draft.stats();
+    let scheduler = BatchScheduler::speculative(
+        load(&target)?,
+        draft,
+        4,
+        PrefixCache::new(4, Duration::from_secs(60)),
+    )?;
+    let receivers: Vec<_> = prompts
+        .iter()
+        .map(|p| collect(&scheduler, p.clone(), 12))
+        .collect();
+    let outputs = futures::future::try_join_all(receivers).await?;
+    for (prompt, output) in prompts.iter().zip(outputs) {
+        assert_eq!(output, reference_generate(&target, prompt, 12)?);
+    }
+    let metrics = stats.snapshot();
+    assert!(metrics.proposed > 0);
+    assert!(metrics.acceptance_rate <= 1.0);
+    Ok(())
+}
+
+#[test]
+fn test_draft_vocabulary_must_match() -> anyhow::Result<()> {
+    let draft = DraftModel::new(load(&tiny_gguf(16, 1)?)?, 4);
+    let result = BatchScheduler::speculative(
+        load(&tiny_gguf(32, 1)?)?,
+        draft,
+        4,
+        PrefixCache::new(4, Duration::from_secs(60)),
+    );
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_speculative_config() -> anyhow::Result<()> {
+    let config: SpeculativeConfig =
+        "TheBloke/TinyLlama-1.1B-Chat-v1.0-GGUF/tinyllama-1.1b-chat-v1.0.Q2_K.gguf:6".parse()?;
+    assert_eq!(config.draft_repo, "TheBloke/TinyLlama-1.1B-Chat-v1.0-GGUF");
+    assert_eq!(config.draft_file, "tinyllama-1.1b-chat-v1.0.Q2_K.gguf");
+    assert_eq!(config.draft_tokens, 6);
+
+    let config: SpeculativeConfig = "owner/repo/draft.gguf".parse()?;
+    assert_eq!(config.draft_tokens, plexus_ai::DEFAULT_DRAFT_TOKENS);
+
+    assert!("draft.gguf".parse::<SpeculativeConfig>().is_err());
+    assert!("owner/repo/draft.gguf:x"
+        .parse::<SpeculativeConfig>()
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use plexus_ai::{ChatHistory, ChatMessage, ChatTemplate, PromptTemplate, Role};
+use std::io::Write;
+
+fn conversation() -> Vec<ChatMessage> {
+    vec![
+        ChatMessage::new(Role::System, "You are helpful.".to_string()),
+        ChatMessage::new(Role::User, "Hi".to_string()),
+    ]
+}
+
+#[test]
+fn test_zephyr_matches_tinyllama_format() -> anyhow::Result<()> {
+    let mut history = ChatHistory::new(10);
+    history.add_system("You are helpful.".to_string());
+    history.add_user("Hi".to_string());
+
+    let prompt = history.format_prompt(&PromptTemplate::new(ChatTemplate::Zephyr))?;
+    assert_eq!(
+        prompt,
+        "<|system|>\nYou are helpful.</s>\n<|user|>\nHi</s>\n<|assistant|>\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_builtin_templates() -> anyhow::Result<()> {
+    let messages = conversation();
+
+    let chatml = PromptTemplate::new(ChatTemplate::ChatMl).render(&messages, true)?;
+    assert_eq!(
+        chatml,
+        "<|im_start|>system\nYou are helpful.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+    );
+
+    let llama3 = PromptTemplate::new(ChatTemplate::Llama3).render(&messages, true)?;
+    assert!(llama3.starts_with("<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\n"));
+    assert!(llama3.ends_with("<|start_header_id|>assistant<|end_header_id|>\n\n"));
+
+    let phi3 = PromptTemplate::new(ChatTemplate::Phi3).render(&messages, false)?;
+    assert_eq!(
+        phi3,
+        "<|system|>\nYou are helpful.<|end|>\n<|user|>\nHi<|end|>\n"
+    );
+
+    // Mistral folds the system prompt into the first user turn
+    let mistral = PromptTemplate::new(ChatTemplate::Mistral).render(&messages, true)?;
+    assert_eq!(mistral, "<s>[INST] You are helpful.\n\nHi [/INST]");
+    Ok(())
+}
+
+#[test]
+fn test_tokenizer_config_template() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("tokenizer_config.json");
+    let config = serde_json::json!({
+        "bos_token": {"content": "<s>", "lstrip": false},
+        "eos_token": "<|im_end|>",
+        "chat_template": "{{ bos_token }}{% for message in messages %}{% if message['role'] == 'assistant' %}{{ message['content'].strip() + eos_token }}{% else %}[{{ message['role'] }}] {{ message['content'] }}\n{% endif %}{% endfor %}{% if add_generation_prompt %}[assistant] {% endif %}"
+    });
+    std::fs::File::create(&path)?.write_all(config.to_string().as_bytes())?;
+
+    let template = PromptTemplate::from_tokenizer_config(&path)?;
+    assert_eq!(template.bos_token(), "<s>");
+    assert!(template.stop_tokens().contains(&"<|im_end|>".to_string()));
+
+    let prompt = template.render(&conversation(), true)?;
+    assert_eq!(
+        prompt,
+        "<s>[system] You are helpful.\n[user] Hi\n[assistant] "
+    );
+    assert!(template.includes_bos(&prompt));
+    Ok(())
+}
+
+#[test]
+fn test_raise_exception_surfaces_error() {
+    let template = PromptTemplate::new(ChatTemplate::Jinja(
+        "{{ raise_exception('roles must alternate') }}".to_string(),
+    ));
+    let err = template.render(&conversation(), true).unwrap_err();
+    assert!(format!("{:#}", err).contains("roles must alternate"));
+}
+
+#[test]
+fn test_strip_stop_tokens() {
+    let template = PromptTemplate::new(ChatTemplate::Llama3);
+    assert_eq!(
+        template.strip_stop_tokens("Hello there<|eot_id|><|start_header_id|>"),
+        "Hello there"
+    );
+    assert_eq!(
+        ChatTemplate::infer_from_name("tinyllama_tinyllama-1.1b-chat-v1.0"),
+        ChatTemplate::Zephyr
+    );
+}
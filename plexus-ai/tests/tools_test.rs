@@ -0,0 +1,207 @@
+mod common;
+
+use common::StubEngine;
+use plexus_ai::{
+    ChatHistory, ChatMessage, ChatTemplate, LLMEngine, PromptTemplate, Role, ToolCall,
+    ToolCallFormat, ToolChoice, ToolDefinition,
+};
+use serde_json::json;
+
+fn weather_tool() -> ToolDefinition {
+    ToolDefinition::function(
+        "get_weather",
+        "Current weather for a city",
+        json!({
+            "type": "object",
+            "properties": {"city": {"type": "string"}},
+            "required": ["city"]
+        }),
+    )
+}
+
+#[test]
+fn test_tool_choice_wire_format() -> anyhow::Result<()> {
+    assert_eq!(
+        serde_json::from_value::<ToolChoice>(json!("required"))?,
+        ToolChoice::Required
+    );
+    let named: ToolChoice =
+        serde_json::from_value(json!({"type": "function", "function": {"name": "get_weather"}}))?;
+    assert_eq!(named, ToolChoice::Function("get_weather".to_string()));
+    assert_eq!(
+        serde_json::to_value(&named)?,
+        json!({"type": "function", "function": {"name": "get_weather"}})
+    );
+    assert!(serde_json::from_value::<ToolChoice>(json!("sometimes")).is_err());
+
+    assert!(named.validate(&[weather_tool()]).is_ok());
+    assert!(named.validate(&[]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_render_tools_in_system_prompt() -> anyhow::Result<()> {
+    let template = PromptTemplate::new(ChatTemplate::ChatMl);
+    let messages = vec![
+        ChatMessage::new(Role::System, "You are helpful.".to_string()),
+        ChatMessage::new(Role::User, "Weather in Oslo?".to_string()),
+    ];
+
+    let prompt =
+        template.render_with_tools(&messages, &[weather_tool()], &ToolChoice::Required, true)?;
+    assert!(prompt.starts_with(
+        "<|im_start|>system\nYou are helpful.\n\nYou are a function calling AI model."
+    ));
+    assert!(prompt.contains("\"name\":\"get_weather\""));
+    assert!(prompt.contains("You must call at least one function."));
+
+    // tool_choice none hides the tools entirely
+    let plain =
+        template.render_with_tools(&messages, &[weather_tool()], &ToolChoice::None, true)?;
+    assert_eq!(plain, template.render(&messages, true)?);
+    Ok(())
+}
+
+#[test]
+fn test_render_tool_results_in_history() -> anyhow::Result<()> {
+    let call = ToolCall::new("get_weather", &json!({"city": "Oslo"}));
+    let mut history = ChatHistory::new(10);
+    history.add_user("Weather in Oslo?".to_string());
+    history.add_tool_calls(String::new(), vec![call.clone()]);
+    history.add_tool_result(&call.id, "{\"temp\": 3}".to_string());
+
+    let chatml = history.format_prompt(&PromptTemplate::new(ChatTemplate::ChatMl))?;
+    assert!(chatml.contains(
+        "<|im_start|>assistant\n<tool_call>\n{\"arguments\":{\"city\":\"Oslo\"},\"name\":\"get_weather\"}\n</tool_call><|im_end|>"
+    ));
+    assert!(chatml
+        .contains("<|im_start|>user\n<tool_response>\n{\"temp\": 3}\n</tool_response><|im_end|>"));
+
+    let llama3 = history.format_prompt(&PromptTemplate::new(ChatTemplate::Llama3))?;
+    assert!(
+        llama3.contains("<|start_header_id|>ipython<|end_header_id|>\n\n{\"temp\": 3}<|eot_id|>")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_native_template_receives_tools() -> anyhow::Result<()> {
+    let source = "{% if tools %}TOOLS:{% for t in tools %}{{ t.function.name }};{% endfor %}\n{% endif %}{% for m in messages %}{{ m.role }}:{% if m.tool_calls %}{% for c in m.tool_calls %}{{ c.function.name }}({{ c.function.arguments | tojson }}){% endfor %}{% else %}{{ m.content }}{% endif %}{% if m.tool_call_id %}#{{ m.tool_call_id }}{% endif %}\n{% endfor %}";
+    let template = PromptTemplate::new(ChatTemplate::Jinja(source.to_string()));
+    assert!(template.template().supports_tools());
+
+    let mut call = ToolCall::new("get_weather", &json!({"city": "Oslo"}));
+    call.id = "call_1".to_string();
+    let messages = vec![
+        ChatMessage::new(Role::User, "Weather?".to_string()),
+        ChatMessage {
+            tool_calls: vec![call],
+            ..ChatMessage::new(Role::Assistant, String::new())
+        },
+        ChatMessage::tool("call_1", "sunny".to_string()),
+    ];
+    let prompt =
+        template.render_with_tools(&messages, &[weather_tool()], &ToolChoice::Auto, false)?;
+    assert_eq!(
+        prompt,
+        "TOOLS:get_weather;\nuser:Weather?\nassistant:get_weather({\"city\":\"Oslo\"})\ntool:sunny#call_1\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parse_tool_calls() -> anyhow::Result<()> {
+    let tools = [weather_tool()];
+    let auto = ToolChoice::Auto;
+    let message = ToolCallFormat::Hermes.parse(
+        "Let me check.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}\n</tool_call>",
+        &tools,
+        &auto,
+    );
+    assert!(matches!(message.role, Role::Assistant));
+    assert_eq!(message.content, "Let me check.");
+    assert_eq!(message.tool_calls.len(), 1);
+    assert_eq!(message.tool_calls[0].function.name, "get_weather");
+    assert_eq!(message.tool_calls[0].arguments()?, json!({"city": "Oslo"}));
+
+    let letters = [
+        ToolDefinition::function("a", "", json!({})),
+        ToolDefinition::function("b", "", json!({})),
+    ];
+    let message = ToolCallFormat::Llama3.parse(
+        "<|python_tag|>{\"name\": \"a\", \"parameters\": {\"x\": 1}}; {\"name\": \"b\", \"parameters\": {\"s\": \"x;y\"}}",
+        &letters,
+        &auto,
+    );
+    assert_eq!(message.content, "");
+    let names: Vec<_> = message
+        .tool_calls
+        .iter()
+        .map(|c| c.function.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["a", "b"]);
+    assert_eq!(message.tool_calls[1].arguments()?, json!({"s": "x;y"}));
+
+    let message = ToolCallFormat::Mistral.parse(
+        "[TOOL_CALLS] [{\"name\": \"get_weather\", \"arguments\": \"{\\\"city\\\": \\\"Oslo\\\"}\"}]",
+        &tools,
+        &auto,
+    );
+    assert_eq!(message.tool_calls[0].arguments()?, json!({"city": "Oslo"}));
+
+    // Markers are often dropped by small models
+    let bare = "{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}";
+    let message = ToolCallFormat::Hermes.parse(bare, &tools, &auto);
+    assert_eq!(message.tool_calls.len(), 1);
+
+    let message = ToolCallFormat::Hermes.parse("It is {probably} sunny.", &tools, &auto);
+    assert!(message.tool_calls.is_empty());
+    assert_eq!(message.content, "It is {probably} sunny.");
+    Ok(())
+}
+
+#[test]
+fn test_only_offered_tools_are_parsed() {
+    let tools = [weather_tool()];
+    let bare = "{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}";
+
+    // Without tools on offer, JSON answers stay answers
+    let message = ToolCallFormat::Hermes.parse(bare, &[], &ToolChoice::Auto);
+    assert!(message.tool_calls.is_empty());
+    assert_eq!(message.content, bare);
+    let message = ToolCallFormat::Hermes.parse(bare, &tools, &ToolChoice::None);
+    assert!(message.tool_calls.is_empty());
+
+    let person = "{\"name\":\"Bob\",\"arguments\":{}}";
+    let message = ToolCallFormat::Hermes.parse(person, &tools, &ToolChoice::Auto);
+    assert!(message.tool_calls.is_empty());
+    assert_eq!(message.content, person);
+
+    // Calls to functions that were not offered are dropped, the others kept
+    let message = ToolCallFormat::Hermes.parse(
+        "<tool_call>{\"name\": \"rm_rf\", \"arguments\": {}}</tool_call><tool_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}</tool_call>",
+        &tools,
+        &ToolChoice::Required,
+    );
+    let names: Vec<_> = message
+        .tool_calls
+        .iter()
+        .map(|c| c.function.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["get_weather"]);
+}
+
+#[tokio::test]
+async fn test_chat_with_tools() -> anyhow::Result<()> {
+    let engine = StubEngine::default().with_reply(
+        "<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Oslo\"}}\n</tool_call><|im_end|>",
+    );
+    let messages = vec![ChatMessage::new(Role::User, "Weather in Oslo?".to_string())];
+    let reply = engine
+        .chat_with_tools(&messages, &[weather_tool()], &ToolChoice::Auto)
+        .await?;
+    assert!(engine.prompts.lock().unwrap()[0].contains("<tools>"));
+    assert_eq!(reply.content, "");
+    assert_eq!(reply.tool_calls[0].function.name, "get_weather");
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use plexus_ai::{Document, SearchFilter, SimpleVectorStore, VectorStore};
+
+async fn store() -> anyhow::Result<SimpleVectorStore> {
+    let store = SimpleVectorStore::new();
+    store
+        .upsert(
+            Document::new("standup", "Standup moved to 10am")
+                .with_source("chat")
+                .with_tags(["work", "meetings"])
+                .with_created_at(1_000),
+            vec![1.0, 0.0],
+        )
+        .await?;
+    store
+        .upsert(
+            Document::new("deploy", "Deploys happen on Thursdays")
+                .with_source("handbook.md")
+                .with_tags(["work"])
+                .with_created_at(2_000),
+            vec![0.9, 0.1],
+        )
+        .await?;
+    store
+        .upsert(
+            Document::new("pizza", "Pizza place closes at 11pm")
+                .with_source("chat")
+                .with_created_at(3_000),
+            vec![0.0, 1.0],
+        )
+        .await?;
+    Ok(store)
+}
+
+fn ids(results: &[plexus_ai::SearchResult]) -> Vec<&str> {
+    results.iter().map(|r| r.id.as_str()).collect()
+}
+
+#[tokio::test]
+async fn test_search_returns_documents() -> anyhow::Result<()> {
+    let store = store().await?;
+    let results = store.search(vec![1.0, 0.0], 2).await?;
+    assert_eq!(ids(&results), ["standup", "deploy"]);
+    assert_eq!(results[0].text, "Standup moved to 10am");
+    assert_eq!(results[0].metadata["source"], "chat");
+    assert!(results[0].score > results[1].score);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_filters() -> anyhow::Result<()> {
+    let store = store().await?;
+    let query = vec![1.0, 0.0];
+
+    let chat = SearchFilter::default().with_source("chat");
+    let results = store.search_filtered(query.clone(), 5, &chat).await?;
+    assert_eq!(ids(&results), ["standup", "pizza"]);
+
+    // Every tag must be present
+    let meetings = SearchFilter::default()
+        .with_tag("work")
+        .with_tag("meetings");
+    let results = store.search_filtered(query.clone(), 5, &meetings).await?;
+    assert_eq!(ids(&results), ["standup"]);
+
+    // Time bounds are inclusive
+    let recent = SearchFilter::default()
+        .with_created_after(2_000)
+        .with_created_before(3_000);
+    let results = store.search_filtered(query.clone(), 5, &recent).await?;
+    assert_eq!(ids(&results), ["deploy", "pizza"]);
+
+    let none = SearchFilter::default().with_source("chat").with_tag("work");
+    let results = store
+        .search_filtered(query, 5, &none.with_created_after(2_000))
+        .await?;
+    assert!(results.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_delete_count() -> anyhow::Result<()> {
+    let store = store().await?;
+    assert_eq!(store.count().await?, 3);
+
+    let deploy = store.get("deploy").await?.unwrap();
+    assert_eq!(deploy.source(), Some("handbook.md"));
+    assert_eq!(deploy.tags(), ["work"]);
+    assert_eq!(deploy.created_at(), Some(2_000));
+
+    // Re-adding an id replaces the document
+    store
+        .add_document("deploy", "Deploys happen on Fridays", vec![0.9, 0.1])
+        .await?;
+    assert_eq!(store.count().await?, 3);
+    let deploy = store.get("deploy").await?.unwrap();
+    assert_eq!(deploy.text, "Deploys happen on Fridays");
+    assert_eq!(deploy.source(), None);
+    assert!(deploy.created_at().is_some());
+
+    store.delete("deploy").await?;
+    store.delete("deploy").await?;
+    assert!(store.get("deploy").await?.is_none());
+    assert_eq!(store.count().await?, 2);
+    Ok(())
+}
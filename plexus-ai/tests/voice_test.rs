@@ -0,0 +1,198 @@
+use plexus_ai::voice::{
+    detect_language, mel_filters, merge_windows, segments_from_tokens, window_starts, Segment,
+    Task, TimestampRules, TranscribeOptions, Transcript, LANGUAGES,
+};
+
+/// Frequency bins of a 400-point FFT at 16 kHz, 40 Hz apart.
+const BINS: usize = 201;
+
+fn segment(start: f64, end: f64, text: &str) -> Segment {
+    Segment {
+        start,
+        end,
+        text: text.to_string(),
+    }
+}
+
+#[test]
+fn test_bundled_mel_filters() -> anyhow::Result<()> {
+    for bins in [80, 128] {
+        let filters = mel_filters(bins)?;
+        assert_eq!(filters.len(), bins * BINS);
+
+        let rows: Vec<&[f32]> = filters.chunks(BINS).collect();
+        let peaks: Vec<usize> = rows
+            .iter()
+            .map(|row| {
+                (0..BINS)
+                    .max_by(|&a, &b| row[a].total_cmp(&row[b]))
+                    .unwrap()
+            })
+            .collect();
+        assert!(peaks.windows(2).all(|p| p[0] <= p[1]), "{:?}", peaks);
+        assert!(rows.iter().all(|row| row.iter().all(|w| *w >= 0.0)));
+
+        // Slaney normalization gives every triangle an area of one over frequency
+        let area: f32 = rows[bins - 1].iter().sum::<f32>() * 40.0;
+        assert!((area - 1.0).abs() < 0.05, "{} bins: area {}", bins, area);
+    }
+    assert!(mel_filters(64).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_window_starts_overlap() {
+    let second = 16_000;
+    assert_eq!(window_starts(5 * second, 2.0), [0]);
+    assert_eq!(window_starts(30 * second, 2.0), [0]);
+    assert_eq!(
+        window_starts(60 * second, 2.0),
+        [0, 28 * second, 56 * second]
+    );
+    // Windows advance by at least half a window whatever the overlap
+    assert_eq!(
+        window_starts(46 * second, 100.0),
+        [0, 15 * second, 30 * second]
+    );
+}
+
+#[test]
+fn test_merge_prefers_window_with_context() {
+    // Windows start at 0 s and 28 s and share 28-30 s; the middle of the overlap is 29 s
+    let merged = merge_windows(vec![
+        (
+            0.0,
+            vec![
+                segment(0.0, 27.5, "first"),
+                segment(28.4, 29.8, "kept from the first"),
+                segment(29.2, 30.0, "cut off"),
+            ],
+        ),
+        (
+            28.0,
+            vec![
+                segment(28.4, 29.0, "seen twice"),
+                segment(29.2, 31.0, "kept from the second"),
+                segment(31.0, 40.0, "last"),
+            ],
+        ),
+    ]);
+    let texts: Vec<&str> = merged.iter().map(|s| s.text.as_str()).collect();
+    assert_eq!(
+        texts,
+        [
+            "first",
+            "kept from the first",
+            "kept from the second",
+            "last"
+        ]
+    );
+}
+
+#[test]
+fn test_segments_from_timestamp_tokens() -> anyhow::Result<()> {
+    // Timestamps start at 100 and are 20 ms apart
+    let decode = |tokens: &[u32]| Ok(tokens.iter().map(|t| format!(" w{}", t)).collect());
+    let tokens = [100, 1, 2, 150, 150, 3, 225, 4];
+    let segments = segments_from_tokens(&tokens, 100, 28.0, 33.0, decode)?;
+    assert_eq!(
+        segments,
+        [
+            segment(28.0, 29.0, " w1 w2"),
+            segment(29.0, 30.5, " w3"),
+            // Cut off by the end of the window
+            segment(30.5, 33.0, " w4"),
+        ]
+    );
+
+    let transcript = Transcript::from_segments(segments);
+    assert_eq!(transcript.text, "w1 w2 w3 w4");
+    Ok(())
+}
+
+#[test]
+fn test_timestamp_rules() {
+    let rules = TimestampRules {
+        eot: 10,
+        timestamp_begin: 20,
+    };
+    let best = |logits: &[f32]| {
+        (0..logits.len())
+            .max_by(|&a, &b| logits[a].total_cmp(&logits[b]))
+            .unwrap() as u32
+    };
+    let vocab = 20 + 1501;
+
+    // A window opens with an early timestamp, even when text looks more likely
+    let mut logits = vec![0.0; vocab];
+    logits[3] = 5.0;
+    logits[20 + 200] = 4.0;
+    logits[20 + 10] = 1.0;
+    rules.apply(&mut logits, &[]);
+    assert_eq!(best(&logits), 30);
+
+    // Special tokens never come out; after an opening timestamp comes text
+    let mut logits = vec![0.0; vocab];
+    logits[12] = 9.0;
+    logits[25] = 8.0;
+    logits[4] = 1.0;
+    rules.apply(&mut logits, &[30]);
+    assert_eq!(best(&logits), 4);
+
+    // After text and a closing timestamp, the next segment opens at or after it
+    let mut logits = vec![0.0; vocab];
+    logits[5] = 9.0;
+    logits[20 + 40] = 8.0;
+    logits[20 + 60] = 7.0;
+    rules.apply(&mut logits, &[30, 4, 80]);
+    assert_eq!(best(&logits), 80);
+    assert_eq!(logits[60], f32::NEG_INFINITY);
+
+    // Many likely timestamps outweigh a single likelier text token
+    let mut logits = vec![f32::NEG_INFINITY; vocab];
+    logits[4] = 2.0;
+    for logit in &mut logits[90..vocab] {
+        *logit = 0.0;
+    }
+    rules.apply(&mut logits, &[30, 4]);
+    assert!(best(&logits) >= 90);
+}
+
+#[test]
+fn test_detect_language_among_language_tokens() {
+    // Language tokens follow <|startoftranscript|> (50258) in vocabulary order
+    let languages: Vec<(&str, u32)> = LANGUAGES
+        .iter()
+        .zip(50259..)
+        .map(|(&code, token)| (code, token))
+        .collect();
+    assert_eq!(languages[2], ("de", 50261));
+
+    let mut logits = vec![0.0; 51865];
+    // Text tokens are not languages, however likely
+    logits[100] = 20.0;
+    logits[50259] = 3.0;
+    logits[50261] = 7.5;
+    assert_eq!(detect_language(&logits, &languages), Some("de"));
+
+    // English-only vocabularies have no language tokens
+    assert_eq!(detect_language(&logits, &[]), None);
+    assert_eq!(detect_language(&logits[..50000], &languages), None);
+}
+
+#[test]
+fn test_transcribe_options() -> anyhow::Result<()> {
+    let options = TranscribeOptions::default();
+    assert_eq!((options.language, options.task), (None, Task::Transcribe));
+
+    let options = TranscribeOptions::default()
+        .with_language("fr")
+        .with_task(Task::Translate);
+    assert_eq!(options.language.as_deref(), Some("fr"));
+    assert_eq!(serde_json::to_string(&options.task)?, "\"translate\"");
+
+    // Transcripts saved before languages were reported still load
+    let transcript: Transcript = serde_json::from_str(r#"{"text": "hi", "segments": []}"#)?;
+    assert_eq!(transcript.language, None);
+    Ok(())
+}
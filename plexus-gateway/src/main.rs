@@ -99,12 +99,159 @@ struct ChatCompletionRequest {
     temperature: Option<f32>,
     #[serde(default)]
     stream: bool,
+    /// Structured output constraint; only validated until mesh dispatch is implemented
+    #[serde(default)]
+    response_format: Option<ResponseFormat>,
+    /// Functions the model may call
+    #[serde(default)]
+    tools: Vec<Tool>,
+    #[serde(default)]
+    tool_choice: Option<ToolChoice>,
+}
+
+impl ChatCompletionRequest {
+    /// Checks that tools, `tool_choice` and tool messages are consistent.
+    fn validate_tools(&self) -> Result<(), String> {
+        let mut names = std::collections::HashSet::new();
+        for tool in &self.tools {
+            if tool.kind != "function" {
+                return Err(format!("Unsupported tool type '{}'", tool.kind));
+            }
+            if tool.function.name.is_empty() || !names.insert(tool.function.name.as_str()) {
+                return Err(format!(
+                    "Tool names must be unique and non-empty, got '{}'",
+                    tool.function.name
+                ));
+            }
+        }
+
+        match &self.tool_choice {
+            None => {}
+            Some(ToolChoice::Mode(mode)) => match mode.as_str() {
+                "none" | "auto" => {}
+                "required" if !self.tools.is_empty() => {}
+                "required" => return Err("tool_choice 'required' needs tools".to_string()),
+                other => return Err(format!("Unknown tool_choice '{}'", other)),
+            },
+            Some(ToolChoice::Named { function }) if !names.contains(function.name.as_str()) => {
+                return Err(format!(
+                    "tool_choice names unknown function '{}'",
+                    function.name
+                ));
+            }
+            Some(ToolChoice::Named { .. }) => {}
+        }
+
+        for message in &self.messages {
+            match message.role.as_str() {
+                "tool" if message.tool_call_id.is_none() => {
+                    return Err("Messages with role 'tool' need a tool_call_id".to_string());
+                }
+                "system" | "user" | "assistant" | "tool" => {}
+                other => return Err(format!("Unknown message role '{}'", other)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Mirrors `plexus_ai::ToolDefinition`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Tool {
+    #[serde(rename = "type", default = "function_kind")]
+    kind: String,
+    function: FunctionDefinition,
+}
+
+fn function_kind() -> String {
+    "function".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FunctionDefinition {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default)]
+    parameters: serde_json::Value,
+}
+
+/// `"none" | "auto" | "required"` or `{"type": "function", "function": {"name": ...}}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum ToolChoice {
+    Mode(String),
+    Named { function: FunctionName },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FunctionName {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ToolCall {
+    id: String,
+    #[serde(rename = "type", default = "function_kind")]
+    kind: String,
+    function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FunctionCall {
+    name: String,
+    /// JSON-encoded arguments
+    arguments: String,
+}
+
+/// Mirrors `plexus_ai::ResponseFormat` (OpenAI's `response_format` plus `regex`/`grammar`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+    Regex { pattern: String },
+    Grammar { grammar: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct JsonSchemaFormat {
+    #[serde(default)]
+    name: Option<String>,
+    schema: serde_json::Value,
+    #[serde(default)]
+    strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    /// Rejects formats that can never be compiled into a constraint.
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            ResponseFormat::JsonSchema { json_schema } if !json_schema.schema.is_object() => {
+                Err("response_format.json_schema.schema must be a JSON object".to_string())
+            }
+            ResponseFormat::Regex { pattern } if pattern.is_empty() => {
+                Err("response_format.pattern must not be empty".to_string())
+            }
+            ResponseFormat::Grammar { grammar } if grammar.trim().is_empty() => {
+                Err("response_format.grammar must not be empty".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct Message {
     role: String,
-    content: String,
+    /// `null` for assistant messages that only call tools
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -246,9 +393,26 @@ async fn chat_completions(
         agent_name, payload.model
     );
 
+    if let Some(format) = &payload.response_format {
+        format
+            .validate()
+            .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+        info!("Constraining output with response_format: {:?}", format);
+    }
+    payload
+        .validate_tools()
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    if !payload.tools.is_empty() {
+        info!(
+            "Offering {} tool(s), tool_choice: {:?}",
+            payload.tools.len(),
+            payload.tool_choice
+        );
+    }
+
     // Dispatch to Mesh (Stub)
     // We map the tuple error to AppError
-    let response_text = dispatch_to_mesh(&payload)
+    let message = dispatch_to_mesh(&payload)
         .await
         .map_err(|(status, json)| AppError(status, json.0.to_string()))?;
 
@@ -262,11 +426,13 @@ async fn chat_completions(
         model: payload.model.clone(),
         choices: vec![Choice {
             index: 0,
-            message: Message {
-                role: "assistant".to_string(),
-                content: response_text,
-            },
-            finish_reason: "stop".to_string(),
+            finish_reason: if message.tool_calls.is_empty() {
+                "stop"
+            } else {
+                "tool_calls"
+            }
+            .to_string(),
+            message,
         }],
         usage: Usage {
             prompt_tokens: 0,
@@ -281,7 +447,7 @@ async fn chat_completions(
 // Internal Logic
 async fn dispatch_to_mesh(
     _req: &ChatCompletionRequest,
-) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
+) -> Result<Message, (StatusCode, Json<serde_json::Value>)> {
     // TODO: Implement actual gRPC or P2P client here.
     // For MVP, we return 503 to indicate this Gateway is not yet connected to a backing node.
 
@@ -1,6 +1,10 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use plexus_p2p::{NodeCommand, NodeService};
+use clap::{Parser, Subcommand};
+use plexus_p2p::{
+    open_memory_backup, ContextPolicy, DeviceRequest, FederatedSearchConfig, MemoryAcl,
+    NamespaceShare, NodeCommand, NodeService, QdrantConfig, RetrievalConfig, SpeculativeConfig,
+    VectorStoreConfig, VectorStoreKind, DEFAULT_EMBEDDING_MODEL,
+};
 use std::path::PathBuf;
 use tokio::sync::mpsc;
 use tracing::{error, info};
@@ -8,20 +12,160 @@ use tracing::{error, info};
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Name of the model to use (e.g., tinyllama, phi)
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Name of the model to use (tinyllama or zephyr)
     #[arg(short, long, default_value = "tinyllama")]
     model: String,
 
     /// Custom data directory (for running multiple nodes)
     #[arg(long)]
     data_dir: Option<PathBuf>,
+
+    /// Memory available to resident models in MB (defaults to half of system RAM)
+    #[arg(long)]
+    model_memory_mb: Option<u64>,
+
+    /// Compute device: auto, cpu, cuda[:N], metal[:N] or vulkan[:N] (falls back to CPU)
+    #[arg(long, default_value = "auto")]
+    device: DeviceRequest,
+
+    /// Draft model for speculative decoding: <owner>/<repo>/<file>.gguf[:<draft tokens>]
+    #[arg(long)]
+    draft_model: Option<SpeculativeConfig>,
+
+    /// Embedding model for memory: minilm, bge-small, e5-small or nomic. Each model keeps
+    /// its memory in a collection of its own
+    #[arg(long, default_value = DEFAULT_EMBEDDING_MODEL)]
+    embedding_model: String,
+
+    /// Vector store for memory: none, memory (embedded HNSW), lancedb or qdrant. Qdrant
+    /// falls back to the embedded store while the server is unreachable
+    #[arg(long, default_value_t = VectorStoreKind::default())]
+    vector_store: VectorStoreKind,
+
+    /// Qdrant gRPC endpoint
+    #[arg(long, default_value_t = QdrantConfig::default().url)]
+    qdrant_url: String,
+
+    /// Qdrant API key
+    #[arg(long)]
+    qdrant_api_key: Option<String>,
+
+    /// Qdrant collection name prefix; the embedding model and dimension are appended
+    #[arg(long, default_value_t = QdrantConfig::default().collection)]
+    qdrant_collection: String,
+
+    /// Timeout of Qdrant connections and requests in milliseconds
+    #[arg(long, default_value_t = QdrantConfig::default().timeout_ms)]
+    qdrant_timeout_ms: u64,
+
+    /// Seconds between attempts to reconnect to Qdrant (0 disables reconnecting)
+    #[arg(long, default_value_t = VectorStoreConfig::default().reconnect_secs)]
+    qdrant_reconnect_secs: u64,
+
+    /// Share memory saved with /share with every peer using the same namespace; their
+    /// shared memory is added to prompts here too
+    #[arg(long)]
+    shared_memory: Option<String>,
+
+    /// Let peers search a memory namespace here: `local` (this node's memory) or the
+    /// --shared-memory namespace, optionally limited to `<namespace>=<peer id>,...`.
+    /// Repeatable; nothing is searchable by peers by default
+    #[arg(long)]
+    share_memory: Vec<NamespaceShare>,
+
+    /// Results kept from a memory search across peers (/search)
+    #[arg(long, default_value_t = FederatedSearchConfig::default().top_k)]
+    search_top_k: usize,
+
+    /// Milliseconds a memory search waits for peers before leaving them out
+    #[arg(long, default_value_t = FederatedSearchConfig::default().peer_timeout_ms)]
+    search_timeout_ms: u64,
+
+    /// Memory passages added to a prompt
+    #[arg(long, default_value_t = RetrievalConfig::default().top_k)]
+    rag_top_k: usize,
+
+    /// Minimum relevance of a memory passage: cosine similarity of vector matches, or the
+    /// reranker's score with --rerank
+    #[arg(long, default_value_t = RetrievalConfig::default().threshold)]
+    rag_threshold: f32,
+
+    /// Without --rerank, keyword matches must share an identifier or a word found in at
+    /// most this fraction of memory passages with the prompt
+    #[arg(long, default_value_t = RetrievalConfig::default().lexical_rarity)]
+    rag_keyword_rarity: f32,
+
+    /// Rerank memory passages with a cross-encoder before adding them to a prompt
+    #[arg(long)]
+    rerank: bool,
+
+    /// Summarize chat turns that no longer fit the context window instead of dropping them
+    #[arg(long)]
+    summarize_history: bool,
+}
+
+/// Memory tools that run instead of the node. They use the node's --data-dir,
+/// --embedding-model and vector store options, given before the subcommand
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the node's memory to a JSON Lines file, with the vectors and their model
+    ExportMemory { path: PathBuf },
+    /// Add the memory of an export, replacing documents with the same id. Documents
+    /// embedded with another model are embedded again
+    ImportMemory { path: PathBuf },
+}
+
+impl Args {
+    fn vector_store_config(&self) -> VectorStoreConfig {
+        VectorStoreConfig::new(self.vector_store)
+            .with_qdrant(
+                QdrantConfig::new(&self.qdrant_url)
+                    .with_api_key(self.qdrant_api_key.clone())
+                    .with_collection(&self.qdrant_collection)
+                    .with_timeout_ms(self.qdrant_timeout_ms),
+            )
+            .with_reconnect_secs(self.qdrant_reconnect_secs)
+    }
+}
+
+async fn run_command(command: Command, args: Args) -> Result<()> {
+    // A one-off run has no use for reconnecting to Qdrant
+    let vector_store = args.vector_store_config().with_reconnect_secs(0);
+    let backup = open_memory_backup(
+        args.data_dir,
+        args.device,
+        &args.embedding_model,
+        vector_store,
+    )
+    .await
+    .context("Failed to open memory")?;
+    match command {
+        Command::ExportMemory { path } => {
+            let count = backup.export_file(&path).await?;
+            info!("Exported {} documents to {:?}", count, path);
+        }
+        Command::ImportMemory { path } => {
+            let report = backup.import_file(&path).await?;
+            info!(
+                "Imported {} documents from {:?} ({} re-embedded)",
+                report.imported, path, report.reembedded
+            );
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     plexus_core::init_tracing();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(command) = args.command.take() {
+        return run_command(command, args).await;
+    }
 
     info!("Starting Plexus Peer Node (Second Node)...");
     info!("Selected Model: {}", args.model);
@@ -39,9 +183,55 @@ async fn main() -> Result<()> {
     let (tx, rx) = mpsc::channel(32);
 
     info!("Initializing Peer NodeService...");
-    let service = NodeService::new(identity_path, rx, args.model, vec![], args.data_dir)
-        .await
-        .context("Failed to init service")?;
+    let vector_store = args.vector_store_config();
+    let mut service = NodeService::new(
+        identity_path,
+        rx,
+        args.model,
+        vec![],
+        args.data_dir,
+        args.model_memory_mb.map(|mb| mb * 1024 * 1024),
+        args.device,
+    )
+    .await
+    .context("Failed to init service")?
+    .with_embedding_model(&args.embedding_model)
+    .context("Failed to select embedding model")?
+    .with_vector_store(vector_store);
+    if let Some(draft) = args.draft_model {
+        info!(
+            "Speculative decoding with draft model {}/{}",
+            draft.draft_repo, draft.draft_file
+        );
+        service = service.with_speculative(draft);
+    }
+    service = service.with_retrieval_config(
+        RetrievalConfig::default()
+            .with_top_k(args.rag_top_k)
+            .with_threshold(args.rag_threshold)
+            .with_lexical_rarity(args.rag_keyword_rarity),
+    );
+    if args.rerank {
+        service = service.with_reranker();
+    }
+    if let Some(namespace) = &args.shared_memory {
+        service = service
+            .with_shared_memory(namespace)
+            .context("Failed to enable shared memory")?;
+    }
+    let acl = args
+        .share_memory
+        .into_iter()
+        .fold(MemoryAcl::new(), MemoryAcl::with_share);
+    service = service.with_federated_search(
+        FederatedSearchConfig::default()
+            .with_acl(acl)
+            .with_top_k(args.search_top_k)
+            .with_peer_timeout_ms(args.search_timeout_ms),
+    );
+    if args.summarize_history {
+        service = service.with_context_policy(ContextPolicy::default().with_summarize(true));
+    }
     info!("Peer NodeService initialized. Listening for main node...");
 
     // Spawn service in background or run it?
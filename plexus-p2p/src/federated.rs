@@ -0,0 +1,206 @@
+use plexus_ai::SearchResult;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Namespace of a node's own vector store; shared memory namespaces go by their name.
+pub const LOCAL_NAMESPACE: &str = "local";
+
+/// A namespace peers may search, parsed from `<namespace>` (any peer) or
+/// `<namespace>=<peer id>,<peer id>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceShare {
+    pub namespace: String,
+    /// Peers allowed to search it; any peer when empty
+    pub peers: Vec<String>,
+}
+
+impl std::str::FromStr for NamespaceShare {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (namespace, peers) = match s.split_once('=') {
+            Some((namespace, peers)) => {
+                let peers: Vec<String> = peers
+                    .split(',')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                if peers.is_empty() {
+                    anyhow::bail!("No peers listed for namespace '{}'", namespace.trim());
+                }
+                (namespace.trim(), peers)
+            }
+            None => (s.trim(), vec![]),
+        };
+        if namespace.is_empty() {
+            anyhow::bail!("Missing namespace in '{}'", s);
+        }
+        Ok(Self {
+            namespace: namespace.to_string(),
+            peers,
+        })
+    }
+}
+
+/// Which memory namespaces peers may search. Nothing is shared by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryAcl {
+    /// Namespace -> peers allowed to search it; any peer when `None`
+    namespaces: BTreeMap<String, Option<BTreeSet<String>>>,
+}
+
+impl MemoryAcl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets the peers of `share` search its namespace, in addition to any already allowed.
+    pub fn with_share(mut self, share: NamespaceShare) -> Self {
+        let allowed = self
+            .namespaces
+            .entry(share.namespace)
+            .or_insert_with(|| Some(BTreeSet::new()));
+        match allowed {
+            Some(peers) if !share.peers.is_empty() => peers.extend(share.peers),
+            _ => *allowed = None,
+        }
+        self
+    }
+
+    /// The namespaces shared with at least one peer.
+    pub fn namespaces(&self) -> Vec<String> {
+        self.namespaces.keys().cloned().collect()
+    }
+
+    /// Whether `peer` may search `namespace`.
+    pub fn allows(&self, namespace: &str, peer: &str) -> bool {
+        match self.namespaces.get(namespace) {
+            Some(None) => true,
+            Some(Some(peers)) => peers.contains(peer),
+            None => false,
+        }
+    }
+}
+
+/// How this node takes part in federated memory search.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FederatedSearchConfig {
+    /// Namespaces peers may search here, advertised in heartbeats.
+    pub acl: MemoryAcl,
+    /// Results kept after merging.
+    pub top_k: usize,
+    /// Peers that have not answered by then are left out of the results.
+    pub peer_timeout_ms: u64,
+}
+
+impl Default for FederatedSearchConfig {
+    fn default() -> Self {
+        Self {
+            acl: MemoryAcl::default(),
+            top_k: 5,
+            peer_timeout_ms: 3000,
+        }
+    }
+}
+
+impl FederatedSearchConfig {
+    pub fn with_acl(mut self, acl: MemoryAcl) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn with_peer_timeout_ms(mut self, peer_timeout_ms: u64) -> Self {
+        self.peer_timeout_ms = peer_timeout_ms;
+        self
+    }
+
+    pub fn peer_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.peer_timeout_ms)
+    }
+}
+
+/// A search result and the peer whose memory it came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerResult {
+    pub peer: String,
+    pub result: SearchResult,
+}
+
+/// Results of one search fanned out to peers, collected as they answer.
+#[derive(Debug)]
+pub struct FederatedSearch {
+    top_k: usize,
+    outstanding: HashSet<String>,
+    results: Vec<PeerResult>,
+    errors: HashMap<String, String>,
+}
+
+impl FederatedSearch {
+    pub fn new(top_k: usize) -> Self {
+        Self {
+            top_k,
+            outstanding: HashSet::new(),
+            results: vec![],
+            errors: HashMap::new(),
+        }
+    }
+
+    /// Records that `peer` was asked.
+    pub fn expect(&mut self, peer: &str) {
+        self.outstanding.insert(peer.to_string());
+    }
+
+    /// Adds the answer of `peer`. Answers from peers not asked, or already answered, are
+    /// ignored.
+    pub fn add(&mut self, peer: &str, answer: Result<Vec<SearchResult>, String>) {
+        if !self.outstanding.remove(peer) {
+            return;
+        }
+        match answer {
+            Ok(results) => self.add_results(peer, results),
+            Err(e) => {
+                self.errors.insert(peer.to_string(), e);
+            }
+        }
+    }
+
+    /// Adds results that need no answer, such as this node's own.
+    pub fn add_results(&mut self, peer: &str, results: Vec<SearchResult>) {
+        self.results
+            .extend(results.into_iter().map(|result| PeerResult {
+                peer: peer.to_string(),
+                result,
+            }));
+    }
+
+    /// Whether every peer asked has answered.
+    pub fn is_complete(&self) -> bool {
+        self.outstanding.is_empty()
+    }
+
+    /// Peers that have not answered yet.
+    pub fn outstanding(&self) -> impl Iterator<Item = &str> {
+        self.outstanding.iter().map(String::as_str)
+    }
+
+    /// Peers that answered with an error, and the error.
+    pub fn errors(&self) -> &HashMap<String, String> {
+        &self.errors
+    }
+
+    /// The `top_k` best results by cosine similarity. A document found on several peers,
+    /// as replicated shared memory is, is kept once with its best score.
+    pub fn finish(self) -> Vec<PeerResult> {
+        let mut results = self.results;
+        results.sort_by(|a, b| b.result.score.total_cmp(&a.result.score));
+        let mut seen = HashSet::new();
+        results.retain(|r| seen.insert((r.result.id.clone(), r.result.text.clone())));
+        results.truncate(self.top_k);
+        results
+    }
+}
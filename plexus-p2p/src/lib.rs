@@ -1,11 +1,28 @@
 pub mod crdt;
+pub mod federated;
 pub mod identity;
 pub mod node_service;
 pub mod protocol;
+pub mod shared_memory;
 pub mod swarm;
 pub use crdt::MeshState;
+pub use federated::{
+    FederatedSearchConfig, MemoryAcl, NamespaceShare, PeerResult, LOCAL_NAMESPACE,
+};
 
 pub use identity::IdentityStore;
-pub use node_service::{NodeCommand, NodeService, NodeStatus, SystemCapabilities};
-pub use protocol::{GenerateRequest, GenerateResponse, Heartbeat, NodeCapabilities};
+pub use node_service::{
+    open_memory_backup, NodeCommand, NodeService, NodeStatus, SystemCapabilities,
+};
+pub use plexus_ai::voice::Transcript;
+pub use plexus_ai::{
+    ChatMessage, ContextPolicy, DeviceRequest, GenerationParams, GenerationRecord, ImportReport,
+    MemoryBackup, MemoryRecord, ModelInfo, QdrantConfig, ResponseFormat, RetrievalConfig,
+    SessionInfo, SpeculativeConfig, VectorStoreConfig, VectorStoreKind, DEFAULT_EMBEDDING_MODEL,
+};
+pub use protocol::{
+    GenerateRequest, GenerateResponse, Heartbeat, MemorySearchRequest, MemorySearchResponse,
+    MemorySyncRequest, MemorySyncResponse, NodeCapabilities,
+};
+pub use shared_memory::{MemoryOp, SharedEntry, SharedMemory, VersionVector};
 pub use swarm::{build_swarm, PlexusBehaviour};
@@ -1,6 +1,11 @@
 use crate::{
     build_swarm,
-    protocol::{Heartbeat, NodeCapabilities},
+    federated::{FederatedSearch, FederatedSearchConfig, PeerResult, LOCAL_NAMESPACE},
+    protocol::{
+        Heartbeat, MemorySearchRequest, MemorySearchResponse, MemorySyncRequest,
+        MemorySyncResponse, NodeCapabilities,
+    },
+    shared_memory::{MemoryOp, SharedMemory},
     swarm::PlexusBehaviourEvent,
     GenerateRequest, GenerateResponse, IdentityStore, PlexusBehaviour,
 };
@@ -8,19 +13,23 @@ use anyhow::{Context, Result};
 use futures::StreamExt;
 use libp2p::{
     gossipsub::{self, IdentTopic},
+    identity::Keypair,
     mdns,
     multiaddr::Protocol,
     request_response::{self, OutboundRequestId},
     swarm::SwarmEvent,
-    Swarm,
+    PeerId, Swarm,
 };
-#[cfg(feature = "lancedb")]
-use plexus_ai::LanceDbStore;
 use plexus_ai::{
-    voice::WhisperEngine, BertEmbedder, ChatHistory, LLMEngine, QdrantStore, SimpleVectorStore,
-    TinyLlamaEngine, VectorStore,
+    expected_footprint,
+    voice::{TranscribeOptions, Transcript, WhisperEngine},
+    BertEmbedder, ChatHistory, ChatMessage, ContextPolicy, CrossEncoderReranker, DeviceRequest,
+    DeviceSelector, Document, EmbedderRegistry, GenerationParams, HybridRetriever, Ingestor,
+    LLMEngine, MemoryBackup, ModelInfo, ModelRegistry, Reranker, RetrievalConfig, SearchFilter,
+    SearchResult, SessionInfo, SessionStore, SimpleVectorStore, SpeculativeConfig, VectorStore,
+    VectorStoreConfig, DEFAULT_SESSION_ID,
 };
-use std::collections::HashMap; // Use HashMap instead of CRDTs
+use std::collections::{HashMap, HashSet}; // Use HashMap instead of CRDTs
 use std::path::PathBuf;
 use std::sync::Arc;
 use sysinfo::{Networks, System};
@@ -53,6 +62,10 @@ pub enum NodeCommand {
     Shutdown,
     Generate {
         prompt: String,
+        /// Model to route the request to; the node's default model when `None`
+        model: Option<String>,
+        /// Conversation to continue; the default session when `None`
+        session_id: Option<String>,
         respond_to: mpsc::Sender<String>,
     },
     GetStatus {
@@ -61,13 +74,18 @@ pub enum NodeCommand {
     GetMeshState {
         respond_to: mpsc::Sender<Vec<Heartbeat>>,
     }, // Return list of heartbeats (state)
+    /// Replaces a session's system prompt, keeping its conversation
     SetSystemPrompt {
+        /// The default session when `None`
+        session_id: Option<String>,
         prompt: String,
         respond_to: mpsc::Sender<()>,
     },
     Transcribe {
         audio_data: Vec<f32>,
-        respond_to: mpsc::Sender<String>,
+        /// Language code of the speech; detected from the audio when `None`
+        language: Option<String>,
+        respond_to: mpsc::Sender<Result<Transcript, String>>,
     },
     GetSystemInfo {
         respond_to: mpsc::Sender<SystemCapabilities>,
@@ -75,28 +93,207 @@ pub enum NodeCommand {
     StartPairing {
         respond_to: mpsc::Sender<String>,
     },
+    LoadModel {
+        model_id: String,
+        respond_to: mpsc::Sender<Result<(), String>>,
+    },
+    UnloadModel {
+        model_id: String,
+        respond_to: mpsc::Sender<Result<(), String>>,
+    },
+    ListModels {
+        respond_to: mpsc::Sender<Vec<ModelInfo>>,
+    },
+    CreateSession {
+        name: String,
+        system_prompt: Option<String>,
+        respond_to: mpsc::Sender<Result<SessionInfo, String>>,
+    },
+    ListSessions {
+        respond_to: mpsc::Sender<Vec<SessionInfo>>,
+    },
+    GetSessionMessages {
+        session_id: String,
+        respond_to: mpsc::Sender<Result<Vec<ChatMessage>, String>>,
+    },
+    RenameSession {
+        session_id: String,
+        name: String,
+        respond_to: mpsc::Sender<Result<(), String>>,
+    },
+    DeleteSession {
+        session_id: String,
+        respond_to: mpsc::Sender<Result<(), String>>,
+    },
+    /// Copies a session into a new one named `name`
+    ForkSession {
+        session_id: String,
+        name: String,
+        respond_to: mpsc::Sender<Result<SessionInfo, String>>,
+    },
+    /// Returns the session as JSON
+    ExportSession {
+        session_id: String,
+        respond_to: mpsc::Sender<Result<String, String>>,
+    },
+    /// Adds a session from the JSON of `ExportSession`
+    ImportSession {
+        data: String,
+        respond_to: mpsc::Sender<Result<SessionInfo, String>>,
+    },
+    /// Searches a memory namespace on this node and the peers sharing it
+    SearchMemory {
+        query: String,
+        /// This node's own memory (`LOCAL_NAMESPACE`) when `None`
+        namespace: Option<String>,
+        respond_to: SearchReply,
+    },
 }
 
 use tokio::sync::Mutex;
 
+type RemoteResult = (
+    request_response::ResponseChannel<GenerateResponse>,
+    GenerateResponse,
+);
+
+type SearchReply = mpsc::Sender<Result<Vec<PeerResult>, String>>;
+
+type LoadReply = mpsc::Sender<Result<(), String>>;
+
+/// A model loaded off the event loop, to be registered; `LoadModel` requests get the outcome.
+type LoadedModel = (
+    String,
+    Result<Arc<dyn LLMEngine>, String>,
+    Option<LoadReply>,
+);
+
+/// An engine acquired for a request, possibly still to be loaded.
+struct AcquiredEngine {
+    model_id: String,
+    engine: Arc<dyn LLMEngine>,
+    needs_load: bool,
+}
+
+impl AcquiredEngine {
+    /// Loads the engine if needed, handing the outcome to the event loop to register it.
+    async fn ready(
+        self,
+        loaded_tx: &mpsc::Sender<LoadedModel>,
+        respond_to: Option<LoadReply>,
+    ) -> Result<Arc<dyn LLMEngine>> {
+        if !self.needs_load {
+            return Ok(self.engine);
+        }
+        info!("Loading model '{}'...", self.model_id);
+        let result = self.engine.load_model(&self.model_id).await;
+        let loaded = match &result {
+            Ok(()) => Ok(self.engine.clone()),
+            Err(e) => Err(format!("{:#}", e)),
+        };
+        let _ = loaded_tx.send((self.model_id, loaded, respond_to)).await;
+        result.map(|_| self.engine)
+    }
+}
+
+/// A local chat turn generated off the event loop, to be recorded in its session.
+struct LocalTurn {
+    session_id: String,
+    /// The session's history with the user message, fitted to the context window;
+    /// `None` if no model could answer it
+    history: Option<ChatHistory>,
+    /// The assistant's reply, if generation succeeded
+    reply: Option<String>,
+}
+
+/// Shared memory operations sent per sync response.
+const MEMORY_SYNC_BATCH: usize = 256;
+/// How often connected peers are asked for shared memory operations this node missed.
+const MEMORY_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// Most results returned to a peer's memory search.
+const MAX_PEER_SEARCH_K: usize = 50;
+/// Most tokens a peer's deterministic generation may produce; it runs outside the batch.
+const MAX_PEER_DETERMINISTIC_TOKENS: usize = 512;
+
+/// An opted-in shared memory namespace and the local index searching it.
+struct SharedNamespace {
+    name: String,
+    topic: IdentTopic,
+    memory: SharedMemory,
+    retriever: Arc<HybridRetriever>,
+}
+
+impl SharedNamespace {
+    /// Brings the index entry of `id` in line with the namespace, re-embedding documents
+    /// shared with another embedding model.
+    async fn reindex(&self, embedder: &BertEmbedder, id: &str) -> Result<()> {
+        match self.memory.get(id).cloned() {
+            Some(entry) => {
+                let vector = if entry.model == embedder.model().id {
+                    entry.vector
+                } else {
+                    embedder.embed(&entry.document.text).await?
+                };
+                self.retriever.upsert(entry.document, vector).await
+            }
+            None => self.retriever.delete(id).await,
+        }
+    }
+}
+
 // ...
 
 pub struct NodeService {
     swarm: Swarm<PlexusBehaviour>,
+    /// Identity of the node; signs its shared memory writes
+    keypair: Keypair,
     command_rx: mpsc::Receiver<NodeCommand>,
-    ai_engine: Box<dyn LLMEngine>,             // Dynamic dispatch
+    models: ModelRegistry, // Resident LLM engines (LRU within budget)
     whisper_engine: Arc<Mutex<WhisperEngine>>, // Wrapped in Arc<Mutex>
     pending_requests: HashMap<OutboundRequestId, mpsc::Sender<String>>,
-    chat_history: ChatHistory,
-    history_path: PathBuf,
-    embedder: BertEmbedder,
-    vector_store: Arc<dyn VectorStore>,
+    // Remote generations run as tasks so the engine can batch them; results come back here
+    remote_results_tx: mpsc::Sender<RemoteResult>,
+    remote_results_rx: mpsc::Receiver<RemoteResult>,
+    // Models load as tasks so the swarm keeps running while weights download
+    loaded_models_tx: mpsc::Sender<LoadedModel>,
+    loaded_models_rx: mpsc::Receiver<LoadedModel>,
+    /// Engines still loading, shared by the requests waiting for them
+    loading_models: HashMap<String, Arc<dyn LLMEngine>>,
+    // Local generations run as tasks too; their turns come back here to be recorded
+    local_turns_tx: mpsc::Sender<LocalTurn>,
+    local_turns_rx: mpsc::Receiver<LocalTurn>,
+    /// Sessions with a reply being generated; they take one message at a time
+    busy_sessions: HashSet<String>,
+    sessions: SessionStore,
+    embedder: Arc<BertEmbedder>,
+    /// Vector store with its lexical index; memory is written and searched through it.
+    /// Opened when the node starts running, and `None` while memory is disabled.
+    retriever: Option<Arc<HybridRetriever>>,
+    vector_store: VectorStoreConfig,
+    retrieval_config: RetrievalConfig,
+    reranker: Option<Arc<dyn Reranker>>,
+    /// Namespace of memory replicated with the peers sharing it, if opted in
+    shared_namespace: Option<String>,
+    /// Opened with the vector store
+    shared: Option<SharedNamespace>,
+    federated: FederatedSearchConfig,
+    /// Searches fanned out to peers that are waiting for answers, by search id
+    pending_searches: HashMap<u64, (FederatedSearch, SearchReply)>,
+    /// Search id and peer of every memory search request sent
+    search_requests: HashMap<OutboundRequestId, (u64, PeerId)>,
+    next_search_id: u64,
+    // Searches whose peer timeout elapsed come back here
+    search_timeouts_tx: mpsc::Sender<u64>,
+    search_timeouts_rx: mpsc::Receiver<u64>,
+    /// Where the embedded vector store lives
+    data_dir: PathBuf,
     system: System,
+    /// Accelerator the engines run on (`None` on CPU), reported in heartbeats
+    gpu_info: Option<String>,
     // REFACTOR: Use HashMap instead of crdts::Map for simplicity and build stability
     // REFACTOR: Use HashMap instead of crdts::Map for simplicity and build stability
     mesh_state: crate::crdt::MeshState,
     heartbeat_topic: IdentTopic,
-    active_model: String,
 }
 
 impl NodeService {
@@ -106,6 +303,8 @@ impl NodeService {
         model_id: String,
         bootstrap_peers: Vec<libp2p::Multiaddr>,
         data_dir: Option<PathBuf>,
+        model_memory_budget: Option<u64>,
+        device: DeviceRequest,
     ) -> Result<Self> {
         info!("NodeService: Initializing...");
         info!("NodeService: Selected Model: {}", model_id);
@@ -118,7 +317,7 @@ impl NodeService {
         info!("NodeService: Identity loaded.");
 
         info!("NodeService: Building Swarm...");
-        let mut swarm = build_swarm(keypair)
+        let mut swarm = build_swarm(keypair.clone())
             .await
             .context("Failed to build swarm")?;
         info!("NodeService: Swarm built.");
@@ -148,100 +347,19 @@ impl NodeService {
             }
         }
 
-        info!("NodeService: Initializing AI Engine...");
-        let ai_engine: Box<dyn plexus_ai::LLMEngine> = match model_id.as_str() {
-            "tinyllama" => Box::new(TinyLlamaEngine::new()),
-            "phi" => {
-                // Placeholder for Phi engine when implemented
-                // For now, fall back or error, but let's just warn and use TinyLlama for demo
-                // warn!("Phi engine not yet implemented. Using TinyLlama.");
-                // We use fully qualified path or ensure warn is imported.
-                // Assuming tracing::* is prelude or imported.
-                tracing::warn!("Phi engine not yet implemented. Using TinyLlama.");
-                Box::new(TinyLlamaEngine::new())
-            }
-            _ => {
-                tracing::warn!("Unknown model '{}'. Defaulting to TinyLlama.", model_id);
-                Box::new(TinyLlamaEngine::new())
-            }
-        };
+        info!("NodeService: Selecting compute device ({})...", device);
+        let device = DeviceSelector::new(device).select();
 
         info!("NodeService: Initializing Whisper Engine...");
-        let whisper_engine = Arc::new(Mutex::new(WhisperEngine::new())); // Wrapped
+        let whisper_engine = Arc::new(Mutex::new(WhisperEngine::with_device(
+            device.device.clone(),
+        ))); // Wrapped
 
         info!("NodeService: Initializing Embedder...");
-        let embedder = BertEmbedder::new();
+        let embedder = Arc::new(BertEmbedder::with_device(device.device.clone()));
 
         // Determine Data Directory early for LanceDB
-        let app_data_dir = if let Some(path) = data_dir.clone() {
-            path
-        } else {
-            let project_dirs = directories_next::ProjectDirs::from("com", "plexus", "mesh")
-                .context("Could not determine data directory")?;
-            project_dirs.data_dir().to_path_buf()
-        };
-        std::fs::create_dir_all(&app_data_dir).context("Failed to create data directory")?;
-
-        // Connect to Qdrant or Fallback with Timeout
-        info!("NodeService: Connecting to Vector Store...");
-        let qdrant_future = async { QdrantStore::new("http://localhost:6334").await };
-
-        // 2-second timeout for Qdrant connection
-        let vector_store: Arc<dyn VectorStore> =
-            match tokio::time::timeout(Duration::from_secs(2), qdrant_future).await {
-                Ok(Ok(store)) => {
-                    info!("Connected to Qdrant Vector Database.");
-                    Arc::new(store)
-                }
-                Ok(Err(e)) => {
-                    error!(
-                        "Failed to connect to Qdrant ({}). Falling back to Embedded Store.",
-                        e
-                    );
-                    #[cfg(feature = "lancedb")]
-                    {
-                        let lance_path = app_data_dir.join("vectors.lance");
-                        match LanceDbStore::new(&lance_path).await {
-                            Ok(store) => {
-                                info!("Connected to Embedded LanceDB at {:?}", lance_path);
-                                Arc::new(store)
-                            }
-                            Err(e) => {
-                                error!("Failed to init LanceDB: {}. Falling back to In-Memory.", e);
-                                Arc::new(SimpleVectorStore::new())
-                            }
-                        }
-                    }
-                    #[cfg(not(feature = "lancedb"))]
-                    {
-                        info!("LanceDB disabled. Falling back to In-Memory SimpleVectorStore.");
-                        Arc::new(SimpleVectorStore::new())
-                    }
-                }
-                Err(_) => {
-                    error!("Qdrant connection timed out. Falling back to Embedded Store.");
-                    #[cfg(feature = "lancedb")]
-                    {
-                        let lance_path = app_data_dir.join("vectors.lance");
-                        match LanceDbStore::new(&lance_path).await {
-                            Ok(store) => {
-                                info!("Connected to Embedded LanceDB at {:?}", lance_path);
-                                Arc::new(store)
-                            }
-                            Err(e) => {
-                                error!("Failed to init LanceDB: {}. Falling back to In-Memory.", e);
-                                Arc::new(SimpleVectorStore::new())
-                            }
-                        }
-                    }
-                    #[cfg(not(feature = "lancedb"))]
-                    {
-                        info!("LanceDB disabled. Falling back to In-Memory SimpleVectorStore.");
-                        Arc::new(SimpleVectorStore::new())
-                    }
-                }
-            };
-        info!("NodeService: Vector Store initialized.");
+        let app_data_dir = node_data_dir(data_dir.clone())?;
 
         // Load Whisper Model (Async & Non-blocking)
         let we_clone = whisper_engine.clone();
@@ -254,20 +372,32 @@ impl NodeService {
             }
         });
 
-        // Load Chat History
-        info!("NodeService: Loading Chat History...");
-        let history_path = identity_path
+        // Load Chat Sessions
+        info!("NodeService: Loading Chat Sessions...");
+        let history_dir = identity_path
             .parent()
             .unwrap_or(&PathBuf::from("."))
-            .join("chat_history.json");
-        let chat_history =
-            ChatHistory::load_from_file(&history_path).unwrap_or_else(|_| ChatHistory::new(10));
+            .to_path_buf();
+        let mut sessions = SessionStore::open(history_dir.join("sessions"))?;
+        if let Err(e) = sessions.migrate_legacy_history(&history_dir.join("chat_history.json")) {
+            error!("Failed to migrate chat history: {}", e);
+        }
 
         // Capabilities & Gossipsub
         info!("NodeService: Refreshing System Stats...");
         let mut system = System::new_all();
         system.refresh_all();
 
+        // Models are loaded on first use. By default they may take half of the RAM.
+        expected_footprint(&model_id)?;
+        let model_memory_budget = model_memory_budget.unwrap_or(system.total_memory() / 2);
+        info!(
+            "NodeService: Model memory budget: {} MB",
+            model_memory_budget / 1024 / 1024
+        );
+        let models =
+            ModelRegistry::new(&model_id, model_memory_budget).with_device(device.device.clone());
+
         info!("NodeService: Subscribing to gossipsub...");
         let heartbeat_topic = IdentTopic::new("plexus-mesh/capabilities/1.0.0");
         swarm
@@ -277,48 +407,570 @@ impl NodeService {
 
         info!("NodeService: Initializing Persistence...");
 
-        let data_dir = if let Some(path) = data_dir {
-            path
-        } else {
-            let project_dirs = directories_next::ProjectDirs::from("com", "plexus", "mesh")
-                .context("Could not determine data directory")?;
-            project_dirs.data_dir().to_path_buf()
-        };
-
-        std::fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
+        let data_dir = node_data_dir(data_dir)?;
         let db_path = data_dir.join("mesh_state.db");
         info!("NodeService: Mesh DB Path: {:?}", db_path);
 
         let mesh_state =
             crate::crdt::MeshState::new(db_path).context("Failed to initialize MeshState DB")?;
 
+        let (remote_results_tx, remote_results_rx) = mpsc::channel(32);
+        let (loaded_models_tx, loaded_models_rx) = mpsc::channel(8);
+        let (local_turns_tx, local_turns_rx) = mpsc::channel(8);
+        let (search_timeouts_tx, search_timeouts_rx) = mpsc::channel(32);
+
         info!("NodeService: Initialization Complete.");
         Ok(Self {
             swarm,
+            keypair,
             command_rx,
-            ai_engine,
+            models,
             whisper_engine,
             pending_requests: HashMap::new(),
-            chat_history,
-            history_path,
+            remote_results_tx,
+            remote_results_rx,
+            loaded_models_tx,
+            loaded_models_rx,
+            loading_models: HashMap::new(),
+            local_turns_tx,
+            local_turns_rx,
+            busy_sessions: HashSet::new(),
+            sessions,
             embedder,
-            vector_store,
+            retriever: None,
+            vector_store: VectorStoreConfig::default(),
+            retrieval_config: RetrievalConfig::default(),
+            reranker: None,
+            shared_namespace: None,
+            shared: None,
+            federated: FederatedSearchConfig::default(),
+            pending_searches: HashMap::new(),
+            search_requests: HashMap::new(),
+            next_search_id: 0,
+            search_timeouts_tx,
+            search_timeouts_rx,
+            data_dir: app_data_dir,
             system,
+            gpu_info: device.gpu_info(),
             mesh_state,
             heartbeat_topic,
-            active_model: model_id,
         })
     }
 
-    fn save_history(&self) {
-        let _ = self.chat_history.save_to_file(&self.history_path);
+    /// Applies `change` to the history of `session_id` and persists it.
+    fn update_session(
+        &mut self,
+        session_id: &str,
+        change: impl FnOnce(&mut ChatHistory),
+    ) -> Result<()> {
+        change(self.sessions.history_mut(session_id)?);
+        self.sessions.save(session_id)
+    }
+
+    /// Decodes the default model speculatively with the draft model in `config`.
+    pub fn with_speculative(mut self, config: SpeculativeConfig) -> Self {
+        let model_id = self.models.default_model().to_string();
+        self.models = self.models.with_speculative(&model_id, config);
+        self
+    }
+
+    /// Embeds memory with `model_id` from the [`EmbedderRegistry`], keeping it in the
+    /// vector collection for that model. Memory written by other models is left untouched.
+    pub fn with_embedding_model(mut self, model_id: &str) -> Result<Self> {
+        let spec = EmbedderRegistry::new().get(model_id)?;
+        if spec == *self.embedder.model() {
+            return Ok(self);
+        }
+        info!(
+            "NodeService: Using embedding model {} ({})",
+            spec.id, spec.repo
+        );
+        self.embedder =
+            Arc::new(BertEmbedder::with_device(self.embedder.device().clone()).with_model(spec));
+        Ok(self)
+    }
+
+    /// Sets which vector store memory is kept in and how to reach it.
+    pub fn with_vector_store(mut self, config: VectorStoreConfig) -> Self {
+        self.vector_store = config;
+        self
+    }
+
+    /// Sets how many memory passages are added to a prompt and how relevant they must be.
+    pub fn with_retrieval_config(mut self, config: RetrievalConfig) -> Self {
+        self.retrieval_config = config;
+        self
+    }
+
+    /// Reranks retrieved memory with a [`CrossEncoderReranker`] before it is used.
+    pub fn with_reranker(mut self) -> Self {
+        let reranker = CrossEncoderReranker::with_device(self.embedder.device().clone());
+        self.reranker = Some(Arc::new(reranker));
+        self
+    }
+
+    /// Replicates memory saved with `/share` to every peer sharing `namespace`, and adds
+    /// theirs to this node's prompts.
+    pub fn with_shared_memory(mut self, namespace: &str) -> Result<Self> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if namespace.is_empty() || !namespace.chars().all(valid) {
+            anyhow::bail!(
+                "Invalid shared memory namespace '{}' (use letters, digits, '-' and '_')",
+                namespace
+            );
+        }
+        self.shared_namespace = Some(namespace.to_string());
+        Ok(self)
+    }
+
+    /// Sets which memory namespaces peers may search here, and how searches started here
+    /// wait for peers.
+    pub fn with_federated_search(mut self, config: FederatedSearchConfig) -> Self {
+        self.federated = config;
+        self
+    }
+
+    /// Opens the configured vector store. Memory is disabled if it cannot be opened.
+    async fn open_memory(&self) -> Option<Arc<HybridRetriever>> {
+        info!(
+            "NodeService: Opening Vector Store ({})...",
+            self.vector_store.kind
+        );
+        let store = match self
+            .vector_store
+            .open(&self.data_dir, self.embedder.model())
+            .await
+        {
+            Ok(Some(store)) => store,
+            Ok(None) => {
+                info!("NodeService: Memory is disabled.");
+                return None;
+            }
+            Err(e) => {
+                error!("Failed to open vector store: {}. Memory is disabled.", e);
+                return None;
+            }
+        };
+        info!("NodeService: Vector Store initialized.");
+        Some(Arc::new(self.retriever_over(store)))
+    }
+
+    /// A retriever over `store` with the configured embedder, settings and reranker.
+    fn retriever_over(&self, store: Arc<dyn VectorStore>) -> HybridRetriever {
+        let mut retriever = HybridRetriever::new(self.embedder.clone(), store)
+            .with_config(self.retrieval_config.clone());
+        if let Some(reranker) = &self.reranker {
+            retriever = retriever.with_reranker(reranker.clone());
+        }
+        retriever
+    }
+
+    /// Opens the operation log of shared memory `namespace` and indexes its documents.
+    async fn open_shared_memory(&self, namespace: &str) -> Result<SharedNamespace> {
+        let path = self.data_dir.join("shared_memory").join(namespace);
+        std::fs::create_dir_all(&path).context("Failed to create shared memory directory")?;
+        let memory = SharedMemory::open(path, self.keypair.clone())?;
+        let shared = SharedNamespace {
+            name: namespace.to_string(),
+            topic: IdentTopic::new(format!("plexus-mesh/memory/{}/1.0.0", namespace)),
+            memory,
+            retriever: Arc::new(self.retriever_over(Arc::new(SimpleVectorStore::new()))),
+        };
+        let ids: Vec<String> = shared
+            .memory
+            .entries()
+            .iter()
+            .map(|entry| entry.document.id.clone())
+            .collect();
+        for id in ids {
+            shared.reindex(&self.embedder, &id).await?;
+        }
+        Ok(shared)
+    }
+
+    /// Passages from private and shared memory most relevant to `prompt`, best first.
+    async fn retrieve_memory(&self, prompt: &str) -> Result<Vec<SearchResult>> {
+        let mut results = match &self.retriever {
+            Some(retriever) => retriever.retrieve(prompt).await?,
+            None => vec![],
+        };
+        if let Some(shared) = &self.shared {
+            results.extend(shared.retriever.retrieve(prompt).await?);
+            results.sort_by(|a, b| b.score.total_cmp(&a.score));
+            results.truncate(self.retrieval_config.top_k);
+        }
+        Ok(results)
+    }
+
+    /// Saves `content` to shared memory and replicates it.
+    async fn share(&mut self, content: &str) -> Result<String> {
+        let Some(shared) = self.shared.as_mut() else {
+            return Ok("Shared memory is disabled.".to_string());
+        };
+        let embedding = self.embedder.embed(content).await?;
+        let id = format!(
+            "{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_nanos()
+        );
+        let document = Document::new(id.clone(), content).with_source("chat");
+        let ops = shared
+            .memory
+            .insert(document, embedding, &self.embedder.model().id)?;
+        shared.reindex(&self.embedder, &id).await?;
+        let reply = format!("Shared to '{}' as {}: \"{}\"", shared.name, id, content);
+        self.publish_shared(&ops);
+        Ok(reply)
+    }
+
+    /// Removes document `id` from shared memory on every node.
+    async fn unshare(&mut self, id: &str) -> Result<String> {
+        let Some(shared) = self.shared.as_mut() else {
+            return Ok("Shared memory is disabled.".to_string());
+        };
+        let Some(op) = shared.memory.remove(id)? else {
+            return Ok(format!("No shared memory with id {}", id));
+        };
+        shared.reindex(&self.embedder, id).await?;
+        let reply = format!("Removed {} from '{}'", id, shared.name);
+        self.publish_shared(&[op]);
+        Ok(reply)
+    }
+
+    /// Broadcasts local writes to the peers sharing the namespace. Peers that miss them
+    /// catch up through sync.
+    fn publish_shared(&mut self, ops: &[MemoryOp]) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        for op in ops {
+            let data = match serde_json::to_vec(op) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("Failed to encode shared memory operation: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(shared.topic.clone(), data)
+            {
+                tracing::warn!(
+                    "Shared memory operation not broadcast ({}); peers will sync it",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Applies operations from peers and updates the index. Returns how many were new.
+    async fn apply_shared_ops(&mut self, ops: Vec<MemoryOp>) -> usize {
+        let Some(shared) = self.shared.as_mut() else {
+            return 0;
+        };
+        let mut changed = HashSet::new();
+        let mut applied = 0;
+        for op in ops {
+            let id = op.document_id().to_string();
+            match shared.memory.apply(op) {
+                Ok(true) => {
+                    applied += 1;
+                    changed.insert(id);
+                }
+                Ok(false) => {}
+                Err(e) => error!("Failed to apply shared memory operation: {}", e),
+            }
+        }
+        for id in changed {
+            if let Err(e) = shared.reindex(&self.embedder, &id).await {
+                error!("Failed to index shared memory {}: {}", id, e);
+            }
+        }
+        applied
+    }
+
+    /// Asks `peer` for the shared memory operations this node is missing.
+    fn request_memory_sync(&mut self, peer: PeerId) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        let request = MemorySyncRequest {
+            namespace: shared.name.clone(),
+            version: shared.memory.version().clone(),
+        };
+        self.swarm
+            .behaviour_mut()
+            .memory_sync
+            .send_request(&peer, request);
+    }
+
+    fn memory_sync_response(&self, request: &MemorySyncRequest) -> MemorySyncResponse {
+        let empty = MemorySyncResponse {
+            ops: vec![],
+            more: false,
+        };
+        let Some(shared) = self
+            .shared
+            .as_ref()
+            .filter(|shared| shared.name == request.namespace)
+        else {
+            return empty;
+        };
+        match shared.memory.ops_since(&request.version, MEMORY_SYNC_BATCH) {
+            Ok((ops, more)) => MemorySyncResponse { ops, more },
+            Err(e) => {
+                error!("Failed to read shared memory log: {}", e);
+                empty
+            }
+        }
+    }
+
+    /// The engine for `model_id` (or the default model). Models that are not resident
+    /// are loaded by [`AcquiredEngine::ready`]; requests for a model that is already
+    /// loading share its engine.
+    fn acquire_engine(&mut self, model_id: Option<&str>) -> Result<AcquiredEngine> {
+        let model_id = model_id.unwrap_or(self.models.default_model()).to_string();
+        let (engine, needs_load) = if let Some(engine) = self.models.get(Some(&model_id)) {
+            (engine, false)
+        } else if let Some(engine) = self.loading_models.get(&model_id) {
+            (engine.clone(), true)
+        } else {
+            let engine = self.models.prepare(&model_id)?;
+            self.loading_models.insert(model_id.clone(), engine.clone());
+            (engine, true)
+        };
+        Ok(AcquiredEngine {
+            model_id,
+            engine,
+            needs_load,
+        })
+    }
+
+    /// The engine serving a peer's request. Peers may use the default model, loading it
+    /// if needed, or another model that is already resident; they never load one.
+    fn remote_engine(&mut self, model_id: Option<&str>) -> Result<AcquiredEngine> {
+        match model_id {
+            Some(id) if id != self.models.default_model() => self
+                .models
+                .get(Some(id))
+                .map(|engine| AcquiredEngine {
+                    model_id: id.to_string(),
+                    engine,
+                    needs_load: false,
+                })
+                .ok_or_else(|| anyhow::anyhow!("Model '{}' is not loaded on this node", id)),
+            _ => self.acquire_engine(None),
+        }
+    }
+
+    /// The index holding memory `namespace` on this node, if any.
+    fn namespace_retriever(&self, namespace: &str) -> Option<Arc<HybridRetriever>> {
+        if namespace == LOCAL_NAMESPACE {
+            return self.retriever.clone();
+        }
+        self.shared
+            .as_ref()
+            .filter(|shared| shared.name == namespace)
+            .map(|shared| shared.retriever.clone())
+    }
+
+    /// Namespaces the ACL shares that this node holds, advertised in heartbeats.
+    fn searchable_namespaces(&self) -> Vec<String> {
+        self.federated
+            .acl
+            .namespaces()
+            .into_iter()
+            .filter(|namespace| self.namespace_retriever(namespace).is_some())
+            .collect()
+    }
+
+    /// Searches `namespace` here and on the connected peers advertising it. `respond_to`
+    /// gets the merged results once every peer answered or the peer timeout elapsed.
+    async fn start_federated_search(
+        &mut self,
+        query: &str,
+        namespace: &str,
+        respond_to: SearchReply,
+    ) {
+        let vector = match self.embedder.embed_query(query).await {
+            Ok(vector) => vector,
+            Err(e) => {
+                let _ = respond_to.send(Err(e.to_string())).await;
+                return;
+            }
+        };
+        let top_k = self.federated.top_k;
+        let local_peer = *self.swarm.local_peer_id();
+        let mut search = FederatedSearch::new(top_k);
+        if let Some(retriever) = self.namespace_retriever(namespace) {
+            match retriever.search(vector.clone(), top_k).await {
+                Ok(results) => search.add_results(&local_peer.to_string(), results),
+                Err(e) => error!("Local memory search failed: {}", e),
+            }
+        }
+
+        let peers: Vec<PeerId> = self
+            .mesh_state
+            .get_all()
+            .into_iter()
+            .filter(|heartbeat| {
+                heartbeat
+                    .capabilities
+                    .memory_namespaces
+                    .iter()
+                    .any(|advertised| advertised == namespace)
+            })
+            .filter_map(|heartbeat| heartbeat.peer_id.parse::<PeerId>().ok())
+            .filter(|peer| *peer != local_peer && self.swarm.is_connected(peer))
+            .collect();
+        if peers.is_empty() {
+            let _ = respond_to.send(Ok(search.finish())).await;
+            return;
+        }
+
+        let search_id = self.next_search_id;
+        self.next_search_id += 1;
+        info!("Searching memory '{}' on {} peers", namespace, peers.len());
+        for peer in peers {
+            let request = MemorySearchRequest {
+                namespace: namespace.to_string(),
+                model: self.embedder.model().id.clone(),
+                vector: vector.clone(),
+                k: top_k,
+                filter: SearchFilter::default(),
+            };
+            let request_id = self
+                .swarm
+                .behaviour_mut()
+                .memory_search
+                .send_request(&peer, request);
+            search.expect(&peer.to_string());
+            self.search_requests.insert(request_id, (search_id, peer));
+        }
+        self.pending_searches
+            .insert(search_id, (search, respond_to));
+
+        let timeouts = self.search_timeouts_tx.clone();
+        let timeout = self.federated.peer_timeout();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let _ = timeouts.send(search_id).await;
+        });
+    }
+
+    /// Records a peer's answer to a search and replies once every peer answered.
+    async fn add_search_answer(
+        &mut self,
+        request_id: OutboundRequestId,
+        answer: Result<Vec<SearchResult>, String>,
+    ) {
+        let Some((search_id, peer)) = self.search_requests.remove(&request_id) else {
+            return;
+        };
+        let Some((search, _)) = self.pending_searches.get_mut(&search_id) else {
+            return;
+        };
+        if let Err(e) = &answer {
+            tracing::warn!("Memory search on {} failed: {}", peer, e);
+        }
+        search.add(&peer.to_string(), answer);
+        if search.is_complete() {
+            self.finish_federated_search(search_id).await;
+        }
+    }
+
+    /// Replies with the results gathered so far; peers yet to answer are left out.
+    async fn finish_federated_search(&mut self, search_id: u64) {
+        let Some((search, respond_to)) = self.pending_searches.remove(&search_id) else {
+            return;
+        };
+        let late: Vec<&str> = search.outstanding().collect();
+        if !late.is_empty() {
+            tracing::warn!("Memory search timed out waiting for {:?}", late);
+        }
+        // Answers arriving after the timeout are dropped
+        self.search_requests.retain(|_, (id, _)| *id != search_id);
+        let _ = respond_to.send(Ok(search.finish())).await;
+    }
+
+    /// Searches a namespace for `peer` if the ACL shares it with them.
+    async fn memory_search_response(
+        &self,
+        peer: &PeerId,
+        request: MemorySearchRequest,
+    ) -> MemorySearchResponse {
+        let refuse = |error: String| MemorySearchResponse {
+            results: vec![],
+            error: Some(error),
+        };
+        if !self
+            .federated
+            .acl
+            .allows(&request.namespace, &peer.to_string())
+        {
+            return refuse(format!(
+                "Memory '{}' is not shared with this peer",
+                request.namespace
+            ));
+        }
+        let Some(retriever) = self.namespace_retriever(&request.namespace) else {
+            return refuse(format!("No memory '{}' on this node", request.namespace));
+        };
+        // Vectors of different models are not comparable
+        if request.model != self.embedder.model().id {
+            return refuse(format!(
+                "Memory is embedded with {}, not {}",
+                self.embedder.model().id,
+                request.model
+            ));
+        }
+        let k = request.k.min(MAX_PEER_SEARCH_K);
+        match retriever
+            .search_filtered(request.vector, k, &request.filter)
+            .await
+        {
+            Ok(results) => MemorySearchResponse {
+                results,
+                error: None,
+            },
+            Err(e) => refuse(e.to_string()),
+        }
+    }
+
+    /// Sets how the chat history is kept within the model's context window.
+    pub fn with_context_policy(mut self, policy: ContextPolicy) -> Self {
+        self.sessions = self.sessions.with_context_policy(policy);
+        self
     }
 
     pub async fn run(mut self) -> Result<()> {
         // Listen on all interfaces
         self.swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
 
+        self.retriever = self.open_memory().await;
+        if let Some(namespace) = self.shared_namespace.clone() {
+            match self.open_shared_memory(&namespace).await {
+                Ok(shared) => {
+                    self.swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .subscribe(&shared.topic)?;
+                    info!(
+                        "NodeService: Sharing memory namespace '{}' ({} documents)",
+                        namespace,
+                        shared.memory.len()
+                    );
+                    self.shared = Some(shared);
+                }
+                Err(e) => error!("Failed to open shared memory '{}': {}", namespace, e),
+            }
+        }
+
         let mut heartbeat_interval = interval(Duration::from_secs(10));
+        let mut memory_sync_interval = interval(MEMORY_SYNC_INTERVAL);
 
         loop {
             tokio::select! {
@@ -327,16 +979,20 @@ impl NodeService {
                     self.system.refresh_cpu_all();
                     self.system.refresh_memory();
 
+                    let resident_models: Vec<String> =
+                        self.models.list().into_iter().map(|m| m.id).collect();
                     let capabilities = NodeCapabilities {
                         cpu_cores: self.system.cpus().len(),
                         total_memory: self.system.total_memory(),
-                        gpu_info: None,
-                        model_loaded: true,
+                        gpu_info: self.gpu_info.clone(),
+                        model_loaded: !resident_models.is_empty(),
+                        memory_namespaces: self.searchable_namespaces(),
                     };
 
                     let heartbeat = Heartbeat {
                         peer_id: self.swarm.local_peer_id().to_string(),
-                        model: self.active_model.clone(),
+                        model: self.models.default_model().to_string(),
+                        resident_models,
                         capabilities: capabilities.clone(),
                         timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
                     };
@@ -350,13 +1006,29 @@ impl NodeService {
                         }
                     }
                 }
+                _ = memory_sync_interval.tick(), if self.shared.is_some() => {
+                    let peers: Vec<PeerId> = self.swarm.connected_peers().cloned().collect();
+                    for peer in peers {
+                        self.request_memory_sync(peer);
+                    }
+                }
                 event = self.swarm.select_next_some() => {
                     match event {
                         SwarmEvent::NewListenAddr { address, .. } => {
                             info!("Listening on {:?}", address);
                         }
                         SwarmEvent::Behaviour(PlexusBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source: _peer_id, message_id: _id, message })) => {
-                            if let Ok(heartbeat) = serde_json::from_slice::<Heartbeat>(&message.data) {
+                            if self.shared.as_ref().is_some_and(|shared| message.topic == shared.topic.hash()) {
+                                match serde_json::from_slice::<MemoryOp>(&message.data) {
+                                    Ok(op) if message.source.map(|source| source.to_string()).as_deref() != Some(op.dot.replica.as_str()) => {
+                                        tracing::warn!("Dropping shared memory operation of {} published by {:?}", op.dot.replica, message.source);
+                                    }
+                                    Ok(op) => {
+                                        self.apply_shared_ops(vec![op]).await;
+                                    }
+                                    Err(e) => tracing::warn!("Invalid shared memory operation: {}", e),
+                                }
+                            } else if let Ok(heartbeat) = serde_json::from_slice::<Heartbeat>(&message.data) {
                                 info!("Received Heartbeat from {}: {} Cores, {} MB RAM",
                                     heartbeat.peer_id,
                                     heartbeat.capabilities.cpu_cores,
@@ -376,6 +1048,8 @@ impl NodeService {
                         }
                         SwarmEvent::ConnectionEstablished { peer_id, .. } => {
                             info!("Connection established with {}", peer_id);
+                            // Catch up on shared memory written while apart
+                            self.request_memory_sync(peer_id);
                         }
                         SwarmEvent::ConnectionClosed { peer_id, .. } => {
                             info!("Connection closed with {}", peer_id);
@@ -386,11 +1060,43 @@ impl NodeService {
                             match message {
                                 request_response::Message::Request { request, channel, .. } => {
                                     info!("Received remote generation request from {}: {}", peer, request.prompt);
-                                    let response = match self.ai_engine.generate(&request.prompt).await {
-                                        Ok(res) => res,
-                                        Err(e) => format!("Error: {}", e),
-                                    };
-                                    let _ = self.swarm.behaviour_mut().request_response.send_response(channel, GenerateResponse { response });
+                                    match self.remote_engine(request.model.as_deref()) {
+                                        Ok(acquired) => {
+                                            // Generate off the event loop so concurrent peers share batches
+                                            let results_tx = self.remote_results_tx.clone();
+                                            let loaded_tx = self.loaded_models_tx.clone();
+                                            tokio::spawn(async move {
+                                                let engine = acquired.ready(&loaded_tx, None).await;
+                                                let result = match (engine, &request.deterministic, &request.response_format) {
+                                                    (Err(e), _, _) => Err(e),
+                                                    (Ok(_), Some(_), Some(_)) => Err(anyhow::anyhow!(
+                                                        "Deterministic generation does not support a response format"
+                                                    )),
+                                                    (Ok(engine), Some(params), None) => {
+                                                        let params = GenerationParams {
+                                                            max_new_tokens: params.max_new_tokens.min(MAX_PEER_DETERMINISTIC_TOKENS),
+                                                            ..params.clone()
+                                                        };
+                                                        engine
+                                                            .generate_deterministic(&request.prompt, &params)
+                                                            .await
+                                                            .map(|(res, record)| (res, Some(record)))
+                                                    }
+                                                    (Ok(engine), None, Some(format)) => engine.generate_constrained(&request.prompt, format).await.map(|res| (res, None)),
+                                                    (Ok(engine), None, None) => engine.generate(&request.prompt).await.map(|res| (res, None)),
+                                                };
+                                                let (response, record) = match result {
+                                                    Ok(res) => res,
+                                                    Err(e) => (format!("Error: {}", e), None),
+                                                };
+                                                let _ = results_tx.send((channel, GenerateResponse { response, record })).await;
+                                            });
+                                        }
+                                        Err(e) => {
+                                            let response = format!("Error: {}", e);
+                                            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, GenerateResponse { response, record: None });
+                                        }
+                                    }
                                 }
                                 request_response::Message::Response { request_id, response } => {
                                     info!("Received remote response: {}", response.response);
@@ -400,17 +1106,99 @@ impl NodeService {
                                 }
                             }
                         }
+                        SwarmEvent::Behaviour(PlexusBehaviourEvent::MemorySync(
+                            request_response::Event::Message { peer, message }
+                        )) => {
+                            match message {
+                                request_response::Message::Request { request, channel, .. } => {
+                                    let response = self.memory_sync_response(&request);
+                                    let _ = self.swarm.behaviour_mut().memory_sync.send_response(channel, response);
+                                }
+                                request_response::Message::Response { response, .. } => {
+                                    let applied = self.apply_shared_ops(response.ops).await;
+                                    if applied > 0 {
+                                        info!("Synced {} shared memory operations from {}", applied, peer);
+                                    }
+                                    // Only ask again while catching up, so a failing log cannot loop
+                                    if response.more && applied > 0 {
+                                        self.request_memory_sync(peer);
+                                    }
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(PlexusBehaviourEvent::MemorySearch(
+                            request_response::Event::Message { peer, message }
+                        )) => {
+                            match message {
+                                request_response::Message::Request { request, channel, .. } => {
+                                    let response = self.memory_search_response(&peer, request).await;
+                                    let _ = self.swarm.behaviour_mut().memory_search.send_response(channel, response);
+                                }
+                                request_response::Message::Response { request_id, response } => {
+                                    let answer = match response.error {
+                                        Some(e) => Err(e),
+                                        None => Ok(response.results),
+                                    };
+                                    self.add_search_answer(request_id, answer).await;
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(PlexusBehaviourEvent::MemorySearch(
+                            request_response::Event::OutboundFailure { request_id, error, .. }
+                        )) => {
+                            self.add_search_answer(request_id, Err(error.to_string())).await;
+                        }
                         SwarmEvent::Behaviour(_) => {}
                         _ => {}
                     }
                 }
+                Some((channel, response)) = self.remote_results_rx.recv() => {
+                    let _ = self.swarm.behaviour_mut().request_response.send_response(channel, response);
+                }
+                Some((model_id, loaded, respond_to)) = self.loaded_models_rx.recv() => {
+                    self.loading_models.remove(&model_id);
+                    let loaded = loaded.map_err(anyhow::Error::msg);
+                    let result = match loaded.and_then(|engine| self.models.insert(&model_id, engine)) {
+                        Ok(evicted) => {
+                            if !evicted.is_empty() {
+                                info!("Evicted models to load '{}': {:?}", model_id, evicted);
+                            }
+                            Ok(())
+                        }
+                        Err(e) => {
+                            error!("Failed to load model '{}': {}", model_id, e);
+                            Err(e.to_string())
+                        }
+                    };
+                    if let Some(respond_to) = respond_to {
+                        let _ = respond_to.send(result).await;
+                    }
+                }
+                Some(LocalTurn { session_id, history, reply }) = self.local_turns_rx.recv() => {
+                    self.busy_sessions.remove(&session_id);
+                    if let Some(history) = history {
+                        // 5. Record the turn, with the Assistant Message if there is one
+                        let recorded = self.update_session(&session_id, |h| {
+                            h.replace_conversation(history);
+                            if let Some(reply) = reply {
+                                h.add_assistant(reply);
+                            }
+                        });
+                        if let Err(e) = recorded {
+                            error!("Failed to save session: {}", e);
+                        }
+                    }
+                }
+                Some(search_id) = self.search_timeouts_rx.recv() => {
+                    self.finish_federated_search(search_id).await;
+                }
                 cmd = self.command_rx.recv() => {
                     match cmd {
                         Some(NodeCommand::Shutdown) => {
                             info!("Shutting down Node Service...");
                             break;
                         }
-                        Some(NodeCommand::Generate { prompt, respond_to }) => {
+                        Some(NodeCommand::Generate { prompt, model, session_id, respond_to }) => {
                             if prompt.starts_with("/remote ") {
                                 let remote_prompt = prompt.trim_start_matches("/remote ").to_string();
                                 info!("Dispatching remote request: {}", remote_prompt);
@@ -420,7 +1208,7 @@ impl NodeService {
                                 if let Some(peer) = peers.first() {
                                     let request_id = self.swarm.behaviour_mut().request_response.send_request(
                                         peer,
-                                        GenerateRequest { prompt: remote_prompt }
+                                        GenerateRequest { prompt: remote_prompt, model, response_format: None, deterministic: None }
                                     );
                                     info!("Sent request {} to peer {}", request_id, peer);
                                     // Store the channel to respond later
@@ -428,14 +1216,67 @@ impl NodeService {
                                 } else {
                                     let _ = respond_to.send("No peers connected for remote inference.".to_string()).await;
                                 }
+                            } else if prompt.starts_with("/share ") {
+                                let content = prompt.trim_start_matches("/share ").to_string();
+                                info!("Saving to shared memory: {}", content);
+                                let reply = match self.share(&content).await {
+                                    Ok(reply) => reply,
+                                    Err(e) => format!("Error sharing: {}", e),
+                                };
+                                let _ = respond_to.send(reply).await;
+                            } else if prompt.starts_with("/unshare ") {
+                                let id = prompt.trim_start_matches("/unshare ").trim().to_string();
+                                let reply = match self.unshare(&id).await {
+                                    Ok(reply) => reply,
+                                    Err(e) => format!("Error unsharing: {}", e),
+                                };
+                                let _ = respond_to.send(reply).await;
+                            } else if prompt.starts_with("/search ") {
+                                let query = prompt.trim_start_matches("/search ").trim().to_string();
+                                // Peers answer later, so the reply is formatted off the event loop
+                                let (results_tx, mut results_rx) = mpsc::channel(1);
+                                self.start_federated_search(&query, LOCAL_NAMESPACE, results_tx).await;
+                                tokio::spawn(async move {
+                                    let reply = match results_rx.recv().await {
+                                        Some(Ok(results)) => format_search_results(&results),
+                                        Some(Err(e)) => format!("Error searching: {}", e),
+                                        None => return,
+                                    };
+                                    let _ = respond_to.send(reply).await;
+                                });
+                            } else if prompt.starts_with("/ingest ") {
+                                let path = PathBuf::from(prompt.trim_start_matches("/ingest ").trim());
+                                info!("Ingesting into memory: {:?}", path);
+
+                                let Some(retriever) = self.retriever.clone() else {
+                                    let _ = respond_to.send("Memory is disabled.".to_string()).await;
+                                    continue;
+                                };
+                                let ingestor = Ingestor::new(self.embedder.clone(), retriever);
+                                match ingestor.ingest_file(&path).await {
+                                    Ok(report) => {
+                                        let _ = respond_to.send(format!(
+                                            "Ingested {}: {} chunks ({} embedded, {} unchanged, {} removed)",
+                                            report.source, report.chunks, report.embedded, report.unchanged, report.removed
+                                        )).await;
+                                    }
+                                    Err(e) => {
+                                        let _ = respond_to.send(format!("Error ingesting: {}", e)).await;
+                                    }
+                                }
                             } else if prompt.starts_with("/save ") {
                                 let content = prompt.trim_start_matches("/save ").to_string();
+                                let Some(retriever) = self.retriever.clone() else {
+                                    let _ = respond_to.send("Memory is disabled.".to_string()).await;
+                                    continue;
+                                };
                                 info!("Saving to memory: {}", content);
 
                                 match self.embedder.embed(&content).await {
                                     Ok(embedding) => {
                                         let id = format!("{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
-                                        if let Err(e) = self.vector_store.add_document(&id, &content, embedding).await {
+                                        let document = Document::new(id, content.clone()).with_source("chat");
+                                        if let Err(e) = retriever.upsert(document, embedding).await {
                                             let _ = respond_to.send(format!("Error saving: {}", e)).await;
                                         } else {
                                             let _ = respond_to.send(format!("Saved to memory: \"{}\"", content)).await;
@@ -447,62 +1288,73 @@ impl NodeService {
                                 }
                             } else {
                                 info!("Processing local generation request: {}", prompt);
+                                let session_id = session_id.unwrap_or_else(|| DEFAULT_SESSION_ID.to_string());
+                                let mut history = match self.sessions.history_mut(&session_id) {
+                                    Ok(history) => history.clone(),
+                                    Err(e) => {
+                                        let _ = respond_to.send(format!("Error: {}", e)).await;
+                                        continue;
+                                    }
+                                };
+                                if !self.busy_sessions.insert(session_id.clone()) {
+                                    let _ = respond_to.send("Error: A reply is still being generated in this session.".to_string()).await;
+                                    continue;
+                                }
 
                                 // 0. RAG Retrieval
-                                // We embed the prompt to find relevant context in our vector store.
-                                if let Ok(query_vec) = self.embedder.embed(&prompt).await {
-                                    if let Ok(results) = self.vector_store.search(query_vec, 1).await {
-                                        if let Some((text, score)) = results.first() {
-                                            // 0.4 is an arbitrary similarity threshold.
-                                            if *score > 0.4 {
-                                                info!("RAG Match found (score {:.2}): {}", score, text);
-                                                // Inject context as a "System" message effectively
-                                                let context_msg = format!("Context information: {}", text);
-                                                self.chat_history.add_system(context_msg);
-                                                self.save_history();
-                                            }
+                                // Keyword and vector matches from private and shared memory, fused (and reranked if enabled).
+                                // The context goes into this prompt only, not into the session.
+                                let memory_context = match self.retrieve_memory(&prompt).await {
+                                    Ok(results) if !results.is_empty() => {
+                                        let mut context_msg = "Context information:".to_string();
+                                        for result in &results {
+                                            info!("RAG Match found (score {:.3}): {}", result.score, result.text);
+                                            context_msg.push_str(&format!("\n- {}", result.text));
                                         }
+                                        Some(context_msg)
                                     }
-                                }
-
-                                // 1. Add User Message to History
-                                self.chat_history.add_user(prompt.clone());
-                                self.save_history();
-
-                                // 2. Format with Context
-                                let context_prompt = self.chat_history.format_for_llama();
-
-                                // 3. Generate with Streaming
-                                // We clone respond_to to keep using it in the stream if needed (async rules)
-                                let stream_tx = respond_to.clone();
+                                    Ok(_) => None,
+                                    Err(e) => {
+                                        error!("Memory retrieval failed: {}", e);
+                                        None
+                                    }
+                                };
 
-                                // We need to accumulate the full response for ChatHistory
-                                // Let's use a proxy channel to capture text for History.
-                                let (proxy_tx, mut proxy_rx): (mpsc::Sender<String>, mpsc::Receiver<String>) = mpsc::channel(32);
+                                let acquired = self.acquire_engine(model.as_deref());
+                                // Load and generate off the event loop; the turn comes back to be recorded
+                                let loaded_tx = self.loaded_models_tx.clone();
+                                let turns_tx = self.local_turns_tx.clone();
+                                tokio::spawn(async move {
+                                    // 1. Route to the requested model, loading it if needed
+                                    let engine = match acquired {
+                                        Ok(acquired) => acquired.ready(&loaded_tx, None).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    let engine = match engine {
+                                        Ok(engine) => engine,
+                                        Err(e) => {
+                                            error!("Failed to load model: {}", e);
+                                            let _ = respond_to.send(format!("Error: {}", e)).await;
+                                            let _ = turns_tx.send(LocalTurn { session_id, history: None, reply: None }).await;
+                                            return;
+                                        }
+                                    };
 
-                                // Spawn a task to forward tokens to Tauri AND accumulate them
-                                let forward_task = tokio::spawn(async move {
-                                    let mut accumulator = String::new();
-                                    while let Some(token) = proxy_rx.recv().await {
-                                        accumulator.push_str(&token);
-                                        let _ = respond_to.send(token).await;
-                                    }
-                                    accumulator
-                                });
+                                    // 2. Add User Message to History, only once a model can answer it
+                                    history.add_user(prompt);
 
-                                match self.ai_engine.generate_stream(&context_prompt, proxy_tx).await {
-                                    Ok(_) => {
-                                        // Wait for forwarding to finish (sender dropped)
-                                        if let Ok(final_text) = forward_task.await {
-                                            // 4. Add Assistant Message to History
-                                            self.chat_history.add_assistant(final_text);
-                                            self.save_history(); // Save after full response
+                                    // 3. Format with the model's chat template, dropping (or summarizing)
+                                    // the oldest turns that no longer fit its context window
+                                    let reply = match history.fit_context_with(engine.as_ref(), memory_context.as_deref()).await {
+                                        Ok(context_prompt) => stream_reply(engine.as_ref(), &context_prompt, respond_to).await,
+                                        Err(e) => {
+                                            error!("Failed to render chat template: {}", e);
+                                            let _ = respond_to.send(format!("Error: {}", e)).await;
+                                            None
                                         }
-                                    }
-                                    Err(e) => {
-                                        let _ = stream_tx.send(format!("Error: {}", e)).await;
-                                    }
-                                }
+                                    };
+                                    let _ = turns_tx.send(LocalTurn { session_id, history: Some(history), reply }).await;
+                                });
                             }
                         }
                         Some(NodeCommand::GetStatus { respond_to }) => {
@@ -518,25 +1370,26 @@ impl NodeService {
                             let state: Vec<Heartbeat> = self.mesh_state.get_all();
                             let _ = respond_to.send(state).await;
                         }
-                        Some(NodeCommand::SetSystemPrompt { prompt, respond_to }) => {
+                        Some(NodeCommand::SetSystemPrompt { session_id, prompt, respond_to }) => {
                             info!("Setting System Prompt to: {}", prompt);
-                            self.chat_history.clear();
-                            self.chat_history.add_system(prompt);
-                            self.save_history();
+                            let session_id = session_id.unwrap_or_else(|| DEFAULT_SESSION_ID.to_string());
+                            if let Err(e) = self.sessions.set_system_prompt(&session_id, prompt) {
+                                error!("Failed to set system prompt: {}", e);
+                            }
                             let _ = respond_to.send(()).await;
                         }
-                        Some(NodeCommand::Transcribe { audio_data, respond_to }) => {
+                        Some(NodeCommand::Transcribe { audio_data, language, respond_to }) => {
                             info!("Received audio transcription request: {} samples", audio_data.len());
                             let engine = self.whisper_engine.lock().await;
-                            match engine.transcribe(audio_data).await {
-                                Ok(text) => {
-                                    let _ = respond_to.send(text).await;
-                                }
-                                Err(e) => {
-                                    error!("Transcribe failed: {}", e);
-                                    let _ = respond_to.send(format!("Error: {}", e)).await;
-                                }
+                            let options = TranscribeOptions {
+                                language,
+                                ..TranscribeOptions::default()
+                            };
+                            let result = engine.transcribe(&audio_data, &options).await;
+                            if let Err(e) = &result {
+                                error!("Transcribe failed: {}", e);
                             }
+                            let _ = respond_to.send(result.map_err(|e| e.to_string())).await;
                         }
                         Some(NodeCommand::GetSystemInfo { respond_to }) => {
                             self.system.refresh_all();
@@ -572,6 +1425,71 @@ impl NodeService {
                             let json_response = serde_json::to_string(&response).unwrap_or_default();
                             let _ = respond_to.send(json_response).await;
                         }
+                        Some(NodeCommand::LoadModel { model_id, respond_to }) => {
+                            match self.acquire_engine(Some(&model_id)) {
+                                Ok(acquired) if !acquired.needs_load => {
+                                    let _ = respond_to.send(Ok(())).await;
+                                }
+                                Ok(acquired) => {
+                                    // Download and load off the event loop; registered when it comes back
+                                    let loaded_tx = self.loaded_models_tx.clone();
+                                    tokio::spawn(async move {
+                                        let _ = acquired.ready(&loaded_tx, Some(respond_to)).await;
+                                    });
+                                }
+                                Err(e) => {
+                                    error!("Failed to load model '{}': {}", model_id, e);
+                                    let _ = respond_to.send(Err(e.to_string())).await;
+                                }
+                            }
+                        }
+                        Some(NodeCommand::UnloadModel { model_id, respond_to }) => {
+                            info!("Unloading model '{}'", model_id);
+                            let result = self.models.unload(&model_id).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::ListModels { respond_to }) => {
+                            let _ = respond_to.send(self.models.list()).await;
+                        }
+                        Some(NodeCommand::CreateSession { name, system_prompt, respond_to }) => {
+                            info!("Creating chat session '{}'", name);
+                            let result = self.sessions.create(&name, system_prompt).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::ListSessions { respond_to }) => {
+                            let _ = respond_to.send(self.sessions.list()).await;
+                        }
+                        Some(NodeCommand::GetSessionMessages { session_id, respond_to }) => {
+                            let result = self.sessions.get(&session_id)
+                                .map(|session| session.history.get_history())
+                                .ok_or_else(|| format!("Session '{}' not found", session_id));
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::RenameSession { session_id, name, respond_to }) => {
+                            let result = self.sessions.rename(&session_id, &name).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::DeleteSession { session_id, respond_to }) => {
+                            info!("Deleting chat session '{}'", session_id);
+                            let result = self.sessions.delete(&session_id).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::ForkSession { session_id, name, respond_to }) => {
+                            let result = self.sessions.fork(&session_id, &name).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::ExportSession { session_id, respond_to }) => {
+                            let result = self.sessions.export(&session_id).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::ImportSession { data, respond_to }) => {
+                            let result = self.sessions.import(&data).map_err(|e| e.to_string());
+                            let _ = respond_to.send(result).await;
+                        }
+                        Some(NodeCommand::SearchMemory { query, namespace, respond_to }) => {
+                            let namespace = namespace.unwrap_or_else(|| LOCAL_NAMESPACE.to_string());
+                            self.start_federated_search(&query, &namespace, respond_to).await;
+                        }
                         None => {
                             // Channel closed
                             break;
@@ -589,3 +1507,81 @@ impl NodeService {
         }
     }
 }
+
+/// `data_dir`, or the platform's data directory for Plexus, created if missing.
+fn node_data_dir(data_dir: Option<PathBuf>) -> Result<PathBuf> {
+    let data_dir = match data_dir {
+        Some(path) => path,
+        None => directories_next::ProjectDirs::from("com", "plexus", "mesh")
+            .context("Could not determine data directory")?
+            .data_dir()
+            .to_path_buf(),
+    };
+    std::fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
+    Ok(data_dir)
+}
+
+/// Opens the memory a node started with these settings keeps, without starting the node,
+/// to export it or import into it. Fails if Qdrant is selected but unreachable.
+pub async fn open_memory_backup(
+    data_dir: Option<PathBuf>,
+    device: DeviceRequest,
+    embedding_model: &str,
+    vector_store: VectorStoreConfig,
+) -> Result<MemoryBackup> {
+    let data_dir = node_data_dir(data_dir)?;
+    let model = EmbedderRegistry::new().get(embedding_model)?;
+    let device = DeviceSelector::new(device).select();
+    let embedder = Arc::new(BertEmbedder::with_device(device.device).with_model(model.clone()));
+    let store = vector_store
+        .open_direct(&data_dir, &model)
+        .await?
+        .context("Memory is disabled by the vector store setting")?;
+    Ok(MemoryBackup::new(embedder, store))
+}
+
+/// Chat reply listing federated search results, best first.
+fn format_search_results(results: &[PeerResult]) -> String {
+    if results.is_empty() {
+        return "No matching memory found.".to_string();
+    }
+    let mut reply = "Memory search results:".to_string();
+    for r in results {
+        // The tail of a peer id is enough to tell peers apart
+        let peer = &r.peer[r.peer.len().saturating_sub(6)..];
+        reply.push_str(&format!(
+            "\n- ({:.2}, peer …{}) {}",
+            r.result.score, peer, r.result.text
+        ));
+    }
+    reply
+}
+
+/// Streams the reply to `prompt` to `respond_to` token by token. Returns the full reply,
+/// or `None` (after sending the error) if generation failed.
+async fn stream_reply(
+    engine: &dyn LLMEngine,
+    prompt: &str,
+    respond_to: mpsc::Sender<String>,
+) -> Option<String> {
+    // A proxy channel captures the text for ChatHistory while forwarding it
+    let (proxy_tx, mut proxy_rx) = mpsc::channel::<String>(32);
+    let stream_tx = respond_to.clone();
+    let forward_task = tokio::spawn(async move {
+        let mut accumulator = String::new();
+        while let Some(token) = proxy_rx.recv().await {
+            accumulator.push_str(&token);
+            let _ = stream_tx.send(token).await;
+        }
+        accumulator
+    });
+
+    match engine.generate_stream(prompt, proxy_tx).await {
+        // Forwarding finishes once the engine drops the sender
+        Ok(_) => forward_task.await.ok(),
+        Err(e) => {
+            let _ = respond_to.send(format!("Error: {}", e)).await;
+            None
+        }
+    }
+}
@@ -1,8 +1,20 @@
+use crate::shared_memory::{MemoryOp, VersionVector};
+use plexus_ai::{GenerationParams, GenerationRecord, ResponseFormat, SearchFilter, SearchResult};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GenerateRequest {
     pub prompt: String,
+    /// Model to generate with; the peer's default model when `None`
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Structured output the response must match; plain text when `None`
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    /// Generate reproducibly with these params and return an audit record. Cannot be
+    /// combined with `response_format`; peers may cap `max_new_tokens`
+    #[serde(default)]
+    pub deterministic: Option<GenerationParams>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
@@ -11,12 +23,17 @@ pub struct NodeCapabilities {
     pub total_memory: u64, // Bytes
     pub gpu_info: Option<String>,
     pub model_loaded: bool,
+    /// Memory namespaces peers may search on this node
+    #[serde(default)]
+    pub memory_namespaces: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Heartbeat {
     pub peer_id: String,
-    pub model: String, // Active model (e.g. tinyllama)
+    pub model: String, // Default model (e.g. tinyllama)
+    #[serde(default)]
+    pub resident_models: Vec<String>, // Models currently loaded in memory
     pub capabilities: NodeCapabilities,
     pub timestamp: u64, // Unix timestamp for LWW
 }
@@ -24,4 +41,43 @@ pub struct Heartbeat {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GenerateResponse {
     pub response: String,
+    /// Audit record of a deterministic generation, which the requester can replay
+    #[serde(default)]
+    pub record: Option<GenerationRecord>,
+}
+
+/// Asks a peer for the shared memory operations of `namespace` missing from `version`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemorySyncRequest {
+    pub namespace: String,
+    pub version: VersionVector,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemorySyncResponse {
+    /// Empty if the peer does not share the namespace
+    pub ops: Vec<MemoryOp>,
+    /// More operations are missing; ask again with the updated version
+    pub more: bool,
+}
+
+/// Searches a peer's memory namespace with a query embedding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemorySearchRequest {
+    pub namespace: String,
+    /// Embedding model of `vector`; peers using another model cannot compare it
+    pub model: String,
+    pub vector: Vec<f32>,
+    pub k: usize,
+    #[serde(default)]
+    pub filter: SearchFilter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemorySearchResponse {
+    /// Scored by cosine similarity, best first
+    pub results: Vec<SearchResult>,
+    /// Why the peer did not search, e.g. the namespace is not shared with the requester
+    #[serde(default)]
+    pub error: Option<String>,
 }
@@ -0,0 +1,331 @@
+use libp2p::identity::{Keypair, PublicKey};
+use plexus_ai::Document;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Highest operation counter of every replica up to which all operations are known.
+pub type VersionVector = BTreeMap<String, u64>;
+
+/// Unique tag of an operation: the replica (peer id) that wrote it and its position in
+/// that replica's sequence of writes, starting at 1.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Dot {
+    pub replica: String,
+    pub counter: u64,
+}
+
+/// A shared document with the embedding it was saved with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharedEntry {
+    pub document: Document,
+    pub vector: Vec<f32>,
+    /// Embedding model `vector` was produced with; other models re-embed the text
+    pub model: String,
+    /// Unix time in milliseconds; the latest of concurrent writes to an id is read
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Change {
+    Add(SharedEntry),
+    /// Removes the adds of `id` the writer had seen. Adds it had not seen survive.
+    Remove {
+        id: String,
+        observed: Vec<Dot>,
+    },
+}
+
+/// A write to shared memory, replicated to every node of the namespace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryOp {
+    pub dot: Dot,
+    pub change: Change,
+    /// Protobuf encoding of the writer's public key; its peer id is `dot.replica`
+    pub public_key: Vec<u8>,
+    /// Signature of `dot` and `change` by the writer's key
+    pub signature: Vec<u8>,
+}
+
+impl MemoryOp {
+    /// Operation `dot` making `change`, signed with the key of `dot.replica`.
+    pub fn signed(dot: Dot, change: Change, keypair: &Keypair) -> anyhow::Result<Self> {
+        let signature = keypair.sign(&signed_bytes(&dot, &change)?)?;
+        Ok(Self {
+            dot,
+            change,
+            public_key: keypair.public().encode_protobuf(),
+            signature,
+        })
+    }
+
+    /// Checks that the replica in the dot wrote the operation, whichever peer relayed it.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let key = PublicKey::try_decode_protobuf(&self.public_key)?;
+        anyhow::ensure!(
+            key.to_peer_id().to_string() == self.dot.replica,
+            "Operation of {} is signed by another key",
+            self.dot.replica
+        );
+        anyhow::ensure!(
+            key.verify(&signed_bytes(&self.dot, &self.change)?, &self.signature),
+            "Operation {} of {} has an invalid signature",
+            self.dot.counter,
+            self.dot.replica
+        );
+        Ok(())
+    }
+
+    /// Id of the document the operation changes.
+    pub fn document_id(&self) -> &str {
+        match &self.change {
+            Change::Add(entry) => &entry.document.id,
+            Change::Remove { id, .. } => id,
+        }
+    }
+}
+
+/// Shared memory namespace replicated as an add-wins observed-remove set.
+///
+/// Every add is tagged with a [`Dot`]; a remove tombstones the dots it observed, so an add
+/// concurrent with a remove wins. Operations commute and are idempotent, so replicas
+/// converge whatever order they arrive in. They are kept in a Sled log, which lets a
+/// replica that was partitioned catch up with just the operations missing from its
+/// [`VersionVector`] (see [`Self::ops_since`]). Every operation is signed by the replica
+/// that wrote it, so peers relaying operations cannot forge or alter them.
+pub struct SharedMemory {
+    keypair: Keypair,
+    replica: String,
+    db: sled::Db,
+    version: VersionVector,
+    /// Entries of add operations that have not been removed
+    adds: HashMap<Dot, SharedEntry>,
+    tombstones: HashSet<Dot>,
+    /// Live add dots of every id
+    live: HashMap<String, HashSet<Dot>>,
+}
+
+impl SharedMemory {
+    /// Opens or creates the operation log at `path`. Local writes are tagged with the
+    /// peer id of `keypair` and signed with it.
+    pub fn open(path: std::path::PathBuf, keypair: Keypair) -> anyhow::Result<Self> {
+        let db = sled::open(path)?;
+        let mut memory = Self {
+            replica: keypair.public().to_peer_id().to_string(),
+            keypair,
+            db,
+            version: VersionVector::new(),
+            adds: HashMap::new(),
+            tombstones: HashSet::new(),
+            live: HashMap::new(),
+        };
+        let mut replicas = HashSet::new();
+        for item in memory.db.iter() {
+            let (_, value) = item?;
+            let op: MemoryOp = serde_json::from_slice(&value)?;
+            replicas.insert(op.dot.replica.clone());
+            memory.integrate(op);
+        }
+        for replica in replicas {
+            memory.advance(&replica);
+        }
+        Ok(memory)
+    }
+
+    /// The peer id local writes are tagged with.
+    pub fn replica(&self) -> &str {
+        &self.replica
+    }
+
+    pub fn version(&self) -> &VersionVector {
+        &self.version
+    }
+
+    /// Number of live documents.
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+
+    /// The live entry of `id`; of concurrent adds, the latest.
+    pub fn get(&self, id: &str) -> Option<&SharedEntry> {
+        self.live
+            .get(id)?
+            .iter()
+            .filter_map(|dot| Some((self.adds.get(dot)?, dot)))
+            .max_by(|(a, a_dot), (b, b_dot)| a.timestamp.cmp(&b.timestamp).then(a_dot.cmp(b_dot)))
+            .map(|(entry, _)| entry)
+    }
+
+    /// All live entries.
+    pub fn entries(&self) -> Vec<&SharedEntry> {
+        self.live.keys().filter_map(|id| self.get(id)).collect()
+    }
+
+    /// Adds `document` with its `vector` from `model`, replacing the entry of its id.
+    /// Returns the operations to replicate.
+    pub fn insert(
+        &mut self,
+        document: Document,
+        vector: Vec<f32>,
+        model: &str,
+    ) -> anyhow::Result<Vec<MemoryOp>> {
+        let mut ops = vec![];
+        // The previous adds are removed so they do not linger in the namespace
+        let observed = self.observed(&document.id);
+        if !observed.is_empty() {
+            let id = document.id.clone();
+            ops.push(self.write(Change::Remove { id, observed })?);
+        }
+        let entry = SharedEntry {
+            document,
+            vector,
+            model: model.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_millis() as u64,
+        };
+        ops.push(self.write(Change::Add(entry))?);
+        Ok(ops)
+    }
+
+    /// Removes `id`, returning the operation to replicate; `None` if it is not present.
+    pub fn remove(&mut self, id: &str) -> anyhow::Result<Option<MemoryOp>> {
+        let observed = self.observed(id);
+        if observed.is_empty() {
+            return Ok(None);
+        }
+        let id = id.to_string();
+        self.write(Change::Remove { id, observed }).map(Some)
+    }
+
+    /// Applies an operation received from another replica. Returns whether it was new.
+    ///
+    /// Fails for an operation its replica did not sign, and for an unknown operation
+    /// tagged with this replica, as only this replica writes those.
+    pub fn apply(&mut self, op: MemoryOp) -> anyhow::Result<bool> {
+        op.verify()?;
+        if op.dot.replica == self.replica && !self.db.contains_key(op_key(&op.dot))? {
+            anyhow::bail!(
+                "Operation {} of local replica {} was not written here",
+                op.dot.counter,
+                op.dot.replica
+            );
+        }
+        self.store(op)
+    }
+
+    /// Logs and integrates `op`. Returns whether it was new.
+    fn store(&mut self, op: MemoryOp) -> anyhow::Result<bool> {
+        let key = op_key(&op.dot);
+        if self.db.contains_key(&key)? {
+            return Ok(false);
+        }
+        self.db.insert(key, serde_json::to_vec(&op)?)?;
+        let replica = op.dot.replica.clone();
+        self.integrate(op);
+        self.advance(&replica);
+        Ok(true)
+    }
+
+    /// Up to `limit` operations that a replica at `version` is missing, in log order, and
+    /// whether more are missing.
+    pub fn ops_since(
+        &self,
+        version: &VersionVector,
+        limit: usize,
+    ) -> anyhow::Result<(Vec<MemoryOp>, bool)> {
+        let mut ops = vec![];
+        for replica in self.version.keys() {
+            let start = version.get(replica).copied().unwrap_or(0) + 1;
+            let range = op_key(&dot(replica, start))..=op_key(&dot(replica, u64::MAX));
+            for item in self.db.range(range) {
+                if ops.len() == limit {
+                    return Ok((ops, true));
+                }
+                let (_, value) = item?;
+                ops.push(serde_json::from_slice(&value)?);
+            }
+        }
+        Ok((ops, false))
+    }
+
+    fn observed(&self, id: &str) -> Vec<Dot> {
+        let mut observed: Vec<Dot> = self
+            .live
+            .get(id)
+            .map(|dots| dots.iter().cloned().collect())
+            .unwrap_or_default();
+        observed.sort();
+        observed
+    }
+
+    fn write(&mut self, change: Change) -> anyhow::Result<MemoryOp> {
+        let counter = self.version.get(&self.replica).copied().unwrap_or(0) + 1;
+        let op = MemoryOp::signed(dot(&self.replica, counter), change, &self.keypair)?;
+        self.store(op.clone())?;
+        Ok(op)
+    }
+
+    /// Adds `op` to the in-memory state.
+    fn integrate(&mut self, op: MemoryOp) {
+        match op.change {
+            Change::Add(entry) => {
+                // The remove may arrive before the add it removes
+                if self.tombstones.contains(&op.dot) {
+                    return;
+                }
+                self.live
+                    .entry(entry.document.id.clone())
+                    .or_default()
+                    .insert(op.dot.clone());
+                self.adds.insert(op.dot, entry);
+            }
+            // The id the add was made under counts, not the one the remove claims
+            Change::Remove { observed, .. } => {
+                for dot in observed {
+                    if let Some(entry) = self.adds.remove(&dot) {
+                        let id = entry.document.id;
+                        if let Some(dots) = self.live.get_mut(&id) {
+                            dots.remove(&dot);
+                            if dots.is_empty() {
+                                self.live.remove(&id);
+                            }
+                        }
+                    }
+                    self.tombstones.insert(dot);
+                }
+            }
+        }
+    }
+
+    /// Moves the version of `replica` past every contiguous operation in the log.
+    fn advance(&mut self, replica: &str) {
+        let mut counter = self.version.get(replica).copied().unwrap_or(0);
+        while let Ok(true) = self.db.contains_key(op_key(&dot(replica, counter + 1))) {
+            counter += 1;
+        }
+        self.version.insert(replica.to_string(), counter);
+    }
+}
+
+fn signed_bytes(dot: &Dot, change: &Change) -> anyhow::Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&(dot, change))?)
+}
+
+fn dot(replica: &str, counter: u64) -> Dot {
+    Dot {
+        replica: replica.to_string(),
+        counter,
+    }
+}
+
+/// Log key ordering operations by replica, then counter.
+fn op_key(dot: &Dot) -> Vec<u8> {
+    let mut key = dot.replica.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(&dot.counter.to_be_bytes());
+    key
+}
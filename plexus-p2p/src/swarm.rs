@@ -1,4 +1,7 @@
-use crate::{GenerateRequest, GenerateResponse};
+use crate::{
+    GenerateRequest, GenerateResponse, MemorySearchRequest, MemorySearchResponse,
+    MemorySyncRequest, MemorySyncResponse,
+};
 use anyhow::Result;
 use libp2p::{
     core::Transport,
@@ -17,6 +20,10 @@ pub struct PlexusBehaviour {
     pub kademlia: kad::Behaviour<kad::store::MemoryStore>,
     pub mdns: mdns::tokio::Behaviour,
     pub request_response: cbor::Behaviour<GenerateRequest, GenerateResponse>,
+    /// Anti-entropy for shared memory namespaces
+    pub memory_sync: cbor::Behaviour<MemorySyncRequest, MemorySyncResponse>,
+    /// Federated search of the memory peers share
+    pub memory_search: cbor::Behaviour<MemorySearchRequest, MemorySearchResponse>,
     pub dcutr: libp2p::dcutr::Behaviour,
     pub relay: libp2p::relay::client::Behaviour,
 }
@@ -56,6 +63,30 @@ pub async fn build_swarm(keypair: Keypair) -> Result<Swarm<PlexusBehaviour>> {
         rr_config,
     );
 
+    let mut sync_config = request_response::Config::default();
+    sync_config.set_request_timeout(Duration::from_secs(30));
+
+    let memory_sync = cbor::Behaviour::new(
+        [(
+            StreamProtocol::new("/plexus/memory-sync/1.0.0"),
+            ProtocolSupport::Full,
+        )],
+        sync_config,
+    );
+
+    // Searches wait for at most the federated search peer timeout; this only bounds
+    // requests that outlive it
+    let mut search_config = request_response::Config::default();
+    search_config.set_request_timeout(Duration::from_secs(30));
+
+    let memory_search = cbor::Behaviour::new(
+        [(
+            StreamProtocol::new("/plexus/memory-search/1.0.0"),
+            ProtocolSupport::Full,
+        )],
+        search_config,
+    );
+
     // Hole Punching (DCUTR)
     let dcutr = libp2p::dcutr::Behaviour::new(peer_id);
 
@@ -75,6 +106,8 @@ pub async fn build_swarm(keypair: Keypair) -> Result<Swarm<PlexusBehaviour>> {
         kademlia,
         mdns,
         request_response,
+        memory_sync,
+        memory_search,
         dcutr,
         relay: relay_behaviour,
     };
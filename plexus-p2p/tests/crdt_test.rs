@@ -16,12 +16,14 @@ fn heartbeat_strategy() -> impl Strategy<Value = Heartbeat> {
             |(peer_id, model, timestamp, cpu_cores, total_memory)| Heartbeat {
                 peer_id,
                 model,
+                resident_models: vec![],
                 timestamp,
                 capabilities: NodeCapabilities {
                     cpu_cores,
                     total_memory,
                     gpu_info: None,
                     model_loaded: true,
+                    memory_namespaces: vec![],
                 },
             },
         )
@@ -0,0 +1,104 @@
+use plexus_ai::SearchResult;
+use plexus_p2p::federated::FederatedSearch;
+use plexus_p2p::{Heartbeat, MemoryAcl, NamespaceShare, LOCAL_NAMESPACE};
+
+fn result(id: &str, score: f32) -> SearchResult {
+    SearchResult {
+        id: id.to_string(),
+        text: format!("text of {}", id),
+        metadata: Default::default(),
+        score,
+    }
+}
+
+#[test]
+fn test_acl_shares_only_listed_namespaces() -> anyhow::Result<()> {
+    let acl = MemoryAcl::new()
+        .with_share("notes=peer-a,peer-b".parse()?)
+        .with_share("notes=peer-c".parse()?)
+        .with_share(LOCAL_NAMESPACE.parse()?);
+
+    assert!(acl.allows("notes", "peer-a"));
+    assert!(acl.allows("notes", "peer-c"));
+    assert!(!acl.allows("notes", "peer-d"));
+    assert!(acl.allows(LOCAL_NAMESPACE, "anyone"));
+    assert!(!acl.allows("secrets", "peer-a"));
+    assert_eq!(acl.namespaces(), ["local", "notes"]);
+
+    // Sharing with any peer takes precedence over a peer list
+    let open = acl.with_share("notes".parse()?);
+    assert!(open.allows("notes", "peer-d"));
+
+    assert!("notes=".parse::<NamespaceShare>().is_err());
+    assert!("=peer-a".parse::<NamespaceShare>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_merge_keeps_best_results_across_peers() {
+    let mut search = FederatedSearch::new(3);
+    search.add_results("me", vec![result("mine", 0.4)]);
+    search.expect("peer-a");
+    search.expect("peer-b");
+
+    search.add("peer-a", Ok(vec![result("shared", 0.7), result("a", 0.9)]));
+    // Replicated shared memory comes back from several peers but is listed once
+    search.add("peer-b", Ok(vec![result("shared", 0.8), result("b", 0.1)]));
+    // Peers not asked, or answering twice, are ignored
+    search.add("peer-c", Ok(vec![result("stranger", 1.0)]));
+    search.add("peer-a", Ok(vec![result("again", 1.0)]));
+    assert!(search.is_complete());
+
+    let results = search.finish();
+    let ranked: Vec<(&str, &str, f32)> = results
+        .iter()
+        .map(|r| (r.peer.as_str(), r.result.id.as_str(), r.result.score))
+        .collect();
+    assert_eq!(
+        ranked,
+        [
+            ("peer-a", "a", 0.9),
+            ("peer-b", "shared", 0.8),
+            ("me", "mine", 0.4)
+        ]
+    );
+}
+
+#[test]
+fn test_unanswered_and_failed_peers_are_left_out() {
+    let mut search = FederatedSearch::new(5);
+    search.expect("slow");
+    search.expect("refusing");
+    search.expect("fast");
+
+    search.add("refusing", Err("Memory 'local' is not shared".to_string()));
+    search.add("fast", Ok(vec![result("f", 0.5)]));
+    assert!(!search.is_complete());
+    assert_eq!(search.outstanding().collect::<Vec<_>>(), ["slow"]);
+    assert!(search.errors().contains_key("refusing"));
+
+    // On timeout the search finishes with what arrived
+    let results = search.finish();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].peer, "fast");
+}
+
+#[test]
+fn test_heartbeats_without_memory_namespaces() -> anyhow::Result<()> {
+    // Heartbeats from nodes predating federated search still parse
+    let json = r#"{
+        "peer_id": "old-peer",
+        "model": "tinyllama",
+        "resident_models": [],
+        "capabilities": {
+            "cpu_cores": 4,
+            "total_memory": 8000000000,
+            "gpu_info": null,
+            "model_loaded": true
+        },
+        "timestamp": 1
+    }"#;
+    let heartbeat: Heartbeat = serde_json::from_str(json)?;
+    assert!(heartbeat.capabilities.memory_namespaces.is_empty());
+    Ok(())
+}
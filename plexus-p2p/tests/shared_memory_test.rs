@@ -0,0 +1,253 @@
+use libp2p::identity::Keypair;
+use plexus_ai::Document;
+use plexus_p2p::shared_memory::{Change, MemoryOp, SharedMemory, VersionVector};
+use proptest::prelude::*;
+use tempfile::{Builder, TempDir};
+
+/// The same key for every replica opened under `name`.
+fn keypair(name: &str) -> Keypair {
+    let mut seed = [0u8; 32];
+    seed[..name.len()].copy_from_slice(name.as_bytes());
+    Keypair::ed25519_from_bytes(seed).unwrap()
+}
+
+fn open(dir: &TempDir, name: &str) -> SharedMemory {
+    SharedMemory::open(dir.path().join(name), keypair(name)).expect("Failed to open shared memory")
+}
+
+fn temp_dir() -> TempDir {
+    Builder::new()
+        .prefix("plexus_shared_memory_test")
+        .tempdir()
+        .unwrap()
+}
+
+fn document(id: &str, text: &str) -> Document {
+    Document::new(id, text)
+}
+
+/// Brings `to` up to date with `from` in batches of `limit`, as peers do on reconnect.
+fn sync(from: &SharedMemory, to: &mut SharedMemory, limit: usize) -> usize {
+    let mut applied = 0;
+    loop {
+        let (ops, more) = from.ops_since(to.version(), limit).unwrap();
+        for op in ops {
+            applied += to.apply(op).unwrap() as usize;
+        }
+        if !more {
+            return applied;
+        }
+    }
+}
+
+fn texts(memory: &SharedMemory) -> Vec<(String, String)> {
+    let mut texts: Vec<_> = memory
+        .entries()
+        .iter()
+        .map(|e| (e.document.id.clone(), e.document.text.clone()))
+        .collect();
+    texts.sort();
+    texts
+}
+
+#[test]
+fn test_concurrent_add_wins_over_remove() {
+    let dir = temp_dir();
+    let mut laptop = open(&dir, "laptop");
+    let mut workstation = open(&dir, "workstation");
+
+    laptop
+        .insert(document("wifi", "password is hunter2"), vec![1.0], "minilm")
+        .unwrap();
+    sync(&laptop, &mut workstation, 10);
+
+    // While partitioned, one side removes the note and the other re-adds it
+    workstation.remove("wifi").unwrap().unwrap();
+    laptop
+        .insert(document("wifi", "password is hunter3"), vec![1.0], "minilm")
+        .unwrap();
+
+    sync(&laptop, &mut workstation, 10);
+    sync(&workstation, &mut laptop, 10);
+    assert_eq!(texts(&laptop), texts(&workstation));
+    assert_eq!(
+        laptop.get("wifi").unwrap().document.text,
+        "password is hunter3"
+    );
+
+    // A remove that has seen the add takes effect everywhere
+    laptop.remove("wifi").unwrap().unwrap();
+    sync(&laptop, &mut workstation, 10);
+    assert!(workstation.get("wifi").is_none());
+    assert!(workstation.remove("missing").unwrap().is_none());
+}
+
+#[test]
+fn test_incremental_sync_after_partition() {
+    let dir = temp_dir();
+    let mut a = open(&dir, "a");
+    let mut b = open(&dir, "b");
+
+    for i in 0..20 {
+        a.insert(document(&format!("a{}", i), "from a"), vec![0.5], "minilm")
+            .unwrap();
+    }
+    assert_eq!(sync(&a, &mut b, 7), 20);
+
+    // Only the writes made during the partition are exchanged afterwards
+    a.insert(document("a-late", "late"), vec![0.5], "minilm")
+        .unwrap();
+    b.insert(document("b0", "from b"), vec![0.5], "nomic")
+        .unwrap();
+    assert_eq!(sync(&a, &mut b, 7), 1);
+    assert_eq!(sync(&b, &mut a, 7), 1);
+    assert_eq!(sync(&a, &mut b, 7), 0);
+
+    assert_eq!(a.len(), 22);
+    assert_eq!(texts(&a), texts(&b));
+    assert_eq!(a.version(), b.version());
+    assert_eq!(a.get("b0").unwrap().model, "nomic");
+}
+
+#[test]
+fn test_forged_ops_are_rejected() {
+    let dir = temp_dir();
+    let mut attacker = open(&dir, "attacker");
+    let mut writer = open(&dir, "writer");
+    let mut reader = open(&dir, "reader");
+
+    // Signed by the attacker but claiming to come from the writer
+    let mut forged = attacker
+        .insert(document("note", "forged"), vec![1.0, 0.0], "minilm")
+        .unwrap()
+        .remove(0);
+    forged.dot.replica = writer.replica().to_string();
+    assert!(reader.apply(forged.clone()).is_err());
+
+    // Written by the writer but altered by the peer relaying it
+    let genuine = writer
+        .insert(document("note", "real"), vec![1.0, 0.0], "minilm")
+        .unwrap()
+        .remove(0);
+    let mut altered = genuine.clone();
+    if let Change::Add(entry) = &mut altered.change {
+        entry.document.text = "altered".to_string();
+    }
+    assert!(reader.apply(altered).is_err());
+    assert!(reader.entries().is_empty());
+
+    // Neither blocks the genuine operation
+    assert!(reader.apply(genuine).unwrap());
+    assert_eq!(reader.get("note").unwrap().document.text, "real");
+
+    // Operations tagged with the local replica only come from its own log
+    forged.dot.replica = reader.replica().to_string();
+    assert!(reader.apply(forged).is_err());
+    let own = reader.remove("note").unwrap().unwrap();
+    assert!(!reader.apply(own).unwrap());
+}
+
+#[test]
+fn test_remove_applies_to_the_id_it_observed() {
+    let dir = temp_dir();
+    let mut writer = open(&dir, "writer");
+    let mut reader = open(&dir, "reader");
+
+    writer
+        .insert(document("kept", "kept"), vec![1.0], "minilm")
+        .unwrap();
+    writer
+        .insert(document("gone", "gone"), vec![1.0], "minilm")
+        .unwrap();
+    sync(&writer, &mut reader, 10);
+
+    // A member of the namespace removing one add while naming another id
+    let remove = writer.remove("gone").unwrap().unwrap();
+    let Change::Remove { observed, .. } = remove.change else {
+        panic!("expected a remove");
+    };
+    let change = Change::Remove {
+        id: "kept".to_string(),
+        observed,
+    };
+    let remove = MemoryOp::signed(remove.dot, change, &keypair("writer")).unwrap();
+    reader.apply(remove).unwrap();
+    assert!(reader.get("gone").is_none());
+    assert_eq!(reader.get("kept").unwrap().document.text, "kept");
+    assert_eq!(reader.entries().len(), 1);
+}
+
+#[test]
+fn test_reopen_restores_state() {
+    let dir = temp_dir();
+    let version: VersionVector;
+    {
+        let mut memory = open(&dir, "node");
+        memory
+            .insert(document("kept", "kept"), vec![1.0, 0.0], "minilm")
+            .unwrap();
+        memory
+            .insert(document("gone", "gone"), vec![0.0, 1.0], "minilm")
+            .unwrap();
+        memory.remove("gone").unwrap();
+        version = memory.version().clone();
+    }
+
+    let mut memory = open(&dir, "node");
+    assert_eq!(memory.version(), &version);
+    assert_eq!(texts(&memory), [("kept".to_string(), "kept".to_string())]);
+
+    // New writes continue the sequence rather than reusing dots
+    let ops = memory
+        .insert(document("new", "new"), vec![1.0, 1.0], "minilm")
+        .unwrap();
+    assert_eq!(ops[0].dot.counter, version[memory.replica()] + 1);
+}
+
+/// Writes by three replicas, each seeing only its own.
+fn ops_strategy() -> impl Strategy<Value = Vec<MemoryOp>> {
+    proptest::collection::vec((0..3usize, 0..4usize, any::<bool>()), 1..30).prop_map(|writes| {
+        let dir = temp_dir();
+        let mut replicas: Vec<SharedMemory> = ["r0", "r1", "r2"]
+            .iter()
+            .map(|name| open(&dir, name))
+            .collect();
+        let mut ops = vec![];
+        for (replica, id, add) in writes {
+            let memory = &mut replicas[replica];
+            let id = format!("doc{}", id);
+            if add {
+                ops.extend(memory.insert(document(&id, &id), vec![1.0], "m").unwrap());
+            } else if let Some(op) = memory.remove(&id).unwrap() {
+                ops.push(op);
+            }
+        }
+        ops
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn test_delivery_order_does_not_matter(ops in ops_strategy(), seed in any::<u64>()) {
+        let dir = temp_dir();
+        let mut in_order = open(&dir, "in_order");
+        for op in ops.clone() {
+            in_order.apply(op).unwrap();
+        }
+
+        // Reversed and with duplicates, as gossip may deliver them
+        let mut shuffled = ops.clone();
+        shuffled.reverse();
+        shuffled.rotate_left(seed as usize % ops.len().max(1));
+        shuffled.extend(ops.iter().take(3).cloned());
+        let mut out_of_order = open(&dir, "out_of_order");
+        for op in shuffled {
+            out_of_order.apply(op).unwrap();
+        }
+
+        prop_assert_eq!(texts(&in_order), texts(&out_of_order));
+        prop_assert_eq!(in_order.version(), out_of_order.version());
+    }
+}
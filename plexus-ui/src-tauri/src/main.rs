@@ -1,7 +1,10 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use plexus_p2p::{Heartbeat, NodeCommand, NodeService, NodeStatus, SystemCapabilities};
+use plexus_p2p::{
+    ChatMessage, DeviceRequest, Heartbeat, ModelInfo, NodeCommand, NodeService, NodeStatus,
+    SessionInfo, SystemCapabilities, Transcript,
+};
 use std::path::PathBuf;
 use tauri::{Emitter, Manager, State}; // v2: emit is replaced by Emitter trait or emit_to
 use tokio::sync::mpsc;
@@ -27,6 +30,8 @@ async fn get_node_status(state: State<'_, AppState>) -> Result<NodeStatus, Strin
 #[tauri::command]
 async fn generate_prompt(
     prompt: String,
+    model: Option<String>,
+    session_id: Option<String>,
     state: State<'_, AppState>,
     app_handle: tauri::AppHandle,
 ) -> Result<(), String> {
@@ -35,6 +40,8 @@ async fn generate_prompt(
         .node_tx
         .send(NodeCommand::Generate {
             prompt,
+            model,
+            session_id,
             respond_to: tx,
         })
         .await
@@ -53,11 +60,16 @@ async fn generate_prompt(
 }
 
 #[tauri::command]
-async fn set_system_prompt(prompt: String, state: State<'_, AppState>) -> Result<(), String> {
+async fn set_system_prompt(
+    prompt: String,
+    session_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
     let (tx, mut rx) = mpsc::channel(1);
     state
         .node_tx
         .send(NodeCommand::SetSystemPrompt {
+            session_id,
             prompt,
             respond_to: tx,
         })
@@ -72,13 +84,15 @@ async fn set_system_prompt(prompt: String, state: State<'_, AppState>) -> Result
 #[tauri::command]
 async fn transcribe_audio(
     audio_data: Vec<f32>,
+    language: Option<String>,
     state: State<'_, AppState>,
-) -> Result<String, String> {
+) -> Result<Transcript, String> {
     let (tx, mut rx) = mpsc::channel(1);
     state
         .node_tx
         .send(NodeCommand::Transcribe {
             audio_data,
+            language,
             respond_to: tx,
         })
         .await
@@ -86,7 +100,7 @@ async fn transcribe_audio(
 
     rx.recv()
         .await
-        .ok_or_else(|| "Node service closed".to_string())
+        .ok_or_else(|| "Node service closed".to_string())?
 }
 
 #[tauri::command]
@@ -132,6 +146,205 @@ async fn start_pairing(state: State<'_, AppState>) -> Result<String, String> {
         .ok_or_else(|| "Node service closed".to_string())
 }
 
+#[tauri::command]
+async fn load_model(model_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::LoadModel {
+            model_id,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn unload_model(model_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::UnloadModel {
+            model_id,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn list_models(state: State<'_, AppState>) -> Result<Vec<ModelInfo>, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::ListModels { respond_to: tx })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())
+}
+
+#[tauri::command]
+async fn create_session(
+    name: String,
+    system_prompt: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<SessionInfo, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::CreateSession {
+            name,
+            system_prompt,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn list_sessions(state: State<'_, AppState>) -> Result<Vec<SessionInfo>, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::ListSessions { respond_to: tx })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())
+}
+
+#[tauri::command]
+async fn get_session_messages(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<ChatMessage>, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::GetSessionMessages {
+            session_id,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn rename_session(
+    session_id: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::RenameSession {
+            session_id,
+            name,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn delete_session(session_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::DeleteSession {
+            session_id,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn fork_session(
+    session_id: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<SessionInfo, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::ForkSession {
+            session_id,
+            name,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn export_session(session_id: String, state: State<'_, AppState>) -> Result<String, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::ExportSession {
+            session_id,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
+#[tauri::command]
+async fn import_session(data: String, state: State<'_, AppState>) -> Result<SessionInfo, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    state
+        .node_tx
+        .send(NodeCommand::ImportSession {
+            data,
+            respond_to: tx,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    rx.recv()
+        .await
+        .ok_or_else(|| "Node service closed".to_string())?
+}
+
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
@@ -159,6 +372,8 @@ fn main() {
                     "tinyllama".to_string(),
                     vec![],
                     Some(app_dir),
+                    None,
+                    DeviceRequest::Auto,
                 )
                 .await
                 {
@@ -183,7 +398,18 @@ fn main() {
             transcribe_audio,
             get_mesh_state,
             check_hardware,
-            start_pairing
+            start_pairing,
+            load_model,
+            unload_model,
+            list_models,
+            create_session,
+            list_sessions,
+            get_session_messages,
+            rename_session,
+            delete_session,
+            fork_session,
+            export_session,
+            import_session
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -2,6 +2,11 @@ import { useState, useRef, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 
+interface Transcript {
+  text: string;
+  language: string | null;
+}
+
 interface DashboardViewProps {
   status: any;
   meshState: any[];
@@ -174,7 +179,7 @@ export function DashboardView({ status, meshState }: DashboardViewProps) {
     setLoading(true);
 
     try {
-      const text = await invoke<string>("transcribe_audio", {
+      const { text } = await invoke<Transcript>("transcribe_audio", {
         audioData: audioDataRef.current,
       });
       setPrompt((prev) => (prev ? prev + " " : "") + text);
@@ -9,6 +9,11 @@ interface NodeStatus {
   connected_peers: number;
 }
 
+interface Transcript {
+  text: string;
+  language: string | null;
+}
+
 interface NodeCapabilities {
   cpu_cores: number;
   total_memory: number;
@@ -221,7 +226,7 @@ function App() {
 
     try {
       // Send to backend
-      const text = await invoke<string>("transcribe_audio", {
+      const { text } = await invoke<Transcript>("transcribe_audio", {
         audioData: audioDataRef.current,
       });
       setPrompt((prev) => (prev ? prev + " " : "") + text);